@@ -1,7 +1,10 @@
 use std::{
     collections::{BTreeMap, VecDeque},
     error::Error,
-    sync::atomic::AtomicBool,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
     thread,
     time::Duration,
 };
@@ -10,48 +13,86 @@ use binance::{
     api::Binance,
     config::Config,
     futures::{
-        account::FuturesAccount,
+        account::{CustomOrderRequest, FuturesAccount, OrderSide, OrderType},
         general::FuturesGeneral,
         market::FuturesMarket,
-        model::{CanceledOrder, Filters::PriceFilter},
+        model::{
+            AccountUpdateEvent, AggTrades, CanceledOrder, Filters, Order,
+            OrderBook as BookSnapshot, OrderTradeEvent,
+        },
+        userstream::FuturesUserStream,
         websockets::{FuturesMarket as FuturesMarketWs, FuturesWebSockets, FuturesWebsocketEvent},
     },
-    model::{Asks, Bids, DepthOrderBookEvent},
+    model::{Asks, Bids, DepthOrderBookEvent, KlineSummaries},
 };
 use ordered_float::OrderedFloat;
 use tokio::task;
+use tokio_stream::{Stream, StreamExt};
 
 use crate::utils::{
+    backoff::{Backoff, MAX_DELAY},
     bot::LiveBot,
-    localorderbook::OrderBook,
+    localorderbook::{
+        dead_zone, level_impact, notional_within, set_level, sweep_price, OrderBook,
+        DEFAULT_DECAY_RATE, IMBALANCE_DEAD_ZONE,
+    },
     logger::Logger,
+    metrics,
     models::{
-        BatchAmend, BatchOrder, BinanceBook, BinanceClient, BinanceMarket, LiveOrder, SymbolInfo,
+        BatchAmend, BatchOrder, BinanceBook, BinanceClient, BinanceMarket, BinancePrivate, Candle,
+        ws_trade_from_agg, FeeTier, LiveOrder, RejectedAmend, RejectedOrder, SymbolInfo,
+        TimeInForce,
     },
-    number::decay,
+    number::{decay, kahan_sum},
 };
 
-use super::exchange::Exchange;
+use super::exchange::{
+    retry_with_backoff, wait_for_shutdown, Exchange, Heartbeat, MarketData, Subscription,
+    TradeType, STREAM_TIMEOUT,
+};
 
 type Result<T> = std::result::Result<T, Box<dyn Error>>;
 
+/// Attempts at a market data request before giving up.
+const REST_ATTEMPTS: u32 = 3;
+
+/// USDⓈ-M futures (maker, taker) rates of the VIP 0 to 9 fee tiers.
+const BINANCE_FEE_TIERS: [(f64, f64); 10] = [
+    (0.0002, 0.0005),
+    (0.00016, 0.0004),
+    (0.00014, 0.00035),
+    (0.00012, 0.00032),
+    (0.0001, 0.0003),
+    (0.00008, 0.00027),
+    (0.00006, 0.00025),
+    (0.00004, 0.00022),
+    (0.00002, 0.0002),
+    (0.0, 0.00017),
+];
+
 impl Exchange for BinanceClient {
     type TimeOutput = Result<u64>;
-    type FeeOutput = Result<f64>;
+    type FeeOutput = Result<FeeTier>;
     type LeverageOutput = Result<bool>;
     type TraderOutput = FuturesAccount;
 
     type StreamData = BinanceMarket;
-    type PrivateStreamData = ();
+    type PrivateStreamData = (String, BinancePrivate);
     type StreamOutput = ();
     type PrivateStreamOutput = ();
     type PlaceOrderOutput = Result<LiveOrder>;
-    type AmendOrderOutput = ();
+    type AmendOrderOutput = Result<LiveOrder>;
     type CancelOrderOutput = Result<CanceledOrder>;
     type CancelAllOutput = Result<()>;
-    type BatchOrdersOutput = ();
+    type OpenOrdersOutput = Result<Vec<LiveOrder>>;
+    type PositionOutput = Result<f64>;
+    type BatchOrdersOutput = Result<(Vec<LiveOrder>, Vec<LiveOrder>, Vec<RejectedOrder>)>;
     type SymbolInformationOutput = Result<SymbolInfo>;
-    type BatchAmendsOutput = ();
+    type KlinesOutput = Result<Vec<Candle>>;
+    type FundingRateOutput = Result<f64>;
+    type WalletBalanceOutput = Result<f64>;
+    type RecentTradesOutput = Result<TradeType>;
+    type BatchAmendsOutput = Result<(Vec<(String, LiveOrder)>, Vec<RejectedAmend>)>;
 
     /// Initializes a new `BinanceClient` instance.
     ///
@@ -65,11 +106,7 @@ impl Exchange for BinanceClient {
     /// A new `BinanceClient` instance
     async fn init(api_key: String, api_secret: String) -> Self {
         let bot = LiveBot::new("/config.toml").await.unwrap();
-        Self {
-            api_key,
-            api_secret,
-            logger: Logger::new(bot),
-        }
+        Self::new(api_key, api_secret, Logger::new(bot))
     }
 
     /// Gets the current server time.
@@ -91,7 +128,7 @@ impl Exchange for BinanceClient {
     ///
     /// # Returns
     ///
-    /// A `Result` containing the fee tier as a u32 for the given symbol.
+    /// A `Result` containing the maker and taker fee rates of the account's fee tier.
     ///
     /// # Notes
     ///
@@ -101,7 +138,7 @@ impl Exchange for BinanceClient {
         let account: FuturesAccount =
             Binance::new(Some(self.api_key.clone()), Some(self.api_secret.clone()));
         let fees = task::spawn_blocking(move || account.account_information()).await?;
-        Ok(fees.map(|f| f.fee_tier)?)
+        Ok(binance_fee_tier(fees?.fee_tier))
     }
 
     /// Sets the leverage for the given symbol.
@@ -157,6 +194,8 @@ impl Exchange for BinanceClient {
     /// * `price`: The price to place the order at.
     /// * `qty`: The quantity of the order.
     /// * `is_buy`: Whether to place a buy or sell order.
+    /// * `reduce_only`: Whether the order may only reduce the position.
+    /// * `time_in_force`: How long the order stays on the book.
     ///
     /// # Returns
     ///
@@ -167,58 +206,74 @@ impl Exchange for BinanceClient {
         price: f64,
         qty: f64,
         is_buy: bool,
+        reduce_only: bool,
+        time_in_force: TimeInForce,
     ) -> Self::PlaceOrderOutput {
         let trader = self.trader(2500);
-        let new_symbol = symbol.to_string();
-        let order = task::spawn_blocking(move || {
-            if is_buy {
-                trader.limit_buy(
-                    new_symbol,
-                    qty,
-                    price,
-                    binance::futures::account::TimeInForce::GTC,
-                )
-            } else {
-                trader.limit_sell(
-                    new_symbol,
-                    qty,
-                    price,
-                    binance::futures::account::TimeInForce::GTC,
-                )
-            }
-        })
-        .await?;
+        let request = CustomOrderRequest {
+            symbol: symbol.to_string(),
+            side: if is_buy { OrderSide::Buy } else { OrderSide::Sell },
+            position_side: None,
+            order_type: OrderType::Limit,
+            time_in_force: Some(time_in_force.to_binance()),
+            qty: Some(qty),
+            // Binance rejects the flag on orders that don't need it
+            reduce_only: reduce_only.then_some(true),
+            price: Some(price),
+            stop_price: None,
+            close_position: None,
+            activation_price: None,
+            callback_rate: None,
+            working_type: None,
+            price_protect: None,
+        };
+        let order = task::spawn_blocking(move || trader.custom_order(request)).await?;
         let order = order?;
-        Ok(LiveOrder::new(
-            order.order_id.to_string(),
-            order.avg_price,
-            order.orig_qty,
-        ))
+        Ok(LiveOrder {
+            reduce_only,
+            time_in_force,
+            ..LiveOrder::new(
+                order.order_id.to_string(),
+                order.avg_price,
+                order.orig_qty,
+                is_buy,
+            )
+        })
     }
 
     /// Amends an existing order on Binance Futures.
     ///
+    /// The client has no modify endpoint, so the order is cancelled and a new one is placed
+    /// at the new price and qty. The returned order carries the id of the new order.
+    ///
+    /// Amends re-price maker quotes, the new order is post-only (GTX) so it can't cross and
+    /// pay taker fees. The amends of `batch_amends` keep the flags of the amended order.
+    ///
+    /// # Arguments
     ///
     /// - `order_id`: The ID of the order to amend.
     /// - `price`: The new price to place the order at.
     /// - `qty`: The new quantity of the order.
     /// - `symbol`: The symbol of the market to amend the order in.
+    /// - `is_buy`: The side of the order, which an amend can't change.
     ///
     /// # Returns
     ///
     /// A `LiveOrder` representing the amended order.
     async fn amend_order(
         &self,
-        _order_id: &str,
-        _price: f64,
-        _qty: f64,
-        _symbol: &str,
+        order_id: &str,
+        price: f64,
+        qty: f64,
+        symbol: &str,
+        is_buy: bool,
     ) -> Self::AmendOrderOutput {
-        unimplemented!();
+        let amend = BatchAmend::new(symbol.to_string(), price, qty, order_id.to_string(), is_buy);
+        self.replace_order(&amend).await
     }
     async fn cancel_order(&self, order_id: &str, symbol: &str) -> Self::CancelOrderOutput {
         let trader = self.trader(2500);
-        let (new_id, new_symbol) = (order_id.parse::<u64>().unwrap(), symbol.to_string());
+        let (new_id, new_symbol) = (order_id.parse::<u64>()?, symbol.to_string());
         let cancel = task::spawn_blocking(move || trader.cancel_order(new_symbol, new_id)).await?;
         Ok(cancel?)
     }
@@ -229,11 +284,102 @@ impl Exchange for BinanceClient {
             task::spawn_blocking(move || trader.cancel_all_open_orders(new_symbol)).await?;
         Ok(cancel?)
     }
-    async fn batch_orders(&self, _orders: Vec<BatchOrder>) -> Self::BatchOrdersOutput {
-        unimplemented!();
+
+    /// Gets the orders currently resting on Binance for `symbol`, sized by their unfilled qty.
+    async fn get_open_orders(&self, symbol: &str) -> Self::OpenOrdersOutput {
+        let trader = self.trader(2500);
+        let new_symbol = symbol.to_string();
+        let orders = task::spawn_blocking(move || trader.get_all_open_orders(new_symbol)).await?;
+        Ok(orders?
+            .iter()
+            .filter_map(LiveOrder::from_binance_order)
+            .collect())
+    }
+
+    /// Gets the signed position size on Binance for `symbol`, positive for long.
+    ///
+    /// Binance already signs `positionAmt`, hedge mode sides are netted.
+    async fn get_position(&self, symbol: &str) -> Self::PositionOutput {
+        let trader = self.trader(2500);
+        let new_symbol = symbol.to_string();
+        let positions =
+            task::spawn_blocking(move || trader.position_information(new_symbol)).await?;
+        Ok(positions?.iter().map(|p| p.position_amount).sum())
+    }
+
+    /// Places the orders one by one, the client has no batch endpoint.
+    ///
+    /// Orders Binance rejects are logged and returned with its error code, like the failed
+    /// entries of a Bybit batch. Only fails when no order was placed or rejected, e.g. while
+    /// the connection is down.
+    ///
+    /// # Returns
+    ///
+    /// The placed buys and sells, priced at the requested price, and the rejected orders.
+    async fn batch_orders(&self, orders: Vec<BatchOrder>) -> Self::BatchOrdersOutput {
+        let (mut live_buys, mut live_sells) = (Vec::new(), Vec::new());
+        let mut rejected = Vec::new();
+        let mut last_error = None;
+        for order in orders {
+            let placed = self
+                .place_order(&order.0, order.1, order.2, order.3, order.4, order.5)
+                .await;
+            match placed {
+                Ok(placed) => {
+                    let live = LiveOrder::placed(placed.order_id, &order);
+                    if live.is_buy {
+                        live_buys.push(live);
+                    } else {
+                        live_sells.push(live);
+                    }
+                }
+                Err(e) => {
+                    let error = format!("Failed to place order on {}: {}", order.0, e);
+                    self.logger.error(&error);
+                    // Orders Binance refused carry its error code, other failures don't
+                    match binance_error_code(&e.to_string()) {
+                        Some(code) => rejected.push(RejectedOrder::new(order, code, e.to_string())),
+                        None => last_error = Some(error),
+                    }
+                }
+            }
+        }
+        match last_error {
+            Some(e) if live_buys.is_empty() && live_sells.is_empty() && rejected.is_empty() => {
+                Err(e.into())
+            }
+            _ => Ok((live_buys, live_sells, rejected)),
+        }
     }
-    async fn batch_amends(&self, _orders: Vec<BatchAmend>) -> Self::BatchAmendsOutput {
-        unimplemented!();
+
+    /// Amends the orders one by one by cancelling and re-placing them, so each amended
+    /// order comes back with a new id next to the id it was amended under. The new order
+    /// only rests the amend's unfilled qty, the filled part of the old one stays filled,
+    /// and keeps the old one's reduce-only flag and time in force.
+    ///
+    /// Amends Binance rejects are logged and returned with its error code, like the
+    /// rejected entries of a Bybit batch. Only fails when no order was amended or rejected.
+    async fn batch_amends(&self, orders: Vec<BatchAmend>) -> Self::BatchAmendsOutput {
+        let mut amends = Vec::new();
+        let mut rejected = Vec::new();
+        let mut last_error = None;
+        for amend in orders {
+            match self.replace_order(&amend).await {
+                Ok(live) => amends.push((amend.3, live)),
+                Err(e) => {
+                    let error = format!("Failed to amend order {}: {}", amend.3, e);
+                    self.logger.error(&error);
+                    match binance_error_code(&e.to_string()) {
+                        Some(code) => rejected.push(RejectedAmend::new(amend, code, e.to_string())),
+                        None => last_error = Some(error),
+                    }
+                }
+            }
+        }
+        match last_error {
+            Some(e) if amends.is_empty() && rejected.is_empty() => Err(e.into()),
+            _ => Ok((amends, rejected)),
+        }
     }
 
     /// Get the symbol information for a given symbol.
@@ -252,35 +398,7 @@ impl Exchange for BinanceClient {
         let market_data: FuturesGeneral = Binance::new(None, None);
         let new_symbol = symbol.to_string();
         let info = task::spawn_blocking(move || match market_data.get_symbol_info(new_symbol) {
-            Ok(res) => {
-                let price_filter = match &res.filters[0] {
-                    PriceFilter { tick_size, .. } => tick_size.parse().unwrap_or(0.0),
-                    _ => 0.0,
-                };
-                let final_data = SymbolInfo {
-                    tick_size: price_filter,
-                    lot_size: match &res.filters[1] {
-                        binance::model::Filters::LotSize { step_size, .. } => {
-                            step_size.parse().unwrap_or(0.0)
-                        }
-                        _ => 0.0,
-                    },
-                    min_notional: match &res.filters[5] {
-                        binance::model::Filters::MinNotional { notional, .. } => {
-                            notional.clone().unwrap().parse().unwrap_or(0.0)
-                        }
-                        _ => 0.0,
-                    },
-                    min_qty: 0.0,
-                    post_only_max: match &res.filters[1] {
-                        binance::model::Filters::LotSize { max_qty, .. } => {
-                            max_qty.parse().unwrap_or(0.0)
-                        }
-                        _ => 0.0,
-                    },
-                };
-                Ok(final_data)
-            }
+            Ok(res) => Ok(SymbolInfo::from_binance_filters(&res.filters)),
             Err(e) => Err(e),
         })
         .await;
@@ -293,6 +411,103 @@ impl Exchange for BinanceClient {
             }
         }
     }
+
+    /// Fetches the latest `limit` USDⓈ-M futures candles of `symbol`, oldest first.
+    ///
+    /// `interval` is Binance's notation, e.g. `1m`, `1h` or `1d`. The request is retried
+    /// up to `REST_ATTEMPTS` times with an exponential backoff.
+    async fn get_klines(&self, symbol: &str, interval: &str, limit: u16) -> Self::KlinesOutput {
+        let klines = retry_with_backoff(REST_ATTEMPTS, || {
+            let (symbol, interval) = (symbol.to_string(), interval.to_string());
+            async move {
+                task::spawn_blocking(move || {
+                    let market: FuturesMarket = Binance::new(None, None);
+                    market.get_klines(symbol, interval, limit, None, None)
+                })
+                .await?
+                .map_err(|e| -> Box<dyn Error> { e.into() })
+            }
+        })
+        .await?;
+
+        let KlineSummaries::AllKlineSummaries(klines) = klines;
+        let parse = |v: &str| v.parse().unwrap_or(0.0);
+        Ok(klines
+            .iter()
+            .map(|kline| Candle {
+                open_time: kline.open_time as u64,
+                open: parse(&kline.open),
+                high: parse(&kline.high),
+                low: parse(&kline.low),
+                close: parse(&kline.close),
+                volume: parse(&kline.volume),
+            })
+            .collect())
+    }
+
+    /// Fetches the latest `limit` aggregated trades of `symbol`, at most 1000, oldest first.
+    async fn get_recent_trades(&self, symbol: &str, limit: u16) -> Self::RecentTradesOutput {
+        let trades = retry_with_backoff(REST_ATTEMPTS, || {
+            let symbol = symbol.to_string();
+            async move {
+                task::spawn_blocking(move || {
+                    let market: FuturesMarket = Binance::new(None, None);
+                    market.get_agg_trades(symbol, None, None, None, limit)
+                })
+                .await?
+                .map_err(|e| -> Box<dyn Error> { e.into() })
+            }
+        })
+        .await?;
+
+        let AggTrades::AllAggTrades(trades) = trades;
+        Ok(trades
+            .iter()
+            .map(|trade| ws_trade_from_agg(symbol, trade))
+            .collect())
+    }
+
+    /// Fetches the last settled funding rate of `symbol`.
+    async fn get_funding_rate(&self, symbol: &str) -> Self::FundingRateOutput {
+        let rates = retry_with_backoff(REST_ATTEMPTS, || {
+            let symbol = symbol.to_string();
+            async move {
+                task::spawn_blocking(move || {
+                    let market: FuturesMarket = Binance::new(None, None);
+                    market.get_funding_rate(symbol, None, None, 1u16)
+                })
+                .await?
+                .map_err(|e| -> Box<dyn Error> { e.into() })
+            }
+        })
+        .await?;
+        match rates.last() {
+            Some(rate) => Ok(rate.funding_rate),
+            None => Err(format!("No funding rate for {}", symbol).into()),
+        }
+    }
+
+    /// Fetches the margin balance of `coin` in the futures account, its wallet balance plus
+    /// the unrealized PnL.
+    async fn get_wallet_balance(&self, coin: &str) -> Self::WalletBalanceOutput {
+        let account: FuturesAccount =
+            Binance::new(Some(self.api_key.clone()), Some(self.api_secret.clone()));
+        let info = task::spawn_blocking(move || account.account_information()).await??;
+        match info.assets.iter().find(|asset| asset.asset == coin) {
+            Some(asset) => Ok(asset.margin_balance),
+            None => Err(format!("No {} balance returned", coin).into()),
+        }
+    }
+
+    /// Runs `market_subscribe` in its own task and streams its updates.
+    fn subscribe(&self, symbols: Vec<String>) -> impl Stream<Item = MarketData> + Send + Unpin {
+        let client = self.clone();
+        Subscription::spawn(move |sender, shutdown| async move {
+            client.market_subscribe(symbols, sender, shutdown).await
+        })
+        .map(MarketData::Binance)
+    }
+
     /// Subscribes to Binance futures market data for the given symbols and sends
     /// it over the given sender channel.
     ///
@@ -310,102 +525,578 @@ impl Exchange for BinanceClient {
     /// The `sender` parameter is an unbounded sender channel that will receive
     /// the market data.
     ///
+    /// The stream and its reconnects stop once `true` is sent on `shutdown`.
+    ///
     /// The function returns an empty tuple.
     async fn market_subscribe(
         &self,
         symbols: Vec<String>,
         sender: tokio::sync::mpsc::UnboundedSender<Self::StreamData>,
+        mut shutdown: tokio::sync::watch::Receiver<bool>,
     ) -> () {
-        let delay = 600;
-        let keep_streaming = AtomicBool::new(true);
+        let mut backoff = Backoff::with_max(self.max_reconnect_delay);
         let request = build_requests(&symbols);
-        let mut market_data = BinanceMarket::default();
-        for k in symbols.clone() {
-            market_data.books.insert(k.clone(), BinanceBook::new());
-            market_data
-                .trades
-                .insert(k.clone(), VecDeque::with_capacity(1000));
-            market_data.ticker.insert(k, VecDeque::with_capacity(10));
+        let heartbeat = Heartbeat::new();
+
+        loop {
+            // Cleared once the connection is dropped, its blocking event loop stops at the
+            // next message it reads
+            let running = Arc::new(AtomicBool::new(true));
+            let handler = market_handler(
+                &symbols,
+                sender.clone(),
+                self.logger.clone(),
+                heartbeat.clone(),
+                running.clone(),
+            );
+            let (request, logger, connection_running) =
+                (request.clone(), self.logger.clone(), running.clone());
+            heartbeat.rearm();
+            let connection = task::spawn_blocking(move || {
+                let mut market: FuturesWebSockets<'_> = FuturesWebSockets::new(handler);
+                if let Err(e) = market.connect_multiple_streams(&FuturesMarketWs::USDM, &request) {
+                    logger.error(&format!("Failed to connect Binance market stream: {}", e));
+                    return;
+                }
+                if let Err(e) = market.event_loop(&connection_running) {
+                    logger.warning(&format!("Binance market stream dropped, reconnecting: {}", e));
+                }
+            });
+
+            // A silent connection blocks its event loop in a read that only a message or a
+            // socket error ends, so it's left behind rather than waited for
+            tokio::select! {
+                _ = connection => {}
+                _ = heartbeat.stale(self.stream_timeout) => {
+                    self.logger.warning(&format!(
+                        "No Binance market data for {:?}, reconnecting",
+                        self.stream_timeout
+                    ));
+                }
+                _ = wait_for_shutdown(&mut shutdown) => {
+                    running.store(false, Ordering::SeqCst);
+                    self.logger.info("Stopped the Binance futures market data stream");
+                    return;
+                }
+            }
+            running.store(false, Ordering::SeqCst);
+            // A connection that delivered data worked, however it ended
+            if heartbeat.received() {
+                backoff.reset();
+            }
+            metrics::inc(metrics::RECONNECTS, &[("stream", "binance_market")]);
+            tokio::select! {
+                _ = tokio::time::sleep(backoff.next_delay()) => {}
+                _ = wait_for_shutdown(&mut shutdown) => return,
+            }
         }
-        let book_snapshot = (market_data.clone(), symbols.clone());
-        let snapshot_update =
-            task::spawn_blocking(move || book_snapshot.0.get_book_snapshot(&book_snapshot.1))
-                .await
-                .unwrap();
-        market_data = snapshot_update;
-
-        let handler = move |event| {
-            match event {
-                FuturesWebsocketEvent::DepthOrderBook(DepthOrderBookEvent {
-                    symbol,
-                    event_time,
-                    bids,
-                    asks,
-                    final_update_id,
-                    ..
-                }) => {
-                    if let Some(book) = market_data.books.get_mut(&symbol) {
-                        market_data.timestamp = event_time;
-                        if bids.len() == 20 && asks.len() == 20 {
-                            book.update_bba(asks, bids, event_time, final_update_id);
-                        } else {
-                            book.update(asks, bids, event_time, 20);
+    }
+
+    /// Subscribes to the Binance Futures user data stream and sends the orders, executions,
+    /// positions and balances of `symbol` over the given sender channel.
+    ///
+    /// A listen key is requested for each connection and kept alive every 30 minutes until
+    /// the connection drops or Binance reports it expired, after which a new one is requested.
+    ///
+    /// This function blocks until the sender channel is closed or `true` is sent on `shutdown`.
+    async fn private_subscribe(
+        &self,
+        symbol: String,
+        sender: tokio::sync::mpsc::UnboundedSender<Self::PrivateStreamData>,
+        shutdown: tokio::sync::watch::Receiver<bool>,
+    ) -> () {
+        let (api_key, api_secret) = (self.api_key.clone(), self.api_secret.clone());
+        let logger = self.logger.clone();
+        let max_reconnect_delay = self.max_reconnect_delay;
+        let keep_streaming = Arc::new(AtomicBool::new(true));
+        stop_on_shutdown(shutdown.clone(), keep_streaming.clone());
+        let _ = task::spawn_blocking(move || {
+            let user_stream: FuturesUserStream =
+                Binance::new(Some(api_key.clone()), Some(api_secret.clone()));
+            let mut private_data = BinancePrivate::default();
+            let mut backoff = Backoff::with_max(max_reconnect_delay);
+
+            while !sender.is_closed() {
+                // Every connection starts from empty data, the new session tells the
+                // generators to reconcile their orders with the exchange
+                private_data = BinancePrivate {
+                    session: private_data.session + 1,
+                    ..Default::default()
+                };
+                // Re-armed for every connection since an expired listen key only stops the
+                // current one, checked after so a shutdown racing the re-arm isn't lost
+                keep_streaming.store(true, Ordering::SeqCst);
+                if *shutdown.borrow() {
+                    break;
+                }
+                let listen_key = match user_stream.start() {
+                    Ok(stream) => stream.listen_key,
+                    Err(e) => {
+                        logger.error(&format!("Failed to start Binance user stream: {}", e));
+                        thread::sleep(backoff.next_delay());
+                        continue;
+                    }
+                };
+                let alive = Arc::new(AtomicBool::new(true));
+                keep_alive(
+                    Binance::new(Some(api_key.clone()), Some(api_secret.clone())),
+                    listen_key.clone(),
+                    alive.clone(),
+                );
+
+                let mut received = false;
+                let handler = |event| {
+                    received = true;
+                    if let FuturesWebsocketEvent::UserDataStreamExpiredEvent(_) = event {
+                        keep_streaming.store(false, Ordering::SeqCst);
+                    } else if private_data.update(&symbol, event) {
+                        let _ = sender.send((symbol.clone(), private_data.clone()));
+                    }
+                    Ok(())
+                };
+                let mut user_data: FuturesWebSockets<'_> = FuturesWebSockets::new(handler);
+                match user_data.connect(&FuturesMarketWs::USDM, &listen_key) {
+                    Ok(_) => {
+                        logger.info("Subscribed to Binance private stream data");
+                        if let Err(e) = user_data.event_loop(&keep_streaming) {
+                            logger.error(&format!("Binance private stream dropped: {}", e));
                         }
+                        let _ = user_data.disconnect();
+                    }
+                    Err(e) => {
+                        logger.error(&format!("Failed to connect Binance private stream: {}", e));
                     }
                 }
-                FuturesWebsocketEvent::AggrTrades(trade_data) => {
-                    if let Some(trades) = market_data.trades.get_mut(&trade_data.symbol) {
-                        if trades.len() == trades.capacity()
-                            || (trades.capacity() - trades.len()) <= 5
-                        {
-                            for _ in 0..10 {
-                                trades.pop_front();
+                drop(user_data);
+                alive.store(false, Ordering::Relaxed);
+                let _ = user_stream.close(&listen_key);
+                // A connection that delivered an event worked, however it ended
+                if received {
+                    backoff.reset();
+                }
+                metrics::inc(metrics::RECONNECTS, &[("stream", "binance_private")]);
+                if !*shutdown.borrow() {
+                    thread::sleep(backoff.next_delay());
+                }
+            }
+        })
+        .await;
+    }
+}
+
+/// Builds the books, trades and tickers of `symbols` from the market stream's events and
+/// sends them over `sender`, each book from a REST snapshot fetched on its first delta
+/// and after gaps. Every event beats `heartbeat`, nothing is handled once `running` is
+/// cleared.
+fn market_handler(
+    symbols: &[String],
+    sender: tokio::sync::mpsc::UnboundedSender<BinanceMarket>,
+    logger: Logger,
+    heartbeat: Heartbeat,
+    running: Arc<AtomicBool>,
+) -> impl FnMut(FuturesWebsocketEvent) -> binance::errors::Result<()> {
+    let mut market_data = BinanceMarket::default();
+    for k in symbols.iter().cloned() {
+        market_data.books.insert(k.clone(), BinanceBook::new());
+        market_data
+            .trades
+            .insert(k.clone(), VecDeque::with_capacity(1000));
+        market_data.ticker.insert(k, VecDeque::with_capacity(10));
+    }
+    // Each book is built from a REST snapshot fetched on its first delta and after gaps
+    let mut depth_syncs: BTreeMap<String, DepthSync> = symbols
+        .iter()
+        .map(|symbol| (symbol.clone(), DepthSync::new()))
+        .collect();
+    let rest: FuturesMarket = Binance::new(None, None);
+
+    move |event| {
+        // A dropped connection reading one last message doesn't publish it
+        if !running.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        heartbeat.beat();
+        // Deltas that re-send known levels don't reach the strategy
+        let changed = match event {
+            FuturesWebsocketEvent::DepthOrderBook(event) => {
+                let symbol = event.symbol.clone();
+                match (
+                    market_data.books.get_mut(&symbol),
+                    depth_syncs.get_mut(&symbol),
+                ) {
+                    (Some(book), Some(sync)) => {
+                        market_data.timestamp = event.event_time;
+                        let gaps = sync.gaps();
+                        let snapshot = || match rest.get_depth(&symbol) {
+                            Ok(snapshot) => Some(snapshot),
+                            Err(e) => {
+                                logger.error(&format!(
+                                    "Failed to fetch the {} book snapshot: {}",
+                                    symbol, e
+                                ));
+                                None
                             }
+                        };
+                        let changed = sync.apply(book, event, snapshot);
+                        if sync.gaps() > gaps {
+                            logger.warning(&format!(
+                                "Gap in the {} depth updates, resyncing the book",
+                                symbol
+                            ));
                         }
-                        trades.push_back(trade_data);
+                        changed
                     }
+                    _ => false,
                 }
-                FuturesWebsocketEvent::BookTicker(book_ticker) => {
-                    if let Some(ticker) = market_data.ticker.get_mut(&book_ticker.symbol) {
-                        if ticker.len() == ticker.capacity()
-                            || (ticker.capacity() - ticker.len()) <= 10
-                        {
-                            for _ in 0..10 {
-                                ticker.pop_front();
-                            }
+            }
+            FuturesWebsocketEvent::AggrTrades(trade_data) => {
+                if let Some(trades) = market_data.trades.get_mut(&trade_data.symbol) {
+                    if trades.len() == trades.capacity()
+                        || (trades.capacity() - trades.len()) <= 5
+                    {
+                        for _ in 0..10 {
+                            trades.pop_front();
                         }
-                        ticker.push_back(book_ticker);
                     }
+                    trades.push_back(trade_data);
                 }
-                _ => {}
+                true
             }
+            FuturesWebsocketEvent::BookTicker(book_ticker) => {
+                if let Some(ticker) = market_data.ticker.get_mut(&book_ticker.symbol) {
+                    if ticker.len() == ticker.capacity()
+                        || (ticker.capacity() - ticker.len()) <= 10
+                    {
+                        for _ in 0..10 {
+                            ticker.pop_front();
+                        }
+                    }
+                    ticker.push_back(book_ticker);
+                }
+                true
+            }
+            _ => true,
+        };
+        if changed {
             let _ = sender.send(market_data.clone());
-            Ok(())
+        }
+        Ok(())
+    }
+}
+
+/// The negative error code Binance reports in an error message, e.g. `-5022` in
+/// `-5022: Due to the order could not be executed as maker...`.
+pub fn binance_error_code(message: &str) -> Option<i64> {
+    message
+        .split(|c: char| c.is_whitespace() || c == ':' || c == ',' || c == '"')
+        .find(|token| token.starts_with('-'))
+        .and_then(|token| token.parse::<i64>().ok())
+}
+
+/// Clears `keep_streaming` once shutdown is requested, ending the blocking event loops
+/// reading it.
+fn stop_on_shutdown(
+    mut shutdown: tokio::sync::watch::Receiver<bool>,
+    keep_streaming: Arc<AtomicBool>,
+) {
+    tokio::spawn(async move {
+        wait_for_shutdown(&mut shutdown).await;
+        keep_streaming.store(false, Ordering::SeqCst);
+    });
+}
+
+/// Keeps `listen_key` alive every 30 minutes on a separate thread until `alive` is cleared.
+fn keep_alive(user_stream: FuturesUserStream, listen_key: String, alive: Arc<AtomicBool>) {
+    thread::spawn(move || {
+        let mut elapsed = 0;
+        while alive.load(Ordering::Relaxed) {
+            thread::sleep(Duration::from_secs(1));
+            elapsed += 1;
+            if elapsed >= 30 * 60 {
+                elapsed = 0;
+                if let Err(e) = user_stream.keep_alive(&listen_key) {
+                    eprintln!("Failed to keep Binance listen key alive: {}", e);
+                }
+            }
+        }
+    });
+}
+
+impl BinanceClient {
+    /// Creates a client around an already constructed `Logger`.
+    pub fn new(api_key: String, api_secret: String, logger: Logger) -> Self {
+        Self {
+            api_key,
+            api_secret,
+            logger,
+            stream_timeout: STREAM_TIMEOUT,
+            max_reconnect_delay: MAX_DELAY,
+        }
+    }
+
+    /// Cancels the order `amend` is for and places its replacement, see
+    /// `BatchAmend::replacement`.
+    async fn replace_order(&self, amend: &BatchAmend) -> Result<LiveOrder> {
+        self.cancel_order(&amend.3, &amend.0).await?;
+        let order = amend.replacement();
+        let placed = self
+            .place_order(&order.0, order.1, order.2, order.3, order.4, order.5)
+            .await?;
+        Ok(LiveOrder::placed(placed.order_id, &order))
+    }
+
+    /// Caps the delay between the reconnects of the next subscriptions at `secs` seconds, 0
+    /// restores the default of 30 seconds.
+    pub fn set_max_reconnect_delay(&mut self, secs: u64) {
+        self.max_reconnect_delay = match secs {
+            0 => MAX_DELAY,
+            secs => Duration::from_secs(secs),
         };
-        let _ = task::spawn_blocking(move || {
-            let mut market: FuturesWebSockets<'_> = FuturesWebSockets::new(handler);
+    }
 
-            loop {
-                market
-                    .connect_multiple_streams(&FuturesMarketWs::USDM, &request)
-                    .unwrap();
+    /// Reconnects the next market subscriptions after `secs` seconds without a message, 0
+    /// restores the default of 30 seconds.
+    pub fn set_stream_timeout(&mut self, secs: u64) {
+        self.stream_timeout = match secs {
+            0 => STREAM_TIMEOUT,
+            secs => Duration::from_secs(secs),
+        };
+    }
+}
 
-                // check error
-                if let Err(e) = market.event_loop(&keep_streaming) {
-                    eprintln!("Error: {}", e);
-                    thread::sleep(Duration::from_millis(delay));
+impl BinanceBook {
+    /// Drops every level of a crossed book and flags it until a snapshot rebuilds it,
+    /// deltas alone can't tell which of the old levels are still there.
+    fn clear_crossed(&mut self) {
+        self.asks.clear();
+        self.bids.clear();
+        self.best_ask = Asks {
+            price: 0.0,
+            qty: 0.0,
+        };
+        self.best_bid = Bids {
+            price: 0.0,
+            qty: 0.0,
+        };
+        self.mid_price = 0.0;
+        self.needs_snapshot = true;
+    }
+
+    /// Applies a diff depth delta to every level of the book, then sets the BBA and mid
+    /// from the levels. Returns `true` when the book changed.
+    ///
+    /// Unlike `update_bba` nothing outside the delta is pruned, the levels it leaves out
+    /// are unchanged. A crossed book is cleared and flagged for a new snapshot.
+    pub fn apply_delta(
+        &mut self,
+        asks: &[Asks],
+        bids: &[Bids],
+        timestamp: u64,
+        sequence: u64,
+    ) -> bool {
+        self.last_update = self.last_update.max(timestamp);
+        self.sequence = sequence;
+        let mut changed = false;
+        for bid in bids.iter() {
+            changed |= set_level(&mut self.bids, bid.price, bid.qty);
+        }
+        for ask in asks.iter() {
+            changed |= set_level(&mut self.asks, ask.price, ask.qty);
+        }
+        self.refresh_bba() || changed
+    }
+
+    /// Sets the BBA and mid from the levels, returns `true` when they moved.
+    fn refresh_bba(&mut self) -> bool {
+        let bba_before = (
+            self.best_bid.price,
+            self.best_bid.qty,
+            self.best_ask.price,
+            self.best_ask.qty,
+        );
+        let (bid_price, bid_qty) = self
+            .bids
+            .iter()
+            .next_back()
+            .map_or((0.0, 0.0), |(price, qty)| (**price, *qty));
+        let (ask_price, ask_qty) = self
+            .asks
+            .iter()
+            .next()
+            .map_or((0.0, 0.0), |(price, qty)| (**price, *qty));
+        self.best_bid = Bids {
+            price: bid_price,
+            qty: bid_qty,
+        };
+        self.best_ask = Asks {
+            price: ask_price,
+            qty: ask_qty,
+        };
+        self.set_mid_price();
+        if bid_price > 0.0 && ask_price > 0.0 && bid_price >= ask_price {
+            self.clear_crossed();
+            return true;
+        }
+        bba_before != (bid_price, bid_qty, ask_price, ask_qty)
+    }
+}
+
+// Shortest interval between two snapshot requests of a symbol while they fail, in ms
+const SNAPSHOT_RETRY_MS: u64 = 1000;
+// Deltas kept while waiting for a snapshot, the oldest are dropped past it
+const MAX_BUFFERED_DELTAS: usize = 1000;
+
+/// Keeps a `BinanceBook` in sync with the diff depth stream of its symbol.
+///
+/// Binance only guarantees the book when the deltas are applied onto a REST snapshot in
+/// sequence. The deltas are buffered until a snapshot is fetched, those older than it are
+/// dropped, the first one applied must straddle its `lastUpdateId`, and every following
+/// one must continue from the previous (`pu` equal to the last `u`). Any gap means a delta
+/// was missed, so the book is flagged with `needs_snapshot` and rebuilt from a new
+/// snapshot.
+#[derive(Debug, Default)]
+pub struct DepthSync {
+    // deltas received while waiting for a snapshot, oldest first
+    buffer: VecDeque<DepthOrderBookEvent>,
+    // final update id the next delta has to continue from, `None` until synced
+    last_update_id: Option<u64>,
+    // the snapshot is applied but no delta straddled its update id yet
+    awaiting_first: bool,
+    // event time of the last failed snapshot request
+    last_attempt: Option<u64>,
+    gaps: usize,
+}
+
+impl DepthSync {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether the book is built from a snapshot and every delta since.
+    pub fn is_synced(&self) -> bool {
+        self.last_update_id.is_some() && !self.awaiting_first
+    }
+
+    /// Number of times the book fell out of sync and had to be rebuilt.
+    pub fn gaps(&self) -> usize {
+        self.gaps
+    }
+
+    /// Applies `event` to `book`, calling `snapshot` for the REST snapshot when the book
+    /// isn't synced yet or the event doesn't continue the sequence. Returns `true` when
+    /// the book changed.
+    pub fn apply<F>(
+        &mut self,
+        book: &mut BinanceBook,
+        event: DepthOrderBookEvent,
+        snapshot: F,
+    ) -> bool
+    where
+        F: FnOnce() -> Option<BookSnapshot>,
+    {
+        if self.last_update_id.is_some() {
+            return match self.apply_in_sequence(book, &event) {
+                Some(changed) => changed,
+                None => {
+                    self.resync(book, event, snapshot);
+                    true
                 }
+            };
+        }
+        let event_time = event.event_time;
+        book.needs_snapshot = true;
+        self.buffer.push_back(event);
+        if self.buffer.len() > MAX_BUFFERED_DELTAS {
+            self.buffer.pop_front();
+        }
+        if self
+            .last_attempt
+            .is_some_and(|attempt| event_time < attempt + SNAPSHOT_RETRY_MS)
+        {
+            return false;
+        }
+        let Some(snapshot) = snapshot() else {
+            self.last_attempt = Some(event_time);
+            return false;
+        };
+        self.last_attempt = None;
+
+        book.asks.clear();
+        book.bids.clear();
+        book.reset(
+            snapshot.asks,
+            snapshot.bids,
+            snapshot.event_time,
+            snapshot.last_update_id,
+        );
+        book.refresh_bba();
+        self.last_update_id = Some(snapshot.last_update_id);
+        self.awaiting_first = true;
+
+        // The deltas received in the meantime go on top, unless one of them leaves a gap
+        while let Some(event) = self.buffer.pop_front() {
+            if self.apply_in_sequence(book, &event).is_none() {
+                self.buffer.push_front(event);
+                self.start_over(book);
+                break;
             }
-        })
-        .await;
+        }
+        true
     }
 
-    async fn private_subscribe(
-        &self,
-        _symbol: String,
-        _sender: tokio::sync::mpsc::UnboundedSender<Self::PrivateStreamOutput>,
-    ) -> () {
-        unimplemented!();
+    /// Applies `event` when it continues the sequence, `None` on a gap.
+    fn apply_in_sequence(
+        &mut self,
+        book: &mut BinanceBook,
+        event: &DepthOrderBookEvent,
+    ) -> Option<bool> {
+        let last_update_id = self.last_update_id?;
+        let continues = if self.awaiting_first {
+            // Older than the snapshot
+            if event.final_update_id < last_update_id {
+                return Some(false);
+            }
+            event.first_update_id <= last_update_id
+        } else {
+            // Replayed
+            if event.final_update_id <= last_update_id {
+                return Some(false);
+            }
+            event.previous_final_update_id == Some(last_update_id)
+        };
+        if !continues {
+            return None;
+        }
+        self.awaiting_first = false;
+        self.last_update_id = Some(event.final_update_id);
+        let changed = book.apply_delta(
+            &event.asks,
+            &event.bids,
+            event.event_time,
+            event.final_update_id,
+        );
+        // A crossed book means the deltas and the snapshot disagree
+        if book.needs_snapshot {
+            self.start_over(book);
+        }
+        Some(changed)
+    }
+
+    /// Drops the sync and fetches a new snapshot starting from `event`.
+    fn resync<F>(&mut self, book: &mut BinanceBook, event: DepthOrderBookEvent, snapshot: F)
+    where
+        F: FnOnce() -> Option<BookSnapshot>,
+    {
+        self.start_over(book);
+        self.apply(book, event, snapshot);
+    }
+
+    fn start_over(&mut self, book: &mut BinanceBook) {
+        self.last_update_id = None;
+        self.awaiting_first = false;
+        self.last_attempt = None;
+        self.gaps += 1;
+        book.needs_snapshot = true;
     }
 }
 
@@ -437,6 +1128,8 @@ impl OrderBook for BinanceBook {
             min_notional: 0.0,
             min_qty: 0.0,
             post_only_max: 0.0,
+            needs_snapshot: false,
+            decay_rate: DEFAULT_DECAY_RATE,
         }
     }
 
@@ -458,6 +1151,7 @@ impl OrderBook for BinanceBook {
     fn reset(&mut self, asks: Vec<Self::Ask>, bids: Vec<Self::Bid>, timestamp: u64, sequence: u64) {
         self.last_update = timestamp;
         self.sequence = sequence;
+        self.needs_snapshot = false;
 
         for ask in asks.iter() {
             let price = OrderedFloat::from(ask.price);
@@ -501,9 +1195,9 @@ impl OrderBook for BinanceBook {
         bids: Vec<Self::Bid>,
         timestamp: u64,
         sequence: u64,
-    ) {
+    ) -> bool {
         if timestamp <= self.last_update || sequence <= self.sequence {
-            return;
+            return false;
         }
 
         self.last_update = timestamp;
@@ -527,14 +1221,18 @@ impl OrderBook for BinanceBook {
             })
             .map(|ask| ask.price);
 
+        let levels_before = self.bids.len() + self.asks.len();
+        let bba_before = (
+            self.best_bid.price,
+            self.best_bid.qty,
+            self.best_ask.price,
+            self.best_ask.qty,
+        );
+        let mut changed = false;
+
         // Update the bids in the order book
         for bid in bids.iter() {
-            let price = OrderedFloat::from(bid.price);
-            // Modify or insert the bid price and quantity into the bids HashMap
-            self.bids
-                .entry(price)
-                .and_modify(|qty| *qty = bid.qty)
-                .or_insert(bid.qty);
+            changed |= set_level(&mut self.bids, bid.price, bid.qty);
         }
         // Remove bids with prices higher than the current bid price
         if let Some(best_bid_price) = best_bid_price {
@@ -543,12 +1241,7 @@ impl OrderBook for BinanceBook {
         }
 
         for ask in asks.iter() {
-            let price = OrderedFloat::from(ask.price);
-            // Modify or insert the ask price and quantity into the asks HashMap
-            self.asks
-                .entry(price)
-                .and_modify(|qty| *qty = ask.qty)
-                .or_insert(ask.qty);
+            changed |= set_level(&mut self.asks, ask.price, ask.qty);
         }
         // Remove asks with prices lower than the current ask price
         if let Some(best_ask_price) = best_ask_price {
@@ -590,6 +1283,27 @@ impl OrderBook for BinanceBook {
 
         // Calculate the mid price
         self.set_mid_price();
+
+        // A crossed book gives a garbage mid, start over from the next snapshot
+        if self.best_bid.price > 0.0
+            && self.best_ask.price > 0.0
+            && self.best_bid.price >= self.best_ask.price
+        {
+            self.clear_crossed();
+            return true;
+        }
+
+        // Pruning changes the book even when every level of the delta was already known,
+        // and the first delta after a snapshot sets the BBA
+        changed
+            || self.bids.len() + self.asks.len() != levels_before
+            || bba_before
+                != (
+                    self.best_bid.price,
+                    self.best_bid.qty,
+                    self.best_ask.price,
+                    self.best_ask.qty,
+                )
     }
 
     /// Updates the order book with the given asks and bids at the given timestamp.
@@ -608,9 +1322,9 @@ impl OrderBook for BinanceBook {
         bids: Vec<Self::Bid>,
         timestamp: u64,
         levels: usize,
-    ) {
+    ) -> bool {
         if timestamp <= self.last_update {
-            return;
+            return false;
         }
         self.last_update = timestamp;
 
@@ -631,29 +1345,26 @@ impl OrderBook for BinanceBook {
             .last()
             .unwrap_or(0.0);
 
+        let levels_before = self.bids.len() + self.asks.len();
+        let mut changed = false;
+
         for ask in asks.iter() {
-            let price = OrderedFloat::from(ask.price);
-            if price >= OrderedFloat::from(top_ask_threshold) {
-                self.asks
-                    .entry(price)
-                    .and_modify(|qty| *qty = ask.qty)
-                    .or_insert(ask.qty);
+            if ask.price >= top_ask_threshold {
+                changed |= set_level(&mut self.asks, ask.price, ask.qty);
             }
         }
 
         // Update bids except top levels
         for bid in bids.iter() {
-            let price = OrderedFloat::from(bid.price);
-            if price <= OrderedFloat::from(top_bid_threshold) {
-                self.bids
-                    .entry(price)
-                    .and_modify(|qty| *qty = bid.qty)
-                    .or_insert(bid.qty);
+            if bid.price <= top_bid_threshold {
+                changed |= set_level(&mut self.bids, bid.price, bid.qty);
             }
         }
 
         self.asks.retain(|_, &mut v| v != 0.0);
         self.bids.retain(|_, &mut v| v != 0.0);
+
+        changed || self.bids.len() + self.asks.len() != levels_before
     }
 
     /// Sets the mid price of the order book.
@@ -666,6 +1377,15 @@ impl OrderBook for BinanceBook {
         self.mid_price = (self.best_ask.price + self.best_bid.price) / 2.0;
     }
 
+    /// Returns `true` when the best bid is at or above the best ask, or while a book cleared
+    /// for being crossed waits for a snapshot.
+    fn is_crossed(&self) -> bool {
+        self.needs_snapshot
+            || (self.best_bid.price > 0.0
+                && self.best_ask.price > 0.0
+                && self.best_bid.price >= self.best_ask.price)
+    }
+
     /// Returns the mid price of the order book.
     ///
     /// The mid price is calculated as the average of the best ask and best bid prices.
@@ -806,8 +1526,8 @@ impl OrderBook for BinanceBook {
             let (weighted_bid_qty, weighted_ask_qty) = if let Some(depth) = depth {
                 // Calculate the weighted bid quantity using the specified depth.
                 (
-                    self.calculate_weighted_bid(depth, Some(0.5)),
-                    self.calculate_weighted_ask(depth, Some(0.5)),
+                    self.calculate_weighted_bid(depth, Some(self.decay_rate)),
+                    self.calculate_weighted_ask(depth, Some(self.decay_rate)),
                 )
             } else {
                 (self.best_bid.qty, self.best_ask.qty)
@@ -862,8 +1582,8 @@ impl OrderBook for BinanceBook {
     fn get_microprice(&self, depth: Option<usize>) -> f64 {
         let (bid_qty, ask_qty) = match depth {
             Some(depth) => (
-                self.calculate_weighted_bid(depth, Some(0.5)),
-                self.calculate_weighted_ask(depth, Some(0.5)),
+                self.calculate_weighted_bid(depth, Some(self.decay_rate)),
+                self.calculate_weighted_ask(depth, Some(self.decay_rate)),
             ),
             None => (self.best_bid.qty, self.best_ask.qty),
         };
@@ -877,6 +1597,26 @@ impl OrderBook for BinanceBook {
         (self.best_ask.price * qty_ratio) + (self.best_bid.price * (1.0 - qty_ratio))
     }
 
+    /// Returns the average price of a market order of `qty`, walking the asks for a buy and
+    /// the bids for a sell, or `None` when the book doesn't hold enough qty to fill it.
+    fn expected_fill_price(&self, qty: f64, is_buy: bool) -> Option<f64> {
+        if is_buy {
+            sweep_price(self.asks.iter(), qty)
+        } else {
+            sweep_price(self.bids.iter().rev(), qty)
+        }
+    }
+
+    /// Returns the notional of the bids and of the asks within `ticks` ticks of the best
+    /// bid and ask, e.g. how much of an unwind each side can absorb nearby.
+    fn depth_notional(&self, ticks: usize) -> (f64, f64) {
+        let distance = ticks as f64 * self.tick_size;
+        (
+            notional_within(self.bids.iter().rev(), distance),
+            notional_within(self.asks.iter(), distance),
+        )
+    }
+
     /// Calculates the price impact of the difference between the order book and the old order book.
     ///
     /// The price impact is a measure of the change in the order book imbalance between the old and
@@ -932,21 +1672,11 @@ impl OrderBook for BinanceBook {
             }
         }
 
-        // Calculate the volume at the bid and ask offsets
-        let bid_impact = if curr_bid_price > old_bid_price || curr_bid_vol > old_bid_vol {
-            curr_bid_vol - old_bid_vol
-        } else if curr_bid_price < old_bid_price || curr_bid_vol < old_bid_vol {
-            curr_bid_vol - old_bid_vol
-        } else {
-            0.0
-        };
-        let ask_impact = if curr_ask_price < old_ask_price || curr_ask_vol > old_ask_vol {
-            curr_ask_vol - old_ask_vol
-        } else if curr_ask_price > old_ask_price || curr_ask_vol < old_ask_vol {
-            curr_ask_vol - old_ask_vol
-        } else {
-            0.0
-        };
+        // Signed change of each side, see `level_impact`
+        let bid_impact =
+            level_impact(old_bid_price, old_bid_vol, curr_bid_price, curr_bid_vol, true);
+        let ask_impact =
+            level_impact(old_ask_price, old_ask_vol, curr_ask_price, curr_ask_vol, false);
 
         // Return the sum of the bid and ask impacts
         bid_impact + ask_impact
@@ -964,16 +1694,26 @@ impl OrderBook for BinanceBook {
     /// that the bid side is stronger, while a negative imbalance ratio indicates that the ask side
     /// is stronger. An imbalance ratio of 0.0 indicates that the order book is balanced.
     ///
+    /// Ratios within `IMBALANCE_DEAD_ZONE` of zero read as balanced, see
+    /// `raw_imbalance_ratio` for the continuous value.
+    ///
     /// # Returns
     ///
     /// The imbalance ratio of the order book.
     fn imbalance_ratio(&self, depth: Option<usize>) -> f64 {
+        dead_zone(self.raw_imbalance_ratio(depth), IMBALANCE_DEAD_ZONE)
+    }
+
+    /// Calculates the imbalance ratio of the order book without the dead zone of
+    /// `imbalance_ratio`, a continuous value between -1.0 and 1.0 that is 0.0 for an empty
+    /// book.
+    fn raw_imbalance_ratio(&self, depth: Option<usize>) -> f64 {
         // Initialize the weighted bid and ask quantities to the quantities of the best bid and ask.
         let (weighted_bid_qty, weighted_ask_qty) = if let Some(depth) = depth {
             // Calculate the weighted bid quantity using the specified depth.
             (
-                self.calculate_weighted_bid(depth, Some(0.5)),
-                self.calculate_weighted_ask(depth, Some(0.5)),
+                self.calculate_weighted_bid(depth, Some(self.decay_rate)),
+                self.calculate_weighted_ask(depth, Some(self.decay_rate)),
             )
         } else {
             (self.best_bid.qty, self.best_ask.qty)
@@ -986,12 +1726,11 @@ impl OrderBook for BinanceBook {
         // Calculate the imbalance ratio by dividing the difference by the sum.
         let ratio = diff / sum;
 
-        // Return the imbalance ratio, checking for NaN and out-of-range values.
-        match ratio {
-            x if x.is_nan() => 0.0, // If NaN, return 0.
-            x if x > 0.20 => x,     // If positive and greater than 0.20, return the ratio.
-            x if x < -0.20 => x,    // If negative and less than -0.20, return the ratio.
-            _ => 0.0,               // Otherwise, return 0.
+        // An empty book has no imbalance.
+        if ratio.is_nan() {
+            0.0
+        } else {
+            ratio
         }
     }
 
@@ -1024,22 +1763,23 @@ impl OrderBook for BinanceBook {
         let bid_ofi = {
             if self.best_bid.price > old_book.best_bid.price {
                 if let Some(depth) = depth {
-                    let weighted_bid = self.calculate_weighted_bid(depth, Some(0.5));
+                    let weighted_bid = self.calculate_weighted_bid(depth, Some(self.decay_rate));
                     weighted_bid
                 } else {
                     self.best_bid.qty
                 }
             } else if self.best_bid.price == old_book.best_bid.price {
                 if let Some(depth) = depth {
-                    let weighted_bid = self.calculate_weighted_bid(depth, Some(0.5));
-                    let prev_weighted_bid = old_book.calculate_weighted_bid(depth, Some(0.5));
+                    let weighted_bid = self.calculate_weighted_bid(depth, Some(self.decay_rate));
+                    let prev_weighted_bid =
+                        old_book.calculate_weighted_bid(depth, Some(self.decay_rate));
                     weighted_bid - prev_weighted_bid
                 } else {
                     self.best_bid.qty - old_book.best_bid.qty
                 }
             } else {
                 if let Some(depth) = depth {
-                    let weighted_bid = self.calculate_weighted_bid(depth, Some(0.5));
+                    let weighted_bid = self.calculate_weighted_bid(depth, Some(self.decay_rate));
                     -weighted_bid
                 } else {
                     -self.best_bid.qty
@@ -1049,22 +1789,23 @@ impl OrderBook for BinanceBook {
         let ask_ofi = {
             if self.best_ask.price < old_book.best_ask.price {
                 if let Some(depth) = depth {
-                    let weighted_ask = self.calculate_weighted_ask(depth, Some(0.5));
+                    let weighted_ask = self.calculate_weighted_ask(depth, Some(self.decay_rate));
                     -weighted_ask
                 } else {
                     -self.best_ask.qty
                 }
             } else if self.best_ask.price == old_book.best_ask.price {
                 if let Some(depth) = depth {
-                    let weighted_ask = self.calculate_weighted_ask(depth, Some(0.5));
-                    let prev_weighted_ask = old_book.calculate_weighted_ask(depth, Some(0.5));
+                    let weighted_ask = self.calculate_weighted_ask(depth, Some(self.decay_rate));
+                    let prev_weighted_ask =
+                        old_book.calculate_weighted_ask(depth, Some(self.decay_rate));
                     prev_weighted_ask - weighted_ask
                 } else {
                     old_book.best_ask.qty - self.best_ask.qty
                 }
             } else {
                 if let Some(depth) = depth {
-                    let weighted_ask = self.calculate_weighted_ask(depth, Some(0.5));
+                    let weighted_ask = self.calculate_weighted_ask(depth, Some(self.decay_rate));
                     weighted_ask
                 } else {
                     self.best_ask.qty
@@ -1101,8 +1842,9 @@ impl OrderBook for BinanceBook {
             x if x < old_book.best_bid.price => 0.0,
             x if x == old_book.best_bid.price => {
                 if let Some(depth) = depth {
-                    let curr_bid_qty = self.calculate_weighted_bid(depth, Some(0.5));
-                    let prev_bid_qty = old_book.calculate_weighted_bid(depth, Some(0.5));
+                    let curr_bid_qty = self.calculate_weighted_bid(depth, Some(self.decay_rate));
+                    let prev_bid_qty =
+                        old_book.calculate_weighted_bid(depth, Some(self.decay_rate));
                     curr_bid_qty - prev_bid_qty
                 } else {
                     self.best_bid.qty - old_book.best_bid.qty
@@ -1110,7 +1852,7 @@ impl OrderBook for BinanceBook {
             }
             x if x > old_book.best_bid.price => {
                 if let Some(depth) = depth {
-                    let curr_bid = self.calculate_weighted_bid(depth, Some(0.5));
+                    let curr_bid = self.calculate_weighted_bid(depth, Some(self.decay_rate));
                     curr_bid
                 } else {
                     self.best_bid.qty
@@ -1123,7 +1865,7 @@ impl OrderBook for BinanceBook {
         let ask_v = match self.best_ask.price {
             x if x < old_book.best_ask.price => {
                 if let Some(depth) = depth {
-                    let curr_ask = self.calculate_weighted_ask(depth, Some(0.5));
+                    let curr_ask = self.calculate_weighted_ask(depth, Some(self.decay_rate));
                     curr_ask
                 } else {
                     self.best_ask.qty
@@ -1131,8 +1873,9 @@ impl OrderBook for BinanceBook {
             }
             x if x == old_book.best_ask.price => {
                 if let Some(depth) = depth {
-                    let curr_ask_qty = self.calculate_weighted_ask(depth, Some(0.5));
-                    let prev_ask_qty = old_book.calculate_weighted_ask(depth, Some(0.5));
+                    let curr_ask_qty = self.calculate_weighted_ask(depth, Some(self.decay_rate));
+                    let prev_ask_qty =
+                        old_book.calculate_weighted_ask(depth, Some(self.decay_rate));
                     curr_ask_qty - prev_ask_qty
                 } else {
                     self.best_ask.qty - old_book.best_ask.qty
@@ -1164,12 +1907,13 @@ impl OrderBook for BinanceBook {
     ///
     /// The weighted ask quantity of the order book.
     fn calculate_weighted_ask(&self, depth: usize, decay_rate: Option<f64>) -> f64 {
-        self.asks
-            .iter()
-            .take(depth)
-            .enumerate()
-            .map(|(i, (_, qty))| (decay(i as f64, decay_rate) * qty) as f64)
-            .sum::<f64>()
+        kahan_sum(
+            self.asks
+                .iter()
+                .take(depth)
+                .enumerate()
+                .map(|(i, (_, qty))| (decay(i as f64, decay_rate) * qty) as f64),
+        )
     }
 
     /// Calculates the weighted bid quantity of the order book.
@@ -1189,16 +1933,155 @@ impl OrderBook for BinanceBook {
     ///
     /// The weighted bid quantity of the order book.
     fn calculate_weighted_bid(&self, depth: usize, decay_rate: Option<f64>) -> f64 {
-        self.bids
-            .iter()
-            .rev()
-            .take(depth)
-            .enumerate()
-            .map(|(i, (_, qty))| (decay(i as f64, decay_rate) * qty) as f64)
-            .sum::<f64>()
+        kahan_sum(
+            self.bids
+                .iter()
+                .rev()
+                .take(depth)
+                .enumerate()
+                .map(|(i, (_, qty))| (decay(i as f64, decay_rate) * qty) as f64),
+        )
+    }
+}
+
+/// Maps a Binance fee tier to its rates, unknown tiers get the VIP 0 rates.
+pub fn binance_fee_tier(tier: f64) -> FeeTier {
+    let (maker, taker) = if tier >= 0.0 {
+        BINANCE_FEE_TIERS
+            .get(tier as usize)
+            .copied()
+            .unwrap_or(BINANCE_FEE_TIERS[0])
+    } else {
+        BINANCE_FEE_TIERS[0]
+    };
+    FeeTier::new(maker, taker)
+}
+
+impl TimeInForce {
+    /// Binance's `timeInForce` value, post-only is `GTX`.
+    pub fn to_binance(self) -> binance::futures::account::TimeInForce {
+        use binance::futures::account::TimeInForce as BinanceTimeInForce;
+        match self {
+            TimeInForce::GoodTilCancel => BinanceTimeInForce::GTC,
+            TimeInForce::PostOnly => BinanceTimeInForce::GTX,
+            TimeInForce::ImmediateOrCancel => BinanceTimeInForce::IOC,
+            TimeInForce::FillOrKill => BinanceTimeInForce::FOK,
+        }
     }
 }
 
+impl LiveOrder {
+    /// Maps an order from Binance's open orders to a `LiveOrder` sized by its unfilled qty.
+    ///
+    /// Returns `None` for orders that no longer rest on the book.
+    pub fn from_binance_order(order: &Order) -> Option<Self> {
+        let resting = matches!(order.status.as_str(), "NEW" | "PARTIALLY_FILLED");
+        let remaining = order.orig_qty - order.executed_qty;
+        if !resting || remaining <= 0.0 {
+            return None;
+        }
+        Some(Self {
+            filled_qty: order.executed_qty,
+            created_at: order.time,
+            ..Self::new(
+                order.order_id.to_string(),
+                order.price,
+                remaining,
+                order.side == "BUY",
+            )
+        })
+    }
+}
+
+impl SymbolInfo {
+    /// Extracts the trading rules of a symbol from its Binance filters.
+    ///
+    /// Binance doesn't guarantee the order of the filters, so each one is matched by type.
+    /// Rules whose filter is missing or fails to parse are left at 0.0, as is the max
+    /// leverage, which Binance only reports to the account.
+    pub fn from_binance_filters(filters: &[Filters]) -> Self {
+        let parse = |value: &str| value.parse().unwrap_or(0.0);
+        let mut info = SymbolInfo {
+            tick_size: 0.0,
+            lot_size: 0.0,
+            min_notional: 0.0,
+            min_qty: 0.0,
+            post_only_max: 0.0,
+            max_leverage: 0.0,
+        };
+        for filter in filters {
+            match filter {
+                Filters::PriceFilter { tick_size, .. } => info.tick_size = parse(tick_size),
+                Filters::LotSize {
+                    min_qty,
+                    max_qty,
+                    step_size,
+                } => {
+                    info.lot_size = parse(step_size);
+                    info.min_qty = parse(min_qty);
+                    info.post_only_max = parse(max_qty);
+                }
+                Filters::MinNotional {
+                    notional,
+                    min_notional,
+                    ..
+                } => {
+                    // Futures report `notional`, spot `minNotional`
+                    if let Some(value) = notional.as_ref().or(min_notional.as_ref()) {
+                        info.min_notional = parse(value);
+                    }
+                }
+                _ => {}
+            }
+        }
+        info
+    }
+}
+
+impl BinancePrivate {
+    /// Applies a user data stream event to the data of `symbol`, returns `false` when the
+    /// event belongs to another symbol or isn't a user data event.
+    pub fn update(&mut self, symbol: &str, event: FuturesWebsocketEvent) -> bool {
+        match event {
+            FuturesWebsocketEvent::OrderTrade(data) => self.process_order_event(symbol, data),
+            FuturesWebsocketEvent::AccountUpdate(data) => self.process_account_event(symbol, data),
+            _ => false,
+        }
+    }
+
+    fn process_order_event(&mut self, symbol: &str, data: OrderTradeEvent) -> bool {
+        if data.order.symbol != symbol {
+            return false;
+        }
+        self.time = data.event_time;
+        if data.order.execution_type == "TRADE" {
+            push_capped(&mut self.executions, data.order.clone(), 500);
+        }
+        push_capped(&mut self.orders, data.order, 500);
+        true
+    }
+
+    fn process_account_event(&mut self, symbol: &str, data: AccountUpdateEvent) -> bool {
+        self.time = data.event_time;
+        for balance in data.data.balances {
+            push_capped(&mut self.wallet, balance, 20);
+        }
+        for position in data.data.positions {
+            if position.symbol == symbol {
+                push_capped(&mut self.positions, position, 500);
+            }
+        }
+        true
+    }
+}
+
+fn push_capped<T>(items: &mut VecDeque<T>, item: T, cap: usize) {
+    if items.len() >= cap {
+        items.pop_front();
+    }
+    items.push_back(item);
+}
+
 impl BinanceMarket {
     /// Gets a snapshot of the order books for the given symbols and updates the order books
     /// in the `BinanceMarket` instance.
@@ -1237,8 +2120,7 @@ impl BinanceMarket {
 /// `<stream>` is the stream name. The streams are:
 ///
 ///  - `aggTrade`: The aggregated trade stream.
-///  - `depth20@100ms`: The order book stream with 20 levels of depth, updated every 100ms.
-///  - `depth@100ms`: The order book stream with all levels of depth, updated every 100ms.
+///  - `depth@100ms`: The diff depth stream, applied onto a REST snapshot by `DepthSync`.
 ///  - `bookTicker`: The order book ticker stream.
 ///
 /// # Arguments
@@ -1258,12 +2140,6 @@ fn build_requests(symbol: &[String]) -> Vec<String> {
         .map(|sub| format!("{}@aggTrade", sub))
         .collect();
     request_args.extend(trade_req);
-    let best_book: Vec<String> = symbol
-        .iter()
-        .map(|sub| sub.to_lowercase())
-        .map(|sub| format!("{}@depth20@100ms", sub))
-        .collect();
-    request_args.extend(best_book);
     let book: Vec<String> = symbol
         .iter()
         .map(|sub| sub.to_lowercase())
@@ -6,40 +6,66 @@ use bybit::{
     general::General,
     market::MarketData,
     model::{
-        AmendOrderRequest, Ask, Bid, CancelOrderRequest, CancelallRequest, Category, FastExecution,
-        InstrumentRequest, LeverageRequest, OrderBookUpdate, OrderEvent, OrderStatus,
-        PositionEvent, Side, Subscription, Tickers, TradeUpdate, WalletEvent, WebsocketEvents,
-        WsTicker,
+        AmendOrderRequest, Ask, Bid, CancelOrderRequest, CancelallRequest, Category, DcpRequest,
+        FastExecution, FundingHistoryRequest, InstrumentRequest, KlineRequest, LeverageRequest,
+        OpenOrdersRequest, Order, OrderBookUpdate, OrderEvent, OrderRequest, OrderStatus,
+        OrderType, PositionEvent, PositionInfo, PositionRequest, RecentTradesRequest, Side,
+        Subscription, Tickers, TradeUpdate, WalletEvent, WebsocketEvents, WsTicker,
     },
     position::PositionManager,
     trade::Trader,
     ws::Stream,
 };
 use ordered_float::OrderedFloat;
+use tokio_stream::StreamExt;
 use std::{
     borrow::Cow,
-    collections::{BTreeMap, VecDeque},
-    time::Duration,
+    collections::{BTreeMap, HashMap, VecDeque},
+    time::{Duration, Instant},
 };
 
 use crate::utils::{
+    backoff::{Backoff, MAX_DELAY},
     bot::LiveBot,
-    localorderbook::OrderBook,
+    latency::RequestLatency,
+    localorderbook::{
+        dead_zone, level_impact, notional_within, set_level, sweep_price, OrderBook,
+        DEFAULT_DECAY_RATE, IMBALANCE_DEAD_ZONE,
+    },
     logger::Logger,
+    metrics,
     models::{
-        BatchAmend, BatchOrder, BybitBook, BybitClient, BybitMarket, BybitPrivate, IntoReq,
-        LiveOrder, SymbolInfo,
+        BatchAmend, BatchOrder, BybitBook, BybitClient, BybitMarket, BybitPrivate, Candle,
+        ws_trade_from_recent, FeeTier, IntoReq, LiveOrder, RejectedAmend, RejectedOrder,
+        SymbolInfo, TimeInForce,
     },
-    number::decay,
+    number::{decay, kahan_sum},
 };
 
-use super::exchange::Exchange;
+use super::exchange::{
+    self, retry_with_backoff, wait_for_shutdown, Exchange, Heartbeat, TradeType, STREAM_TIMEOUT,
+};
 
 type Result<T> = std::result::Result<T, BybitError>;
 
+/// Awaits an order request of `$client` for `$symbol`, recording its round trip under
+/// `$request` and logging it at debug level.
+macro_rules! timed {
+    ($client:expr, $request:expr, $symbol:expr, $future:expr) => {{
+        let start = Instant::now();
+        let output = $future.await;
+        let latency = $client.latency.record($request, $symbol, start.elapsed());
+        $client.logger.debug(&format!(
+            "{} took {:.1}ms (avg {:.1}ms)",
+            $request, latency.last_ms, latency.avg_ms
+        ));
+        output
+    }};
+}
+
 impl Exchange for BybitClient {
     type TimeOutput = Result<u64>;
-    type FeeOutput = Result<String>;
+    type FeeOutput = anyhow::Result<FeeTier>;
     type LeverageOutput = Result<bool>;
     type TraderOutput = Trader;
     type StreamData = BybitMarket;
@@ -50,9 +76,15 @@ impl Exchange for BybitClient {
     type AmendOrderOutput = Result<LiveOrder>;
     type CancelOrderOutput = Result<OrderStatus>;
     type CancelAllOutput = Result<Vec<OrderStatus>>;
-    type BatchOrdersOutput = Result<(Vec<LiveOrder>, Vec<LiveOrder>)>;
-    type BatchAmendsOutput = Result<Vec<LiveOrder>>;
+    type OpenOrdersOutput = Result<Vec<LiveOrder>>;
+    type PositionOutput = Result<f64>;
+    type BatchOrdersOutput = Result<(Vec<LiveOrder>, Vec<LiveOrder>, Vec<RejectedOrder>)>;
+    type BatchAmendsOutput = Result<(Vec<(String, LiveOrder)>, Vec<RejectedAmend>)>;
     type SymbolInformationOutput = Result<SymbolInfo>;
+    type KlinesOutput = Result<Vec<Candle>>;
+    type FundingRateOutput = Result<f64>;
+    type WalletBalanceOutput = Result<f64>;
+    type RecentTradesOutput = Result<TradeType>;
     /// Initializes a new `BybitClient` instance.
     ///
     /// # Arguments
@@ -65,11 +97,7 @@ impl Exchange for BybitClient {
     /// A new `BybitClient` instance
     async fn init(api_key: String, api_secret: String) -> Self {
         let bot = LiveBot::new("./config.toml").await.unwrap();
-        Self {
-            api_key,
-            api_secret,
-            logger: Logger::new(bot),
-        }
+        Self::new(api_key, api_secret, Logger::new(bot))
     }
 
     /// Gets the current server time in milliseconds.
@@ -90,17 +118,22 @@ impl Exchange for BybitClient {
     ///
     /// # Returns
     ///
-    /// A `Result` containing the fee tier as a `String` for the given symbol.
-    ///
-    /// # Notes
-    ///
-    /// The `symbol` argument is currently ignored, and the fee tier is always
-    /// retrieved for the entire account.
+    /// A `Result` containing the linear maker and taker fee rates for the given symbol.
     async fn fees(&self, symbol: String) -> Self::FeeOutput {
         let account =
             AccountManager::new(Some(self.api_key.clone()), Some(self.api_secret.clone()));
-        let fee = account.get_fee_rate(Category::Spot, Some(symbol)).await?;
-        Ok(fee.result.list[0].maker_fee_rate.clone())
+        let fee = account
+            .get_fee_rate(Category::Linear, Some(symbol.clone()))
+            .await?;
+        let rate = fee
+            .result
+            .list
+            .first()
+            .ok_or_else(|| anyhow::anyhow!("No fee rate returned for {}", symbol))?;
+        Ok(FeeTier::new(
+            rate.maker_fee_rate.parse()?,
+            rate.taker_fee_rate.parse()?,
+        ))
     }
 
     /// Sets the leverage for the given symbol.
@@ -154,6 +187,8 @@ impl Exchange for BybitClient {
     /// - `price`: The price to place the order at.
     /// - `qty`: The quantity of the order.
     /// - `is_buy`: Whether to place a buy or sell order.
+    /// - `reduce_only`: Whether the order may only reduce the position.
+    /// - `time_in_force`: How long the order stays on the book.
     ///
     /// # Returns
     ///
@@ -164,22 +199,38 @@ impl Exchange for BybitClient {
     /// The `price` is the limit price, and the `qty` is the quantity of the order.
     /// The `is_buy` argument is used to determine whether to place a buy or sell
     /// order. If `is_buy` is `true`, a buy order is placed. If `is_buy` is `false`,
-    /// a sell order is placed.
+    /// a sell order is placed. A reduce-only order is shrunk or cancelled by Bybit
+    /// instead of flipping the position.
     async fn place_order(
         &self,
         symbol: &str,
         price: f64,
         qty: f64,
         is_buy: bool,
+        reduce_only: bool,
+        time_in_force: TimeInForce,
     ) -> Self::PlaceOrderOutput {
         let trader = self.trader(2500);
-        let side = if is_buy { Side::Buy } else { Side::Sell };
+        let request = OrderRequest {
+            category: Category::Linear,
+            symbol: Cow::Borrowed(symbol),
+            side: if is_buy { Side::Buy } else { Side::Sell },
+            order_type: OrderType::Limit,
+            qty,
+            price: Some(price),
+            time_in_force: Some(Cow::Borrowed(time_in_force.to_bybit())),
+            position_idx: Some(is_buy as u8 + 1),
+            reduce_only: reduce_only.then_some(true),
+            ..Default::default()
+        };
 
-        let res = trader
-            .place_futures_limit_order(Category::Linear, symbol, side, qty, price, is_buy as u8 + 1)
-            .await?;
+        let res = timed!(self, "place_order", symbol, trader.place_custom_order(request))?;
 
-        Ok(LiveOrder::new(res.result.order_id, price, qty))
+        Ok(LiveOrder {
+            reduce_only,
+            time_in_force,
+            ..LiveOrder::new(res.result.order_id, price, qty, is_buy)
+        })
     }
 
     /// Amends an existing order on Bybit.
@@ -190,6 +241,7 @@ impl Exchange for BybitClient {
     /// - `price`: The new price to place the order at.
     /// - `qty`: The new quantity of the order.
     /// - `symbol`: The symbol of the market to amend the order in.
+    /// - `is_buy`: The side of the order, which an amend can't change.
     ///
     /// # Returns
     ///
@@ -206,6 +258,7 @@ impl Exchange for BybitClient {
         price: f64,
         qty: f64,
         symbol: &str,
+        is_buy: bool,
     ) -> Self::AmendOrderOutput {
         let trader = self.trader(2500);
         let request = AmendOrderRequest {
@@ -216,8 +269,8 @@ impl Exchange for BybitClient {
             price: Some(price),
             ..Default::default()
         };
-        let amend = trader.amend_order(request).await?;
-        Ok(LiveOrder::new(amend.result.order_id, price, qty))
+        let amend = timed!(self, "amend_order", symbol, trader.amend_order(request))?;
+        Ok(LiveOrder::new(amend.result.order_id, price, qty, is_buy))
     }
 
     /// Cancels an existing order on Bybit.
@@ -244,7 +297,7 @@ impl Exchange for BybitClient {
             order_filter: None,
             order_link_id: None,
         };
-        let cancel = trader.cancel_order(request).await?;
+        let cancel = timed!(self, "cancel_order", symbol, trader.cancel_order(request))?;
 
         Ok(cancel.result)
     }
@@ -269,10 +322,60 @@ impl Exchange for BybitClient {
             symbol,
             ..Default::default()
         };
-        let cancel_all = trader.cancel_all_orders(request).await?;
+        let cancel_all = timed!(self, "cancel_all", symbol, trader.cancel_all_orders(request))?;
         Ok(cancel_all.result.list)
     }
 
+    /// Gets the orders currently resting on Bybit.
+    ///
+    /// # Arguments
+    ///
+    /// - `symbol`: The symbol of the market to list the open orders of.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the open orders with their remaining quantity.
+    ///
+    /// # Notes
+    ///
+    /// Uses the realtime endpoint, so only active orders are returned. Orders in a
+    /// final state are skipped by `LiveOrder::from_bybit_order`.
+    async fn get_open_orders(&self, symbol: &str) -> Self::OpenOrdersOutput {
+        let trader = self.trader(2500);
+        let request = OpenOrdersRequest {
+            category: Category::Linear,
+            symbol: Cow::Borrowed(symbol),
+            open_only: Some(0),
+            limit: Some(50),
+            ..Default::default()
+        };
+        let open_orders = trader.get_open_orders(request).await?;
+        Ok(open_orders
+            .result
+            .list
+            .iter()
+            .filter_map(LiveOrder::from_bybit_order)
+            .collect())
+    }
+
+    /// Gets the position held on Bybit.
+    ///
+    /// # Arguments
+    ///
+    /// - `symbol`: The symbol of the market to get the position of.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the signed position size, positive for long and
+    /// negative for short. 0.0 when there is no open position.
+    async fn get_position(&self, symbol: &str) -> Self::PositionOutput {
+        let position =
+            PositionManager::new(Some(self.api_key.clone()), Some(self.api_secret.clone()));
+        let request = PositionRequest::new(Category::Linear, Some(symbol), None, None, None);
+        let info = position.get_info(request).await?;
+        Ok(signed_position(&info.result.list))
+    }
+
     /// Amends multiple orders on Bybit.
     ///
     /// # Arguments
@@ -281,7 +384,8 @@ impl Exchange for BybitClient {
     ///
     /// # Returns
     ///
-    /// A `Result` containing a list of the orders that were amended.
+    /// A `Result` containing the amended orders, each with the id it was amended under, and
+    /// the amends Bybit rejected with the code and message of their `ret_ext_info` entry.
     ///
     /// # Notes
     ///
@@ -293,8 +397,15 @@ impl Exchange for BybitClient {
     async fn batch_amends(&self, orders: Vec<BatchAmend>) -> Self::BatchAmendsOutput {
         let trader = self.trader(2500);
         let mut amends = Vec::with_capacity(10);
+        let mut rejected = Vec::new();
+        let symbol = orders.first().map(|o| o.0.clone()).unwrap_or_default();
         let request = orders.clone().into_req();
-        let batch_amend = trader.batch_amend_order(request).await?;
+        let batch_amend = timed!(
+            self,
+            "batch_amends",
+            &symbol,
+            trader.batch_amend_order(request)
+        )?;
 
         for ((live_order, ext_info), order_req) in batch_amend
             .result
@@ -308,15 +419,29 @@ impl Exchange for BybitClient {
                     "Order amended. Symbol: {}, Order ID: {}, Price: {}, Quantity: {}",
                     live_order.symbol, live_order.order_id, order_req.1, order_req.2
                 );
-                amends.push(LiveOrder::new(
-                    live_order.order_id.clone(),
-                    order_req.1,
-                    order_req.2,
+                amends.push((
+                    order_req.3.clone(),
+                    LiveOrder::new(
+                        live_order.order_id.clone(),
+                        order_req.1,
+                        order_req.2,
+                        order_req.4,
+                    ),
                 ));
                 self.logger.info(&order_message);
+            } else {
+                self.logger.warning(&format!(
+                    "Amend rejected ({}): {}, Symbol: {}, Order ID: {}, Price: {}, Quantity: {}",
+                    ext_info.code, ext_info.msg, order_req.0, order_req.3, order_req.1, order_req.2
+                ));
+                rejected.push(RejectedAmend::new(
+                    order_req,
+                    i64::from(ext_info.code),
+                    ext_info.msg.clone(),
+                ));
             }
         }
-        Ok(amends)
+        Ok((amends, rejected))
     }
 
     /// Places multiple orders on Bybit.
@@ -327,7 +452,8 @@ impl Exchange for BybitClient {
     ///
     /// # Returns
     ///
-    /// A `Result` containing a list of the orders that were placed.
+    /// A `Result` containing the placed buys and sells, and the orders Bybit rejected
+    /// with the code and message of their `ret_ext_info` entry.
     ///
     /// # Notes
     ///
@@ -342,7 +468,14 @@ impl Exchange for BybitClient {
         let request = orders.clone().into_req();
         let mut live_sells = Vec::with_capacity(5);
         let mut live_buys = Vec::with_capacity(5);
-        let batch_orders = trader.batch_place_order(request).await?;
+        let mut rejected = Vec::new();
+        let symbol = orders.first().map(|o| o.0.clone()).unwrap_or_default();
+        let batch_orders = timed!(
+            self,
+            "batch_orders",
+            &symbol,
+            trader.batch_place_order(request)
+        )?;
         for ((live_order, ext_info), order_req) in batch_orders
             .result
             .list
@@ -351,23 +484,25 @@ impl Exchange for BybitClient {
             .zip(orders)
         {
             if ext_info.code == 0 && ext_info.msg == "OK" {
-                if order_req.3 {
-                    live_buys.push(LiveOrder::new(
-                        live_order.order_id.clone(),
-                        order_req.1,
-                        order_req.2,
-                    ));
+                let live = LiveOrder::placed(live_order.order_id.clone(), &order_req);
+                if live.is_buy {
+                    live_buys.push(live);
                 } else {
-                    live_sells.push(LiveOrder::new(
-                        live_order.order_id.clone(),
-                        order_req.1,
-                        order_req.2,
-                    ));
+                    live_sells.push(live);
                 }
             } else {
+                self.logger.warning(&format!(
+                    "Order rejected ({}): {}, Symbol: {}, Price: {}, Quantity: {}",
+                    ext_info.code, ext_info.msg, order_req.0, order_req.1, order_req.2
+                ));
+                rejected.push(RejectedOrder::new(
+                    order_req,
+                    i64::from(ext_info.code),
+                    ext_info.msg.clone(),
+                ));
             }
         }
-        Ok((live_buys, live_sells))
+        Ok((live_buys, live_sells, rejected))
     }
 
     /// Retrieves symbol information from Bybit.
@@ -380,6 +515,7 @@ impl Exchange for BybitClient {
     /// * `min_notional`: The minimum notional value of the symbol.
     /// * `min_qty`: The minimum quantity of the symbol.
     /// * `post_only_max`: The maximum post-only quantity of the symbol.
+    /// * `max_leverage`: The maximum leverage of the symbol.
     ///
     /// If the request fails, the function will panic with the error message.
     async fn get_symbol_info(&self, symbol: &str) -> Self::SymbolInformationOutput {
@@ -397,8 +533,112 @@ impl Exchange for BybitClient {
             min_notional: parse_float(&info.lot_size_filter.min_notional_value),
             post_only_max: info.lot_size_filter.max_order_qty,
             min_qty: info.lot_size_filter.min_order_qty,
+            max_leverage: info.leverage_filter.max_leverage.parse().unwrap_or(0.0),
         })
     }
+
+    /// Fetches the latest `limit` linear candles of `symbol`, oldest first.
+    ///
+    /// `interval` is Bybit's notation: minutes as `1`, `5`, `60`..., or `D`, `W` and `M`.
+    /// The request is retried up to `REST_ATTEMPTS` times with an exponential backoff.
+    async fn get_klines(&self, symbol: &str, interval: &str, limit: u16) -> Self::KlinesOutput {
+        let market_data = MarketData::new(None, None);
+        let res = retry_with_backoff(REST_ATTEMPTS, || {
+            let request = KlineRequest::new(
+                Some(Category::Linear),
+                symbol,
+                interval,
+                None,
+                None,
+                Some(limit as u64),
+            );
+            market_data.get_klines(request)
+        })
+        .await?;
+
+        let parse = |v: &str| v.parse().unwrap_or(0.0);
+        // Bybit lists the newest candle first
+        Ok(res
+            .result
+            .list
+            .iter()
+            .rev()
+            .map(|kline| Candle {
+                open_time: kline.start_time,
+                open: parse(&kline.open_price),
+                high: parse(&kline.high_price),
+                low: parse(&kline.low_price),
+                close: parse(&kline.close_price),
+                volume: parse(&kline.volume),
+            })
+            .collect())
+    }
+
+    /// Fetches the latest `limit` linear trades of `symbol`, at most 1000, oldest first.
+    ///
+    /// The request is retried up to `REST_ATTEMPTS` times with an exponential backoff.
+    async fn get_recent_trades(&self, symbol: &str, limit: u16) -> Self::RecentTradesOutput {
+        let market_data = MarketData::new(None, None);
+        let res = retry_with_backoff(REST_ATTEMPTS, || {
+            let request =
+                RecentTradesRequest::new(Category::Linear, Some(symbol), None, Some(limit as u64));
+            market_data.get_recent_trades(request)
+        })
+        .await?;
+
+        // Bybit lists the newest trade first
+        Ok(res
+            .result
+            .list
+            .iter()
+            .rev()
+            .map(ws_trade_from_recent)
+            .collect())
+    }
+
+    /// Runs `market_subscribe` in its own task and streams its updates.
+    fn subscribe(
+        &self,
+        symbols: Vec<String>,
+    ) -> impl tokio_stream::Stream<Item = exchange::MarketData> + Send + Unpin {
+        let client = self.clone();
+        exchange::Subscription::spawn(move |sender, shutdown| async move {
+            client.market_subscribe(symbols, sender, shutdown).await
+        })
+        .map(exchange::MarketData::Bybit)
+    }
+
+    /// Fetches the equity of `coin` in the unified trading account.
+    async fn get_wallet_balance(&self, coin: &str) -> Self::WalletBalanceOutput {
+        let account =
+            AccountManager::new(Some(self.api_key.clone()), Some(self.api_secret.clone()));
+        let wallet = account.get_wallet_balance("UNIFIED", Some(coin)).await?;
+        let balance = wallet
+            .result
+            .list
+            .iter()
+            .flat_map(|wallet| wallet.coin.iter())
+            .find(|balance| balance.coin == coin)
+            .ok_or_else(|| BybitError::Base(format!("No {} balance returned", coin)))?;
+        balance.equity.parse().map_err(|_| {
+            BybitError::Base(format!("Invalid {} equity {}", coin, balance.equity))
+        })
+    }
+
+    /// Fetches the last settled funding rate of `symbol`, the ticker stream carries the
+    /// predicted one afterwards.
+    async fn get_funding_rate(&self, symbol: &str) -> Self::FundingRateOutput {
+        let market_data = MarketData::new(None, None);
+        let res = retry_with_backoff(REST_ATTEMPTS, || {
+            let request = FundingHistoryRequest::new(Category::Linear, symbol, None, None, Some(1));
+            market_data.get_funding_history(request)
+        })
+        .await?;
+        match res.result.list.first() {
+            Some(funding) => Ok(funding.funding_rate),
+            None => Err(BybitError::Base(format!("No funding rate for {}", symbol))),
+        }
+    }
     /// Subscribes to Bybit futures market data for the given symbols and sends
     /// it over the given sender channel.
     ///
@@ -416,11 +656,14 @@ impl Exchange for BybitClient {
     /// The `sender` parameter is an unbounded sender channel that will receive
     /// the market data.
     ///
+    /// The subscription and its reconnects stop once `true` is sent on `shutdown`.
+    ///
     /// The function returns an empty tuple.
     async fn market_subscribe(
         &self,
         symbols: Vec<String>,
         sender: tokio::sync::mpsc::UnboundedSender<Self::StreamData>,
+        mut shutdown: tokio::sync::watch::Receiver<bool>,
     ) {
         let market_stream: Stream = Bybit::new(None, None);
         let mut market_data = BybitMarket::default();
@@ -433,7 +676,12 @@ impl Exchange for BybitClient {
         let results = futures::future::join_all(init_futures).await;
 
         for (symbol, info) in results {
-            market_data.books.insert(symbol.clone(), BybitBook::new());
+            // Nothing to quote on until the first snapshot
+            let book = BybitBook {
+                needs_snapshot: true,
+                ..BybitBook::new()
+            };
+            market_data.books.insert(symbol.clone(), book);
             market_data
                 .trades
                 .insert(symbol.clone(), VecDeque::with_capacity(1000));
@@ -445,33 +693,65 @@ impl Exchange for BybitClient {
                 book.update_symbol_info(&info);
             }
         }
-        let args = build_request(&symbols);
+        let args = build_request(&symbols, &self.book_depths);
         let request = Subscription::new("subscribe", args.iter().map(String::as_str).collect());
 
+        let heartbeat = Heartbeat::new();
+        let beats = heartbeat.clone();
+        let depths = self.book_depths.clone();
+        let mut sequence = BookSequence::new();
+        // Every connection starts from the books above, a reconnect rebuilds them from the
+        // snapshots sent on subscribing
         let handler = move |event| {
-            handle_websocket_event(&mut market_data, event);
-            let _ = sender.send(market_data.clone());
-            Ok(())
+            beats.beat();
+            // Deltas that re-send known levels don't reach the strategy, a book that lost
+            // sync does so quoting stops on it
+            let result = handle_websocket_event(&mut market_data, &mut sequence, event, &depths);
+            if !matches!(result, Ok(false)) {
+                let _ = sender.send(market_data.clone());
+            }
+            result.map(|_| ())
         };
 
-        let mut backoff = 600;
-
-        loop {
-            match market_stream
-                .ws_subscribe(request.clone(), Category::Linear, handler.clone())
-                .await
-            {
-                Ok(_) => {
-                    backoff = 600;
-                    self.logger.info("Subscribed to Bybit futures market data");
+        let mut backoff = Backoff::with_max(self.max_reconnect_delay);
+
+        let stream = async {
+            loop {
+                heartbeat.rearm();
+                tokio::select! {
+                    result = market_stream.ws_subscribe(
+                        request.clone(),
+                        Category::Linear,
+                        handler.clone(),
+                    ) => match result {
+                        Ok(_) => {
+                            self.logger.info("Subscribed to Bybit futures market data");
+                        }
+                        Err(e) => {
+                            let error_message = format!("Bybit_Market_Error: {}", e);
+                            self.logger.error(&error_message);
+                        }
+                    },
+                    _ = heartbeat.stale(self.stream_timeout) => {
+                        self.logger.warning(&format!(
+                            "No Bybit market data for {:?}, reconnecting",
+                            self.stream_timeout
+                        ));
+                    }
                 }
-                Err(e) => {
-                    let delay = backoff * 2;
-                    backoff = delay;
-                    let error_message = format!("Bybit_Market_Error: {}", e);
-                    self.logger.error(&error_message);
-                    tokio::time::sleep(Duration::from_millis(delay)).await;
+                // A connection that delivered data worked, however it ended
+                if heartbeat.received() {
+                    backoff.reset();
                 }
+                metrics::inc(metrics::RECONNECTS, &[("stream", "bybit_market")]);
+                tokio::time::sleep(backoff.next_delay()).await;
+            }
+        };
+
+        tokio::select! {
+            _ = stream => {}
+            _ = wait_for_shutdown(&mut shutdown) => {
+                self.logger.info("Stopped the Bybit futures market data stream");
             }
         }
     }
@@ -488,6 +768,7 @@ impl Exchange for BybitClient {
     ///
     /// * `symbol` - The symbol to subscribe to.
     /// * `sender` - An unbounded sender channel that will receive the user stream data.
+    /// * `shutdown` - Stops the subscription and its reconnects once `true` is sent.
     ///
     /// # Returns
     ///
@@ -496,6 +777,7 @@ impl Exchange for BybitClient {
         &self,
         symbol: String,
         sender: tokio::sync::mpsc::UnboundedSender<Self::PrivateStreamData>,
+        mut shutdown: tokio::sync::watch::Receiver<bool>,
     ) -> () {
         let user_stream: Stream = Bybit::new(
             Some(self.api_key.clone()),    // API key
@@ -507,40 +789,296 @@ impl Exchange for BybitClient {
             args.push("execution.fast".to_string());
             args.push("order.linear".to_string());
             args.push("wallet".to_string());
+            // DCP only counts down on connections subscribed to its topic
+            if let Some(window) = self.dcp_window {
+                if self.enable_cancel_on_disconnect(window).await {
+                    args.push("dcp.future".to_string());
+                }
+            }
             args
         };
-        let mut private_data = BybitPrivate::default();
         let request = Subscription::new(
             "subscribe",
             request_args.iter().map(String::as_str).collect(),
         );
-        let handler = move |event| {
-            handle_private_websocket_event(&mut private_data, event);
-            let _ = sender.send((symbol.clone(), private_data.clone()));
-            Ok(())
-        };
-        let mut backoff = 600;
-        loop {
-            match user_stream
-                .ws_priv_subscribe(request.clone(), handler.clone())
-                .await
-            {
-                Ok(_) => {
-                    backoff = 600;
-                    self.logger.info("Subscribed to Bybit private stream data");
+        let mut backoff = Backoff::with_max(self.max_reconnect_delay);
+        let mut session = 0;
+        let heartbeat = Heartbeat::new();
+        let stream = async {
+            loop {
+                // Every connection starts from empty data, the new session tells the
+                // generators to reconcile their orders with the exchange
+                session += 1;
+                let mut private_data = BybitPrivate {
+                    session,
+                    ..Default::default()
+                };
+                let sender = sender.clone();
+                let symbol = symbol.clone();
+                // Pongs count too, so a connection without account activity isn't dropped
+                let beats = heartbeat.clone();
+                let handler = move |event| {
+                    beats.beat();
+                    handle_private_websocket_event(&mut private_data, event);
+                    let _ = sender.send((symbol.clone(), private_data.clone()));
+                    Ok(())
+                };
+                heartbeat.rearm();
+                tokio::select! {
+                    result = user_stream.ws_priv_subscribe(request.clone(), handler) => {
+                        match result {
+                            Ok(_) => {
+                                self.logger.info("Subscribed to Bybit private stream data");
+                            }
+                            Err(e) => {
+                                let error_message = format!("Error: {}", e);
+                                self.logger.error(&error_message);
+                            }
+                        }
+                    }
+                    _ = heartbeat.stale(self.stream_timeout) => {
+                        self.logger.warning(&format!(
+                            "No Bybit private stream message for {:?}, reconnecting",
+                            self.stream_timeout
+                        ));
+                    }
                 }
-                Err(e) => {
-                    let delay = backoff * 2;
-                    backoff = delay;
-                    let error_message = format!("Error: {}", e);
-                    self.logger.error(&error_message);
-                    tokio::time::sleep(Duration::from_millis(delay)).await;
+                if heartbeat.received() {
+                    backoff.reset();
                 }
+                metrics::inc(metrics::RECONNECTS, &[("stream", "bybit_private")]);
+                tokio::time::sleep(backoff.next_delay()).await;
+            }
+        };
+
+        tokio::select! {
+            _ = stream => {}
+            _ = wait_for_shutdown(&mut shutdown) => {
+                self.logger.info("Stopped the Bybit private stream");
+            }
+        }
+    }
+}
+
+impl BybitClient {
+    /// Creates a client around an already constructed `Logger`, with cancel on disconnect off.
+    pub fn new(api_key: String, api_secret: String, logger: Logger) -> Self {
+        Self {
+            api_key,
+            api_secret,
+            logger,
+            dcp_window: None,
+            stream_timeout: STREAM_TIMEOUT,
+            max_reconnect_delay: MAX_DELAY,
+            book_depths: BOOK_DEPTHS.to_vec(),
+            latency: RequestLatency::new(),
+        }
+    }
+
+    /// Subscribes the next market subscriptions to the orderbook feeds covering `depths`
+    /// levels (see `book_depths`), an empty `depths` restores every feed.
+    pub fn set_book_depths(&mut self, depths: &[usize]) {
+        self.book_depths = book_depths(depths);
+    }
+
+    /// Caps the delay between the reconnects of the next subscriptions at `secs` seconds, 0
+    /// restores the default of 30 seconds.
+    pub fn set_max_reconnect_delay(&mut self, secs: u64) {
+        self.max_reconnect_delay = match secs {
+            0 => MAX_DELAY,
+            secs => Duration::from_secs(secs),
+        };
+    }
+
+    /// Enables cancel on disconnect for the next private subscriptions, a window of 0 disables it.
+    pub fn set_cancel_on_disconnect(&mut self, window: u16) {
+        self.dcp_window = (window > 0).then_some(window);
+    }
+
+    /// Reconnects the next subscriptions after `secs` seconds without a message, 0 restores
+    /// the default of 30 seconds.
+    pub fn set_stream_timeout(&mut self, secs: u64) {
+        self.stream_timeout = match secs {
+            0 => STREAM_TIMEOUT,
+            secs => Duration::from_secs(secs),
+        };
+    }
+
+    /// Builds the disconnect cancel all (DCP) request for derivatives.
+    ///
+    /// Bybit accepts a time window of 3 to 300 seconds.
+    pub fn dcp_request(window: u16) -> DcpRequest<'static> {
+        DcpRequest {
+            product: Some("DERIVATIVES"),
+            time_window: window.clamp(3, 300) as i32,
+        }
+    }
+
+    /// Turns on Bybit's dead man's switch: every order is cancelled once the private
+    /// stream subscribed to `dcp.future` has been down for `window` seconds.
+    pub async fn enable_cancel_on_disconnect(&self, window: u16) -> bool {
+        let trader: Trader = self.trader(5000);
+        let request = Self::dcp_request(window);
+        let time_window = request.time_window;
+        match trader.set_dcp_options(request).await {
+            Ok(_) => {
+                self.logger.success(&format!(
+                    "Cancel on disconnect enabled with a {}s window",
+                    time_window
+                ));
+                true
             }
+            Err(e) => {
+                self.logger
+                    .error(&format!("Failed to enable cancel on disconnect: {}", e));
+                false
+            }
+        }
+    }
+}
+
+/// Nets Bybit positions into a signed size, positive for long and negative for short.
+///
+/// Hedge mode reports a position per side and a flat one-way position has no side,
+/// an empty list means there is no position at all.
+pub fn signed_position(positions: &[PositionInfo]) -> f64 {
+    positions
+        .iter()
+        .map(|position| match position.side.as_str() {
+            "Buy" => position.size,
+            "Sell" => -position.size,
+            _ => 0.0,
+        })
+        .sum()
+}
+
+impl TimeInForce {
+    /// Bybit's `timeInForce` value.
+    pub fn to_bybit(self) -> &'static str {
+        match self {
+            TimeInForce::GoodTilCancel => "GTC",
+            TimeInForce::PostOnly => "PostOnly",
+            TimeInForce::ImmediateOrCancel => "IOC",
+            TimeInForce::FillOrKill => "FOK",
         }
     }
 }
 
+impl LiveOrder {
+    /// Maps an order from Bybit's open orders to a `LiveOrder` sized by its unfilled qty.
+    ///
+    /// Returns `None` for orders that no longer rest on the book, i.e. filled,
+    /// cancelled or rejected ones and fully filled partials.
+    pub fn from_bybit_order(order: &Order) -> Option<Self> {
+        let resting = matches!(
+            order.order_status.as_str(),
+            "New" | "PartiallyFilled" | "Untriggered"
+        );
+        if !resting || order.leaves_qty <= 0.0 {
+            return None;
+        }
+        Some(Self {
+            filled_qty: order.cum_exec_qty,
+            created_at: order.created_time.parse().unwrap_or_default(),
+            ..Self::new(
+                order.order_id.clone(),
+                order.price,
+                order.leaves_qty,
+                matches!(order.side, Side::Buy),
+            )
+        })
+    }
+}
+
+impl BybitBook {
+    /// Drops every level of a crossed or diverged book and flags it until a snapshot
+    /// rebuilds it, deltas alone can't tell which of the old levels are still there.
+    fn clear_crossed(&mut self) {
+        self.asks.clear();
+        self.bids.clear();
+        self.best_ask = Ask {
+            price: 0.0,
+            qty: 0.0,
+        };
+        self.best_bid = Bid {
+            price: 0.0,
+            qty: 0.0,
+        };
+        self.mid_price = 0.0;
+        self.needs_snapshot = true;
+    }
+}
+
+/// Attempts at a market data request before giving up.
+const REST_ATTEMPTS: u32 = 3;
+
+/// Orderbook feeds subscribed by default, by their number of levels.
+pub const BOOK_DEPTHS: [usize; 3] = [1, 50, 200];
+
+/// Depths of the linear orderbook feeds deeper than the touch, shallowest first.
+const DEEP_BOOK_FEEDS: [usize; 3] = [50, 200, 500];
+
+/// The orderbook feeds to subscribe to for a strategy reading `depths` levels: the depth 1
+/// feed, the fastest at the touch, and the shallowest deeper feed covering the deepest of
+/// `depths`, or the deepest feed past it. An empty `depths` keeps the default feeds.
+pub fn book_depths(depths: &[usize]) -> Vec<usize> {
+    let Some(&deepest) = depths.iter().max() else {
+        return BOOK_DEPTHS.to_vec();
+    };
+    let feed = DEEP_BOOK_FEEDS
+        .into_iter()
+        .find(|&feed| feed >= deepest)
+        .unwrap_or(DEEP_BOOK_FEEDS[DEEP_BOOK_FEEDS.len() - 1]);
+    vec![1, feed]
+}
+
+/// Tells from the update ids of the orderbook feeds when a delta was missed.
+///
+/// Bybit numbers the messages of each orderbook topic with `u`: a snapshot starts the
+/// count over and every delta continues it by one. A delta skipping ids means one went
+/// missing and the book no longer matches the exchange's.
+#[derive(Debug, Clone, Default)]
+pub struct BookSequence {
+    // update id of the last message applied, by topic
+    update_ids: HashMap<String, u64>,
+    gaps: usize,
+}
+
+impl BookSequence {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of gaps found in the feeds.
+    pub fn gaps(&self) -> usize {
+        self.gaps
+    }
+
+    /// Returns whether to apply `ob`: snapshots and the deltas continuing their topic are,
+    /// replayed deltas and those of a topic without a snapshot yet aren't. `None` on a gap,
+    /// the topic then waits for a new snapshot.
+    pub fn accept(&mut self, ob: &OrderBookUpdate) -> Option<bool> {
+        let update_id = ob.data.update_id;
+        if ob.event_type == "snapshot" {
+            self.update_ids.insert(ob.topic.clone(), update_id);
+            return Some(true);
+        }
+        let Some(last) = self.update_ids.get_mut(&ob.topic) else {
+            return Some(false);
+        };
+        if update_id <= *last {
+            return Some(false);
+        }
+        if update_id != *last + 1 {
+            self.update_ids.remove(&ob.topic);
+            self.gaps += 1;
+            return None;
+        }
+        *last = update_id;
+        Some(true)
+    }
+}
+
 impl OrderBook for BybitBook {
     type Ask = Ask;
     type Bid = Bid;
@@ -567,6 +1105,8 @@ impl OrderBook for BybitBook {
             min_notional: 0.0,
             min_qty: 0.0,
             post_only_max: 0.0,
+            needs_snapshot: false,
+            decay_rate: DEFAULT_DECAY_RATE,
         }
     }
 
@@ -588,6 +1128,7 @@ impl OrderBook for BybitBook {
     fn reset(&mut self, asks: Vec<Self::Ask>, bids: Vec<Self::Bid>, timestamp: u64, sequence: u64) {
         self.last_update = timestamp;
         self.sequence = sequence;
+        self.needs_snapshot = false;
 
         for ask in asks.iter() {
             let price = OrderedFloat::from(ask.price);
@@ -629,9 +1170,9 @@ impl OrderBook for BybitBook {
         bids: Vec<Self::Bid>,
         timestamp: u64,
         sequence: u64,
-    ) {
+    ) -> bool {
         if timestamp <= self.last_update || sequence <= self.sequence {
-            return;
+            return false;
         }
 
         self.last_update = timestamp;
@@ -655,14 +1196,18 @@ impl OrderBook for BybitBook {
             })
             .map(|ask| ask.price);
 
+        let levels_before = self.bids.len() + self.asks.len();
+        let bba_before = (
+            self.best_bid.price,
+            self.best_bid.qty,
+            self.best_ask.price,
+            self.best_ask.qty,
+        );
+        let mut changed = false;
+
         // Update the bids in the order book
         for bid in bids.iter() {
-            let price = OrderedFloat::from(bid.price);
-            // Modify or insert the bid price and quantity into the bids HashMap
-            self.bids
-                .entry(price)
-                .and_modify(|qty| *qty = bid.qty)
-                .or_insert(bid.qty);
+            changed |= set_level(&mut self.bids, bid.price, bid.qty);
         }
         // Remove bids with prices higher than the current bid price
         if let Some(best_bid_price) = best_bid_price {
@@ -671,12 +1216,7 @@ impl OrderBook for BybitBook {
         }
 
         for ask in asks.iter() {
-            let price = OrderedFloat::from(ask.price);
-            // Modify or insert the ask price and quantity into the asks HashMap
-            self.asks
-                .entry(price)
-                .and_modify(|qty| *qty = ask.qty)
-                .or_insert(ask.qty);
+            changed |= set_level(&mut self.asks, ask.price, ask.qty);
         }
         // Remove asks with prices lower than the current ask price
         if let Some(best_ask_price) = best_ask_price {
@@ -718,6 +1258,27 @@ impl OrderBook for BybitBook {
 
         // Calculate the mid price
         self.set_mid_price();
+
+        // A crossed book gives a garbage mid, start over from the next snapshot
+        if self.best_bid.price > 0.0
+            && self.best_ask.price > 0.0
+            && self.best_bid.price >= self.best_ask.price
+        {
+            self.clear_crossed();
+            return true;
+        }
+
+        // Pruning changes the book even when every level of the delta was already known,
+        // and the first delta after a snapshot sets the BBA
+        changed
+            || self.bids.len() + self.asks.len() != levels_before
+            || bba_before
+                != (
+                    self.best_bid.price,
+                    self.best_bid.qty,
+                    self.best_ask.price,
+                    self.best_ask.qty,
+                )
     }
 
     /// Updates the order book with the given asks and bids at the given timestamp.
@@ -728,6 +1289,7 @@ impl OrderBook for BybitBook {
     /// The update is done in the following way:
     ///  - The asks and bids are iterated over and only the ones with a price higher than or equal
     ///    to the top ask threshold and lower than or equal to the top bid threshold are considered.
+    ///    With 0 `levels` every ask and bid is considered.
     ///  - The quantity of the asks and bids is updated in the order book.
     ///  - Any asks or bids with a quantity of 0 are removed from the order book.
     fn update(
@@ -736,52 +1298,55 @@ impl OrderBook for BybitBook {
         bids: Vec<Self::Bid>,
         timestamp: u64,
         levels: usize,
-    ) {
+    ) -> bool {
         if timestamp <= self.last_update {
-            return;
+            return false;
         }
         self.last_update = timestamp;
 
-        let top_ask_threshold = self
-            .asks
-            .iter()
-            .take(levels)
-            .map(|(price, _)| **price)
-            .last()
-            .unwrap_or(f64::MAX);
+        let top_ask_threshold = match levels {
+            0 => f64::MIN,
+            _ => self
+                .asks
+                .iter()
+                .take(levels)
+                .map(|(price, _)| **price)
+                .last()
+                .unwrap_or(f64::MAX),
+        };
 
-        let top_bid_threshold = self
-            .bids
-            .iter()
-            .rev()
-            .take(levels)
-            .map(|(price, _)| **price)
-            .last()
-            .unwrap_or(0.0);
+        let top_bid_threshold = match levels {
+            0 => f64::MAX,
+            _ => self
+                .bids
+                .iter()
+                .rev()
+                .take(levels)
+                .map(|(price, _)| **price)
+                .last()
+                .unwrap_or(0.0),
+        };
+
+        let levels_before = self.bids.len() + self.asks.len();
+        let mut changed = false;
 
         for ask in asks.iter() {
-            let price = OrderedFloat::from(ask.price);
-            if price >= OrderedFloat::from(top_ask_threshold) {
-                self.asks
-                    .entry(price)
-                    .and_modify(|qty| *qty = ask.qty)
-                    .or_insert(ask.qty);
+            if ask.price >= top_ask_threshold {
+                changed |= set_level(&mut self.asks, ask.price, ask.qty);
             }
         }
 
         // Update bids except top levels
         for bid in bids.iter() {
-            let price = OrderedFloat::from(bid.price);
-            if price <= OrderedFloat::from(top_bid_threshold) {
-                self.bids
-                    .entry(price)
-                    .and_modify(|qty| *qty = bid.qty)
-                    .or_insert(bid.qty);
+            if bid.price <= top_bid_threshold {
+                changed |= set_level(&mut self.bids, bid.price, bid.qty);
             }
         }
 
         self.asks.retain(|_, &mut v| v != 0.0);
         self.bids.retain(|_, &mut v| v != 0.0);
+
+        changed || self.bids.len() + self.asks.len() != levels_before
     }
 
     /// Sets the mid price of the order book.
@@ -791,6 +1356,15 @@ impl OrderBook for BybitBook {
         self.mid_price = (self.best_ask.price + self.best_bid.price) * 0.5;
     }
 
+    /// Returns `true` when the best bid is at or above the best ask, or while a book cleared
+    /// for being crossed waits for a snapshot.
+    fn is_crossed(&self) -> bool {
+        self.needs_snapshot
+            || (self.best_bid.price > 0.0
+                && self.best_ask.price > 0.0
+                && self.best_bid.price >= self.best_ask.price)
+    }
+
     /// Returns the mid price of the order book.
     ///
     /// The mid price is calculated as the average of the best ask and best bid prices.
@@ -928,8 +1502,8 @@ impl OrderBook for BybitBook {
             let (weighted_bid_qty, weighted_ask_qty) = if let Some(depth) = depth {
                 // Calculate the weighted bid quantity using the specified depth.
                 (
-                    self.calculate_weighted_bid(depth, Some(0.5)),
-                    self.calculate_weighted_ask(depth, Some(0.5)),
+                    self.calculate_weighted_bid(depth, Some(self.decay_rate)),
+                    self.calculate_weighted_ask(depth, Some(self.decay_rate)),
                 )
             } else {
                 (self.best_bid.qty, self.best_ask.qty)
@@ -981,8 +1555,8 @@ impl OrderBook for BybitBook {
     fn get_microprice(&self, depth: Option<usize>) -> f64 {
         let (bid_qty, ask_qty) = match depth {
             Some(depth) => (
-                self.calculate_weighted_bid(depth, Some(0.5)),
-                self.calculate_weighted_ask(depth, Some(0.5)),
+                self.calculate_weighted_bid(depth, Some(self.decay_rate)),
+                self.calculate_weighted_ask(depth, Some(self.decay_rate)),
             ),
             None => (self.best_bid.qty, self.best_ask.qty),
         };
@@ -996,6 +1570,26 @@ impl OrderBook for BybitBook {
         (self.best_ask.price * qty_ratio) + (self.best_bid.price * (1.0 - qty_ratio))
     }
 
+    /// Returns the average price of a market order of `qty`, walking the asks for a buy and
+    /// the bids for a sell, or `None` when the book doesn't hold enough qty to fill it.
+    fn expected_fill_price(&self, qty: f64, is_buy: bool) -> Option<f64> {
+        if is_buy {
+            sweep_price(self.asks.iter(), qty)
+        } else {
+            sweep_price(self.bids.iter().rev(), qty)
+        }
+    }
+
+    /// Returns the notional of the bids and of the asks within `ticks` ticks of the best
+    /// bid and ask, e.g. how much of an unwind each side can absorb nearby.
+    fn depth_notional(&self, ticks: usize) -> (f64, f64) {
+        let distance = ticks as f64 * self.tick_size;
+        (
+            notional_within(self.bids.iter().rev(), distance),
+            notional_within(self.asks.iter(), distance),
+        )
+    }
+
     /// Calculates the price impact of the difference between the order book and the old order book.
     ///
     /// The price impact is a measure of the change in the order book imbalance between the old and
@@ -1051,21 +1645,11 @@ impl OrderBook for BybitBook {
             }
         }
 
-        // Calculate the volume at the bid and ask offsets
-        let bid_impact = if curr_bid_price > old_bid_price || curr_bid_vol > old_bid_vol {
-            curr_bid_vol - old_bid_vol
-        } else if curr_bid_price < old_bid_price || curr_bid_vol < old_bid_vol {
-            curr_bid_vol - old_bid_vol
-        } else {
-            0.0
-        };
-        let ask_impact = if curr_ask_price < old_ask_price || curr_ask_vol > old_ask_vol {
-            curr_ask_vol - old_ask_vol
-        } else if curr_ask_price > old_ask_price || curr_ask_vol < old_ask_vol {
-            curr_ask_vol - old_ask_vol
-        } else {
-            0.0
-        };
+        // Signed change of each side, see `level_impact`
+        let bid_impact =
+            level_impact(old_bid_price, old_bid_vol, curr_bid_price, curr_bid_vol, true);
+        let ask_impact =
+            level_impact(old_ask_price, old_ask_vol, curr_ask_price, curr_ask_vol, false);
 
         // Return the sum of the bid and ask impacts
         bid_impact + ask_impact
@@ -1083,16 +1667,26 @@ impl OrderBook for BybitBook {
     /// that the bid side is stronger, while a negative imbalance ratio indicates that the ask side
     /// is stronger. An imbalance ratio of 0.0 indicates that the order book is balanced.
     ///
+    /// Ratios within `IMBALANCE_DEAD_ZONE` of zero read as balanced, see
+    /// `raw_imbalance_ratio` for the continuous value.
+    ///
     /// # Returns
     ///
     /// The imbalance ratio of the order book.
     fn imbalance_ratio(&self, depth: Option<usize>) -> f64 {
+        dead_zone(self.raw_imbalance_ratio(depth), IMBALANCE_DEAD_ZONE)
+    }
+
+    /// Calculates the imbalance ratio of the order book without the dead zone of
+    /// `imbalance_ratio`, a continuous value between -1.0 and 1.0 that is 0.0 for an empty
+    /// book.
+    fn raw_imbalance_ratio(&self, depth: Option<usize>) -> f64 {
         // Initialize the weighted bid and ask quantities to the quantities of the best bid and ask.
         let (weighted_bid_qty, weighted_ask_qty) = if let Some(depth) = depth {
             // Calculate the weighted bid quantity using the specified depth.
             (
-                self.calculate_weighted_bid(depth, Some(0.5)),
-                self.calculate_weighted_ask(depth, Some(0.5)),
+                self.calculate_weighted_bid(depth, Some(self.decay_rate)),
+                self.calculate_weighted_ask(depth, Some(self.decay_rate)),
             )
         } else {
             (self.best_bid.qty, self.best_ask.qty)
@@ -1105,12 +1699,11 @@ impl OrderBook for BybitBook {
         // Calculate the imbalance ratio by dividing the difference by the sum.
         let ratio = diff / sum;
 
-        // Return the imbalance ratio, checking for NaN and out-of-range values.
-        match ratio {
-            x if x.is_nan() => 0.0, // If NaN, return 0.
-            x if x > 0.20 => x,     // If positive and greater than 0.20, return the ratio.
-            x if x < -0.20 => x,    // If negative and less than -0.20, return the ratio.
-            _ => 0.0,               // Otherwise, return 0.
+        // An empty book has no imbalance.
+        if ratio.is_nan() {
+            0.0
+        } else {
+            ratio
         }
     }
 
@@ -1143,22 +1736,23 @@ impl OrderBook for BybitBook {
         let bid_ofi = {
             if self.best_bid.price > old_book.best_bid.price {
                 if let Some(depth) = depth {
-                    let weighted_bid = self.calculate_weighted_bid(depth, Some(0.5));
+                    let weighted_bid = self.calculate_weighted_bid(depth, Some(self.decay_rate));
                     weighted_bid
                 } else {
                     self.best_bid.qty
                 }
             } else if self.best_bid.price == old_book.best_bid.price {
                 if let Some(depth) = depth {
-                    let weighted_bid = self.calculate_weighted_bid(depth, Some(0.5));
-                    let prev_weighted_bid = old_book.calculate_weighted_bid(depth, Some(0.5));
+                    let weighted_bid = self.calculate_weighted_bid(depth, Some(self.decay_rate));
+                    let prev_weighted_bid =
+                        old_book.calculate_weighted_bid(depth, Some(self.decay_rate));
                     weighted_bid - prev_weighted_bid
                 } else {
                     self.best_bid.qty - old_book.best_bid.qty
                 }
             } else {
                 if let Some(depth) = depth {
-                    let weighted_bid = self.calculate_weighted_bid(depth, Some(0.5));
+                    let weighted_bid = self.calculate_weighted_bid(depth, Some(self.decay_rate));
                     -weighted_bid
                 } else {
                     -self.best_bid.qty
@@ -1168,22 +1762,23 @@ impl OrderBook for BybitBook {
         let ask_ofi = {
             if self.best_ask.price < old_book.best_ask.price {
                 if let Some(depth) = depth {
-                    let weighted_ask = self.calculate_weighted_ask(depth, Some(0.5));
+                    let weighted_ask = self.calculate_weighted_ask(depth, Some(self.decay_rate));
                     -weighted_ask
                 } else {
                     -self.best_ask.qty
                 }
             } else if self.best_ask.price == old_book.best_ask.price {
                 if let Some(depth) = depth {
-                    let weighted_ask = self.calculate_weighted_ask(depth, Some(0.5));
-                    let prev_weighted_ask = old_book.calculate_weighted_ask(depth, Some(0.5));
+                    let weighted_ask = self.calculate_weighted_ask(depth, Some(self.decay_rate));
+                    let prev_weighted_ask =
+                        old_book.calculate_weighted_ask(depth, Some(self.decay_rate));
                     prev_weighted_ask - weighted_ask
                 } else {
                     old_book.best_ask.qty - self.best_ask.qty
                 }
             } else {
                 if let Some(depth) = depth {
-                    let weighted_ask = self.calculate_weighted_ask(depth, Some(0.5));
+                    let weighted_ask = self.calculate_weighted_ask(depth, Some(self.decay_rate));
                     weighted_ask
                 } else {
                     self.best_ask.qty
@@ -1220,8 +1815,9 @@ impl OrderBook for BybitBook {
             x if x < old_book.best_bid.price => 0.0,
             x if x == old_book.best_bid.price => {
                 if let Some(depth) = depth {
-                    let curr_bid_qty = self.calculate_weighted_bid(depth, Some(0.5));
-                    let prev_bid_qty = old_book.calculate_weighted_bid(depth, Some(0.5));
+                    let curr_bid_qty = self.calculate_weighted_bid(depth, Some(self.decay_rate));
+                    let prev_bid_qty =
+                        old_book.calculate_weighted_bid(depth, Some(self.decay_rate));
                     curr_bid_qty - prev_bid_qty
                 } else {
                     self.best_bid.qty - old_book.best_bid.qty
@@ -1229,7 +1825,7 @@ impl OrderBook for BybitBook {
             }
             x if x > old_book.best_bid.price => {
                 if let Some(depth) = depth {
-                    let curr_bid = self.calculate_weighted_bid(depth, Some(0.5));
+                    let curr_bid = self.calculate_weighted_bid(depth, Some(self.decay_rate));
                     curr_bid
                 } else {
                     self.best_bid.qty
@@ -1242,7 +1838,7 @@ impl OrderBook for BybitBook {
         let ask_v = match self.best_ask.price {
             x if x < old_book.best_ask.price => {
                 if let Some(depth) = depth {
-                    let curr_ask = self.calculate_weighted_ask(depth, Some(0.5));
+                    let curr_ask = self.calculate_weighted_ask(depth, Some(self.decay_rate));
                     curr_ask
                 } else {
                     self.best_ask.qty
@@ -1250,8 +1846,9 @@ impl OrderBook for BybitBook {
             }
             x if x == old_book.best_ask.price => {
                 if let Some(depth) = depth {
-                    let curr_ask_qty = self.calculate_weighted_ask(depth, Some(0.5));
-                    let prev_ask_qty = old_book.calculate_weighted_ask(depth, Some(0.5));
+                    let curr_ask_qty = self.calculate_weighted_ask(depth, Some(self.decay_rate));
+                    let prev_ask_qty =
+                        old_book.calculate_weighted_ask(depth, Some(self.decay_rate));
                     curr_ask_qty - prev_ask_qty
                 } else {
                     self.best_ask.qty - old_book.best_ask.qty
@@ -1283,12 +1880,13 @@ impl OrderBook for BybitBook {
     ///
     /// The weighted ask quantity of the order book.
     fn calculate_weighted_ask(&self, depth: usize, decay_rate: Option<f64>) -> f64 {
-        self.asks
-            .iter()
-            .take(depth)
-            .enumerate()
-            .map(|(i, (_, qty))| (decay(i as f64, decay_rate) * qty) as f64)
-            .sum::<f64>()
+        kahan_sum(
+            self.asks
+                .iter()
+                .take(depth)
+                .enumerate()
+                .map(|(i, (_, qty))| (decay(i as f64, decay_rate) * qty) as f64),
+        )
     }
 
     /// Calculates the weighted bid quantity of the order book.
@@ -1308,13 +1906,14 @@ impl OrderBook for BybitBook {
     ///
     /// The weighted bid quantity of the order book.
     fn calculate_weighted_bid(&self, depth: usize, decay_rate: Option<f64>) -> f64 {
-        self.bids
-            .iter()
-            .rev()
-            .take(depth)
-            .enumerate()
-            .map(|(i, (_, qty))| (decay(i as f64, decay_rate) * qty) as f64)
-            .sum::<f64>()
+        kahan_sum(
+            self.bids
+                .iter()
+                .rev()
+                .take(depth)
+                .enumerate()
+                .map(|(i, (_, qty))| (decay(i as f64, decay_rate) * qty) as f64),
+        )
     }
 }
 
@@ -1322,39 +1921,65 @@ impl OrderBook for BybitBook {
 ///
 /// The subscriptions that are built are:
 ///
-/// - Orderbook with 1, 50, and 200 levels for each symbol
+/// - Orderbook with each of `depths` levels for each symbol
 /// - The ticker for each symbol
 /// - The public trades for each symbol
 ///
 /// # Arguments
 ///
 /// * `symbol` - A vector of strings representing the symbols to subscribe to.
+/// * `depths` - The orderbook feeds to subscribe to, by their number of levels.
 ///
 /// # Returns
 ///
 /// A vector of strings representing the subscriptions to make.
-fn build_request(symbols: &[String]) -> Vec<String> {
+pub fn build_request(symbols: &[String], depths: &[usize]) -> Vec<String> {
     symbols
-        .into_iter()
+        .iter()
         .flat_map(|s| {
-            vec![
-                format!("orderbook.1.{s}"),
-                format!("orderbook.50.{s}"),
-                format!("orderbook.200.{s}"),
-                format!("tickers.{s}"),
-                format!("publicTrade.{s}"),
-            ]
+            depths
+                .iter()
+                .map(move |depth| format!("orderbook.{depth}.{s}"))
+                .chain([format!("tickers.{s}"), format!("publicTrade.{s}")])
         })
         .collect()
 }
 
-fn handle_websocket_event(market_data: &mut BybitMarket, event: WebsocketEvents) {
+/// Applies a public event to the market data, returns `false` when it left it unchanged.
+/// `depths` are the subscribed orderbook feeds and `sequence` their update ids.
+///
+/// Errors once a book loses sync with the exchange's and needs a snapshot. Bybit only
+/// sends snapshots on subscribing, so the connection has to start over.
+fn handle_websocket_event(
+    market_data: &mut BybitMarket,
+    sequence: &mut BookSequence,
+    event: WebsocketEvents,
+    depths: &[usize],
+) -> Result<bool> {
     match event {
-        WebsocketEvents::OrderBookEvent(ob) => process_orderbook_event(market_data, ob),
+        WebsocketEvents::OrderBookEvent(ob) => {
+            let symbol = ob.topic.split('.').nth(2).unwrap_or_default().to_string();
+            let synced = |market_data: &BybitMarket| {
+                market_data
+                    .books
+                    .get(&symbol)
+                    .is_some_and(|book| !book.needs_snapshot)
+            };
+            let was_synced = synced(market_data);
+            let changed = process_orderbook_event(market_data, sequence, ob, depths);
+            if was_synced && !synced(market_data) {
+                return Err(BybitError::Base(format!(
+                    "Orderbook of {} lost sync, resubscribing for a snapshot",
+                    symbol
+                )));
+            }
+            return Ok(changed);
+        }
         WebsocketEvents::TickerEvent(ticker) => process_ticker_event(market_data, ticker),
         WebsocketEvents::TradeEvent(data) => process_trade_update(market_data, data),
         _ => (),
     }
+    Ok(true)
 }
 
 fn handle_private_websocket_event(private_data: &mut BybitPrivate, event: WebsocketEvents) {
@@ -1382,32 +2007,60 @@ fn handle_private_websocket_event(private_data: &mut BybitPrivate, event: Websoc
 ///
 /// The function does nothing if the event is not an order book event, or if the symbol is
 /// not found in the `BybitMarket` struct.
-fn process_orderbook_event(market_data: &mut BybitMarket, ob: OrderBookUpdate) {
+///
+/// Only the updates `sequence` accepts are applied. On a gap in the update ids a delta was
+/// dropped: the book is cleared and flagged like a crossed one, pausing quoting until a
+/// snapshot rebuilds it.
+///
+/// Returns whether the order book changed, a delta re-sending the current levels doesn't.
+fn process_orderbook_event(
+    market_data: &mut BybitMarket,
+    sequence: &mut BookSequence,
+    ob: OrderBookUpdate,
+    depths: &[usize],
+) -> bool {
     let symbol = ob.topic.split('.').nth(2).unwrap_or_default();
 
     if let Some(book) = market_data.books.get_mut(symbol) {
+        match sequence.accept(&ob) {
+            Some(true) => {}
+            Some(false) => return false,
+            None => {
+                book.clear_crossed();
+                return true;
+            }
+        }
         market_data.timestamp = ob.timestamp;
         match ob.event_type.as_str() {
-            "snapshot" => book.reset(
-                ob.data.asks.clone(),
-                ob.data.bids.clone(),
-                ob.timestamp,
-                ob.cts,
-            ),
-            "delta" => match ob.topic.split('.').nth(1) {
-                Some("1") => book.update_bba(
+            "snapshot" => {
+                book.reset(
+                    ob.data.asks.clone(),
+                    ob.data.bids.clone(),
+                    ob.timestamp,
+                    ob.cts,
+                );
+                true
+            }
+            "delta" => match ob.topic.split('.').nth(1).and_then(|d| d.parse::<usize>().ok()) {
+                Some(1) => book.update_bba(
                     ob.data.asks.clone(),
                     ob.data.bids.clone(),
                     ob.timestamp,
                     ob.cts,
                 ),
-                Some("50") => {
-                    book.update(ob.data.asks.clone(), ob.data.bids.clone(), ob.timestamp, 1)
+                // Leaves the levels of the next shallower feed to it, it updates them faster
+                depth => {
+                    let levels = depth
+                        .and_then(|depth| depths.iter().filter(|&&d| d < depth).max())
+                        .copied()
+                        .unwrap_or(0);
+                    book.update(ob.data.asks.clone(), ob.data.bids.clone(), ob.timestamp, levels)
                 }
-                _ => book.update(ob.data.asks.clone(), ob.data.bids.clone(), ob.timestamp, 50),
             },
-            _ => (),
+            _ => false,
         }
+    } else {
+        false
     }
 }
 
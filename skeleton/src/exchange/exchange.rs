@@ -1,8 +1,19 @@
-use bybit::model::WsTrade;
-use std::{collections::VecDeque, future::Future};
-use tokio::sync::mpsc::UnboundedSender;
+pub use bybit::model::WsTrade;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use tokio::sync::{
+    mpsc::{self, UnboundedSender},
+    watch,
+};
+use tokio_stream::{wrappers::UnboundedReceiverStream, Stream};
 
-use crate::utils::models::{BatchAmend, BatchOrder, BinanceMarket, BybitMarket};
+use crate::utils::models::{BatchAmend, BatchOrder, BinanceMarket, BybitMarket, TimeInForce};
 
 pub trait Exchange {
     type TimeOutput;
@@ -17,9 +28,15 @@ pub trait Exchange {
     type AmendOrderOutput;
     type CancelOrderOutput;
     type CancelAllOutput;
+    type OpenOrdersOutput;
+    type PositionOutput;
     type BatchOrdersOutput;
     type BatchAmendsOutput;
     type SymbolInformationOutput;
+    type KlinesOutput;
+    type FundingRateOutput;
+    type WalletBalanceOutput;
+    type RecentTradesOutput;
 
     fn init(api_key: String, api_secret: String) -> impl Future<Output = Self>;
     fn time(&self) -> impl Future<Output = Self::TimeOutput>;
@@ -36,6 +53,8 @@ pub trait Exchange {
         price: f64,
         qty: f64,
         is_buy: bool,
+        reduce_only: bool,
+        time_in_force: TimeInForce,
     ) -> impl Future<Output = Self::PlaceOrderOutput>;
     fn amend_order(
         &self,
@@ -43,6 +62,7 @@ pub trait Exchange {
         price: f64,
         qty: f64,
         symbol: &str,
+        is_buy: bool,
     ) -> impl Future<Output = Self::AmendOrderOutput>;
     fn cancel_order(
         &self,
@@ -50,6 +70,8 @@ pub trait Exchange {
         symbol: &str,
     ) -> impl Future<Output = Self::CancelOrderOutput>;
     fn cancel_all(&self, symbol: &str) -> impl Future<Output = Self::CancelAllOutput>;
+    fn get_open_orders(&self, symbol: &str) -> impl Future<Output = Self::OpenOrdersOutput>;
+    fn get_position(&self, symbol: &str) -> impl Future<Output = Self::PositionOutput>;
     fn batch_orders(
         &self,
         orders: Vec<BatchOrder>,
@@ -59,16 +81,182 @@ pub trait Exchange {
         orders: Vec<BatchAmend>,
     ) -> impl Future<Output = Self::BatchAmendsOutput>;
     fn get_symbol_info(&self, symbol: &str) -> impl Future<Output = Self::SymbolInformationOutput>;
+    /// The latest `limit` candles of `symbol` in the venue's `interval` notation, oldest first.
+    fn get_klines(
+        &self,
+        symbol: &str,
+        interval: &str,
+        limit: u16,
+    ) -> impl Future<Output = Self::KlinesOutput>;
+    /// The latest `limit` public trades of `symbol` as stream trades, oldest first.
+    fn get_recent_trades(
+        &self,
+        symbol: &str,
+        limit: u16,
+    ) -> impl Future<Output = Self::RecentTradesOutput>;
+    /// Latest funding rate of `symbol` as a fraction per funding interval. Positive when
+    /// longs pay shorts.
+    fn get_funding_rate(&self, symbol: &str) -> impl Future<Output = Self::FundingRateOutput>;
+    /// Equity of `coin` in the account, its wallet balance plus the unrealized PnL.
+    fn get_wallet_balance(&self, coin: &str) -> impl Future<Output = Self::WalletBalanceOutput>;
     fn market_subscribe(
         &self,
         symbols: Vec<String>,
         sender: UnboundedSender<Self::StreamData>,
+        shutdown: watch::Receiver<bool>,
     ) -> impl Future<Output = Self::StreamOutput>;
     fn private_subscribe(
         &self,
         symbol: String,
         sender: UnboundedSender<Self::PrivateStreamData>,
+        shutdown: watch::Receiver<bool>,
     ) -> impl Future<Output = Self::PrivateStreamOutput>;
+    /// Market data of `symbols` as a stream, an alternative to `market_subscribe` for
+    /// consumers that would rather `.next().await` than own a channel and a shutdown
+    /// signal. Dropping the stream ends the subscription.
+    fn subscribe(&self, symbols: Vec<String>) -> impl Stream<Item = MarketData> + Send + Unpin;
+}
+
+/// Runs `request` up to `attempts` times, waiting 600ms after the first failure and
+/// doubling the wait after each one. Returns the last error when every attempt fails.
+pub async fn retry_with_backoff<T, E, F, Fut>(attempts: u32, mut request: F) -> Result<T, E>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut backoff = 600;
+    let mut attempt = 1;
+    loop {
+        match request().await {
+            Err(_) if attempt < attempts => {
+                tokio::time::sleep(Duration::from_millis(backoff)).await;
+                backoff *= 2;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Resolves once `true` is sent on the shutdown channel. Never resolves if the sender is
+/// dropped first, since shutdown can no longer be requested.
+pub async fn wait_for_shutdown(shutdown: &mut watch::Receiver<bool>) {
+    if shutdown.wait_for(|stop| *stop).await.is_err() {
+        std::future::pending::<()>().await;
+    }
+}
+
+/// Default time without any message after which a websocket stream is reconnected.
+pub const STREAM_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Time of the last message received on a websocket stream, shared by the stream's
+/// handler and the watchdog racing the stream.
+///
+/// A connection can die without closing, leaving the subscription waiting for messages
+/// forever while the strategy quotes on the last data it got. Racing the subscription
+/// against `stale` drops such a connection so it can be reopened.
+#[derive(Debug, Clone)]
+pub struct Heartbeat(Arc<Mutex<Beats>>);
+
+#[derive(Debug)]
+struct Beats {
+    last: Instant,
+    // whether a message arrived since the connection was opened
+    received: bool,
+}
+
+impl Heartbeat {
+    pub fn new() -> Self {
+        Self(Arc::new(Mutex::new(Beats {
+            last: Instant::now(),
+            received: false,
+        })))
+    }
+
+    /// Records a message received now.
+    pub fn beat(&self) {
+        let mut beats = self.0.lock().unwrap();
+        beats.last = Instant::now();
+        beats.received = true;
+    }
+
+    /// Starts the count for a new connection, which gets the full timeout to deliver its
+    /// first message.
+    pub fn rearm(&self) {
+        let mut beats = self.0.lock().unwrap();
+        beats.last = Instant::now();
+        beats.received = false;
+    }
+
+    /// Whether a message arrived since the last `rearm`, i.e. the connection worked and
+    /// its reconnect doesn't need to back off further.
+    pub fn received(&self) -> bool {
+        self.0.lock().unwrap().received
+    }
+
+    /// Time since the last message, or since the heartbeat was created or rearmed before any.
+    pub fn elapsed(&self) -> Duration {
+        self.0.lock().unwrap().last.elapsed()
+    }
+
+    /// Resolves once no message was received for `timeout`.
+    pub async fn stale(&self, timeout: Duration) {
+        loop {
+            let idle = self.elapsed();
+            if idle >= timeout {
+                return;
+            }
+            tokio::time::sleep(timeout - idle).await;
+        }
+    }
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A subscription running in its own task, read as a stream of what it sends.
+///
+/// Wraps the channel and shutdown signal `market_subscribe` and `private_subscribe` take:
+/// the subscription sends into the channel the stream reads, and dropping the stream
+/// requests its shutdown.
+#[derive(Debug)]
+pub struct Subscription<T> {
+    receiver: UnboundedReceiverStream<T>,
+    shutdown: watch::Sender<bool>,
+}
+
+impl<T: Send + 'static> Subscription<T> {
+    /// Spawns the subscription `subscribe` returns for a sender and a shutdown receiver.
+    pub fn spawn<F, Fut>(subscribe: F) -> Self
+    where
+        F: FnOnce(UnboundedSender<T>, watch::Receiver<bool>) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let (shutdown, shutdown_receiver) = watch::channel(false);
+        tokio::spawn(subscribe(sender, shutdown_receiver));
+        Self {
+            receiver: UnboundedReceiverStream::new(receiver),
+            shutdown,
+        }
+    }
+}
+
+impl<T> Stream for Subscription<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        Pin::new(&mut self.receiver).poll_next(cx)
+    }
+}
+
+impl<T> Drop for Subscription<T> {
+    fn drop(&mut self) {
+        let _ = self.shutdown.send(true);
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -77,6 +265,68 @@ pub enum MarketData {
     Binance(BinanceMarket),
 }
 
+/// Best bid and best ask of a symbol across every venue, with the venue quoting each.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConsolidatedBBA {
+    pub bid: f64,
+    pub bid_venue: &'static str,
+    pub ask: f64,
+    pub ask_venue: &'static str,
+}
+
+impl MarketData {
+    pub fn venue(&self) -> &'static str {
+        match self {
+            MarketData::Bybit(_) => "bybit",
+            MarketData::Binance(_) => "binance",
+        }
+    }
+
+    /// Returns the (best bid, best ask) of every symbol with a book on this venue.
+    pub fn best_prices(&self) -> BTreeMap<String, (f64, f64)> {
+        match self {
+            MarketData::Bybit(market) => market
+                .books
+                .iter()
+                .map(|(symbol, book)| (symbol.clone(), (book.best_bid.price, book.best_ask.price)))
+                .collect(),
+            MarketData::Binance(market) => market
+                .books
+                .iter()
+                .map(|(symbol, book)| (symbol.clone(), (book.best_bid.price, book.best_ask.price)))
+                .collect(),
+        }
+    }
+
+    /// Builds the consolidated BBA per symbol: the highest bid and the lowest ask across `markets`.
+    ///
+    /// A symbol quoted on a single venue takes both sides from it, and empty sides (price of 0.0)
+    /// are skipped so a book that hasn't received data yet doesn't win the best ask.
+    pub fn consolidated_bba(markets: &[MarketData]) -> BTreeMap<String, ConsolidatedBBA> {
+        let mut consolidated: BTreeMap<String, ConsolidatedBBA> = BTreeMap::new();
+        for market in markets {
+            let venue = market.venue();
+            for (symbol, (bid, ask)) in market.best_prices() {
+                let entry = consolidated.entry(symbol).or_insert(ConsolidatedBBA {
+                    bid: 0.0,
+                    bid_venue: venue,
+                    ask: 0.0,
+                    ask_venue: venue,
+                });
+                if bid > 0.0 && bid > entry.bid {
+                    entry.bid = bid;
+                    entry.bid_venue = venue;
+                }
+                if ask > 0.0 && (entry.ask <= 0.0 || ask < entry.ask) {
+                    entry.ask = ask;
+                    entry.ask_venue = venue;
+                }
+            }
+        }
+        consolidated
+    }
+}
+
 // #[derive(Debug, Clone)]
 // pub enum TradeType {
 //     Bybit(VecDeque<WsTrade>),
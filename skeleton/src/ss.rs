@@ -1,19 +1,98 @@
-use std::{collections::BTreeMap, sync::Arc, vec};
+use std::{collections::BTreeMap, vec};
 
-use tokio::sync::{mpsc, Mutex};
+use tokio::sync::{mpsc, watch};
 
 use crate::{
-    exchange::exchange::{Exchange, MarketData},
-    utils::models::{BinanceClient, BinanceMarket, BybitClient, BybitMarket, BybitPrivate},
+    exchange::exchange::{wait_for_shutdown, ConsolidatedBBA, Exchange, MarketData},
+    utils::{
+        metrics,
+        models::{
+            BinanceClient, BinanceMarket, BinancePrivate, BybitClient, BybitMarket, BybitPrivate,
+        },
+        recorder::Recorder,
+        time::generate_timestamp,
+    },
 };
 
+/// A single change to the shared state, merged by the consumer with `SharedState::apply`.
+#[derive(Debug, Clone)]
+pub enum StateUpdate {
+    BybitMarket(BybitMarket),
+    BinanceMarket(BinanceMarket),
+    BybitPrivate(String, BybitPrivate),
+    BinancePrivate(String, BinancePrivate),
+}
+
+impl StateUpdate {
+    fn is_market(&self) -> bool {
+        matches!(
+            self,
+            StateUpdate::BybitMarket(_) | StateUpdate::BinanceMarket(_)
+        )
+    }
+
+    /// Stream the update came from, as labelled in the metrics.
+    fn stream(&self) -> &'static str {
+        match self {
+            StateUpdate::BybitMarket(_) => "bybit_market",
+            StateUpdate::BinanceMarket(_) => "binance_market",
+            StateUpdate::BybitPrivate(..) => "bybit_private",
+            StateUpdate::BinancePrivate(..) => "binance_private",
+        }
+    }
+}
+
+/// Stamps the stream's last update time for the staleness metric and records the update
+/// when a recorder is set.
+fn observe(update: &StateUpdate, recorder: Option<&Recorder>) {
+    let now = generate_timestamp().unwrap_or_default();
+    metrics::set(
+        metrics::LAST_UPDATE_MS,
+        &[("stream", update.stream())],
+        now as f64,
+    );
+    if let Some(recorder) = recorder {
+        recorder.record(update);
+    }
+}
+
+/// Where the loaders publish each state update.
+enum StateSender {
+    Unbounded(mpsc::UnboundedSender<StateUpdate>),
+    Bounded(mpsc::Sender<StateUpdate>),
+}
+
+impl StateSender {
+    /// Publishes `update`, returns false once the receiver is gone.
+    ///
+    /// A full bounded channel skips market updates, each one carries the venue's whole
+    /// market so the next that fits brings the consumer up to date. Private updates are
+    /// rare and wait for room instead, so no fill or order change is lost.
+    async fn send(&self, update: StateUpdate) -> bool {
+        match self {
+            StateSender::Unbounded(sender) => sender.send(update).is_ok(),
+            StateSender::Bounded(sender) if update.is_market() => match sender.try_send(update) {
+                Ok(()) | Err(mpsc::error::TrySendError::Full(_)) => true,
+                Err(mpsc::error::TrySendError::Closed(_)) => false,
+            },
+            StateSender::Bounded(sender) => sender.send(update).await.is_ok(),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct SharedState {
     pub exchange: String,
     pub clients: BTreeMap<String, BybitClient>,
     pub privates: BTreeMap<String, BybitPrivate>,
+    pub binance_clients: BTreeMap<String, BinanceClient>,
+    pub binance_privates: BTreeMap<String, BinancePrivate>,
     pub markets: Vec<MarketData>,
     pub symbols: Vec<String>,
+    /// Records the updates streamed by the loaders when set
+    pub recorder: Option<Recorder>,
+    /// Decay rate given to the books of the applied market updates, `None` keeps theirs
+    pub book_decay_rate: Option<f64>,
 }
 
 impl SharedState {
@@ -22,11 +101,15 @@ impl SharedState {
             exchange,
             clients: BTreeMap::new(),
             privates: BTreeMap::new(),
+            binance_clients: BTreeMap::new(),
+            binance_privates: BTreeMap::new(),
             markets: vec![
                 MarketData::Bybit(BybitMarket::default()),
                 MarketData::Binance(BinanceMarket::default()),
             ],
             symbols: Vec::new(),
+            recorder: None,
+            book_decay_rate: None,
         }
     }
 
@@ -38,20 +121,158 @@ impl SharedState {
             .or_insert(BybitPrivate::default());
     }
 
-    pub async fn load_data(state: SharedState, state_sender: mpsc::UnboundedSender<SharedState>) {
+    pub fn add_binance_clients(&mut self, symbol: String, client: BinanceClient) {
+        if !self.symbols.contains(&symbol) {
+            self.symbols.push(symbol.clone());
+        }
+        self.binance_clients.insert(symbol.clone(), client);
+        self.binance_privates.entry(symbol).or_default();
+    }
+
+    /// Records every update the loaders stream from now on.
+    pub fn set_recorder(&mut self, recorder: Recorder) {
+        self.recorder = Some(recorder);
+    }
+
+    /// Decay of the level weights the books' depth weighted analytics use from the next
+    /// applied market update, 0.0 keeps the default of 0.5. Higher rates weigh the levels
+    /// near the touch more.
+    pub fn set_book_decay_rate(&mut self, rate: f64) {
+        self.book_decay_rate = (rate > 0.0 && rate.is_finite()).then_some(rate);
+    }
+
+    /// Merges an update from the loaders into this state.
+    pub fn apply(&mut self, update: StateUpdate) {
+        match update {
+            StateUpdate::BybitMarket(mut market) => {
+                if let Some(rate) = self.book_decay_rate {
+                    market
+                        .books
+                        .values_mut()
+                        .for_each(|book| book.decay_rate = rate);
+                }
+                self.markets[0] = MarketData::Bybit(market);
+            }
+            StateUpdate::BinanceMarket(mut market) => {
+                if let Some(rate) = self.book_decay_rate {
+                    market
+                        .books
+                        .values_mut()
+                        .for_each(|book| book.decay_rate = rate);
+                }
+                self.markets[1] = MarketData::Binance(market);
+            }
+            StateUpdate::BybitPrivate(symbol, private) => {
+                self.privates.insert(symbol, private);
+            }
+            StateUpdate::BinancePrivate(symbol, private) => {
+                self.binance_privates.insert(symbol, private);
+            }
+        }
+    }
+
+    /// Returns the best bid and best ask of each symbol across all the loaded venues.
+    pub fn consolidated_bba(&self) -> BTreeMap<String, ConsolidatedBBA> {
+        MarketData::consolidated_bba(&self.markets)
+    }
+
+    /// Streams every market and private update to `state_sender` until `true` is sent on
+    /// `shutdown`, which also stops the subscriptions.
+    ///
+    /// Only the update is sent, the consumer keeps its own copy of the state and merges
+    /// them with `apply`. A consumer slower than the market lets the queue grow without
+    /// limit, prefer `load_data_bounded` when that is a concern.
+    pub async fn load_data(
+        state: SharedState,
+        state_sender: mpsc::UnboundedSender<StateUpdate>,
+        shutdown: watch::Receiver<bool>,
+    ) {
+        Self::load(state, StateSender::Unbounded(state_sender), shutdown).await
+    }
+
+    /// Streams the updates to a bounded `state_sender`, skipping market updates while it
+    /// is full.
+    ///
+    /// Memory stays capped at the channel capacity and the market streams never wait on
+    /// the consumer, at the cost of the consumer missing intermediate market updates when
+    /// it falls behind. It still sees the latest market on the next update that fits.
+    pub async fn load_data_bounded(
+        state: SharedState,
+        state_sender: mpsc::Sender<StateUpdate>,
+        shutdown: watch::Receiver<bool>,
+    ) {
+        Self::load(state, StateSender::Bounded(state_sender), shutdown).await
+    }
+
+    async fn load(state: SharedState, state_sender: StateSender, shutdown: watch::Receiver<bool>) {
         match state.exchange.as_str() {
-            "bybit" => Self::load_bybit(state, state_sender).await,
-            "binance" => Self::load_binance(state, state_sender).await,
-            "both" => Self::load_both(state, state_sender).await,
+            "bybit" => Self::load_bybit(state, state_sender, shutdown).await,
+            "binance" => Self::load_binance(state, state_sender, shutdown).await,
+            "both" => Self::load_both(state, state_sender, shutdown).await,
             _ => panic!("Invalid exchange"),
         }
     }
 
-    async fn load_binance(_state: SharedState, _state_sender: mpsc::UnboundedSender<SharedState>) {
-        unimplemented!("Binance not implemented");
+    async fn load_binance(
+        state: SharedState,
+        state_sender: StateSender,
+        mut shutdown: watch::Receiver<bool>,
+    ) {
+        let recorder = state.recorder.clone();
+        let symbols = state.symbols.clone();
+
+        let (binance_market_sender, mut binance_market_receiver) =
+            mpsc::unbounded_channel::<BinanceMarket>();
+        let (binance_private_sender, mut binance_private_receiver) =
+            mpsc::unbounded_channel::<(String, BinancePrivate)>();
+
+        // The public market stream takes the stream settings of the symbols' clients
+        let binance_market_client = state.binance_clients.values().next().cloned();
+        for (symbol, client) in state.binance_clients {
+            let private_clone = binance_private_sender.clone();
+            let shutdown = shutdown.clone();
+            tokio::spawn(async move {
+                client
+                    .private_subscribe(symbol, private_clone, shutdown)
+                    .await;
+            });
+        }
+        // Only the subscriptions hold senders, so a stream that gives up closes its channel
+        drop(binance_private_sender);
+
+        let market_shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            let market_stream = match binance_market_client {
+                Some(client) => client,
+                None => BinanceClient::init("".to_string(), "".to_string()).await,
+            };
+            market_stream
+                .market_subscribe(symbols, binance_market_sender, market_shutdown)
+                .await;
+        });
+
+        loop {
+            let update = tokio::select! {
+                Some(data) = binance_market_receiver.recv() => StateUpdate::BinanceMarket(data),
+                Some(data) = binance_private_receiver.recv() => {
+                    StateUpdate::BinancePrivate(data.0, data.1)
+                }
+                _ = wait_for_shutdown(&mut shutdown) => break,
+                else => break,
+            };
+            observe(&update, recorder.as_ref());
+            if !state_sender.send(update).await {
+                break;
+            }
+        }
     }
 
-    async fn load_bybit(state: SharedState, state_sender: mpsc::UnboundedSender<SharedState>) {
+    async fn load_bybit(
+        state: SharedState,
+        state_sender: StateSender,
+        mut shutdown: watch::Receiver<bool>,
+    ) {
+        let recorder = state.recorder.clone();
         let symbols = state.symbols.clone();
 
         let (bybit_market_sender, mut bybit_market_receiver) =
@@ -59,87 +280,121 @@ impl SharedState {
         let (bybit_private_sender, mut bybit_private_receiver) =
             mpsc::unbounded_channel::<(String, BybitPrivate)>();
 
-        for (symbol, client) in state.clients.clone() {
+        // The public market stream takes the stream settings of the symbols' clients
+        let market_client = state.clients.values().next().cloned();
+        for (symbol, client) in state.clients {
             let private_clone = bybit_private_sender.clone();
+            let shutdown = shutdown.clone();
             tokio::spawn(async move {
-                client.private_subscribe(symbol, private_clone).await;
+                client
+                    .private_subscribe(symbol, private_clone, shutdown)
+                    .await;
             });
         }
+        let market_shutdown = shutdown.clone();
         tokio::spawn(async move {
-            let market_stream = BybitClient::init("".to_string(), "".to_string()).await;
+            let market_stream = match market_client {
+                Some(client) => client,
+                None => BybitClient::init("".to_string(), "".to_string()).await,
+            };
             market_stream
-                .market_subscribe(symbols, bybit_market_sender)
+                .market_subscribe(symbols, bybit_market_sender, market_shutdown)
                 .await;
         });
-        let state = Arc::new(Mutex::new(state.clone()));
 
         loop {
-            tokio::select! {
-            Some(data) = bybit_market_receiver.recv() => {
-                let mut state = state.lock().await;
-                state.markets[0] = MarketData::Bybit(data);
-                state_sender.send(state.clone()).unwrap();
-                }
-            Some(data) = bybit_private_receiver.recv() => {
-                let mut state = state.lock().await;
-                state.privates.insert(data.0, data.1);
-                state_sender.send(state.clone()).unwrap();
+            let update = tokio::select! {
+                Some(data) = bybit_market_receiver.recv() => StateUpdate::BybitMarket(data),
+                Some(data) = bybit_private_receiver.recv() => {
+                    StateUpdate::BybitPrivate(data.0, data.1)
                 }
+                _ = wait_for_shutdown(&mut shutdown) => break,
+            };
+            observe(&update, recorder.as_ref());
+            if !state_sender.send(update).await {
+                break;
             }
         }
     }
 
-    async fn load_both(state: SharedState, state_sender: mpsc::UnboundedSender<SharedState>) {
+    async fn load_both(
+        state: SharedState,
+        state_sender: StateSender,
+        mut shutdown: watch::Receiver<bool>,
+    ) {
+        let recorder = state.recorder.clone();
         let (bybit_market_sender, mut bybit_market_receiver) =
             mpsc::unbounded_channel::<BybitMarket>();
         let (binance_market_sender, mut binance_market_receiver) =
             mpsc::unbounded_channel::<BinanceMarket>();
         let (bybit_private_sender, mut bybit_private_receiver) =
             mpsc::unbounded_channel::<(String, BybitPrivate)>();
+        let (binance_private_sender, mut binance_private_receiver) =
+            mpsc::unbounded_channel::<(String, BinancePrivate)>();
 
         let binance_symbols = state.symbols.clone();
         let bybit_symbols = state.symbols.clone();
 
-        for (symbol, client) in state.clients.clone() {
+        // The public market stream takes the stream settings of the symbols' clients
+        let market_client = state.clients.values().next().cloned();
+        for (symbol, client) in state.clients {
             let private_clone = bybit_private_sender.clone();
+            let shutdown = shutdown.clone();
             tokio::spawn(async move {
-                client.private_subscribe(symbol, private_clone).await;
+                client
+                    .private_subscribe(symbol, private_clone, shutdown)
+                    .await;
             });
         }
 
-        let state = Arc::new(Mutex::new(state.clone()));
+        let binance_market_client = state.binance_clients.values().next().cloned();
+        for (symbol, client) in state.binance_clients {
+            let private_clone = binance_private_sender.clone();
+            let shutdown = shutdown.clone();
+            tokio::spawn(async move {
+                client
+                    .private_subscribe(symbol, private_clone, shutdown)
+                    .await;
+            });
+        }
 
+        let market_shutdown = shutdown.clone();
         tokio::spawn(async move {
-            let market_stream = BybitClient::init("".to_string(), "".to_string()).await;
+            let market_stream = match market_client {
+                Some(client) => client,
+                None => BybitClient::init("".to_string(), "".to_string()).await,
+            };
             market_stream
-                .market_subscribe(bybit_symbols, bybit_market_sender)
+                .market_subscribe(bybit_symbols, bybit_market_sender, market_shutdown)
                 .await;
         });
 
+        let market_shutdown = shutdown.clone();
         tokio::spawn(async move {
-            let market_stream = BinanceClient::init("".to_string(), "".to_string()).await;
+            let market_stream = match binance_market_client {
+                Some(client) => client,
+                None => BinanceClient::init("".to_string(), "".to_string()).await,
+            };
             market_stream
-                .market_subscribe(binance_symbols, binance_market_sender)
+                .market_subscribe(binance_symbols, binance_market_sender, market_shutdown)
                 .await;
         });
 
         loop {
-            tokio::select! {
-              Some(data) = bybit_market_receiver.recv() => {
-                let mut state = state.lock().await;
-                state.markets[0] = MarketData::Bybit(data);
-                state_sender.send(state.clone()).unwrap();
-              }
-              Some(data) = binance_market_receiver.recv() => {
-                let mut state = state.lock().await;
-                state.markets[1] = MarketData::Binance(data);
-                state_sender.send(state.clone()).unwrap();
-              }
-              Some(data) = bybit_private_receiver.recv() => {
-                let mut state = state.lock().await;
-                state.privates.insert(data.0, data.1);
-                state_sender.send(state.clone()).unwrap();
-              }
+            let update = tokio::select! {
+                Some(data) = bybit_market_receiver.recv() => StateUpdate::BybitMarket(data),
+                Some(data) = binance_market_receiver.recv() => StateUpdate::BinanceMarket(data),
+                Some(data) = bybit_private_receiver.recv() => {
+                    StateUpdate::BybitPrivate(data.0, data.1)
+                }
+                Some(data) = binance_private_receiver.recv() => {
+                    StateUpdate::BinancePrivate(data.0, data.1)
+                }
+                _ = wait_for_shutdown(&mut shutdown) => break,
+            };
+            observe(&update, recorder.as_ref());
+            if !state_sender.send(update).await {
+                break;
             }
         }
     }
@@ -0,0 +1,62 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    time::Duration,
+};
+
+/// Delay before the first reconnect.
+pub const BASE_DELAY: Duration = Duration::from_millis(600);
+/// Default cap on the reconnect delay.
+pub const MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// Delay between reconnect attempts, doubling after each failure up to a cap.
+///
+/// Each delay is drawn between half and all of the current one, so the streams of several
+/// symbols dropped together don't all reconnect at the same instant.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    base: Duration,
+    max: Duration,
+    current: Duration,
+}
+
+impl Backoff {
+    /// Starts at `base` and never waits longer than `max`, which is raised to `base` if lower.
+    pub fn new(base: Duration, max: Duration) -> Self {
+        let max = max.max(base);
+        Self {
+            base,
+            max,
+            current: base,
+        }
+    }
+
+    /// Starts at the base delay of 600ms, capped at `max`.
+    pub fn with_max(max: Duration) -> Self {
+        Self::new(BASE_DELAY, max)
+    }
+
+    /// Returns the delay to wait before the next attempt and doubles the following one.
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.current.mul_f64(0.5 + 0.5 * jitter());
+        self.current = (self.current * 2).min(self.max);
+        delay
+    }
+
+    /// Goes back to the base delay, once a connection succeeded.
+    pub fn reset(&mut self) {
+        self.current = self.base;
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new(BASE_DELAY, MAX_DELAY)
+    }
+}
+
+/// A random fraction in [0, 1), from the random keys std seeds each `RandomState` with.
+fn jitter() -> f64 {
+    let bits = RandomState::new().build_hasher().finish();
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
@@ -6,11 +6,64 @@ use tokio::{fs, sync::mpsc};
 use tracing::{error, info};
 
 /// Async config reader with efficient error handling
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 
 pub async fn read_toml<T: AsRef<Path>, U: DeserializeOwned>(path: T) -> Result<U> {
     let contents = fs::read_to_string(path).await?;
-    toml::from_str(&contents).map_err(Into::into)
+    parse_toml(&contents)
+}
+
+/// Parses a config, replacing `${VAR}` in string values with the environment variable so
+/// keys and secrets can stay out of the file. The environment takes precedence over the
+/// file: a referenced variable that isn't set is an error rather than an empty string.
+pub fn parse_toml<U: DeserializeOwned>(contents: &str) -> Result<U> {
+    let mut value: toml::Value = toml::from_str(contents)?;
+    expand_env(&mut value, "")?;
+    value.try_into().map_err(Into::into)
+}
+
+fn expand_env(value: &mut toml::Value, key: &str) -> Result<()> {
+    match value {
+        toml::Value::String(s) => {
+            let mut expanded = String::with_capacity(s.len());
+            let mut rest = s.as_str();
+            while let Some(start) = rest.find("${") {
+                let Some(len) = rest[start..].find('}') else {
+                    break;
+                };
+                let name = &rest[start + 2..start + len];
+                let var = std::env::var(name).map_err(|_| {
+                    anyhow!(
+                        "environment variable `{}` referenced by `{}` is not set",
+                        name,
+                        key
+                    )
+                })?;
+                expanded.push_str(&rest[..start]);
+                expanded.push_str(&var);
+                rest = &rest[start + len + 1..];
+            }
+            expanded.push_str(rest);
+            *s = expanded;
+        }
+        toml::Value::Array(values) => {
+            for value in values.iter_mut() {
+                expand_env(value, key)?;
+            }
+        }
+        toml::Value::Table(table) => {
+            for (name, value) in table.iter_mut() {
+                let key = if key.is_empty() {
+                    name.clone()
+                } else {
+                    format!("{}.{}", key, name)
+                };
+                expand_env(value, &key)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
 }
 /// Debounced file watcher with zero-copy parsing
 pub async fn watch_config<T, U>(
@@ -50,10 +103,15 @@ where
     // Efficient debounce loop
     let mut debounce_timer = tokio::time::interval(Duration::from_millis(500));
     let mut config_version = 0u32;
+    // only reload once a change was seen, the timer keeps ticking regardless
+    let mut changed = false;
 
     loop {
         tokio::select! {
             _ = debounce_timer.tick() => {
+                if !std::mem::take(&mut changed) {
+                    continue;
+                }
                 match read_toml(&path).await {
                     Ok(new_config) => {
                         config_version += 1;
@@ -65,6 +123,7 @@ where
             }
             _ = debounce_rx.recv() => {
                 // Reset debounce timer on change detection
+                changed = true;
                 debounce_timer.reset();
             }
         }
@@ -0,0 +1,85 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use super::{ema::EMA, metrics};
+
+/// Requests of a kind the rolling average roughly spans.
+pub const LATENCY_WINDOW: usize = 20;
+/// Requests of a kind and symbol before its average counts as the exchange's latency, a
+/// single slow request doesn't make a slow exchange.
+pub const MIN_LATENCY_REQUESTS: u64 = 5;
+
+/// Round trip times of one kind of request, in ms.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Latency {
+    pub last_ms: f64,
+    /// EMA over about the last `LATENCY_WINDOW` requests
+    pub avg_ms: f64,
+    pub requests: u64,
+}
+
+/// Round trip times of a client's order requests by request kind and symbol, shared by the
+/// clones of the client.
+///
+/// A slowing exchange shows up in the rolling averages within a few requests and fades out
+/// of them once it recovers. Each average is exported as the `smm_request_latency_ms` gauge.
+#[derive(Debug, Clone, Default)]
+pub struct RequestLatency(Arc<Mutex<Latencies>>);
+
+// (request, symbol) to its latency and the EMA averaging it
+type Latencies = BTreeMap<(&'static str, String), (Latency, EMA)>;
+
+impl RequestLatency {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a `request` for `symbol` that took `elapsed` and returns its updated latency.
+    pub fn record(&self, request: &'static str, symbol: &str, elapsed: Duration) -> Latency {
+        let ms = elapsed.as_secs_f64() * 1000.0;
+        let Ok(mut requests) = self.0.lock() else {
+            return Latency::default();
+        };
+        let (latency, ema) = requests
+            .entry((request, symbol.to_string()))
+            .or_insert_with(|| (Latency::default(), EMA::new(LATENCY_WINDOW)));
+        latency.last_ms = ms;
+        latency.avg_ms = ema.update(ms);
+        latency.requests += 1;
+        metrics::set(
+            metrics::REQUEST_LATENCY,
+            &[("request", request), ("symbol", symbol)],
+            latency.avg_ms,
+        );
+        *latency
+    }
+
+    /// Latency of the `request` kind for `symbol`, `None` before its first request.
+    pub fn get(&self, request: &'static str, symbol: &str) -> Option<Latency> {
+        let requests = self.0.lock().ok()?;
+        requests
+            .get(&(request, symbol.to_string()))
+            .map(|(latency, _)| *latency)
+    }
+
+    /// Forgets every request, e.g. once quoting was pulled for a slow exchange.
+    pub fn reset(&self) {
+        if let Ok(mut requests) = self.0.lock() {
+            requests.clear();
+        }
+    }
+
+    /// Highest rolling average of the request kinds and symbols with at least
+    /// `MIN_LATENCY_REQUESTS` requests, `None` before any has that many.
+    pub fn slowest_avg_ms(&self) -> Option<f64> {
+        let requests = self.0.lock().ok()?;
+        requests
+            .values()
+            .filter(|(latency, _)| latency.requests >= MIN_LATENCY_REQUESTS)
+            .map(|(latency, _)| latency.avg_ms)
+            .max_by(f64::total_cmp)
+    }
+}
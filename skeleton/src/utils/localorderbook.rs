@@ -1,17 +1,36 @@
+use std::collections::BTreeMap;
+
+use ordered_float::OrderedFloat;
+
+/// Decay of the level weights in the depth weighted analytics (wmid, microprice, imbalance,
+/// VOI, OFI), the level `i` levels from the touch weighs `exp(-decay_rate * i)`.
+pub const DEFAULT_DECAY_RATE: f64 = 0.5;
+
 pub trait OrderBook {
     type Ask;
     type Bid;
     fn new() -> Self;
+    /// Returns whether the delta changed any level of the book.
     fn update_bba(
         &mut self,
         asks: Vec<Self::Ask>,
         bids: Vec<Self::Bid>,
         timestamp: u64,
         sequence: u64,
-    );
-    fn update(&mut self, asks: Vec<Self::Ask>, bids: Vec<Self::Bid>, timestamp: u64, levels: usize);
+    ) -> bool;
+    /// Returns whether the delta changed any level of the book.
+    fn update(
+        &mut self,
+        asks: Vec<Self::Ask>,
+        bids: Vec<Self::Bid>,
+        timestamp: u64,
+        levels: usize,
+    ) -> bool;
     fn reset(&mut self, asks: Vec<Self::Ask>, bids: Vec<Self::Bid>, timestamp: u64, sequence: u64);
     fn set_mid_price(&mut self);
+    /// Whether the best bid is at or above the best ask, or a crossed book was cleared and
+    /// hasn't been rebuilt yet. Nothing derived from the book should be trusted meanwhile.
+    fn is_crossed(&self) -> bool;
     fn get_mid_price(&self) -> f64;
     fn get_depth(&self, depth: usize) -> (Vec<Self::Ask>, Vec<Self::Bid>);
     fn get_best_ask(&self) -> Self::Ask;
@@ -27,10 +46,112 @@ pub trait OrderBook {
     fn get_wmid(&self, depth: Option<usize>) -> f64;
     fn effective_spread(&self, is_buy: bool) -> f64;
     fn get_microprice(&self, depth: Option<usize>) -> f64;
+    /// Volume weighted price of sweeping `qty` from the asks (buy) or the bids (sell), `None`
+    /// when the book can't fill it.
+    fn expected_fill_price(&self, qty: f64, is_buy: bool) -> Option<f64>;
+    /// Notional (`price * qty`) resting within `ticks` ticks of the best bid and of the best
+    /// ask, `(bid_notional, ask_notional)`.
+    fn depth_notional(&self, ticks: usize) -> (f64, f64);
+    /// Imbalance between -1.0 (all asks) and 1.0 (all bids), 0.0 within the dead zone.
     fn imbalance_ratio(&self, depth: Option<usize>) -> f64;
+    /// Imbalance between -1.0 (all asks) and 1.0 (all bids) without the dead zone.
+    fn raw_imbalance_ratio(&self, depth: Option<usize>) -> f64;
     fn price_impact(&self, old_book: &Self, depth: Option<usize>) -> f64;
     fn ofi(&self, old_book: &Self, depth: Option<usize>) -> f64;
     fn voi(&self, old_book: &Self, depth: Option<usize>) -> f64;
     fn calculate_weighted_ask(&self, depth: usize, decay_rate: Option<f64>) -> f64;
     fn calculate_weighted_bid(&self, depth: usize, decay_rate: Option<f64>) -> f64;
 }
+
+/// Imbalance ratios within this distance of zero read as balanced in
+/// `OrderBook::imbalance_ratio`.
+pub const IMBALANCE_DEAD_ZONE: f64 = 0.2;
+
+/// Reads an imbalance `ratio` within `threshold` of zero as a balanced 0.0.
+///
+/// The imbalance at the touch flips with every small quote, the dead zone kept that noise
+/// from moving the quotes back when only the sign of the imbalance was used. A threshold of
+/// 0.0 keeps every value.
+pub fn dead_zone(ratio: f64, threshold: f64) -> f64 {
+    if ratio.abs() <= threshold {
+        0.0
+    } else {
+        ratio
+    }
+}
+
+/// Sets the qty of a price level, a qty of 0 removes it.
+///
+/// Returns whether the level actually changed, so deltas re-sending known levels can be skipped.
+pub fn set_level(levels: &mut BTreeMap<OrderedFloat<f64>, f64>, price: f64, qty: f64) -> bool {
+    let price = OrderedFloat::from(price);
+    if qty == 0.0 {
+        levels.remove(&price).is_some()
+    } else {
+        levels.insert(price, qty) != Some(qty)
+    }
+}
+
+/// Signed change of one side of the book between two snapshots.
+///
+/// A bid moving up or an ask moving down counts the volume at the new price, a bid moving down
+/// or an ask moving up counts the volume that left, and an unchanged price counts the change in
+/// volume. Positive when the side strengthens, negative when it weakens.
+pub fn level_impact(
+    old_price: f64,
+    old_vol: f64,
+    curr_price: f64,
+    curr_vol: f64,
+    is_bid: bool,
+) -> f64 {
+    let (improved, worsened) = if is_bid {
+        (curr_price > old_price, curr_price < old_price)
+    } else {
+        (curr_price < old_price, curr_price > old_price)
+    };
+    if improved {
+        curr_vol
+    } else if worsened {
+        -old_vol
+    } else {
+        curr_vol - old_vol
+    }
+}
+
+/// Sums `price * qty` over `levels`, walked from the best price, as long as the price is
+/// within `distance` of the best one.
+pub fn notional_within<'a>(
+    levels: impl Iterator<Item = (&'a OrderedFloat<f64>, &'a f64)>,
+    distance: f64,
+) -> f64 {
+    let mut best = None;
+    levels
+        .map_while(|(price, qty)| {
+            let best = *best.get_or_insert(**price);
+            // Leaves room for the float error of prices a whole number of ticks apart
+            ((**price - best).abs() <= distance * (1.0 + 1e-9)).then(|| **price * qty)
+        })
+        .sum()
+}
+
+/// Walks `levels` from the best price, taking qty until `qty` is filled, and returns the volume
+/// weighted fill price. `None` when the levels run out first or `qty` isn't positive.
+pub fn sweep_price<'a>(
+    levels: impl Iterator<Item = (&'a OrderedFloat<f64>, &'a f64)>,
+    qty: f64,
+) -> Option<f64> {
+    if qty <= 0.0 || !qty.is_finite() {
+        return None;
+    }
+    let mut remaining = qty;
+    let mut notional = 0.0;
+    for (price, level_qty) in levels {
+        let take = remaining.min(*level_qty);
+        notional += **price * take;
+        remaining -= take;
+        if remaining <= f64::EPSILON * qty {
+            return Some(notional / qty);
+        }
+    }
+    None
+}
@@ -3,17 +3,155 @@ use super::{
     bot::LiveBot,
     time::{get_formatted_date, get_formatted_time},
 };
-use std::fmt;
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+use std::{
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{BufWriter, Write},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+// Sinks shared by every Logger, set once at startup
+static SINKS: OnceCell<LogSinks> = OnceCell::new();
+
+/// Where the log lines go besides stdout and the lowest level each destination gets.
+#[derive(Debug, Default)]
+pub struct LogSinks {
+    /// Lowest level new Loggers log at all, every level when `None`
+    pub min_level: Option<LogLevel>,
+    /// Daily log files, off when `None`
+    pub file: Option<FileSink>,
+    /// Lowest level sent to Telegram, every level when `None`
+    pub telegram_level: Option<LogLevel>,
+}
+
+// Open log file and the date it was opened for
+type DatedFile = ((String, u8, i32), BufWriter<File>);
+
+/// Appends the log lines at or above `min_level` to `<dir>/log-<Mon>-<day>-<year>.log`,
+/// moving on to a new file when the date changes.
+#[derive(Debug)]
+pub struct FileSink {
+    dir: PathBuf,
+    min_level: Option<LogLevel>,
+    file: Mutex<Option<DatedFile>>,
+}
+
+impl FileSink {
+    /// `min_level` of `None` writes every level.
+    pub fn new(dir: impl Into<PathBuf>, min_level: Option<LogLevel>) -> Self {
+        Self {
+            dir: dir.into(),
+            min_level,
+            file: Mutex::new(None),
+        }
+    }
+
+    /// Path of the file written today.
+    pub fn current_path(&self) -> PathBuf {
+        let (month, day, year) = get_formatted_date();
+        self.dir.join(format!("log-{}-{}-{}.log", month, day, year))
+    }
+
+    /// Writes `line` when `level` clears the sink's minimum level.
+    pub fn write(&self, level: LogLevel, line: &str) {
+        if !level.reaches(self.min_level) {
+            return;
+        }
+        let Ok(mut file) = self.file.lock() else {
+            return;
+        };
+        let date = get_formatted_date();
+        if file.as_ref().is_none_or(|(opened, _)| *opened != date) {
+            let path = self.current_path();
+            let opened = fs::create_dir_all(&self.dir)
+                .and_then(|_| OpenOptions::new().create(true).append(true).open(&path));
+            match opened {
+                Ok(opened) => *file = Some((date, BufWriter::new(opened))),
+                Err(e) => {
+                    eprintln!("Failed to open the log file {}: {}", path.display(), e);
+                    return;
+                }
+            }
+        }
+        if let Some((_, writer)) = file.as_mut() {
+            if let Err(e) = writeln!(writer, "{}", line).and_then(|_| writer.flush()) {
+                eprintln!("Failed to write the log file: {}", e);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Sink {
+    Telegram(LiveBot),
+    /// Only stdout and the log file
+    Local,
+    Memory(Arc<Mutex<Vec<(LogLevel, String)>>>),
+}
 
 #[derive(Debug, Clone)]
 pub struct Logger {
-    bot: LiveBot,
+    sink: Sink,
+    /// Messages below this level are dropped
+    min_level: LogLevel,
 }
 
 impl Logger {
     /// Create a new Logger instance with a LiveBot.
     pub fn new(bot: LiveBot) -> Self {
-        Logger { bot }
+        Logger {
+            sink: Sink::Telegram(bot),
+            min_level: SINKS
+                .get()
+                .and_then(|sinks| sinks.min_level)
+                .unwrap_or(LogLevel::Debug),
+        }
+    }
+
+    /// Create a Logger that only logs to stdout and the log file, for when there's no bot
+    /// to send the messages with.
+    pub fn local() -> Self {
+        Logger {
+            sink: Sink::Local,
+            min_level: SINKS
+                .get()
+                .and_then(|sinks| sinks.min_level)
+                .unwrap_or(LogLevel::Debug),
+        }
+    }
+
+    /// Create a Logger that records messages in memory instead of sending them to Telegram.
+    ///
+    /// Clones share the same buffer, read it back with `captured`.
+    pub fn capturing() -> Self {
+        Logger {
+            sink: Sink::Memory(Arc::new(Mutex::new(Vec::new()))),
+            min_level: LogLevel::Debug,
+        }
+    }
+
+    /// Drops the messages below `level`.
+    pub fn with_min_level(mut self, level: LogLevel) -> Self {
+        self.min_level = level;
+        self
+    }
+
+    /// Returns the level and message of every log recorded by a capturing Logger.
+    pub fn captured(&self) -> Vec<(LogLevel, String)> {
+        match &self.sink {
+            Sink::Memory(logs) => logs.lock().unwrap().clone(),
+            Sink::Telegram(_) | Sink::Local => Vec::new(),
+        }
+    }
+
+    /// Sets the sinks of every Logger, returns false when they were already set.
+    ///
+    /// Set it before creating the Loggers, they pick up `min_level` when created.
+    pub fn set_sinks(sinks: LogSinks) -> bool {
+        SINKS.set(sinks).is_ok()
     }
 
     /// Returns the current date and time in a formatted tuple.
@@ -24,24 +162,40 @@ impl Logger {
         (month.to_string(), day, hours, mins, secs, am_pm.to_string())
     }
 
-    /// Logs a message with the given level and sends it to Telegram.
+    /// Logs a message with the given level to stdout and the configured sinks.
+    ///
+    /// A message below the Logger's minimum level goes nowhere and returns an empty string.
     pub fn log(&self, level: LogLevel, msg: &str) -> String {
+        if level < self.min_level {
+            return String::new();
+        }
         let (month, day, hours, mins, secs, am_pm) = Self::get_formatted_datetime();
         let formatted_msg = format!(
             "{} {}, {:02}:{:02}:{:02} {} | {:<8} | {}",
             day, month, hours, mins, secs, am_pm, level, msg
         );
 
-        // Clone necessary data for the async block
-        let bot_clone = self.bot.clone();
-        let msg_clone = formatted_msg.clone();
+        let sinks = SINKS.get();
+        if let Some(file) = sinks.and_then(|sinks| sinks.file.as_ref()) {
+            file.write(level, &formatted_msg);
+        }
+
+        match &self.sink {
+            Sink::Telegram(bot) if level.reaches(sinks.and_then(|sinks| sinks.telegram_level)) => {
+                // Clone necessary data for the async block
+                let bot_clone = bot.clone();
+                let msg_clone = formatted_msg.clone();
 
-        // Spawn the async task without awaiting it
-        tokio::spawn(async move {
-            if let Err(err) = bot_clone.send_message(&msg_clone).await {
-                eprintln!("Failed to send message: {:?}", err);
+                // Spawn the async task without awaiting it
+                tokio::spawn(async move {
+                    if let Err(err) = bot_clone.send_message(&msg_clone).await {
+                        eprintln!("Failed to send message: {:?}", err);
+                    }
+                });
             }
-        });
+            Sink::Telegram(_) | Sink::Local => {}
+            Sink::Memory(logs) => logs.lock().unwrap().push((level, msg.to_string())),
+        }
 
         println!("{}", formatted_msg);
         formatted_msg
@@ -78,19 +232,28 @@ impl Logger {
     }
 }
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+/// Log levels from the least to the most severe.
+#[derive(Deserialize, Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+#[serde(rename_all = "lowercase")]
 pub enum LogLevel {
-    Success,
-    Info,
     Debug,
+    Info,
+    Success,
     Warning,
     Error,
     Critical,
 }
 
+impl LogLevel {
+    /// Whether a message at this level gets past `min_level`, always when `None`.
+    fn reaches(self, min_level: Option<LogLevel>) -> bool {
+        min_level.is_none_or(|min| self >= min)
+    }
+}
+
 impl fmt::Display for LogLevel {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        const LEVEL_NAMES: [&str; 6] = ["SUCCESS", "INFO", "DEBUG", "WARNING", "ERROR", "CRITICAL"];
+        const LEVEL_NAMES: [&str; 6] = ["DEBUG", "INFO", "SUCCESS", "WARNING", "ERROR", "CRITICAL"];
         let idx = *self as usize;
         write!(f, "{}", LEVEL_NAMES[idx])
     }
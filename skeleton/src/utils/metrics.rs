@@ -0,0 +1,192 @@
+use std::{collections::BTreeMap, fmt::Write as _, sync::Mutex, time::Duration};
+
+use once_cell::sync::Lazy;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::watch,
+};
+
+use crate::exchange::exchange::wait_for_shutdown;
+
+// Time a scrape has to send its request line before the connection is dropped
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 1 while the symbol has orders on the book, 0 while it isn't quoting
+pub const QUOTING: &str = "smm_quoting";
+/// Fills of the grid orders by symbol and side
+pub const FILLS: &str = "smm_fills_total";
+/// Position in base units by symbol
+pub const POSITION: &str = "smm_position";
+pub const REALIZED_PNL: &str = "smm_realized_pnl";
+pub const UNREALIZED_PNL: &str = "smm_unrealized_pnl";
+/// Requests left in the budget of each side until it refills
+pub const RATE_LIMIT_REMAINING: &str = "smm_rate_limit_remaining";
+/// Times a websocket stream was reconnected
+pub const RECONNECTS: &str = "smm_reconnects_total";
+/// Local time in ms of the last update received from each stream
+pub const LAST_UPDATE_MS: &str = "smm_last_update_timestamp_ms";
+/// Seconds the oldest order of each symbol has rested at its price
+pub const OLDEST_ORDER_AGE: &str = "smm_oldest_order_age_seconds";
+/// Rolling average round trip of each kind of order request in ms
+pub const REQUEST_LATENCY: &str = "smm_request_latency_ms";
+/// Stream updates the recorder dropped because its writer fell behind
+pub const RECORDER_DROPPED: &str = "smm_recorder_dropped_total";
+/// Config reloads and operator commands rejected by validation, by source
+pub const REJECTED_COMMANDS: &str = "smm_rejected_commands_total";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Counter,
+    Gauge,
+}
+
+// Exported metrics in the order they are rendered
+const METRICS: [(&str, Kind, &str); 12] = [
+    (QUOTING, Kind::Gauge, "Whether the symbol has live quotes"),
+    (FILLS, Kind::Counter, "Fills of the grid orders"),
+    (POSITION, Kind::Gauge, "Position in base units"),
+    (REALIZED_PNL, Kind::Gauge, "Realized PnL in quote units"),
+    (
+        UNREALIZED_PNL,
+        Kind::Gauge,
+        "Unrealized PnL at the mid in quote units",
+    ),
+    (
+        RATE_LIMIT_REMAINING,
+        Kind::Gauge,
+        "Order requests left in the side's budget",
+    ),
+    (RECONNECTS, Kind::Counter, "Websocket stream reconnects"),
+    (
+        LAST_UPDATE_MS,
+        Kind::Gauge,
+        "Local time of the stream's last update in ms",
+    ),
+    (
+        OLDEST_ORDER_AGE,
+        Kind::Gauge,
+        "Seconds the oldest live order has rested at its price",
+    ),
+    (
+        REQUEST_LATENCY,
+        Kind::Gauge,
+        "Rolling average round trip of the order requests in ms",
+    ),
+    (
+        RECORDER_DROPPED,
+        Kind::Counter,
+        "Stream updates dropped by the recorder",
+    ),
+    (
+        REJECTED_COMMANDS,
+        Kind::Counter,
+        "Config reloads and operator commands rejected",
+    ),
+];
+
+// (metric, rendered labels) to value
+static VALUES: Lazy<Mutex<BTreeMap<(&'static str, String), f64>>> =
+    Lazy::new(|| Mutex::new(BTreeMap::new()));
+
+fn label_set(labels: &[(&str, &str)]) -> String {
+    labels
+        .iter()
+        .map(|(key, value)| {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{}=\"{}\"", key, value)
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Sets a gauge, or a counter kept as a running total elsewhere.
+pub fn set(metric: &'static str, labels: &[(&str, &str)], value: f64) {
+    if let Ok(mut values) = VALUES.lock() {
+        values.insert((metric, label_set(labels)), value);
+    }
+}
+
+/// Adds one to a counter.
+pub fn inc(metric: &'static str, labels: &[(&str, &str)]) {
+    if let Ok(mut values) = VALUES.lock() {
+        *values.entry((metric, label_set(labels))).or_default() += 1.0;
+    }
+}
+
+/// Every metric set so far in the Prometheus text exposition format.
+pub fn render() -> String {
+    let Ok(values) = VALUES.lock() else {
+        return String::new();
+    };
+    let mut out = String::new();
+    for (metric, kind, help) in METRICS {
+        let mut samples = values
+            .range((metric, String::new())..)
+            .take_while(|((name, _), _)| *name == metric)
+            .peekable();
+        if samples.peek().is_none() {
+            continue;
+        }
+        let kind = match kind {
+            Kind::Counter => "counter",
+            Kind::Gauge => "gauge",
+        };
+        let _ = writeln!(out, "# HELP {} {}", metric, help);
+        let _ = writeln!(out, "# TYPE {} {}", metric, kind);
+        for ((_, labels), value) in samples {
+            if labels.is_empty() {
+                let _ = writeln!(out, "{} {}", metric, value);
+            } else {
+                let _ = writeln!(out, "{}{{{}}} {}", metric, labels, value);
+            }
+        }
+    }
+    out
+}
+
+/// Serves `render` on `GET /metrics` at `host` and `port` until `true` is sent on
+/// `shutdown`. Bind it to 127.0.0.1 unless the scraper runs on another machine.
+pub async fn serve(
+    host: &str,
+    port: u16,
+    mut shutdown: watch::Receiver<bool>,
+) -> std::io::Result<()> {
+    let listener = TcpListener::bind((host, port)).await?;
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                if let Ok((stream, _)) = accepted {
+                    tokio::spawn(respond(stream));
+                }
+            }
+            _ = wait_for_shutdown(&mut shutdown) => return Ok(()),
+        }
+    }
+}
+
+async fn respond(mut stream: TcpStream) {
+    // Only the request line matters, the headers are left unread. A client that never
+    // sends it doesn't hold the connection open
+    let mut buf = [0u8; 1024];
+    let Ok(Ok(read)) = tokio::time::timeout(READ_TIMEOUT, stream.read(&mut buf)).await else {
+        return;
+    };
+    let request = String::from_utf8_lossy(&buf[..read]);
+    let mut parts = request.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", render()),
+        _ => ("404 Not Found", String::new()),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
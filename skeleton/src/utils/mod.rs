@@ -6,4 +6,10 @@ pub mod bot;
 pub mod localorderbook;
 pub mod number;
 pub mod ema;
-pub mod vol;
\ No newline at end of file
+pub mod vol;
+pub mod persistence;
+pub mod recorder;
+pub mod metrics;
+pub mod spreadbook;
+pub mod backoff;
+pub mod latency;
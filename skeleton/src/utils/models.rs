@@ -1,17 +1,27 @@
 use std::{
     borrow::Cow,
     collections::{BTreeMap, VecDeque},
+    time::Duration,
 };
 
-use binance::model::{AggrTradesEvent, Asks, Bids, BookTickerEvent};
+use binance::{
+    futures::model::{AggTrade, EventBalance, EventPosition, OrderUpdate},
+    model::{AggrTradesEvent, Asks, Bids, BookTickerEvent},
+};
 use bybit::model::{
     AmendOrderRequest, Ask, BatchAmendRequest, BatchPlaceRequest, Bid, Category, FastExecData,
-    LinearTickerData, OrderData, OrderRequest, PositionData, Side, WalletData, WsTrade,
+    LinearTickerData, OrderData, OrderRequest, PositionData, RecentTrade, Side, WalletData,
+    WsTrade,
 };
 use ordered_float::OrderedFloat;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-use super::logger::Logger;
+use super::{
+    latency::RequestLatency,
+    localorderbook::DEFAULT_DECAY_RATE,
+    logger::{LogLevel, Logger},
+    time::generate_timestamp,
+};
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct Config {
@@ -25,6 +35,150 @@ pub struct Config {
     pub rate_limit: usize,
     pub bps: Vec<f64>,
     pub tick_window: usize,
+    #[serde(default)]
+    pub log_intended_quotes: bool,
+    #[serde(default)]
+    pub fallback_min_notional: f64,
+    #[serde(default)]
+    pub floor_size_to_lot: bool,
+    #[serde(default)]
+    pub dust_fraction: f64,
+    #[serde(default)]
+    pub quote_anchor: QuoteAnchor,
+    #[serde(default)]
+    pub spread_model: SpreadModel,
+    #[serde(default)]
+    pub vol_estimator: VolEstimator,
+    #[serde(default)]
+    pub cancel_on_disconnect: u16,
+    #[serde(default)]
+    pub anomaly_sigma: f64,
+    #[serde(default)]
+    pub warmup_snapshots: usize,
+    #[serde(default)]
+    pub reduce_only_delta: f64,
+    #[serde(default)]
+    pub time_in_force: TimeInForce,
+    #[serde(default)]
+    pub quoting_model: QuotingModel,
+    #[serde(default = "default_grid_ratio")]
+    pub bid_ratio: f64,
+    #[serde(default = "default_grid_ratio")]
+    pub ask_ratio: f64,
+    #[serde(default)]
+    pub skew_weighted_ratio: bool,
+    #[serde(default = "default_final_order_distance")]
+    pub final_order_distance: f64,
+    #[serde(default)]
+    pub dry_run: bool,
+    #[serde(default)]
+    pub max_drawdown_pct: f64,
+    #[serde(default)]
+    pub flatten_on_kill_switch: bool,
+    #[serde(default)]
+    pub skew_weights: SkewWeights,
+    #[serde(default)]
+    pub range_vol_weight: f64,
+    #[serde(default)]
+    pub state_dir: String,
+    #[serde(default)]
+    pub stale_data_ms: u64,
+    #[serde(default)]
+    pub record_dir: String,
+    #[serde(default)]
+    pub funding_skew_weight: f64,
+    #[serde(default)]
+    pub min_edge_bps: f64,
+    #[serde(default)]
+    pub toxicity_weight: f64,
+    #[serde(default)]
+    pub feature_interval_ms: u64,
+    #[serde(default)]
+    pub feature_log: String,
+    #[serde(default)]
+    pub metrics_port: Option<u16>,
+    #[serde(default)]
+    pub log_level: Option<LogLevel>,
+    #[serde(default)]
+    pub log_dir: String,
+    #[serde(default)]
+    pub log_file_level: Option<LogLevel>,
+    #[serde(default)]
+    pub telegram_level: Option<LogLevel>,
+    /// (key, secret, symbol) of the symbols quoted on Binance, a symbol in both lists is
+    /// quoted on Bybit
+    #[serde(default)]
+    pub binance_api_keys: Vec<(String, String, String)>,
+    #[serde(default = "default_inventory_adjustment")]
+    pub inventory_adjustment: f64,
+    #[serde(default = "default_volatility_multiplier")]
+    pub volatility_multiplier: f64,
+    #[serde(default = "default_max_spread_multiplier")]
+    pub max_spread_multiplier: f64,
+    #[serde(default)]
+    pub book_decay_rate: f64,
+    #[serde(default)]
+    pub imbalance_dead_zone: f64,
+    #[serde(default)]
+    pub stream_timeout: u64,
+    #[serde(default)]
+    pub max_reconnect_delay: u64,
+    #[serde(default)]
+    pub vwap_band_sigma: f64,
+    #[serde(default)]
+    pub vwap_weight: f64,
+    #[serde(default)]
+    pub max_order_age: u64,
+    #[serde(default)]
+    pub post_only_retries: usize,
+    #[serde(default)]
+    pub quote_side: QuoteSide,
+    #[serde(default)]
+    pub account_rate_limit: u32,
+    #[serde(default)]
+    pub wallet_refresh: u64,
+    #[serde(default)]
+    pub circuit_breaker_sigma: f64,
+    #[serde(default)]
+    pub circuit_breaker_bps: f64,
+    #[serde(default)]
+    pub circuit_breaker_cooldown: u64,
+    #[serde(default)]
+    pub trail_drift_bps: f64,
+    #[serde(default)]
+    pub trail_min_ticks: u32,
+    #[serde(default)]
+    pub safety_factor: f64,
+    #[serde(default)]
+    pub max_order_latency: u64,
+    /// Address the metrics endpoint listens on, only this machine by default
+    #[serde(default = "default_metrics_host")]
+    pub metrics_host: String,
+}
+
+fn default_metrics_host() -> String {
+    "127.0.0.1".to_string()
+}
+
+// The quote generator's own defaults, used when the fields are left out
+fn default_grid_ratio() -> f64 {
+    0.37
+}
+
+fn default_final_order_distance() -> f64 {
+    10.0
+}
+
+fn default_inventory_adjustment() -> f64 {
+    -0.63
+}
+
+fn default_volatility_multiplier() -> f64 {
+    100.0
+}
+
+fn default_max_spread_multiplier() -> f64 {
+    3.7
 }
 
 impl PartialEq for Config {
@@ -39,35 +193,484 @@ impl PartialEq for Config {
             && self.rate_limit == other.rate_limit
             && self.bps == other.bps
             && self.tick_window == other.tick_window
+            && self.log_intended_quotes == other.log_intended_quotes
+            && self.fallback_min_notional == other.fallback_min_notional
+            && self.floor_size_to_lot == other.floor_size_to_lot
+            && self.dust_fraction == other.dust_fraction
+            && self.quote_anchor == other.quote_anchor
+            && self.spread_model == other.spread_model
+            && self.vol_estimator == other.vol_estimator
+            && self.cancel_on_disconnect == other.cancel_on_disconnect
+            && self.anomaly_sigma == other.anomaly_sigma
+            && self.warmup_snapshots == other.warmup_snapshots
+            && self.reduce_only_delta == other.reduce_only_delta
+            && self.time_in_force == other.time_in_force
+            && self.quoting_model == other.quoting_model
+            && self.bid_ratio == other.bid_ratio
+            && self.ask_ratio == other.ask_ratio
+            && self.skew_weighted_ratio == other.skew_weighted_ratio
+            && self.final_order_distance == other.final_order_distance
+            && self.dry_run == other.dry_run
+            && self.max_drawdown_pct == other.max_drawdown_pct
+            && self.flatten_on_kill_switch == other.flatten_on_kill_switch
+            && self.skew_weights == other.skew_weights
+            && self.range_vol_weight == other.range_vol_weight
+            && self.state_dir == other.state_dir
+            && self.stale_data_ms == other.stale_data_ms
+            && self.record_dir == other.record_dir
+            && self.funding_skew_weight == other.funding_skew_weight
+            && self.min_edge_bps == other.min_edge_bps
+            && self.toxicity_weight == other.toxicity_weight
+            && self.feature_interval_ms == other.feature_interval_ms
+            && self.feature_log == other.feature_log
+            && self.metrics_port == other.metrics_port
+            && self.log_level == other.log_level
+            && self.log_dir == other.log_dir
+            && self.log_file_level == other.log_file_level
+            && self.telegram_level == other.telegram_level
+            && self.binance_api_keys == other.binance_api_keys
+            && self.inventory_adjustment == other.inventory_adjustment
+            && self.volatility_multiplier == other.volatility_multiplier
+            && self.max_spread_multiplier == other.max_spread_multiplier
+            && self.book_decay_rate == other.book_decay_rate
+            && self.imbalance_dead_zone == other.imbalance_dead_zone
+            && self.stream_timeout == other.stream_timeout
+            && self.max_reconnect_delay == other.max_reconnect_delay
+            && self.vwap_band_sigma == other.vwap_band_sigma
+            && self.vwap_weight == other.vwap_weight
+            && self.max_order_age == other.max_order_age
+            && self.post_only_retries == other.post_only_retries
+            && self.quote_side == other.quote_side
+            && self.account_rate_limit == other.account_rate_limit
+            && self.wallet_refresh == other.wallet_refresh
+            && self.circuit_breaker_sigma == other.circuit_breaker_sigma
+            && self.circuit_breaker_bps == other.circuit_breaker_bps
+            && self.circuit_breaker_cooldown == other.circuit_breaker_cooldown
+            && self.trail_drift_bps == other.trail_drift_bps
+            && self.trail_min_ticks == other.trail_min_ticks
+            && self.safety_factor == other.safety_factor
+            && self.max_order_latency == other.max_order_latency
+            && self.metrics_host == other.metrics_host
     }
+}
 
-    fn ne(&self, other: &Self) -> bool {
-        self.token != other.token
-            && self.chat_id != other.chat_id
-            && self.api_keys != other.api_keys
-            && self.balances != other.balances
-            && self.leverage != other.leverage
-            && self.orders_per_side != other.orders_per_side
-            && self.depths != other.depths
-            && self.rate_limit != other.rate_limit
-            && self.bps != other.bps
-            && self.tick_window != other.tick_window
+/// A config value the bot can't run with, naming the offending field.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigError {
+    pub field: &'static str,
+    pub reason: String,
+}
+
+impl ConfigError {
+    fn new(field: &'static str, reason: impl Into<String>) -> Self {
+        Self {
+            field,
+            reason: reason.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid `{}`: {}", self.field, self.reason)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    /// Symbols of every client, Bybit's first, without duplicates.
+    pub fn symbols(&self) -> Vec<&str> {
+        let mut symbols: Vec<&str> = Vec::new();
+        for (_, _, symbol) in self.api_keys.iter().chain(self.binance_api_keys.iter()) {
+            if !symbols.contains(&symbol.as_str()) {
+                symbols.push(symbol);
+            }
+        }
+        symbols
+    }
+
+    /// Checks the values the bot would otherwise panic on or trade nonsense with, and
+    /// that every symbol has its balance and spread.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let symbols = self.symbols();
+        if symbols.is_empty() {
+            return Err(ConfigError::new(
+                "api_keys",
+                "no symbols, add one to api_keys or binance_api_keys",
+            ));
+        }
+        for symbol in symbols.iter() {
+            if !self.balances.iter().any(|(name, _)| name == symbol) {
+                return Err(ConfigError::new(
+                    "balances",
+                    format!("no balance for {}", symbol),
+                ));
+            }
+        }
+        if let Some((symbol, balance)) = self
+            .balances
+            .iter()
+            .find(|(_, balance)| !(balance.is_finite() && *balance > 0.0))
+        {
+            return Err(ConfigError::new(
+                "balances",
+                format!("balance of {} must be positive, got {}", symbol, balance),
+            ));
+        }
+        if self.bps.len() != symbols.len() {
+            return Err(ConfigError::new(
+                "bps",
+                format!(
+                    "expected one spread per symbol ({}), got {}",
+                    symbols.len(),
+                    self.bps.len()
+                ),
+            ));
+        }
+        if let Some(bps) = self
+            .bps
+            .iter()
+            .find(|bps| !(bps.is_finite() && **bps > 0.0))
+        {
+            return Err(ConfigError::new(
+                "bps",
+                format!("spreads must be positive, got {}", bps),
+            ));
+        }
+        // The widest range of the exchanges, each symbol's own max is applied once fetched
+        if !(1.0..=125.0).contains(&self.leverage) {
+            return Err(ConfigError::new(
+                "leverage",
+                format!("must be between 1 and 125, got {}", self.leverage),
+            ));
+        }
+        // Set on the exchanges as a whole number, a fraction would size the position caps
+        // with a leverage the account doesn't have
+        if self.leverage.fract() != 0.0 {
+            return Err(ConfigError::new(
+                "leverage",
+                format!("must be a whole number, got {}", self.leverage),
+            ));
+        }
+        if !(0.0..=1.0).contains(&self.safety_factor) {
+            return Err(ConfigError::new(
+                "safety_factor",
+                format!("must be between 0 and 1, got {}", self.safety_factor),
+            ));
+        }
+        if self.orders_per_side == 0 {
+            return Err(ConfigError::new("orders_per_side", "must be at least 1"));
+        }
+        // Split between the bids and the asks, each needs a request
+        if self.rate_limit < 2 {
+            return Err(ConfigError::new("rate_limit", "must be at least 2"));
+        }
+        if self.tick_window < 2 {
+            return Err(ConfigError::new(
+                "tick_window",
+                format!("must be at least 2, got {}", self.tick_window),
+            ));
+        }
+        if self.depths.is_empty() {
+            return Err(ConfigError::new("depths", "needs at least one depth"));
+        }
+        if self.depths.contains(&0) {
+            return Err(ConfigError::new("depths", "depths must be at least 1"));
+        }
+        for (field, ratio) in [("bid_ratio", self.bid_ratio), ("ask_ratio", self.ask_ratio)] {
+            if !(ratio > 0.0 && ratio <= 1.0) {
+                return Err(ConfigError::new(
+                    field,
+                    format!("must be above 0 and at most 1, got {}", ratio),
+                ));
+            }
+        }
+        if !(self.final_order_distance.is_finite() && self.final_order_distance > 0.0) {
+            return Err(ConfigError::new(
+                "final_order_distance",
+                format!("must be positive, got {}", self.final_order_distance),
+            ));
+        }
+        if !(-1.0..=0.0).contains(&self.inventory_adjustment) {
+            return Err(ConfigError::new(
+                "inventory_adjustment",
+                format!(
+                    "must be between -1 and 0, got {}",
+                    self.inventory_adjustment
+                ),
+            ));
+        }
+        if !(self.volatility_multiplier.is_finite() && self.volatility_multiplier >= 0.0) {
+            return Err(ConfigError::new(
+                "volatility_multiplier",
+                format!("can't be negative, got {}", self.volatility_multiplier),
+            ));
+        }
+        if !(self.funding_skew_weight.is_finite() && self.funding_skew_weight >= 0.0) {
+            return Err(ConfigError::new(
+                "funding_skew_weight",
+                format!("can't be negative, got {}", self.funding_skew_weight),
+            ));
+        }
+        if !(self.max_spread_multiplier.is_finite() && self.max_spread_multiplier >= 1.0) {
+            return Err(ConfigError::new(
+                "max_spread_multiplier",
+                format!("must be at least 1, got {}", self.max_spread_multiplier),
+            ));
+        }
+        if !(self.book_decay_rate.is_finite() && self.book_decay_rate >= 0.0) {
+            return Err(ConfigError::new(
+                "book_decay_rate",
+                format!("can't be negative, got {}", self.book_decay_rate),
+            ));
+        }
+        if !(0.0..=1.0).contains(&self.imbalance_dead_zone) {
+            return Err(ConfigError::new(
+                "imbalance_dead_zone",
+                format!("must be between 0 and 1, got {}", self.imbalance_dead_zone),
+            ));
+        }
+        if !(self.vwap_band_sigma.is_finite() && self.vwap_band_sigma >= 0.0) {
+            return Err(ConfigError::new(
+                "vwap_band_sigma",
+                format!("can't be negative, got {}", self.vwap_band_sigma),
+            ));
+        }
+        if !(0.0..=1.0).contains(&self.vwap_weight) {
+            return Err(ConfigError::new(
+                "vwap_weight",
+                format!("must be between 0 and 1, got {}", self.vwap_weight),
+            ));
+        }
+        for (field, value) in [
+            ("circuit_breaker_sigma", self.circuit_breaker_sigma),
+            ("circuit_breaker_bps", self.circuit_breaker_bps),
+            ("trail_drift_bps", self.trail_drift_bps),
+        ] {
+            if !(value.is_finite() && value >= 0.0) {
+                return Err(ConfigError::new(
+                    field,
+                    format!("can't be negative, got {}", value),
+                ));
+            }
+        }
+        self.skew_weights
+            .validate()
+            .map_err(|reason| ConfigError::new("skew_weights", reason))
     }
 }
+
+/// Price the quote grid is centered on.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum QuoteAnchor {
+    #[default]
+    Mid,
+    Wmid,
+    Microprice,
+}
+
+/// How the quoted spread is derived from the configured `bps`.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SpreadModel {
+    /// The `bps` spread as is.
+    Constant,
+    /// The `bps` spread widened linearly with volatility, clipped to the book spread.
+    #[default]
+    VolLinear,
+}
+
+/// How the grid's center and touch spread are chosen.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum QuotingModel {
+    /// Fair value shifted by the feature skew and inventory, spread from `spread_model`.
+    #[default]
+    Heuristic,
+    /// Avellaneda-Stoikov reservation price and optimal spread, with the risk aversion
+    /// `gamma` and the order arrival decay `k` (per unit of price).
+    AvellanedaStoikov { gamma: f64, k: f64 },
+}
+
+/// Volatility estimator feeding the spread and quote bounds.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum VolEstimator {
+    /// Equal weighted log returns over `tick_window`.
+    #[default]
+    Rolling,
+    /// Exponentially weighted log returns with a decay of 0.94, widens faster on spikes.
+    Ewma,
+    /// High-low ranges of one minute trade bars, steadier than close to close returns.
+    Parkinson,
+}
+
+/// Coefficients blending the engine's signals into the composite skew.
+///
+/// The weights are relative, the engine scales them to sum to 1.0 which keeps the skew
+/// within -1.0..=1.0. Weights left out of a config table keep their default, so
+/// `{ rsi = 0.2 }` blends the RSI in at 0.2 / 1.2 of the skew.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct SkewWeights {
+    pub trade: f64,
+    pub book: f64,
+    pub depth: f64,
+    pub basis: f64,
+    pub order_flow: f64,
+    /// Leans against the RSI extremes instead of chasing them, off by default
+    pub rsi: f64,
+    /// Follows the micro-price trend, which leads the mid, off by default
+    pub microprice_momentum: f64,
+    /// Leans away from the quoted venue trading rich against the other one, off by default
+    pub cross_venue_basis: f64,
+}
+
+impl Default for SkewWeights {
+    fn default() -> Self {
+        Self {
+            trade: 0.3,
+            book: 0.25,
+            depth: 0.2,
+            basis: 0.15,
+            order_flow: 0.1,
+            rsi: 0.0,
+            microprice_momentum: 0.0,
+            cross_venue_basis: 0.0,
+        }
+    }
+}
+
+impl SkewWeights {
+    fn weights(&self) -> [f64; 8] {
+        [
+            self.trade,
+            self.book,
+            self.depth,
+            self.basis,
+            self.order_flow,
+            self.rsi,
+            self.microprice_momentum,
+            self.cross_venue_basis,
+        ]
+    }
+
+    /// Errors unless every weight is finite and non-negative and at least one is positive.
+    pub fn validate(&self) -> Result<(), String> {
+        let weights = self.weights();
+        if weights.iter().any(|w| !w.is_finite() || *w < 0.0) {
+            return Err(format!(
+                "Skew weights must be finite and non-negative, got {:?}",
+                self
+            ));
+        }
+        if weights.iter().sum::<f64>() <= 0.0 {
+            return Err("At least one skew weight must be positive".to_string());
+        }
+        Ok(())
+    }
+
+    /// The weights scaled to sum to 1.0, unchanged if they sum to 0.
+    pub fn normalized(&self) -> Self {
+        let sum: f64 = self.weights().iter().sum();
+        if sum <= 0.0 {
+            return *self;
+        }
+        Self {
+            trade: self.trade / sum,
+            book: self.book / sum,
+            depth: self.depth / sum,
+            basis: self.basis / sum,
+            order_flow: self.order_flow / sum,
+            rsi: self.rsi / sum,
+            microprice_momentum: self.microprice_momentum / sum,
+            cross_venue_basis: self.cross_venue_basis / sum,
+        }
+    }
+}
+
+/// How long an order stays on the book, mapped to each exchange's own value.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TimeInForce {
+    /// Rests until filled or cancelled.
+    GoodTilCancel,
+    /// Rejected instead of taking liquidity.
+    #[default]
+    PostOnly,
+    /// Fills what it can right away and cancels the rest.
+    ImmediateOrCancel,
+    /// Fills in full right away or is cancelled.
+    FillOrKill,
+}
+
+/// Sides of the book the grid is quoted on.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum QuoteSide {
+    #[default]
+    Both,
+    /// Only bids, e.g. to accumulate a position.
+    BidOnly,
+    /// Only asks, e.g. to distribute a position.
+    AskOnly,
+}
+
+impl QuoteSide {
+    pub fn quotes_bids(&self) -> bool {
+        *self != QuoteSide::AskOnly
+    }
+
+    pub fn quotes_asks(&self) -> bool {
+        *self != QuoteSide::BidOnly
+    }
+}
+
+/// Maker and taker fee rates as fractions of the notional, e.g. 0.0002 for 2 bps.
+///
+/// A negative maker rate is a rebate.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FeeTier {
+    pub maker: f64,
+    pub taker: f64,
+}
+
+impl FeeTier {
+    pub fn new(maker: f64, taker: f64) -> Self {
+        Self { maker, taker }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct BybitClient {
     pub api_key: String,
     pub api_secret: String,
     pub logger: Logger,
+    /// Seconds before Bybit cancels every order once the private stream drops, `None` disables it.
+    pub dcp_window: Option<u16>,
+    /// Time without any message after which the websocket streams are reconnected.
+    pub stream_timeout: Duration,
+    /// Cap on the delay between the reconnects of the websocket streams.
+    pub max_reconnect_delay: Duration,
+    /// Orderbook feeds subscribed for each symbol, by their number of levels.
+    pub book_depths: Vec<usize>,
+    /// Round trip times of the order requests, shared by the clones of the client.
+    pub latency: RequestLatency,
 }
 #[derive(Clone, Debug)]
 pub struct BinanceClient {
     pub api_key: String,
     pub api_secret: String,
     pub logger: Logger,
+    /// Time without any message after which the market stream is reconnected.
+    pub stream_timeout: Duration,
+    /// Cap on the delay between the reconnects of the websocket streams.
+    pub max_reconnect_delay: Duration,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct BybitMarket {
     pub timestamp: u64,
     pub books: BTreeMap<String, BybitBook>,
@@ -86,6 +689,17 @@ impl Default for BybitMarket {
     }
 }
 
+impl BybitMarket {
+    /// Funding rate of the newest ticker update carrying one, positive when longs pay shorts.
+    pub fn funding_rate(&self, symbol: &str) -> Option<f64> {
+        self.ticker
+            .get(symbol)?
+            .iter()
+            .rev()
+            .find_map(|ticker| ticker.funding_rate.as_ref()?.parse::<f64>().ok())
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct BinanceMarket {
     pub timestamp: u64,
@@ -104,11 +718,57 @@ impl Default for BinanceMarket {
     }
 }
 
-#[derive(Debug, Clone)]
+/// An aggregated Binance trade as a Bybit one, the side being the taker's.
+pub fn ws_trade_from_aggr(trade: &AggrTradesEvent) -> WsTrade {
+    WsTrade {
+        timestamp: trade.trade_order_time,
+        symbol: trade.symbol.clone(),
+        side: if trade.is_buyer_maker { "Sell" } else { "Buy" }.to_string(),
+        volume: trade.qty.parse().unwrap_or(0.0),
+        price: trade.price.parse().unwrap_or(0.0),
+        tick_direction: String::new(),
+        id: trade.aggregated_trade_id.to_string(),
+        buyer_is_maker: trade.is_buyer_maker,
+    }
+}
+
+/// A public trade from Bybit's recent trades endpoint as a stream one, the side being the
+/// taker's.
+pub fn ws_trade_from_recent(trade: &RecentTrade) -> WsTrade {
+    WsTrade {
+        timestamp: trade.trade_time.parse().unwrap_or(0),
+        symbol: trade.symbol.clone(),
+        side: trade.side.clone(),
+        volume: trade.qty.parse().unwrap_or(0.0),
+        price: trade.price.parse().unwrap_or(0.0),
+        tick_direction: String::new(),
+        id: trade.exec_id.clone(),
+        buyer_is_maker: trade.side == "Sell",
+    }
+}
+
+/// An aggregated trade from Binance's REST endpoint as a Bybit one, the side being the
+/// taker's. The REST shape doesn't carry the symbol.
+pub fn ws_trade_from_agg(symbol: &str, trade: &AggTrade) -> WsTrade {
+    WsTrade {
+        timestamp: trade.time,
+        symbol: symbol.to_string(),
+        side: if trade.maker { "Sell" } else { "Buy" }.to_string(),
+        volume: trade.qty,
+        price: trade.price,
+        tick_direction: String::new(),
+        id: trade.agg_id.to_string(),
+        buyer_is_maker: trade.maker,
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct BybitBook {
     pub last_update: u64,
     pub sequence: u64,
+    #[serde(with = "price_levels")]
     pub asks: BTreeMap<OrderedFloat<f64>, f64>,
+    #[serde(with = "price_levels")]
     pub bids: BTreeMap<OrderedFloat<f64>, f64>,
     pub best_ask: Ask,
     pub best_bid: Bid,
@@ -118,7 +778,18 @@ pub struct BybitBook {
     pub min_notional: f64,
     pub min_qty: f64,
     pub post_only_max: f64,
+    /// Set when a crossed book was cleared, until a snapshot rebuilds it
+    pub needs_snapshot: bool,
+    /// Decay of the level weights in the depth weighted analytics, higher rates weigh the
+    /// levels near the touch more
+    #[serde(default = "default_decay_rate")]
+    pub decay_rate: f64,
 }
+
+fn default_decay_rate() -> f64 {
+    DEFAULT_DECAY_RATE
+}
+
 impl BybitBook {
     pub fn update_symbol_info(&mut self, info: &SymbolInfo) {
         self.tick_size = info.tick_size;
@@ -129,16 +800,135 @@ impl BybitBook {
     }
 }
 
-/// symbol, price, qty, side
+impl From<&BinanceBook> for BybitBook {
+    fn from(book: &BinanceBook) -> Self {
+        Self {
+            last_update: book.last_update,
+            sequence: book.sequence,
+            asks: book.asks.clone(),
+            bids: book.bids.clone(),
+            best_ask: Ask {
+                price: book.best_ask.price,
+                qty: book.best_ask.qty,
+            },
+            best_bid: Bid {
+                price: book.best_bid.price,
+                qty: book.best_bid.qty,
+            },
+            mid_price: book.mid_price,
+            tick_size: book.tick_size,
+            lot_size: book.lot_size,
+            min_notional: book.min_notional,
+            min_qty: book.min_qty,
+            post_only_max: book.post_only_max,
+            needs_snapshot: book.needs_snapshot,
+            decay_rate: book.decay_rate,
+        }
+    }
+}
+
+/// (De)serializes a side of a book as `[price, qty]` pairs, JSON keys can't be floats.
+mod price_levels {
+    use std::collections::BTreeMap;
+
+    use ordered_float::OrderedFloat;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        levels: &BTreeMap<OrderedFloat<f64>, f64>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(levels.iter().map(|(price, qty)| (price.0, *qty)))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<BTreeMap<OrderedFloat<f64>, f64>, D::Error> {
+        let levels = Vec::<(f64, f64)>::deserialize(deserializer)?;
+        Ok(levels
+            .into_iter()
+            .map(|(price, qty)| (OrderedFloat(price), qty))
+            .collect())
+    }
+}
+
+/// symbol, price, qty, side, reduce only, time in force
 #[derive(Debug, Clone)]
-pub struct BatchOrder(pub String, pub f64, pub f64, pub bool);
+pub struct BatchOrder(
+    pub String,
+    pub f64,
+    pub f64,
+    pub bool,
+    pub bool,
+    pub TimeInForce,
+);
 
 impl BatchOrder {
-    pub fn new(symbol: String, price: f64, qty: f64, is_buy: bool) -> Self {
-        Self(symbol, price, qty, is_buy)
+    pub fn new(
+        symbol: String,
+        price: f64,
+        qty: f64,
+        is_buy: bool,
+        reduce_only: bool,
+        time_in_force: TimeInForce,
+    ) -> Self {
+        Self(symbol, price, qty, is_buy, reduce_only, time_in_force)
+    }
+}
+
+/// An order of a batch the exchange refused, with the exchange's code and reason.
+#[derive(Debug, Clone)]
+pub struct RejectedOrder {
+    pub order: BatchOrder,
+    pub code: i64,
+    pub reason: String,
+}
+
+impl RejectedOrder {
+    pub fn new(order: BatchOrder, code: i64, reason: String) -> Self {
+        Self {
+            order,
+            code,
+            reason,
+        }
+    }
+
+    /// Whether a post-only order was refused because it would have crossed the book.
+    pub fn is_post_only(&self) -> bool {
+        let reason = self.reason.to_lowercase();
+        self.code == BINANCE_POST_ONLY_REJECTED
+            || ["post only", "postonly", "post-only", "take liquidity"]
+                .iter()
+                .any(|hint| reason.contains(hint))
+    }
+}
+
+/// An amend of a batch the exchange refused, with the exchange's code and reason. The
+/// order may still rest at its old price or be gone, e.g. filled before the amend.
+#[derive(Debug, Clone)]
+pub struct RejectedAmend {
+    pub amend: BatchAmend,
+    pub code: i64,
+    pub reason: String,
+}
+
+impl RejectedAmend {
+    pub fn new(amend: BatchAmend, code: i64, reason: String) -> Self {
+        Self {
+            amend,
+            code,
+            reason,
+        }
     }
 }
 
+/// Binance's code for a post-only (GTX) order that could not rest as a maker
+pub const BINANCE_POST_ONLY_REJECTED: i64 = -5022;
+
+/// Bybit's reject reason on the order stream for a post-only order it cancelled rather than
+/// let take liquidity
+pub const BYBIT_POST_ONLY_REJECTED: &str = "EC_PostOnlyWillTakeLiquidity";
+
 pub trait IntoReq<'a> {
     type BatchOrdersOutput;
 
@@ -158,19 +948,72 @@ impl<'a> IntoReq<'a> for Vec<BatchOrder> {
                     qty: order.2,
                     side: if order.3 { Side::Buy } else { Side::Sell },
                     order_type: bybit::model::OrderType::Limit,
-                    time_in_force: Some(Cow::Borrowed("PostOnly")),
+                    time_in_force: Some(Cow::Borrowed(order.5.to_bybit())),
+                    reduce_only: order.4.then_some(true),
                     ..Default::default()
                 })
                 .collect(),
         }
     }
 }
+/// symbol, price, total qty, order id, side, filled qty, reduce only, time in force
 #[derive(Debug, Clone)]
-pub struct BatchAmend(pub String, pub f64, pub f64, pub String);
+pub struct BatchAmend(
+    pub String,
+    pub f64,
+    pub f64,
+    pub String,
+    pub bool,
+    pub f64,
+    pub bool,
+    pub TimeInForce,
+);
 
 impl BatchAmend {
-    pub fn new(symbol: String, price: f64, qty: f64, order_id: String) -> Self {
-        Self(symbol, price, qty, order_id)
+    pub fn new(symbol: String, price: f64, qty: f64, order_id: String, is_buy: bool) -> Self {
+        Self(
+            symbol,
+            price,
+            qty,
+            order_id,
+            is_buy,
+            0.0,
+            false,
+            TimeInForce::default(),
+        )
+    }
+
+    /// Amends `order` to rest `qty` at `price`, keeping the part of it already filled
+    pub fn of_order(symbol: String, order: &LiveOrder, price: f64, qty: f64) -> Self {
+        Self(
+            symbol,
+            price,
+            qty + order.filled_qty,
+            order.order_id.clone(),
+            order.is_buy,
+            order.filled_qty,
+            order.reduce_only,
+            order.time_in_force,
+        )
+    }
+
+    /// Qty left to rest once the filled part is taken off. Exchanges that amend by
+    /// cancelling and re-placing the order, like Binance, place this instead of the total.
+    pub fn resting_qty(&self) -> f64 {
+        (self.2 - self.5).max(0.0)
+    }
+
+    /// The order such an exchange places in place of the amended one, with the
+    /// amended order's flags.
+    pub fn replacement(&self) -> BatchOrder {
+        BatchOrder::new(
+            self.0.clone(),
+            self.1,
+            self.resting_qty(),
+            self.4,
+            self.6,
+            self.7,
+        )
     }
 }
 
@@ -194,11 +1037,28 @@ impl<'a> IntoReq<'a> for Vec<BatchAmend> {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct LiveOrder {
     pub order_id: String,
     pub price: f64,
+    /// Qty still resting on the book
     pub qty: f64,
+    pub is_buy: bool,
+    /// Qty executed so far, the order's total qty is `qty + filled_qty`
+    #[serde(default)]
+    pub filled_qty: f64,
+    /// Milliseconds since the epoch the order was placed or last re-priced at, 0 when unknown
+    #[serde(default)]
+    pub created_at: u64,
+    /// Mid price the order was placed or last re-priced at, 0 when unknown
+    #[serde(default)]
+    pub placement_mid: f64,
+    /// Flags the order was placed with, an amend can't change them. Orders read back from
+    /// the exchange get the defaults.
+    #[serde(default)]
+    pub reduce_only: bool,
+    #[serde(default)]
+    pub time_in_force: TimeInForce,
 }
 impl Default for LiveOrder {
     fn default() -> Self {
@@ -206,18 +1066,46 @@ impl Default for LiveOrder {
             order_id: String::new(),
             price: 0.0,
             qty: 0.0,
+            is_buy: false,
+            filled_qty: 0.0,
+            created_at: 0,
+            placement_mid: 0.0,
+            reduce_only: false,
+            time_in_force: TimeInForce::default(),
         }
     }
 }
 
 impl LiveOrder {
-    pub fn new(order_id: String, price: f64, qty: f64) -> Self {
+    /// An order placed now.
+    pub fn new(order_id: String, price: f64, qty: f64, is_buy: bool) -> Self {
         Self {
             order_id,
             price,
             qty,
+            is_buy,
+            filled_qty: 0.0,
+            created_at: generate_timestamp().unwrap_or_default(),
+            placement_mid: 0.0,
+            reduce_only: false,
+            time_in_force: TimeInForce::default(),
         }
     }
+
+    /// `order` placed now under `order_id`, with its flags.
+    pub fn placed(order_id: String, order: &BatchOrder) -> Self {
+        Self {
+            reduce_only: order.4,
+            time_in_force: order.5,
+            ..Self::new(order_id, order.1, order.2, order.3)
+        }
+    }
+
+    /// Milliseconds the order has rested at its price by `now`, `None` when its placement
+    /// time is unknown.
+    pub fn age(&self, now: u64) -> Option<u64> {
+        (self.created_at > 0).then(|| now.saturating_sub(self.created_at))
+    }
 }
 
 pub fn sort_grid(orders: &mut VecDeque<LiveOrder>, side: i32) -> VecDeque<LiveOrder> {
@@ -263,6 +1151,21 @@ pub struct BinanceBook {
     pub min_notional: f64,
     pub min_qty: f64,
     pub post_only_max: f64,
+    /// Set when a crossed book was cleared, until a snapshot rebuilds it
+    pub needs_snapshot: bool,
+    /// Decay of the level weights in the depth weighted analytics, see `BybitBook`
+    pub decay_rate: f64,
+}
+
+/// A historical OHLCV candle, `open_time` in milliseconds.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Candle {
+    pub open_time: u64,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume: f64,
 }
 
 #[derive(Debug, Clone)]
@@ -272,11 +1175,15 @@ pub struct SymbolInfo {
     pub min_notional: f64,
     pub min_qty: f64,
     pub post_only_max: f64,
+    /// Highest leverage the exchange allows on the symbol, 0 when unknown
+    pub max_leverage: f64,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct BybitPrivate {
     pub time: u64,
+    /// Connection the data came from, bumped on every (re)connection of the private stream
+    pub session: u64,
     pub wallet: VecDeque<WalletData>,
     pub orders: VecDeque<OrderData>,
     pub positions: VecDeque<PositionData>,
@@ -286,6 +1193,7 @@ impl Default for BybitPrivate {
     fn default() -> Self {
         Self {
             time: 0,
+            session: 0,
             wallet: VecDeque::with_capacity(20),
             orders: VecDeque::with_capacity(500),
             positions: VecDeque::with_capacity(500),
@@ -293,3 +1201,69 @@ impl Default for BybitPrivate {
         }
     }
 }
+
+#[derive(Clone, Debug)]
+pub struct BinancePrivate {
+    pub time: u64,
+    /// Connection the data came from, bumped on every (re)connection of the private stream
+    pub session: u64,
+    pub wallet: VecDeque<EventBalance>,
+    pub orders: VecDeque<OrderUpdate>,
+    pub positions: VecDeque<EventPosition>,
+    /// Order updates with an execution type of `TRADE`
+    pub executions: VecDeque<OrderUpdate>,
+}
+impl Default for BinancePrivate {
+    fn default() -> Self {
+        Self {
+            time: 0,
+            session: 0,
+            wallet: VecDeque::with_capacity(20),
+            orders: VecDeque::with_capacity(500),
+            positions: VecDeque::with_capacity(500),
+            executions: VecDeque::with_capacity(500),
+        }
+    }
+}
+
+/// An execution normalized from the exchange payload.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fill {
+    pub order_id: String,
+    pub exec_id: String,
+    pub price: f64,
+    pub qty: f64,
+    pub is_buy: bool,
+    pub time: u64,
+    pub seq: u64,
+}
+
+impl From<&FastExecData> for Fill {
+    fn from(exec: &FastExecData) -> Self {
+        let parse_float = |s: &str| s.replace(',', "").parse::<f64>().unwrap_or(0.0);
+        Self {
+            order_id: exec.order_id.clone(),
+            exec_id: exec.exec_id.clone(),
+            price: parse_float(&exec.exec_price),
+            qty: parse_float(&exec.exec_qty),
+            is_buy: exec.side == "Buy",
+            time: exec.exec_time.parse().unwrap_or(0),
+            seq: exec.seq,
+        }
+    }
+}
+
+/// Binance has no execution sequence, trade ids increase within a symbol instead.
+impl From<&OrderUpdate> for Fill {
+    fn from(update: &OrderUpdate) -> Self {
+        Self {
+            order_id: update.order_id.to_string(),
+            exec_id: update.trade_id.to_string(),
+            price: update.price_last_filled_trade.parse().unwrap_or(0.0),
+            qty: update.qty_last_filled_trade.parse().unwrap_or(0.0),
+            is_buy: update.side == "BUY",
+            time: update.trade_order_time,
+            seq: update.trade_id,
+        }
+    }
+}
@@ -50,6 +50,37 @@ pub fn geometric_weights(ratio: f64, n: usize, reverse: bool) -> Vec<f64> {
     weights
 }
 
+/// Zeroes the weights below `min_fraction` of the total and rescales the rest to keep the total,
+/// positions are kept so the weights stay aligned with their price levels
+pub fn truncate_weights(weights: &[f64], min_fraction: f64) -> Vec<f64> {
+    let total: f64 = weights.iter().sum();
+    let threshold = total * min_fraction;
+    let kept: f64 = weights.iter().filter(|&&w| w >= threshold).sum();
+    if kept <= 0.0 {
+        return vec![0.0; weights.len()];
+    }
+
+    let scale = total / kept;
+    weights
+        .iter()
+        .map(|&w| if w >= threshold { w * scale } else { 0.0 })
+        .collect()
+}
+
+/// Compensated (Kahan) summation, carries the low order bits a naive sum drops when
+/// adding small values to a large running total
+pub fn kahan_sum<I: IntoIterator<Item = f64>>(values: I) -> f64 {
+    let mut sum = 0.0;
+    let mut compensation = 0.0;
+    for value in values {
+        let y = value - compensation;
+        let t = sum + y;
+        compensation = (t - sum) - y;
+        sum = t;
+    }
+    sum
+}
+
 /// Optimized linear space using iterator
 pub fn linspace<T: Float + NumCast>(start: T, end: T, n: usize) -> Vec<T> {
     assert!(n > 1, "n must be > 1");
@@ -106,11 +137,11 @@ pub trait Round<T> {
 }
 
 impl Round<f64> for f64 {
-    /// Optimized rounding using bitmask technique
+    /// Rounds half away from zero, so negative values mirror positive ones
     fn round_to(&self, digits: u8) -> f64 {
         assert!(digits <= 17, "Max 17 digits supported");
         let factor = 10.0f64.powi(digits as i32);
-        (self * factor + 0.5).trunc() / factor
+        (self * factor).round() / factor
     }
 
     /// Branchless clipping
@@ -0,0 +1,82 @@
+use std::{fs, io::ErrorKind, path::PathBuf};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use super::models::LiveOrder;
+
+/// Orders, position and PnL of a generator, saved so a restart picks up its resting orders.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct PersistedState {
+    pub live_buys: Vec<LiveOrder>,
+    pub live_sells: Vec<LiveOrder>,
+    pub position_qty: f64,
+    /// Average price the position was entered at
+    pub entry_price: f64,
+    pub realized_pnl: f64,
+}
+
+impl PartialEq for PersistedState {
+    /// Unlike `LiveOrder`'s own equality, an amended price or a partial fill counts as a change.
+    fn eq(&self, other: &Self) -> bool {
+        let same_orders = |a: &[LiveOrder], b: &[LiveOrder]| {
+            a.len() == b.len()
+                && a.iter().zip(b).all(|(a, b)| {
+                    a.order_id == b.order_id
+                        && a.price == b.price
+                        && a.qty == b.qty
+                        && a.is_buy == b.is_buy
+                })
+        };
+        same_orders(&self.live_buys, &other.live_buys)
+            && same_orders(&self.live_sells, &other.live_sells)
+            && self.position_qty == other.position_qty
+            && self.entry_price == other.entry_price
+            && self.realized_pnl == other.realized_pnl
+    }
+}
+
+/// Where the generators keep their `PersistedState` between runs, one entry per symbol.
+///
+/// Implement it to store the state somewhere other than local files, e.g. Redis.
+pub trait StatePersistence: std::fmt::Debug + Send + Sync {
+    fn save(&self, symbol: &str, state: &PersistedState) -> Result<()>;
+    /// Returns `None` when nothing was saved for `symbol` yet.
+    fn load(&self, symbol: &str) -> Result<Option<PersistedState>>;
+}
+
+/// Saves the state of each symbol as JSON to `<dir>/<symbol>.json`.
+#[derive(Debug, Clone)]
+pub struct FilePersistence {
+    dir: PathBuf,
+}
+
+impl FilePersistence {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn path(&self, symbol: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", symbol))
+    }
+}
+
+impl StatePersistence for FilePersistence {
+    /// Writes to a temporary file first, so a crash mid write leaves the previous state intact.
+    fn save(&self, symbol: &str, state: &PersistedState) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let path = self.path(symbol);
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec(state)?)?;
+        fs::rename(tmp, path)?;
+        Ok(())
+    }
+
+    fn load(&self, symbol: &str) -> Result<Option<PersistedState>> {
+        match fs::read(self.path(symbol)) {
+            Ok(contents) => Ok(Some(serde_json::from_slice(&contents)?)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
@@ -0,0 +1,350 @@
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Result};
+use ordered_float::OrderedFloat;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::{sync::mpsc, task::JoinHandle};
+
+use super::{
+    metrics,
+    time::{generate_timestamp, get_formatted_date},
+};
+use crate::ss::StateUpdate;
+
+/// Version written with every record, bump it when `RecordedEvent` changes shape.
+pub const RECORDING_VERSION: u32 = 2;
+
+/// Updates queued for the writer at most, past it they are dropped and counted in
+/// `smm_recorder_dropped_total`.
+pub const RECORDER_CAPACITY: usize = 1024;
+
+/// What changed in a symbol's data since its last record.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum RecordedEvent {
+    /// Levels of the book that changed, a qty of 0 removes the level. The first record of
+    /// a book carries all its levels.
+    Book {
+        exchange: String,
+        symbol: String,
+        timestamp: u64,
+        bids: Vec<(f64, f64)>,
+        asks: Vec<(f64, f64)>,
+    },
+    /// Entries the exchange appended to one of the symbol's streams (trades, ticker,
+    /// wallet, orders, positions or executions), as it sent them.
+    Entries {
+        exchange: String,
+        symbol: String,
+        stream: String,
+        entries: Vec<Value>,
+    },
+    /// The private stream of the symbol reconnected.
+    Session {
+        exchange: String,
+        symbol: String,
+        session: u64,
+    },
+}
+
+/// One line of a recording.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Record {
+    pub version: u32,
+    /// Milliseconds since the epoch when the update was recorded
+    pub time: u64,
+    pub event: RecordedEvent,
+}
+
+/// Appends what changed in the market and private updates of both exchanges to newline
+/// delimited JSON files, one per day, so they can be replayed later.
+///
+/// Recording only queues the update, a dedicated thread works out what changed since the
+/// last record of each symbol and writes it. An update that doesn't fit the queue is
+/// dropped, the next one recorded covers its changes.
+#[derive(Debug, Clone)]
+pub struct Recorder {
+    sender: mpsc::Sender<(u64, StateUpdate)>,
+}
+
+impl Recorder {
+    /// Starts the writer thread, recording to `<dir>/recording-<Mon>-<day>-<year>.ndjson`.
+    ///
+    /// The thread flushes whenever it runs out of queued updates and stops once every
+    /// `Recorder` clone is dropped.
+    pub fn start(dir: impl Into<PathBuf>) -> (Self, JoinHandle<()>) {
+        let dir = dir.into();
+        let (sender, mut receiver) = mpsc::channel::<(u64, StateUpdate)>(RECORDER_CAPACITY);
+        let handle = tokio::task::spawn_blocking(move || {
+            let mut writer = DailyWriter::new(dir);
+            let mut changes = Changes::default();
+            while let Some((time, update)) = receiver.blocking_recv() {
+                changes.write(&mut writer, time, &update);
+                while let Ok((time, update)) = receiver.try_recv() {
+                    changes.write(&mut writer, time, &update);
+                }
+                writer.flush();
+            }
+        });
+        (Self { sender }, handle)
+    }
+
+    /// Queues an update, dropping it when the writer is behind.
+    pub fn record(&self, update: &StateUpdate) {
+        let time = generate_timestamp().unwrap_or(0);
+        if let Err(mpsc::error::TrySendError::Full(_)) =
+            self.sender.try_send((time, update.clone()))
+        {
+            metrics::inc(metrics::RECORDER_DROPPED, &[]);
+        }
+    }
+}
+
+type Levels = BTreeMap<OrderedFloat<f64>, f64>;
+
+/// What was last recorded of each symbol, to record only what changed since.
+#[derive(Default)]
+struct Changes {
+    // bids and asks by exchange and symbol
+    books: HashMap<(&'static str, String), (Levels, Levels)>,
+    // newest entry by exchange, symbol and stream
+    entries: HashMap<(&'static str, String, &'static str), Value>,
+    sessions: HashMap<(&'static str, String), u64>,
+}
+
+impl Changes {
+    fn write(&mut self, writer: &mut DailyWriter, time: u64, update: &StateUpdate) {
+        for event in self.events(update) {
+            writer.write(&Record {
+                version: RECORDING_VERSION,
+                time,
+                event,
+            });
+        }
+    }
+
+    fn events(&mut self, update: &StateUpdate) -> Vec<RecordedEvent> {
+        let mut events = Vec::new();
+        match update {
+            StateUpdate::BybitMarket(market) => {
+                for (symbol, book) in &market.books {
+                    events.extend(self.book(
+                        "bybit",
+                        symbol,
+                        book.last_update,
+                        &book.bids,
+                        &book.asks,
+                    ));
+                }
+                for (symbol, trades) in &market.trades {
+                    events.extend(self.entries("bybit", symbol, "trades", trades));
+                }
+                for (symbol, ticker) in &market.ticker {
+                    events.extend(self.entries("bybit", symbol, "ticker", ticker));
+                }
+            }
+            StateUpdate::BinanceMarket(market) => {
+                for (symbol, book) in &market.books {
+                    events.extend(self.book(
+                        "binance",
+                        symbol,
+                        book.last_update,
+                        &book.bids,
+                        &book.asks,
+                    ));
+                }
+                for (symbol, trades) in &market.trades {
+                    events.extend(self.entries("binance", symbol, "trades", trades));
+                }
+                for (symbol, ticker) in &market.ticker {
+                    events.extend(self.entries("binance", symbol, "ticker", ticker));
+                }
+            }
+            StateUpdate::BybitPrivate(symbol, private) => {
+                events.extend(self.session("bybit", symbol, private.session));
+                events.extend(self.entries("bybit", symbol, "wallet", &private.wallet));
+                events.extend(self.entries("bybit", symbol, "orders", &private.orders));
+                events.extend(self.entries("bybit", symbol, "positions", &private.positions));
+                events.extend(self.entries("bybit", symbol, "executions", &private.executions));
+            }
+            StateUpdate::BinancePrivate(symbol, private) => {
+                events.extend(self.session("binance", symbol, private.session));
+                events.extend(self.entries("binance", symbol, "wallet", &private.wallet));
+                events.extend(self.entries("binance", symbol, "orders", &private.orders));
+                events.extend(self.entries("binance", symbol, "positions", &private.positions));
+                events.extend(self.entries("binance", symbol, "executions", &private.executions));
+            }
+        }
+        events
+    }
+
+    fn book(
+        &mut self,
+        exchange: &'static str,
+        symbol: &str,
+        timestamp: u64,
+        bids: &Levels,
+        asks: &Levels,
+    ) -> Option<RecordedEvent> {
+        let (last_bids, last_asks) = self
+            .books
+            .entry((exchange, symbol.to_string()))
+            .or_default();
+        let changed_bids = changed_levels(last_bids, bids);
+        let changed_asks = changed_levels(last_asks, asks);
+        if changed_bids.is_empty() && changed_asks.is_empty() {
+            return None;
+        }
+        last_bids.clone_from(bids);
+        last_asks.clone_from(asks);
+        Some(RecordedEvent::Book {
+            exchange: exchange.to_string(),
+            symbol: symbol.to_string(),
+            timestamp,
+            bids: changed_bids,
+            asks: changed_asks,
+        })
+    }
+
+    /// The entries after the last recorded one. Streams only grow at the back, the front
+    /// is trimmed, so those past the newest recorded entry are new.
+    fn entries<T: Serialize>(
+        &mut self,
+        exchange: &'static str,
+        symbol: &str,
+        stream: &'static str,
+        items: &VecDeque<T>,
+    ) -> Option<RecordedEvent> {
+        let key = (exchange, symbol.to_string(), stream);
+        let last = self.entries.get(&key);
+        let mut entries = Vec::new();
+        for item in items.iter().rev() {
+            let Ok(entry) = serde_json::to_value(item) else {
+                continue;
+            };
+            if last == Some(&entry) {
+                break;
+            }
+            entries.push(entry);
+        }
+        let newest = entries.first()?.clone();
+        self.entries.insert(key, newest);
+        entries.reverse();
+        Some(RecordedEvent::Entries {
+            exchange: exchange.to_string(),
+            symbol: symbol.to_string(),
+            stream: stream.to_string(),
+            entries,
+        })
+    }
+
+    fn session(
+        &mut self,
+        exchange: &'static str,
+        symbol: &str,
+        session: u64,
+    ) -> Option<RecordedEvent> {
+        let last = self
+            .sessions
+            .insert((exchange, symbol.to_string()), session);
+        (last != Some(session)).then(|| RecordedEvent::Session {
+            exchange: exchange.to_string(),
+            symbol: symbol.to_string(),
+            session,
+        })
+    }
+}
+
+/// Levels of `levels` that differ from `last`, those gone with a qty of 0.
+fn changed_levels(last: &Levels, levels: &Levels) -> Vec<(f64, f64)> {
+    let removed = last
+        .keys()
+        .filter(|price| !levels.contains_key(*price))
+        .map(|price| (price.0, 0.0));
+    let changed = levels
+        .iter()
+        .filter(|(price, qty)| last.get(*price) != Some(*qty))
+        .map(|(price, qty)| (price.0, *qty));
+    let mut changed: Vec<_> = removed.chain(changed).collect();
+    changed.sort_by(|a, b| a.0.total_cmp(&b.0));
+    changed
+}
+
+/// Reads back a recording, failing on a record written by another version.
+pub fn read_recording(path: impl AsRef<Path>) -> Result<Vec<Record>> {
+    let mut records = Vec::new();
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        let record: Record = serde_json::from_str(&line)?;
+        if record.version != RECORDING_VERSION {
+            bail!("Unsupported recording version {}", record.version);
+        }
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// Writer that moves on to a new file when the date changes.
+struct DailyWriter {
+    dir: PathBuf,
+    date: (String, u8, i32),
+    file: Option<BufWriter<File>>,
+}
+
+impl DailyWriter {
+    fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            date: (String::new(), 0, 0),
+            file: None,
+        }
+    }
+
+    fn write(&mut self, record: &Record) {
+        let date = get_formatted_date();
+        if self.file.is_none() || date != self.date {
+            self.flush();
+            self.file = self.open(&date);
+            self.date = date;
+        }
+        let Some(file) = self.file.as_mut() else {
+            return;
+        };
+        let written = serde_json::to_writer(&mut *file, record)
+            .map_err(std::io::Error::from)
+            .and_then(|_| file.write_all(b"\n"));
+        if let Err(e) = written {
+            eprintln!("Failed to record an update: {}", e);
+        }
+    }
+
+    fn open(&self, (month, day, year): &(String, u8, i32)) -> Option<BufWriter<File>> {
+        let path = self
+            .dir
+            .join(format!("recording-{}-{}-{}.ndjson", month, day, year));
+        let file = fs::create_dir_all(&self.dir)
+            .and_then(|_| OpenOptions::new().create(true).append(true).open(&path));
+        match file {
+            Ok(file) => Some(BufWriter::new(file)),
+            Err(e) => {
+                eprintln!("Failed to open the recording {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    fn flush(&mut self) {
+        if let Some(Err(e)) = self.file.as_mut().map(|file| file.flush()) {
+            eprintln!("Failed to flush the recording: {}", e);
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use std::collections::BTreeMap;
+
+use ordered_float::OrderedFloat;
+
+use super::{
+    localorderbook::OrderBook,
+    models::{BybitBook, BybitMarket},
+    number::Round,
+};
+
+/// Synthetic book of the spread between two symbols, one unit of leg A minus one unit of
+/// leg B.
+///
+/// Selling the spread sells A into its bids and buys B from its asks, so the spread's bids
+/// pair A's bids with B's asks and its asks pair A's asks with B's bids. Each level holds the
+/// qty both legs can fill together. The synthetic book is a `BybitBook`, so every `OrderBook`
+/// analytic (mid, microprice, imbalance, OFI against the previous book...) works on it and
+/// the feature engine can run on it like on any symbol. Prices can be negative.
+#[derive(Debug, Clone)]
+pub struct SpreadBook {
+    pub leg_a: String,
+    pub leg_b: String,
+    book: BybitBook,
+    previous: BybitBook,
+}
+
+impl SpreadBook {
+    pub fn new(leg_a: &str, leg_b: &str) -> Self {
+        Self {
+            leg_a: leg_a.to_string(),
+            leg_b: leg_b.to_string(),
+            book: BybitBook::new(),
+            previous: BybitBook::new(),
+        }
+    }
+
+    /// Rebuilds the spread from the latest books of both legs, keeping the last one as the
+    /// previous book.
+    ///
+    /// While either leg is crossed the spread is flagged as crossed too. Min notional is
+    /// a per leg limit, so it's left unset on the spread.
+    pub fn update(&mut self, leg_a: &BybitBook, leg_b: &BybitBook) {
+        // Rounds off the float error of the differences, unless a tick size is unknown
+        let decimals = (leg_a.tick_size > 0.0 && leg_b.tick_size > 0.0).then(|| {
+            leg_a
+                .tick_size
+                .count_decimal_places()
+                .max(leg_b.tick_size.count_decimal_places()) as u8
+        });
+        let mut book = BybitBook::new();
+        book.bids = implied_levels(leg_a.bids.iter().rev(), leg_b.asks.iter(), decimals);
+        book.asks = implied_levels(leg_a.asks.iter(), leg_b.bids.iter().rev(), decimals);
+        if let Some((price, qty)) = book.bids.iter().next_back() {
+            book.best_bid.price = price.0;
+            book.best_bid.qty = *qty;
+        }
+        if let Some((price, qty)) = book.asks.iter().next() {
+            book.best_ask.price = price.0;
+            book.best_ask.qty = *qty;
+        }
+        book.set_mid_price();
+        book.last_update = leg_a.last_update.max(leg_b.last_update);
+        book.sequence = self.book.sequence + 1;
+        book.tick_size = leg_a.tick_size.max(leg_b.tick_size);
+        book.lot_size = leg_a.lot_size.max(leg_b.lot_size);
+        book.min_qty = leg_a.min_qty.max(leg_b.min_qty);
+        book.post_only_max = match (leg_a.post_only_max, leg_b.post_only_max) {
+            (a, b) if a > 0.0 && b > 0.0 => a.min(b),
+            (a, b) => a.max(b),
+        };
+        book.needs_snapshot = leg_a.is_crossed() || leg_b.is_crossed();
+        book.decay_rate = leg_a.decay_rate;
+        self.previous = std::mem::replace(&mut self.book, book);
+    }
+
+    /// Rebuilds the spread from the legs' books in `market`. Returns `false` and leaves the
+    /// spread unchanged while either leg has no book.
+    pub fn update_from_market(&mut self, market: &BybitMarket) -> bool {
+        match (market.books.get(&self.leg_a), market.books.get(&self.leg_b)) {
+            (Some(leg_a), Some(leg_b)) => {
+                self.update(leg_a, leg_b);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The synthetic book of the spread.
+    pub fn book(&self) -> &BybitBook {
+        &self.book
+    }
+
+    /// The book before the last update, for the analytics comparing two books.
+    pub fn previous(&self) -> &BybitBook {
+        &self.previous
+    }
+}
+
+/// Walks one side of leg A and the opposite side of leg B from their best prices, pairing
+/// their qty level by level. Each pair trades at A's price minus B's price.
+fn implied_levels<'a>(
+    mut leg_a: impl Iterator<Item = (&'a OrderedFloat<f64>, &'a f64)>,
+    mut leg_b: impl Iterator<Item = (&'a OrderedFloat<f64>, &'a f64)>,
+    decimals: Option<u8>,
+) -> BTreeMap<OrderedFloat<f64>, f64> {
+    let mut levels = BTreeMap::new();
+    let mut a = leg_a.next().map(|(price, qty)| (price.0, *qty));
+    let mut b = leg_b.next().map(|(price, qty)| (price.0, *qty));
+    while let (Some((a_price, a_qty)), Some((b_price, b_qty))) = (a, b) {
+        let qty = a_qty.min(b_qty);
+        let price = match decimals {
+            Some(decimals) => (a_price - b_price).round_to(decimals),
+            None => a_price - b_price,
+        };
+        *levels.entry(OrderedFloat(price)).or_insert(0.0) += qty;
+        // Moves on from whichever leg the pair used up, both when they match
+        a = if a_qty - qty <= f64::EPSILON * a_qty {
+            leg_a.next().map(|(price, qty)| (price.0, *qty))
+        } else {
+            Some((a_price, a_qty - qty))
+        };
+        b = if b_qty - qty <= f64::EPSILON * b_qty {
+            leg_b.next().map(|(price, qty)| (price.0, *qty))
+        } else {
+            Some((b_price, b_qty - qty))
+        };
+    }
+    levels
+}
@@ -8,11 +8,13 @@ pub struct RollingVolatility {
     sum_squares: f64,
     last_price: Option<f64>,
    pub current_vol: f64,
+    z_score: f64,
 }
 
 impl RollingVolatility {
-    /// Creates a new RollingVolatility with specified window size
+    /// Creates a new RollingVolatility with specified window size, at least 2 returns are needed
     pub fn new(window_size: usize) -> Self {
+        let window_size = window_size.max(2);
         Self {
             window_size,
             returns: VecDeque::with_capacity(window_size),
@@ -20,14 +22,14 @@ impl RollingVolatility {
             sum_squares: 0.0,
             last_price: None,
             current_vol: 0.0,
+            z_score: 0.0,
         }
     }
 
     /// Update with new price and return current volatility and Z-score if available
     pub fn update(&mut self, price: f64) -> Option<(f64, f64)> {
         if let Some(prev_price) = self.last_price.replace(price) {
-            // Calculate log return: ln(price/prev_price)
-            let ret = (price / prev_price).ln();
+            let ret = log_return(prev_price, price);
 
             // Maintain rolling window
             if self.returns.len() == self.window_size {
@@ -58,6 +60,7 @@ impl RollingVolatility {
                 (latest_ret - mean) / vol
             };
             self.current_vol = vol;
+            self.z_score = z_score;
             Some((vol, z_score))
         } else {
             None
@@ -72,6 +75,11 @@ impl RollingVolatility {
         variance.sqrt().max(0.0) // Ensure non-negative
     }
 
+    /// Z-score of the latest return against the window, 0.0 until there are 2 returns
+    pub fn z_score(&self) -> f64 {
+        self.z_score
+    }
+
     /// Get current number of observations in window
     pub fn current_count(&self) -> usize {
         self.returns.len()
@@ -84,5 +92,148 @@ impl RollingVolatility {
         self.sum_squares = 0.0;
         self.last_price = None;
         self.current_vol = 0.0;
+        self.z_score = 0.0;
+    }
+}
+
+/// Log return between two consecutive prices: ln(price/prev_price)
+fn log_return(prev_price: f64, price: f64) -> f64 {
+    (price / prev_price).ln()
+}
+
+/// RiskMetrics default decay for daily returns
+pub const EWMA_DEFAULT_LAMBDA: f64 = 0.94;
+
+/// Exponentially weighted volatility of the log returns, recent returns weigh the most
+/// so it reacts to a regime shift faster than the equal weighted window.
+#[derive(Debug, Clone)]
+pub struct EwmaVolatility {
+    lambda: f64,
+    variance: f64,
+    count: usize,
+    last_price: Option<f64>,
+    pub current_vol: f64,
+}
+
+impl EwmaVolatility {
+    /// Creates an estimator decaying the variance by `lambda` (0 < lambda < 1) each return
+    pub fn new(lambda: f64) -> Self {
+        let lambda = if lambda > 0.0 && lambda < 1.0 {
+            lambda
+        } else {
+            EWMA_DEFAULT_LAMBDA
+        };
+        Self {
+            lambda,
+            variance: 0.0,
+            count: 0,
+            last_price: None,
+            current_vol: 0.0,
+        }
+    }
+
+    /// Decay matching the center of mass of an equal weighted window of `window_size`
+    /// returns, i.e. `1 - 2 / (window_size + 1)`
+    pub fn with_window(window_size: usize) -> Self {
+        Self::new(1.0 - 2.0 / (window_size.max(2) + 1) as f64)
+    }
+
+    /// Update with new price and return current volatility and Z-score if available,
+    /// the Z-score is the latest return over the volatility before it
+    pub fn update(&mut self, price: f64) -> Option<(f64, f64)> {
+        let prev_price = self.last_price.replace(price)?;
+        let ret = log_return(prev_price, price);
+
+        let prev_vol = self.variance.sqrt();
+        // The first return seeds the variance, RiskMetrics assumes a zero mean
+        self.variance = if self.count == 0 {
+            ret.powi(2)
+        } else {
+            self.lambda * self.variance + (1.0 - self.lambda) * ret.powi(2)
+        };
+        self.count += 1;
+        self.current_vol = self.variance.sqrt();
+
+        if self.count < 2 {
+            return None;
+        }
+        let z_score = if prev_vol == 0.0 { 0.0 } else { ret / prev_vol };
+        Some((self.current_vol, z_score))
+    }
+
+    /// Get number of returns seen so far
+    pub fn current_count(&self) -> usize {
+        self.count
+    }
+
+    /// Clear all historical data
+    pub fn reset(&mut self) {
+        self.variance = 0.0;
+        self.count = 0;
+        self.last_price = None;
+        self.current_vol = 0.0;
+    }
+}
+
+impl Default for EwmaVolatility {
+    fn default() -> Self {
+        Self::new(EWMA_DEFAULT_LAMBDA)
+    }
+}
+
+/// Parkinson volatility from high-low ranges, several times more efficient than close to
+/// close returns since the range also catches the moves that reverted within the period.
+#[derive(Debug, Clone)]
+pub struct ParkinsonVolatility {
+    window_size: usize,
+    // squared log ranges ln(high/low)^2
+    ranges: VecDeque<f64>,
+    sum: f64,
+    pub current_vol: f64,
+}
+
+impl ParkinsonVolatility {
+    /// Creates a new ParkinsonVolatility averaging the latest `window_size` ranges
+    pub fn new(window_size: usize) -> Self {
+        let window_size = window_size.max(1);
+        Self {
+            window_size,
+            ranges: VecDeque::with_capacity(window_size),
+            sum: 0.0,
+            current_vol: 0.0,
+        }
+    }
+
+    /// Update with the high and low of a period and return the volatility over one period,
+    /// `sqrt(mean(ln(high/low)^2) / (4 ln 2))`. Invalid ranges are skipped.
+    pub fn update(&mut self, high: f64, low: f64) -> Option<f64> {
+        if !(low > 0.0 && high >= low && high.is_finite()) {
+            return None;
+        }
+        let range = (high / low).ln().powi(2);
+
+        if self.ranges.len() == self.window_size {
+            if let Some(old_range) = self.ranges.pop_front() {
+                self.sum -= old_range;
+            }
+        }
+        self.ranges.push_back(range);
+        self.sum += range;
+
+        let mean = (self.sum / self.ranges.len() as f64).max(0.0);
+        self.current_vol = (mean / (4.0 * std::f64::consts::LN_2)).sqrt();
+        Some(self.current_vol)
+    }
+
+    /// Get current number of ranges in window
+    pub fn current_count(&self) -> usize {
+        self.ranges.len()
+    }
+
+    /// Clear all historical data
+    pub fn reset(&mut self) {
+        self.ranges.clear();
+        self.sum = 0.0;
+        self.current_vol = 0.0;
     }
 }
@@ -0,0 +1,39 @@
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use skeleton::utils::backoff::Backoff;
+
+    #[test]
+    fn test_backoff_cap_and_jitter() {
+        let base = Duration::from_millis(600);
+        let max = Duration::from_secs(5);
+        let mut backoff = Backoff::new(base, max);
+        // 600ms, 1.2s, 2.4s, 4.8s and then the cap
+        let expected = [600, 1200, 2400, 4800, 5000, 5000, 5000];
+        for full in expected.map(Duration::from_millis) {
+            let delay = backoff.next_delay();
+            assert!(
+                delay >= full / 2 && delay <= full,
+                "{:?} outside of {:?}",
+                delay,
+                full
+            );
+        }
+
+        // Reconnects don't all wait the same
+        let delays: Vec<Duration> = (0..20).map(|_| backoff.next_delay()).collect();
+        assert!(delays.iter().any(|delay| *delay != delays[0]));
+
+        backoff.reset();
+        assert!(backoff.next_delay() <= base);
+    }
+
+    #[test]
+    fn test_backoff_cap_below_base() {
+        let mut backoff = Backoff::new(Duration::from_secs(2), Duration::from_secs(1));
+        for _ in 0..5 {
+            assert!(backoff.next_delay() <= Duration::from_secs(2));
+        }
+    }
+}
@@ -1,13 +1,231 @@
 #[cfg(test)]
 mod tests {
-    use skeleton::utils::config::{read_toml, watch_config};
-    use skeleton::utils::models::Config;
+    use skeleton::utils::config::{parse_toml, read_toml, watch_config};
+    use skeleton::utils::models::{
+        Config, QuoteAnchor, QuotingModel, SkewWeights, SpreadModel, TimeInForce, VolEstimator,
+    };
+
+    const BASE_TOML: &str = r#"
+        token = "token"
+        chat_id = 1
+        api_keys = [["key", "secret", "NOTUSDT"]]
+        balances = [["NOTUSDT", 100.0]]
+        leverage = 12.0
+        orders_per_side = 4
+        depths = [3, 8, 34]
+        rate_limit = 10
+        bps = [25]
+        tick_window = 180
+    "#;
     #[tokio::test]
     async fn test_read_toml() {
         let config = read_toml::<&str, Config>("./tests/test.toml").await;
         println!("{:#?}", config);
     }
 
+    #[test]
+    fn test_strategy_modes() {
+        let config: Config = toml::from_str(BASE_TOML).unwrap();
+        assert_eq!(config.quote_anchor, QuoteAnchor::Mid);
+        assert_eq!(config.spread_model, SpreadModel::VolLinear);
+        assert_eq!(config.vol_estimator, VolEstimator::Rolling);
+
+        let anchors = [
+            ("mid", QuoteAnchor::Mid),
+            ("wmid", QuoteAnchor::Wmid),
+            ("microprice", QuoteAnchor::Microprice),
+        ];
+        let spread_models = [
+            ("constant", SpreadModel::Constant),
+            ("vol-linear", SpreadModel::VolLinear),
+        ];
+        for (anchor, expected_anchor) in anchors {
+            for (model, expected_model) in spread_models {
+                let toml = format!(
+                    "{}\nquote_anchor = \"{}\"\nspread_model = \"{}\"\nvol_estimator = \"rolling\"",
+                    BASE_TOML, anchor, model
+                );
+                let config: Config = toml::from_str(&toml).unwrap();
+                assert_eq!(config.quote_anchor, expected_anchor);
+                assert_eq!(config.spread_model, expected_model);
+                assert_eq!(config.vol_estimator, VolEstimator::Rolling);
+            }
+        }
+
+        for (estimator, expected) in [
+            ("ewma", VolEstimator::Ewma),
+            ("parkinson", VolEstimator::Parkinson),
+        ] {
+            let toml = format!("{}\nvol_estimator = \"{}\"", BASE_TOML, estimator);
+            let config: Config = toml::from_str(&toml).unwrap();
+            assert_eq!(config.vol_estimator, expected);
+        }
+
+        // Unknown modes are rejected at load
+        let toml = format!("{}\nspread_model = \"magic\"", BASE_TOML);
+        assert!(toml::from_str::<Config>(&toml).is_err());
+    }
+
+    #[test]
+    fn test_quoting_model() {
+        let config: Config = toml::from_str(BASE_TOML).unwrap();
+        assert_eq!(config.quoting_model, QuotingModel::Heuristic);
+
+        let toml = format!(
+            "{}\nquoting_model = {{ avellaneda-stoikov = {{ gamma = 0.1, k = 1.5 }} }}",
+            BASE_TOML
+        );
+        let config: Config = toml::from_str(&toml).unwrap();
+        assert_eq!(
+            config.quoting_model,
+            QuotingModel::AvellanedaStoikov { gamma: 0.1, k: 1.5 }
+        );
+    }
+
+    #[test]
+    fn test_skew_weights() {
+        let config: Config = toml::from_str(BASE_TOML).unwrap();
+        assert_eq!(config.skew_weights, SkewWeights::default());
+
+        // Omitted weights keep their default
+        let toml = format!(
+            "{}\nskew_weights = {{ trade = 0.5, book = 0.05 }}",
+            BASE_TOML
+        );
+        let config: Config = toml::from_str(&toml).unwrap();
+        assert_eq!(config.skew_weights.trade, 0.5);
+        assert_eq!(config.skew_weights.book, 0.05);
+        assert_eq!(config.skew_weights.depth, 0.2);
+        assert!(config.skew_weights.validate().is_ok());
+
+        // A partial table adds to the defaults, the weights are scaled back to 1.0
+        let toml = format!("{}\nskew_weights = {{ rsi = 0.2 }}", BASE_TOML);
+        let config: Config = toml::from_str(&toml).unwrap();
+        assert!(config.validate().is_ok());
+        let weights = config.skew_weights.normalized();
+        assert!((weights.rsi - 0.2 / 1.2).abs() < 1e-9);
+        assert!((weights.trade - 0.3 / 1.2).abs() < 1e-9);
+
+        let toml = format!("{}\nskew_weights = {{ trade = -0.1 }}", BASE_TOML);
+        let config: Config = toml::from_str(&toml).unwrap();
+        assert_eq!(config.validate().unwrap_err().field, "skew_weights");
+    }
+
+    #[test]
+    fn test_time_in_force() {
+        let config: Config = toml::from_str(BASE_TOML).unwrap();
+        assert_eq!(config.time_in_force, TimeInForce::PostOnly);
+
+        let modes = [
+            ("good-til-cancel", TimeInForce::GoodTilCancel),
+            ("post-only", TimeInForce::PostOnly),
+            ("immediate-or-cancel", TimeInForce::ImmediateOrCancel),
+            ("fill-or-kill", TimeInForce::FillOrKill),
+        ];
+        for (mode, expected) in modes {
+            let toml = format!("{}\ntime_in_force = \"{}\"", BASE_TOML, mode);
+            let config: Config = toml::from_str(&toml).unwrap();
+            assert_eq!(config.time_in_force, expected);
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let config: Config = toml::from_str(BASE_TOML).unwrap();
+        assert!(config.validate().is_ok());
+        // Left out, the multipliers keep the quote generator's defaults
+        assert_eq!(config.inventory_adjustment, -0.63);
+        assert_eq!(config.volatility_multiplier, 100.0);
+        assert_eq!(config.max_spread_multiplier, 3.7);
+        assert_eq!((config.bid_ratio, config.ask_ratio), (0.37, 0.37));
+        assert_eq!(config.final_order_distance, 10.0);
+        // 0 turns the inventory pull and the volatility scaling off
+        let toml = format!(
+            "{}\ninventory_adjustment = 0.0\nvolatility_multiplier = 0.0",
+            BASE_TOML
+        );
+        let config: Config = toml::from_str(&toml).unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.inventory_adjustment, 0.0);
+        assert_eq!(config.volatility_multiplier, 0.0);
+
+        let broken = [
+            ("leverage = 0.0", "leverage"),
+            ("leverage = 200.0", "leverage"),
+            ("leverage = 2.5", "leverage"),
+            ("safety_factor = 1.5", "safety_factor"),
+            ("tick_window = 1", "tick_window"),
+            ("depths = []", "depths"),
+            ("bps = [25, 30]", "bps"),
+            ("balances = [[\"BTCUSDT\", 100.0]]", "balances"),
+            ("api_keys = []", "api_keys"),
+            ("inventory_adjustment = 0.5", "inventory_adjustment"),
+            ("volatility_multiplier = -1.0", "volatility_multiplier"),
+            ("max_spread_multiplier = 0.5", "max_spread_multiplier"),
+            ("max_spread_multiplier = 0.0", "max_spread_multiplier"),
+            ("bid_ratio = 0.0", "bid_ratio"),
+            ("ask_ratio = 1.5", "ask_ratio"),
+            ("final_order_distance = 0.0", "final_order_distance"),
+            ("funding_skew_weight = -0.1", "funding_skew_weight"),
+            ("vwap_weight = 1.5", "vwap_weight"),
+        ];
+        for (line, field) in broken {
+            let toml = BASE_TOML
+                .lines()
+                .filter(|l| {
+                    l.split('=').next().map(str::trim) != line.split('=').next().map(str::trim)
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            let config: Config = toml::from_str(&format!("{}\n{}", toml, line)).unwrap();
+            let error = config.validate().unwrap_err();
+            assert_eq!(error.field, field);
+            assert!(error.to_string().contains(field));
+        }
+
+        // A symbol quoted on Binance too needs only one balance and spread
+        let toml = format!(
+            "{}\nbinance_api_keys = [[\"key\", \"secret\", \"NOTUSDT\"]]",
+            BASE_TOML
+        );
+        let config: Config = toml::from_str(&toml).unwrap();
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_config_inequality() {
+        let config: Config = toml::from_str(BASE_TOML).unwrap();
+        assert_eq!(config, config.clone());
+        // One changed field is enough to tell two configs apart
+        let toml = format!("{}\nmetrics_host = \"0.0.0.0\"", BASE_TOML);
+        let changed: Config = toml::from_str(&toml).unwrap();
+        assert!(config != changed);
+    }
+
+    #[test]
+    fn test_env_overrides() {
+        std::env::set_var("SKELETON_TEST_KEY", "env_key");
+        std::env::set_var("SKELETON_TEST_SECRET", "env_secret");
+        let toml = BASE_TOML.replace(
+            r#"["key", "secret", "NOTUSDT"]"#,
+            r#"["${SKELETON_TEST_KEY}", "${SKELETON_TEST_SECRET}", "NOTUSDT"]"#,
+        );
+        let config: Config = parse_toml(&toml).unwrap();
+        assert_eq!(
+            config.api_keys,
+            vec![("env_key".into(), "env_secret".into(), "NOTUSDT".into())]
+        );
+
+        // Plain values are left alone
+        let config: Config = parse_toml(BASE_TOML).unwrap();
+        assert_eq!(config.api_keys[0].0, "key");
+
+        let toml = BASE_TOML.replace(r#""token""#, r#""${SKELETON_TEST_UNSET}""#);
+        let error = parse_toml::<Config>(&toml).unwrap_err().to_string();
+        assert!(error.contains("SKELETON_TEST_UNSET"));
+        assert!(error.contains("token"));
+    }
+
     #[tokio::test]
     async fn test_watch_config() {
         use tokio::time::{timeout, Duration};
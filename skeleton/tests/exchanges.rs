@@ -2,12 +2,23 @@
 mod tests {
     use std::time::Duration;
 
-    use skeleton::exchange::exchange::{Exchange, MarketData};
+    use skeleton::exchange::exchange::{
+        wait_for_shutdown, Exchange, Heartbeat, MarketData, Subscription,
+    };
     use skeleton::ss::SharedState;
 
     use skeleton::utils::localorderbook::OrderBook;
-    use skeleton::utils::models::{BinanceClient, BinanceMarket, BybitClient};
-    use tokio::sync::mpsc;
+    use skeleton::utils::logger::{LogLevel, Logger};
+    use binance::{futures::websockets::FuturesWebsocketEvent, model::Filters};
+    use bybit::model::{Order, PositionInfo, Side};
+    use skeleton::exchange::ex_binance::{binance_error_code, binance_fee_tier};
+    use skeleton::exchange::ex_bybit::{book_depths, build_request, signed_position};
+    use skeleton::utils::models::{
+        BinanceClient, BinanceMarket, BinancePrivate, BybitClient, FeeTier, LiveOrder, SymbolInfo,
+        TimeInForce,
+    };
+    use tokio::sync::{mpsc, oneshot, watch};
+    use tokio_stream::StreamExt;
 
     #[tokio::test]
     async fn test_bybit_market() {
@@ -15,9 +26,10 @@ mod tests {
         let api_secret: String = String::from("");
         let client = BybitClient::init(api_key, api_secret).await;
         let (sender, mut receiver) = mpsc::unbounded_channel();
+        let (_stop, shutdown) = watch::channel(false);
         tokio::spawn(async move {
             client
-                .market_subscribe(vec!["SOLUSDT".to_string()], sender)
+                .market_subscribe(vec!["SOLUSDT".to_string()], sender, shutdown)
                 .await;
         });
         while let Some(data) = receiver.recv().await {
@@ -40,6 +52,293 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_dropping_a_subscription_stops_it() {
+        let (stopped_sender, stopped) = oneshot::channel();
+        let mut stream = Subscription::spawn(|sender, mut shutdown| async move {
+            for update in 1..=3 {
+                let _ = sender.send(update);
+            }
+            wait_for_shutdown(&mut shutdown).await;
+            let _ = stopped_sender.send(());
+        })
+        .map(|update| update * 10);
+        let first: Vec<i32> = (&mut stream).take(3).collect().await;
+        assert_eq!(first, [10, 20, 30]);
+
+        drop(stream);
+        tokio::time::timeout(Duration::from_secs(1), stopped)
+            .await
+            .expect("the subscription stops once its stream is dropped")
+            .unwrap();
+    }
+
+    #[test]
+    fn test_build_request_only_subscribes_the_requested_depths() {
+        let symbols = vec!["BTCUSDT".to_string(), "ETHUSDT".to_string()];
+        // Imbalance over 5 and 50 levels only needs the depth 50 feed past the touch
+        let depths = book_depths(&[5, 50]);
+        assert_eq!(depths, [1, 50]);
+        assert_eq!(
+            build_request(&symbols, &depths),
+            [
+                "orderbook.1.BTCUSDT",
+                "orderbook.50.BTCUSDT",
+                "tickers.BTCUSDT",
+                "publicTrade.BTCUSDT",
+                "orderbook.1.ETHUSDT",
+                "orderbook.50.ETHUSDT",
+                "tickers.ETHUSDT",
+                "publicTrade.ETHUSDT",
+            ]
+        );
+        assert_eq!(book_depths(&[100]), [1, 200]);
+        assert_eq!(book_depths(&[1000]), [1, 500]);
+        assert_eq!(book_depths(&[]), [1, 50, 200]);
+    }
+
+    #[test]
+    fn test_binance_error_code() {
+        let rejected = r#"BinanceContentError { code: -5022, msg: "Due to the order could not be executed as maker, the Post Only order will be rejected." }"#;
+        assert_eq!(binance_error_code(rejected), Some(-5022));
+        assert_eq!(binance_error_code("-2019: Margin is insufficient."), Some(-2019));
+        assert_eq!(binance_error_code("error sending request: connection reset"), None);
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_goes_stale() {
+        let heartbeat = Heartbeat::new();
+        let timeout = Duration::from_millis(50);
+        // Each message pushes the timeout back
+        let beats = heartbeat.clone();
+        let stale = tokio::spawn(async move { heartbeat.stale(timeout).await });
+        for _ in 0..4 {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            beats.beat();
+        }
+        assert!(!stale.is_finished());
+        assert!(beats.elapsed() < timeout);
+
+        tokio::time::timeout(Duration::from_secs(1), stale)
+            .await
+            .expect("a quiet stream goes stale")
+            .unwrap();
+        assert!(beats.elapsed() >= timeout);
+
+        // A new connection only counts as working once it delivers a message
+        assert!(beats.received());
+        beats.rearm();
+        assert!(!beats.received());
+        assert!(beats.elapsed() < timeout);
+        beats.beat();
+        assert!(beats.received());
+    }
+
+    #[test]
+    fn test_bybit_dcp_request() {
+        let request = BybitClient::dcp_request(10);
+        assert_eq!(request.product, Some("DERIVATIVES"));
+        assert_eq!(request.time_window, 10);
+        // Bybit only accepts 3 to 300 seconds
+        assert_eq!(BybitClient::dcp_request(1).time_window, 3);
+        assert_eq!(BybitClient::dcp_request(1000).time_window, 300);
+    }
+
+    #[test]
+    fn test_bybit_open_order_mapping() {
+        let order = Order {
+            order_id: "1".to_string(),
+            price: 100.0,
+            qty: 2.0,
+            side: Side::Sell,
+            order_status: "PartiallyFilled".to_string(),
+            leaves_qty: 1.5,
+            cum_exec_qty: 0.5,
+            ..Default::default()
+        };
+        let live = LiveOrder::from_bybit_order(&order).unwrap();
+        assert_eq!(live.order_id, "1");
+        assert_eq!(live.qty, 1.5);
+        assert!(!live.is_buy);
+
+        // Orders that no longer rest on the book are skipped
+        let filled = Order {
+            order_status: "Filled".to_string(),
+            leaves_qty: 0.0,
+            ..order.clone()
+        };
+        assert!(LiveOrder::from_bybit_order(&filled).is_none());
+        let cancelled = Order {
+            order_status: "Cancelled".to_string(),
+            ..order
+        };
+        assert!(LiveOrder::from_bybit_order(&cancelled).is_none());
+    }
+
+    #[test]
+    fn test_bybit_signed_position() {
+        let position = |side: &str, size: f64| PositionInfo {
+            side: side.to_string(),
+            size,
+            ..Default::default()
+        };
+        // No open position comes back as an empty list
+        assert_eq!(signed_position(&[]), 0.0);
+        assert_eq!(signed_position(&[position("", 0.0)]), 0.0);
+        assert_eq!(signed_position(&[position("Buy", 1.5)]), 1.5);
+        assert_eq!(signed_position(&[position("Sell", 2.0)]), -2.0);
+        // Hedge mode nets both sides
+        assert_eq!(
+            signed_position(&[position("Buy", 1.5), position("Sell", 2.0)]),
+            -0.5
+        );
+    }
+
+    #[test]
+    fn test_time_in_force_mapping() {
+        use binance::futures::account::TimeInForce as BinanceTimeInForce;
+
+        assert_eq!(TimeInForce::GoodTilCancel.to_bybit(), "GTC");
+        assert_eq!(TimeInForce::PostOnly.to_bybit(), "PostOnly");
+        assert_eq!(TimeInForce::ImmediateOrCancel.to_bybit(), "IOC");
+        assert_eq!(TimeInForce::FillOrKill.to_bybit(), "FOK");
+
+        assert!(matches!(
+            TimeInForce::GoodTilCancel.to_binance(),
+            BinanceTimeInForce::GTC
+        ));
+        assert!(matches!(
+            TimeInForce::PostOnly.to_binance(),
+            BinanceTimeInForce::GTX
+        ));
+        assert!(matches!(
+            TimeInForce::ImmediateOrCancel.to_binance(),
+            BinanceTimeInForce::IOC
+        ));
+        assert!(matches!(
+            TimeInForce::FillOrKill.to_binance(),
+            BinanceTimeInForce::FOK
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_bybit_cancel_on_disconnect() {
+        // Needs real credentials, skipped otherwise
+        let (Ok(api_key), Ok(api_secret)) = (
+            std::env::var("BYBIT_API_KEY"),
+            std::env::var("BYBIT_API_SECRET"),
+        ) else {
+            return;
+        };
+        let client = BybitClient::new(api_key, api_secret, Logger::capturing());
+        assert!(client.enable_cancel_on_disconnect(10).await);
+        assert_eq!(client.logger.captured()[0].0, LogLevel::Success);
+    }
+
+    #[tokio::test]
+    async fn test_binance_amend_order() {
+        let client = BinanceClient::new(String::new(), String::new(), Logger::capturing());
+        // Binance order ids are numeric, a bad one fails before any request is sent
+        assert!(client
+            .amend_order("not-an-id", 100.0, 1.0, "SOLUSDT", true)
+            .await
+            .is_err());
+
+        // Needs real credentials and a resting order, skipped otherwise
+        let (Ok(api_key), Ok(api_secret), Ok(order_id)) = (
+            std::env::var("BINANCE_API_KEY"),
+            std::env::var("BINANCE_API_SECRET"),
+            std::env::var("BINANCE_ORDER_ID"),
+        ) else {
+            return;
+        };
+        let client = BinanceClient::new(api_key, api_secret, Logger::capturing());
+        let amended = client
+            .amend_order(&order_id, 100.0, 1.0, "SOLUSDT", true)
+            .await
+            .unwrap();
+        assert_ne!(amended.order_id, order_id);
+        assert_eq!((amended.price, amended.qty, amended.is_buy), (100.0, 1.0, true));
+    }
+
+    #[test]
+    fn test_binance_symbol_info_filters() {
+        // Shuffled, Binance doesn't guarantee the order
+        let filters = vec![
+            Filters::MinNotional {
+                notional: Some("5".to_string()),
+                min_notional: None,
+                apply_to_market: None,
+                avg_price_mins: None,
+            },
+            Filters::MaxNumOrders {
+                max_num_orders: None,
+                limit: Some(200),
+            },
+            Filters::LotSize {
+                min_qty: "0.01".to_string(),
+                max_qty: "1000000".to_string(),
+                step_size: "0.01".to_string(),
+            },
+            Filters::PriceFilter {
+                min_price: "0.4200".to_string(),
+                max_price: "6857".to_string(),
+                tick_size: "0.0100".to_string(),
+            },
+            Filters::MarketLotSize {
+                min_qty: "0.01".to_string(),
+                max_qty: "5000".to_string(),
+                step_size: "0.01".to_string(),
+            },
+        ];
+
+        let info = SymbolInfo::from_binance_filters(&filters);
+        assert_eq!(info.tick_size, 0.01);
+        assert_eq!(info.lot_size, 0.01);
+        assert_eq!(info.min_notional, 5.0);
+        assert_eq!(info.min_qty, 0.01);
+        assert_eq!(info.post_only_max, 1_000_000.0);
+
+        let missing = SymbolInfo::from_binance_filters(&filters[..2]);
+        assert_eq!((missing.tick_size, missing.min_qty), (0.0, 0.0));
+    }
+
+    #[test]
+    fn test_binance_fee_tier() {
+        assert_eq!(binance_fee_tier(0.0), FeeTier::new(0.0002, 0.0005));
+        assert_eq!(binance_fee_tier(9.0), FeeTier::new(0.0, 0.00017));
+        // Unknown tiers are charged like VIP 0
+        assert_eq!(binance_fee_tier(42.0), FeeTier::new(0.0002, 0.0005));
+    }
+
+    // Payloads from the Binance Futures user data stream docs
+    const ORDER_TRADE_UPDATE: &str = r#"{"e":"ORDER_TRADE_UPDATE","E":1568879465651,"T":1568879465650,"o":{"s":"SOLUSDT","c":"TEST","S":"BUY","o":"LIMIT","f":"GTX","q":"2","p":"150.5","ap":"150.5","sp":"0","x":"TRADE","X":"PARTIALLY_FILLED","i":8886774,"l":"1","z":"1","L":"150.5","N":"USDT","n":"0.03","T":1568879465650,"t":42,"b":"0","a":"0","m":true,"R":false,"wt":"CONTRACT_PRICE","ot":"LIMIT","ps":"BOTH","cp":false,"rp":"0"}}"#;
+    const ACCOUNT_UPDATE: &str = r#"{"e":"ACCOUNT_UPDATE","E":1564745798939,"T":1564745798938,"a":{"m":"ORDER","B":[{"a":"USDT","wb":"122624.12345678","cw":"100.12345678","bc":"50.12345678"}],"P":[{"s":"SOLUSDT","pa":"1","ep":"150.5","cr":"200","up":"0","mt":"cross","iw":"0.00000000","ps":"BOTH"},{"s":"BTCUSDT","pa":"0","ep":"0.00000","cr":"200","up":"0","mt":"isolated","iw":"0.00000000","ps":"BOTH"}]}}"#;
+
+    #[test]
+    fn test_binance_private_update() {
+        let order =
+            FuturesWebsocketEvent::OrderTrade(serde_json::from_str(ORDER_TRADE_UPDATE).unwrap());
+        let account =
+            FuturesWebsocketEvent::AccountUpdate(serde_json::from_str(ACCOUNT_UPDATE).unwrap());
+
+        let mut private = BinancePrivate::default();
+        // The stream is account wide, other symbols are skipped
+        assert!(!private.update("BTCUSDT", order.clone()));
+        assert!(private.orders.is_empty());
+
+        assert!(private.update("SOLUSDT", order));
+        assert_eq!(private.time, 1568879465651);
+        assert_eq!(private.orders.len(), 1);
+        assert_eq!(private.executions[0].order_id, 8886774);
+        assert_eq!(private.executions[0].qty_last_filled_trade, "1");
+
+        assert!(private.update("SOLUSDT", account));
+        assert_eq!(private.wallet[0].asset, "USDT");
+        assert_eq!(private.positions.len(), 1);
+        assert_eq!(private.positions[0].position_amount, "1");
+    }
+
     #[tokio::test]
     async fn test_binance_market() {
         let api_key: String = String::from("");
@@ -47,9 +346,10 @@ mod tests {
         let client = BinanceClient::init(api_key, api_secret).await;
         let (sender, mut receiver) = mpsc::unbounded_channel::<BinanceMarket>();
         let sender_clone = sender.clone();
+        let (_stop, shutdown) = watch::channel(false);
         tokio::spawn(async move {
             client
-                .market_subscribe(vec!["SOLUSDT".to_string()], sender_clone)
+                .market_subscribe(vec!["SOLUSDT".to_string()], sender_clone, shutdown)
                 .await;
         });
 
@@ -75,9 +375,10 @@ mod tests {
         let api_secret: String = String::from("");
         let client = BybitClient::init(api_key, api_secret).await;
         let (sender, mut receiver) = mpsc::unbounded_channel();
+        let (_stop, shutdown) = watch::channel(false);
         tokio::spawn(async move {
             client
-                .private_subscribe("SOLUSDT".to_string(), sender)
+                .private_subscribe("SOLUSDT".to_string(), sender, shutdown)
                 .await;
         });
 
@@ -98,12 +399,15 @@ mod tests {
             "DOGSUSDT".to_string(),
             BybitClient::init(api_key, api_secret).await,
         );
+        let mut v = ss.clone();
         let (sender, mut receiver) = mpsc::unbounded_channel();
+        let (_stop, shutdown) = watch::channel(false);
         tokio::spawn(async move {
-            SharedState::load_data(ss, sender).await;
+            SharedState::load_data(ss, sender, shutdown).await;
         });
         let instant = std::time::Instant::now();
-        while let Some(v) = receiver.recv().await {
+        while let Some(update) = receiver.recv().await {
+            v.apply(update);
             println!(
                 "Shared State: Bybit WMID: {:.7}",
                 match &v.markets[0] {
@@ -0,0 +1,40 @@
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use skeleton::utils::{
+        latency::{RequestLatency, MIN_LATENCY_REQUESTS},
+        metrics,
+    };
+
+    #[test]
+    fn test_request_latency() {
+        let latency = RequestLatency::new();
+        assert_eq!(latency.get("place_order", "SOLUSDT"), None);
+        assert_eq!(latency.slowest_avg_ms(), None);
+
+        // Clones share the latencies
+        let clone = latency.clone();
+        clone.record("place_order", "SOLUSDT", Duration::from_millis(10));
+        let place = latency.record("place_order", "SOLUSDT", Duration::from_millis(32));
+        assert_eq!(place.requests, 2);
+        assert!((place.last_ms - 32.0).abs() < 1e-9);
+        // EMA over 20 requests: 10 + 2 / 21 * (32 - 10)
+        assert!((place.avg_ms - (10.0 + 2.0 / 21.0 * 22.0)).abs() < 1e-9);
+        assert_eq!(latency.get("place_order", "SOLUSDT"), Some(place));
+        assert_eq!(latency.get("place_order", "ETHUSDT"), None);
+
+        // A few slow requests aren't a slow exchange yet
+        for _ in 0..MIN_LATENCY_REQUESTS - 1 {
+            latency.record("cancel_all", "ETHUSDT", Duration::from_millis(50));
+        }
+        assert_eq!(latency.slowest_avg_ms(), None);
+        latency.record("cancel_all", "ETHUSDT", Duration::from_millis(50));
+        assert_eq!(latency.slowest_avg_ms(), Some(50.0));
+        assert!(metrics::render()
+            .contains("smm_request_latency_ms{request=\"cancel_all\",symbol=\"ETHUSDT\"} 50"));
+
+        latency.reset();
+        assert_eq!(clone.slowest_avg_ms(), None);
+    }
+}
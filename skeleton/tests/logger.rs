@@ -1,6 +1,9 @@
 #[cfg(test)]
 mod tests {
-    use skeleton::utils::{bot::LiveBot, logger::Logger};
+    use skeleton::utils::{
+        bot::LiveBot,
+        logger::{FileSink, LogLevel, Logger},
+    };
 
     #[tokio::test]
     async fn test_logger() {
@@ -10,4 +13,51 @@ mod tests {
         tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
         println!("Logger test passed");
     }
+
+    #[test]
+    fn test_capturing_logger() {
+        let log = Logger::capturing();
+        let _ = log.clone().error("Failed to send batch orders");
+        assert_eq!(
+            log.captured(),
+            vec![(LogLevel::Error, "Failed to send batch orders".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_local_logger_keeps_nothing() {
+        let log = Logger::local();
+        assert!(log
+            .error("Failed to send batch orders")
+            .ends_with("Failed to send batch orders"));
+        assert!(log.captured().is_empty());
+    }
+
+    #[test]
+    fn test_min_level_drops_lower_levels() {
+        let log = Logger::capturing().with_min_level(LogLevel::Warning);
+        assert_eq!(log.info("Generated 8 orders"), "");
+        assert!(log
+            .error("Failed to send batch orders")
+            .ends_with("Failed to send batch orders"));
+        assert_eq!(
+            log.captured(),
+            vec![(LogLevel::Error, "Failed to send batch orders".to_string())]
+        );
+        assert!(LogLevel::Debug < LogLevel::Info && LogLevel::Error < LogLevel::Critical);
+    }
+
+    #[test]
+    fn test_file_sink_filters_levels() {
+        let dir = std::env::temp_dir().join(format!("rs_smm_v2_logs_{}", std::process::id()));
+        let sink = FileSink::new(&dir, Some(LogLevel::Info));
+        sink.write(LogLevel::Debug, "debug line");
+        sink.write(LogLevel::Info, "info line");
+        sink.write(LogLevel::Success, "success line");
+        sink.write(LogLevel::Error, "error line");
+
+        let contents = std::fs::read_to_string(sink.current_path()).unwrap();
+        assert_eq!(contents, "info line\nsuccess line\nerror line\n");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
 }
@@ -0,0 +1,470 @@
+#[cfg(test)]
+mod tests {
+    use binance::{
+        futures::model::OrderBook as BookSnapshot,
+        model::{Asks, Bids, DepthOrderBookEvent},
+    };
+    use bybit::model::{Ask, Bid, OrderBookUpdate, WsOrderBook};
+    use skeleton::{
+        exchange::{ex_binance::DepthSync, ex_bybit::BookSequence, exchange::MarketData},
+        ss::{SharedState, StateUpdate},
+        utils::{
+            localorderbook::OrderBook,
+            logger::Logger,
+            models::{
+                BinanceBook, BinanceClient, BinanceMarket, BybitBook, BybitClient, BybitMarket,
+                BybitPrivate,
+            },
+        },
+    };
+
+    #[test]
+    fn test_consolidated_bba() {
+        let mut bybit_book = BybitBook::new();
+        bybit_book.best_bid.price = 100.0;
+        bybit_book.best_ask.price = 100.3;
+        let mut binance_book = BinanceBook::new();
+        binance_book.best_bid.price = 100.1;
+        binance_book.best_ask.price = 100.2;
+
+        let mut state = SharedState::new("both".to_string());
+        if let MarketData::Bybit(market) = &mut state.markets[0] {
+            market.books.insert("BTCUSDT".to_string(), bybit_book);
+            let mut only_bybit = BybitBook::new();
+            only_bybit.best_bid.price = 2.0;
+            only_bybit.best_ask.price = 2.1;
+            market.books.insert("NOTUSDT".to_string(), only_bybit);
+        }
+        if let MarketData::Binance(market) = &mut state.markets[1] {
+            market.books.insert("BTCUSDT".to_string(), binance_book);
+        }
+
+        let bba = state.consolidated_bba();
+        let btc = &bba["BTCUSDT"];
+        // max of the bids, min of the asks
+        assert_eq!(btc.bid, 100.1);
+        assert_eq!(btc.bid_venue, "binance");
+        assert_eq!(btc.ask, 100.2);
+        assert_eq!(btc.ask_venue, "binance");
+
+        let not = &bba["NOTUSDT"];
+        assert_eq!((not.bid, not.ask), (2.0, 2.1));
+        assert_eq!((not.bid_venue, not.ask_venue), ("bybit", "bybit"));
+    }
+
+    #[test]
+    fn test_redundant_delta_is_noop() {
+        let asks = vec![
+            Ask {
+                price: 100.1,
+                qty: 2.0,
+            },
+            Ask {
+                price: 100.2,
+                qty: 3.0,
+            },
+        ];
+        let bids = vec![
+            Bid {
+                price: 100.0,
+                qty: 1.0,
+            },
+            Bid {
+                price: 99.9,
+                qty: 4.0,
+            },
+        ];
+        let mut book = BybitBook::new();
+        book.reset(asks.clone(), bids.clone(), 1, 1);
+        // The snapshot levels are known but the BBA is only set by the first delta
+        assert!(book.update_bba(asks.clone(), bids.clone(), 2, 2));
+
+        // Same levels re-sent with a newer timestamp
+        assert!(!book.update_bba(asks.clone(), bids.clone(), 3, 3));
+        assert!(!book.update(asks.clone(), bids.clone(), 4, 1));
+        // Removing a level nobody quotes changes nothing either
+        let gone = vec![Bid {
+            price: 99.0,
+            qty: 0.0,
+        }];
+        assert!(!book.update(vec![], gone, 5, 1));
+        assert_eq!(book.get_mid_price(), 100.05);
+
+        let mut moved = bids.clone();
+        moved[0].qty = 5.0;
+        assert!(book.update_bba(asks.clone(), moved, 6, 6));
+        assert_eq!(book.best_bid.qty, 5.0);
+        // Stale deltas are dropped
+        assert!(!book.update_bba(asks, bids, 6, 7));
+    }
+
+    fn snapshot(bid: (f64, f64), ask: (f64, f64)) -> BybitBook {
+        let mut book = BybitBook::new();
+        let asks = vec![Ask {
+            price: ask.0,
+            qty: ask.1,
+        }];
+        let bids = vec![Bid {
+            price: bid.0,
+            qty: bid.1,
+        }];
+        book.update_bba(asks, bids, 1, 1);
+        book
+    }
+
+    #[test]
+    fn test_price_impact_sign() {
+        let old = snapshot((100.0, 2.0), (100.2, 3.0));
+
+        // Bid moves up, the new level's volume counts
+        let bid_up = snapshot((100.1, 1.0), (100.2, 3.0));
+        assert_eq!(bid_up.price_impact(&old, None), 1.0);
+        // Bid moves down, the volume that left counts against it
+        let bid_down = snapshot((99.9, 5.0), (100.2, 3.0));
+        assert_eq!(bid_down.price_impact(&old, None), -2.0);
+        // Ask moves down
+        let ask_down = snapshot((100.0, 2.0), (100.1, 4.0));
+        assert_eq!(ask_down.price_impact(&old, None), 4.0);
+        // Ask moves up
+        let ask_up = snapshot((100.0, 2.0), (100.3, 1.0));
+        assert_eq!(ask_up.price_impact(&old, None), -3.0);
+
+        // Unchanged prices count the change in volume
+        let more_bids = snapshot((100.0, 3.5), (100.2, 3.0));
+        assert_eq!(more_bids.price_impact(&old, None), 1.5);
+        let fewer_asks = snapshot((100.0, 2.0), (100.2, 1.0));
+        assert_eq!(fewer_asks.price_impact(&old, None), -2.0);
+        assert_eq!(old.price_impact(&old, None), 0.0);
+    }
+
+    #[test]
+    fn test_crossed_book_is_cleared() {
+        let mut book = snapshot((100.0, 2.0), (100.2, 3.0));
+        assert!(!book.is_crossed());
+
+        // A bid through the stale ask crosses the book
+        let through = vec![Bid {
+            price: 100.3,
+            qty: 1.0,
+        }];
+        assert!(book.update_bba(vec![], through, 2, 2));
+        assert!(book.is_crossed());
+        assert!(book.needs_snapshot);
+        assert!(book.bids.is_empty() && book.asks.is_empty());
+        assert_eq!(book.get_mid_price(), 0.0);
+
+        // Deltas don't say which of the old levels are left, even with both sides back
+        let bid = vec![Bid {
+            price: 100.3,
+            qty: 1.0,
+        }];
+        let ask = vec![Ask {
+            price: 100.4,
+            qty: 2.0,
+        }];
+        book.update_bba(ask.clone(), bid.clone(), 3, 3);
+        assert!(book.is_crossed());
+        assert!(book.needs_snapshot);
+
+        // Only a snapshot clears the flag
+        book.reset(ask, bid, 4, 4);
+        assert!(!book.needs_snapshot);
+        assert!(!book.is_crossed());
+    }
+
+    #[test]
+    fn test_expected_fill_price() {
+        let mut book = BybitBook::new();
+        let asks = vec![
+            Ask {
+                price: 100.1,
+                qty: 1.0,
+            },
+            Ask {
+                price: 100.2,
+                qty: 3.0,
+            },
+        ];
+        let bids = vec![
+            Bid {
+                price: 100.0,
+                qty: 2.0,
+            },
+            Bid {
+                price: 99.8,
+                qty: 2.0,
+            },
+        ];
+        book.reset(asks, bids, 1, 1);
+
+        // Within the best level
+        assert_eq!(book.expected_fill_price(0.5, true), Some(100.1));
+        // 1 @ 100.1 and 1 @ 100.2
+        let buy = book.expected_fill_price(2.0, true).unwrap();
+        assert!((buy - 100.15).abs() < 1e-9);
+        // 2 @ 100.0 and 2 @ 99.8
+        let sell = book.expected_fill_price(4.0, false).unwrap();
+        assert!((sell - 99.9).abs() < 1e-9);
+
+        // More than the book holds, or nothing at all
+        assert_eq!(book.expected_fill_price(4.5, false), None);
+        assert_eq!(book.expected_fill_price(0.0, true), None);
+
+        let mut binance_book = BinanceBook::new();
+        binance_book.asks.insert(100.1.into(), 1.0);
+        binance_book.asks.insert(100.3.into(), 1.0);
+        let buy = binance_book.expected_fill_price(2.0, true).unwrap();
+        assert!((buy - 100.2).abs() < 1e-9);
+        assert_eq!(binance_book.expected_fill_price(1.0, false), None);
+    }
+
+    #[test]
+    fn test_depth_notional() {
+        let mut book = BybitBook::new();
+        book.tick_size = 0.1;
+        let asks = [(100.1, 1.0), (100.2, 2.0), (100.3, 3.0), (100.4, 4.0)]
+            .map(|(price, qty)| Ask { price, qty })
+            .to_vec();
+        let bids = [(100.0, 1.0), (99.9, 2.0), (99.8, 3.0), (99.6, 4.0)]
+            .map(|(price, qty)| Bid { price, qty })
+            .to_vec();
+        book.reset(asks, bids, 1, 1);
+
+        // The touch and the levels up to 2 ticks behind it
+        let (bids, asks) = book.depth_notional(2);
+        assert!((bids - (100.0 + 99.9 * 2.0 + 99.8 * 3.0)).abs() < 1e-9);
+        assert!((asks - (100.1 + 100.2 * 2.0 + 100.3 * 3.0)).abs() < 1e-9);
+        let (bids, asks) = book.depth_notional(0);
+        assert!((bids - 100.0).abs() < 1e-9 && (asks - 100.1).abs() < 1e-9);
+
+        let mut binance_book = BinanceBook::new();
+        binance_book.tick_size = 0.1;
+        binance_book.asks.insert(100.1.into(), 1.0);
+        binance_book.asks.insert(100.4.into(), 1.0);
+        assert_eq!(binance_book.depth_notional(2), (0.0, 100.1));
+    }
+
+    #[test]
+    fn test_add_binance_clients() {
+        let mut state = SharedState::new("both".to_string());
+        let logger = Logger::capturing();
+        state.add_clients(
+            "SOLUSDT".to_string(),
+            BybitClient::new(String::new(), String::new(), logger.clone()),
+        );
+        for symbol in ["SOLUSDT", "BTCUSDT"] {
+            state.add_binance_clients(
+                symbol.to_string(),
+                BinanceClient::new(String::new(), String::new(), logger.clone()),
+            );
+        }
+
+        // A symbol quoted on both venues is only streamed once
+        assert_eq!(state.symbols, vec!["SOLUSDT", "BTCUSDT"]);
+        assert_eq!(state.binance_clients.len(), 2);
+        assert!(state.binance_privates["BTCUSDT"].orders.is_empty());
+        assert!(!state.privates.contains_key("BTCUSDT"));
+    }
+
+    #[test]
+    fn test_apply_state_updates() {
+        let mut state = SharedState::new("both".to_string());
+
+        let mut bybit = BybitMarket {
+            timestamp: 1,
+            ..Default::default()
+        };
+        bybit.books.insert("BTCUSDT".to_string(), BybitBook::new());
+        state.apply(StateUpdate::BybitMarket(bybit));
+        let binance = BinanceMarket {
+            timestamp: 2,
+            ..Default::default()
+        };
+        state.apply(StateUpdate::BinanceMarket(binance));
+        let private = BybitPrivate {
+            session: 3,
+            ..Default::default()
+        };
+        state.apply(StateUpdate::BybitPrivate("BTCUSDT".to_string(), private));
+
+        // Each update only replaces its own part of the state
+        match &state.markets[..] {
+            [MarketData::Bybit(bybit), MarketData::Binance(binance)] => {
+                assert_eq!(bybit.timestamp, 1);
+                assert!(bybit.books.contains_key("BTCUSDT"));
+                assert_eq!(binance.timestamp, 2);
+            }
+            other => panic!("unexpected markets {:?}", other.len()),
+        }
+        assert_eq!(state.privates["BTCUSDT"].session, 3);
+        assert!(state.binance_privates.is_empty());
+    }
+
+    fn book_update(event_type: &str, depth: usize, update_id: u64) -> OrderBookUpdate {
+        OrderBookUpdate {
+            topic: format!("orderbook.{}.BTCUSDT", depth),
+            event_type: event_type.to_string(),
+            timestamp: update_id,
+            data: WsOrderBook {
+                symbol: "BTCUSDT".to_string(),
+                asks: vec![],
+                bids: vec![],
+                update_id,
+                seq: update_id,
+            },
+            cts: update_id,
+        }
+    }
+
+    #[test]
+    fn test_bybit_sequence_gaps() {
+        let mut sequence = BookSequence::new();
+
+        // Deltas wait for the snapshot of their topic
+        assert_eq!(sequence.accept(&book_update("delta", 50, 7)), Some(false));
+        assert_eq!(sequence.accept(&book_update("snapshot", 50, 8)), Some(true));
+        assert_eq!(sequence.accept(&book_update("delta", 50, 9)), Some(true));
+        assert_eq!(sequence.accept(&book_update("delta", 50, 10)), Some(true));
+        // A replay is skipped
+        assert_eq!(sequence.accept(&book_update("delta", 50, 10)), Some(false));
+
+        // Each topic counts on its own
+        assert_eq!(sequence.accept(&book_update("snapshot", 1, 500)), Some(true));
+        assert_eq!(sequence.accept(&book_update("delta", 1, 501)), Some(true));
+        assert_eq!(sequence.gaps(), 0);
+
+        // Update 11 went missing, the topic waits for a new snapshot
+        assert_eq!(sequence.accept(&book_update("delta", 50, 12)), None);
+        assert_eq!(sequence.gaps(), 1);
+        assert_eq!(sequence.accept(&book_update("delta", 50, 13)), Some(false));
+        assert_eq!(sequence.accept(&book_update("delta", 1, 502)), Some(true));
+        // A restarted service starts over from 1
+        assert_eq!(sequence.accept(&book_update("snapshot", 50, 1)), Some(true));
+        assert_eq!(sequence.accept(&book_update("delta", 50, 2)), Some(true));
+    }
+
+    #[test]
+    fn test_decay_rate_focuses_on_the_touch() {
+        let mut book = BybitBook::new();
+        // A thin touch on the asks, the bids' size sits further out
+        let asks = [(100.5, 3.0), (101.0, 1.0), (101.5, 1.0)]
+            .map(|(price, qty)| Ask { price, qty })
+            .to_vec();
+        let bids = [(100.0, 1.0), (99.5, 6.0), (99.0, 6.0)]
+            .map(|(price, qty)| Bid { price, qty })
+            .to_vec();
+        book.update_bba(asks, bids, 1, 1);
+
+        // A slow decay sees the deep bids, a fast one only the heavier ask at the touch
+        book.decay_rate = 0.1;
+        assert!(book.imbalance_ratio(Some(3)) > 0.0);
+        assert!(book.get_microprice(Some(3)) > book.get_mid_price());
+        book.decay_rate = 3.0;
+        assert!(book.imbalance_ratio(Some(3)) < 0.0);
+        assert!(book.get_microprice(Some(3)) < book.get_mid_price());
+
+        // The state hands its decay rate to the books it applies
+        let mut state = SharedState::new("bybit".to_string());
+        state.set_book_decay_rate(2.0);
+        let mut market = BybitMarket::default();
+        market.books.insert("BTCUSDT".to_string(), BybitBook::new());
+        state.apply(StateUpdate::BybitMarket(market));
+        match &state.markets[0] {
+            MarketData::Bybit(market) => assert_eq!(market.books["BTCUSDT"].decay_rate, 2.0),
+            _ => panic!("expected the Bybit market first"),
+        }
+    }
+
+    fn delta(
+        ids: (u64, u64, u64),
+        time: u64,
+        bid: (f64, f64),
+        ask: (f64, f64),
+    ) -> DepthOrderBookEvent {
+        let (first_update_id, final_update_id, previous_final_update_id) = ids;
+        DepthOrderBookEvent {
+            event_type: "depthUpdate".to_string(),
+            event_time: time,
+            symbol: "BTCUSDT".to_string(),
+            first_update_id,
+            final_update_id,
+            previous_final_update_id: Some(previous_final_update_id),
+            bids: vec![Bids {
+                price: bid.0,
+                qty: bid.1,
+            }],
+            asks: vec![Asks {
+                price: ask.0,
+                qty: ask.1,
+            }],
+        }
+    }
+
+    fn book_snapshot(last_update_id: u64) -> Option<BookSnapshot> {
+        Some(BookSnapshot {
+            last_update_id,
+            event_time: 0,
+            trade_order_time: 0,
+            bids: vec![Bids {
+                price: 100.0,
+                qty: 1.0,
+            }],
+            asks: vec![Asks {
+                price: 100.2,
+                qty: 1.0,
+            }],
+        })
+    }
+
+    #[test]
+    fn test_depth_sync_resnapshots_on_gaps() {
+        let mut book = BinanceBook::new();
+        let mut sync = DepthSync::new();
+
+        // Deltas are buffered until a snapshot comes through, retried once a second
+        assert!(!sync.apply(
+            &mut book,
+            delta((5, 8, 4), 1_000, (99.9, 1.0), (100.3, 1.0)),
+            || None
+        ));
+        let no_retry = || -> Option<BookSnapshot> { panic!("snapshot retried too soon") };
+        sync.apply(
+            &mut book,
+            delta((9, 12, 8), 1_100, (100.0, 2.0), (100.3, 0.0)),
+            no_retry,
+        );
+        assert!(book.is_crossed());
+
+        // The first delta is older than the snapshot, the second straddles it
+        let resync = delta((13, 15, 12), 2_000, (100.1, 1.0), (100.2, 3.0));
+        assert!(sync.apply(&mut book, resync, || book_snapshot(10)));
+        assert!(sync.is_synced());
+        assert!(!book.is_crossed());
+        assert_eq!((book.best_bid.price, book.best_bid.qty), (100.1, 1.0));
+        assert_eq!(book.bids[&100.0.into()], 2.0);
+        assert_eq!((book.best_ask.price, book.best_ask.qty), (100.2, 3.0));
+        assert_eq!(book.sequence, 15);
+
+        // A replayed delta is ignored
+        let replayed = delta((13, 15, 12), 2_000, (100.1, 5.0), (100.2, 3.0));
+        assert!(!sync.apply(&mut book, replayed, no_retry));
+        assert_eq!(sync.gaps(), 0);
+
+        // Updates 16 to 19 went missing
+        let gap = delta((20, 22, 19), 2_100, (100.1, 2.0), (100.2, 3.0));
+        sync.apply(&mut book, gap, || book_snapshot(30));
+        assert_eq!(sync.gaps(), 1);
+        assert!(!sync.is_synced());
+        // The old levels didn't survive the new snapshot
+        assert_eq!(book.bids.len(), 1);
+        assert_eq!(book.sequence, 30);
+        assert!(sync.apply(
+            &mut book,
+            delta((29, 31, 22), 2_200, (100.1, 1.0), (100.3, 1.0)),
+            no_retry
+        ));
+        assert!(sync.is_synced());
+        assert!(!book.is_crossed());
+        assert_eq!(book.get_mid_price(), 100.15);
+    }
+}
@@ -0,0 +1,49 @@
+#[cfg(test)]
+mod tests {
+    use skeleton::utils::metrics::{self, FILLS, LAST_UPDATE_MS, RECONNECTS};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+        sync::watch,
+    };
+
+    #[test]
+    fn test_render_prometheus_text() {
+        metrics::inc(RECONNECTS, &[("stream", "test_stream")]);
+        metrics::inc(RECONNECTS, &[("stream", "test_stream")]);
+        metrics::set(FILLS, &[("symbol", "TESTUSDT"), ("side", "buy")], 3.0);
+        metrics::set(LAST_UPDATE_MS, &[("stream", "quoted \"name\"")], 1.5);
+
+        let text = metrics::render();
+        assert!(text.contains("# TYPE smm_reconnects_total counter\n"));
+        assert!(text.contains("smm_reconnects_total{stream=\"test_stream\"} 2\n"));
+        assert!(text.contains("smm_fills_total{symbol=\"TESTUSDT\",side=\"buy\"} 3\n"));
+        assert!(text.contains("# TYPE smm_last_update_timestamp_ms gauge\n"));
+        assert!(text.contains("smm_last_update_timestamp_ms{stream=\"quoted \\\"name\\\"\"} 1.5\n"));
+
+        // Each metric is described once, ahead of its samples
+        assert_eq!(text.matches("# HELP smm_reconnects_total").count(), 1);
+        let help = text.find("# HELP smm_fills_total").unwrap();
+        assert!(help < text.find("smm_fills_total{").unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_serve_metrics() {
+        let (shutdown_sender, shutdown) = watch::channel(false);
+        let server = tokio::spawn(metrics::serve("127.0.0.1", 19184, shutdown));
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+        let mut stream = TcpStream::connect(("127.0.0.1", 19184)).await.unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("# TYPE smm_reconnects_total counter"));
+
+        shutdown_sender.send(true).unwrap();
+        assert!(server.await.unwrap().is_ok());
+    }
+}
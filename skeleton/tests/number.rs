@@ -1,7 +1,8 @@
 #[cfg(test)]
 mod tests {
     use skeleton::utils::number::{
-        decay, geometric_weights, geomspace, linspace, nbsqrt, round_step, Round,
+        decay, geometric_weights, geomspace, kahan_sum, linspace, nbsqrt, round_step,
+        truncate_weights, Round,
     };
 
     #[test]
@@ -20,6 +21,26 @@ mod tests {
         println!("Sell Weights: {:?}", sell_weights);
     }
     #[test]
+    fn test_truncate_weights() {
+        let weights = geometric_weights(0.37, 6, false);
+        let truncated = truncate_weights(&weights, 0.05);
+        // The last three levels are under 5% of the total and get dropped
+        assert_eq!(&truncated[3..], &[0.0, 0.0, 0.0]);
+        assert!(truncated[..3].iter().all(|&w| w > 0.0));
+        assert!((truncated.iter().sum::<f64>() - 1.0).abs() < 1e-12);
+        assert_eq!(truncate_weights(&weights, 0.0), weights);
+    }
+    #[test]
+    fn test_kahan_sum() {
+        // Each 1e-16 is under half an ulp of 1.0 and vanishes from a naive sum
+        let values: Vec<f64> = std::iter::once(1.0)
+            .chain(std::iter::repeat_n(1e-16, 10_000))
+            .collect();
+        let naive: f64 = values.iter().sum();
+        assert_eq!(naive, 1.0);
+        assert!((kahan_sum(values) - (1.0 + 1e-12)).abs() < 1e-15);
+    }
+    #[test]
     fn test_geomspace() {
         let (start, end, size) = (0.5, 0.76, 5);
         let result = geomspace(start, end, size);
@@ -62,4 +83,17 @@ mod tests {
         assert_eq!(value.clip(0.0, 100.0), 35.46324566);
         assert_eq!(value.count_decimal_places(), 8);
     }
+
+    #[test]
+    fn test_round_to_negative() {
+        assert_eq!((-2.5f64).round_to(0), -3.0);
+        assert_eq!(2.5f64.round_to(0), 3.0);
+        assert_eq!((-2.5f64).round_to(1), -2.5);
+        assert_eq!((-2.45f64).round_to(1), -2.5);
+        assert_eq!((-2.45f64).round_to(2), -2.45);
+        assert_eq!(2.5f64.round_to(2), 2.5);
+        assert_eq!(2.44f64.round_to(1), 2.4);
+        assert_eq!(2.44f64.round_to(2), 2.44);
+        assert_eq!((-2.44f64).round_to(1), -2.4);
+    }
 }
@@ -0,0 +1,32 @@
+#[cfg(test)]
+mod tests {
+    use skeleton::utils::{
+        models::LiveOrder,
+        persistence::{FilePersistence, PersistedState, StatePersistence},
+    };
+
+    #[test]
+    fn test_file_persistence_round_trip() {
+        let dir = std::env::temp_dir().join(format!("rs_smm_v2_state_{}", std::process::id()));
+        let persistence = FilePersistence::new(&dir);
+        assert!(persistence.load("SOLUSDT").unwrap().is_none());
+
+        let state = PersistedState {
+            live_buys: vec![LiveOrder::new("buy-1".to_string(), 99.5, 2.0, true)],
+            live_sells: vec![LiveOrder::new("sell-1".to_string(), 100.5, 1.5, false)],
+            position_qty: -0.5,
+            entry_price: 100.2,
+            realized_pnl: 1.25,
+        };
+        persistence.save("SOLUSDT", &state).unwrap();
+        let loaded = persistence.load("SOLUSDT").unwrap().unwrap();
+        assert_eq!(loaded, state);
+
+        // An amend keeps the order id but is still a change
+        let mut amended = loaded.clone();
+        amended.live_buys[0].price = 99.6;
+        assert_ne!(amended, state);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,114 @@
+#[cfg(test)]
+mod tests {
+    use bybit::model::WsTrade;
+    use ordered_float::OrderedFloat;
+    use skeleton::{
+        ss::StateUpdate,
+        utils::{
+            localorderbook::OrderBook,
+            models::{BinanceBook, BinanceMarket, BybitBook, BybitMarket, BybitPrivate},
+            recorder::{read_recording, RecordedEvent, Recorder, RECORDING_VERSION},
+            time::get_formatted_date,
+        },
+    };
+
+    fn trade(id: &str, price: f64) -> WsTrade {
+        WsTrade {
+            id: id.to_string(),
+            price,
+            volume: 1.0,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_recorder_round_trip() {
+        let dir = std::env::temp_dir().join(format!("rs_smm_v2_rec_{}", std::process::id()));
+        let (recorder, writer) = Recorder::start(&dir);
+
+        let mut book = BybitBook::new();
+        book.asks.insert(OrderedFloat(100.5), 2.0);
+        book.bids.insert(OrderedFloat(99.5), 1.5);
+        let mut market = BybitMarket {
+            timestamp: 42,
+            ..Default::default()
+        };
+        market.books.insert("SOLUSDT".to_string(), book);
+        market
+            .trades
+            .insert("SOLUSDT".to_string(), [trade("1", 100.0)].into());
+        recorder.record(&StateUpdate::BybitMarket(market.clone()));
+
+        // Only what changed is recorded again
+        let book = market.books.get_mut("SOLUSDT").unwrap();
+        book.asks.clear();
+        book.asks.insert(OrderedFloat(100.6), 1.0);
+        market
+            .trades
+            .get_mut("SOLUSDT")
+            .unwrap()
+            .push_back(trade("2", 100.1));
+        recorder.record(&StateUpdate::BybitMarket(market.clone()));
+        recorder.record(&StateUpdate::BybitMarket(market));
+
+        let mut binance_book = BinanceBook::new();
+        binance_book.bids.insert(OrderedFloat(99.4), 3.0);
+        let mut binance = BinanceMarket::default();
+        binance.books.insert("SOLUSDT".to_string(), binance_book);
+        recorder.record(&StateUpdate::BinanceMarket(binance));
+
+        let private = BybitPrivate {
+            session: 1,
+            ..Default::default()
+        };
+        recorder.record(&StateUpdate::BybitPrivate("SOLUSDT".to_string(), private));
+        drop(recorder);
+        writer.await.unwrap();
+
+        let (month, day, year) = get_formatted_date();
+        let path = dir.join(format!("recording-{}-{}-{}.ndjson", month, day, year));
+        let records = read_recording(&path).unwrap();
+        assert!(records.iter().all(|r| r.version == RECORDING_VERSION));
+        let events: Vec<_> = records.into_iter().map(|r| r.event).collect();
+        let book = |exchange: &str, bids: Vec<(f64, f64)>, asks: Vec<(f64, f64)>| {
+            RecordedEvent::Book {
+                exchange: exchange.to_string(),
+                symbol: "SOLUSDT".to_string(),
+                timestamp: 0,
+                bids,
+                asks,
+            }
+        };
+        let trades = |trades: &[WsTrade]| RecordedEvent::Entries {
+            exchange: "bybit".to_string(),
+            symbol: "SOLUSDT".to_string(),
+            stream: "trades".to_string(),
+            entries: trades
+                .iter()
+                .map(|trade| serde_json::to_value(trade).unwrap())
+                .collect(),
+        };
+        assert_eq!(
+            events,
+            [
+                book("bybit", vec![(99.5, 1.5)], vec![(100.5, 2.0)]),
+                trades(&[trade("1", 100.0)]),
+                book("bybit", vec![], vec![(100.5, 0.0), (100.6, 1.0)]),
+                trades(&[trade("2", 100.1)]),
+                book("binance", vec![(99.4, 3.0)], vec![]),
+                RecordedEvent::Session {
+                    exchange: "bybit".to_string(),
+                    symbol: "SOLUSDT".to_string(),
+                    session: 1,
+                },
+            ]
+        );
+
+        // Records of an unknown version are rejected
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::write(&path, contents.replace("\"version\":2", "\"version\":99")).unwrap();
+        assert!(read_recording(&path).is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
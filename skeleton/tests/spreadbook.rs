@@ -0,0 +1,81 @@
+#[cfg(test)]
+mod tests {
+    use skeleton::utils::{
+        localorderbook::OrderBook,
+        models::{BybitBook, BybitMarket},
+        spreadbook::SpreadBook,
+    };
+
+    fn book(bids: &[(f64, f64)], asks: &[(f64, f64)]) -> BybitBook {
+        let mut book = BybitBook::new();
+        book.tick_size = 0.01;
+        book.lot_size = 0.1;
+        for &(price, qty) in bids {
+            book.bids.insert(price.into(), qty);
+        }
+        for &(price, qty) in asks {
+            book.asks.insert(price.into(), qty);
+        }
+        let (bid, bid_qty) = bids[0];
+        let (ask, ask_qty) = asks[0];
+        book.best_bid.price = bid;
+        book.best_bid.qty = bid_qty;
+        book.best_ask.price = ask;
+        book.best_ask.qty = ask_qty;
+        book.set_mid_price();
+        book
+    }
+
+    #[test]
+    fn test_spread_book_pairs_the_legs() {
+        let leg_a = book(&[(100.0, 2.0), (99.9, 5.0)], &[(100.1, 1.0), (100.2, 4.0)]);
+        let leg_b = book(&[(50.0, 3.0), (49.9, 1.0)], &[(50.05, 3.0), (50.1, 2.0)]);
+        let mut spread = SpreadBook::new("AUSDT", "BUSDT");
+        spread.update(&leg_a, &leg_b);
+        let synthetic = spread.book();
+
+        // Selling the spread: A's bids against B's asks, as deep as both legs go
+        let bids: Vec<(f64, f64)> = synthetic
+            .bids
+            .iter()
+            .rev()
+            .map(|(p, q)| (p.0, *q))
+            .collect();
+        assert_eq!(bids, vec![(49.95, 2.0), (49.85, 1.0), (49.8, 2.0)]);
+        // Buying it: A's asks against B's bids
+        let asks: Vec<(f64, f64)> = synthetic.asks.iter().map(|(p, q)| (p.0, *q)).collect();
+        assert_eq!(asks, vec![(50.1, 1.0), (50.2, 2.0), (50.3, 1.0)]);
+
+        assert_eq!(synthetic.get_best_bid().price, 49.95);
+        assert_eq!(synthetic.get_best_ask().price, 50.1);
+        assert!((synthetic.get_mid_price() - 50.025).abs() < 1e-9);
+        assert!(!synthetic.is_crossed());
+        // The bigger touch is on the bid, which pulls the microprice up from the mid
+        assert!(synthetic.imbalance_ratio(None) > 0.0);
+        assert!(synthetic.get_microprice(None) > synthetic.get_mid_price());
+    }
+
+    #[test]
+    fn test_spread_book_from_market() {
+        let mut market = BybitMarket::default();
+        market
+            .books
+            .insert("AUSDT".to_string(), book(&[(100.0, 1.0)], &[(100.1, 1.0)]));
+        let mut spread = SpreadBook::new("AUSDT", "BUSDT");
+        assert!(!spread.update_from_market(&market));
+
+        market
+            .books
+            .insert("BUSDT".to_string(), book(&[(101.0, 1.0)], &[(101.1, 1.0)]));
+        assert!(spread.update_from_market(&market));
+        // A calendar spread trading below zero
+        assert!((spread.book().get_mid_price() + 1.0).abs() < 1e-9);
+        assert_eq!(spread.previous().get_mid_price(), 0.0);
+
+        assert!(spread.update_from_market(&market));
+        assert_eq!(
+            spread.previous().get_mid_price(),
+            spread.book().get_mid_price()
+        );
+    }
+}
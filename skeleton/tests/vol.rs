@@ -0,0 +1,83 @@
+#[cfg(test)]
+mod tests {
+    use skeleton::utils::vol::{EwmaVolatility, ParkinsonVolatility, RollingVolatility};
+
+    #[test]
+    fn test_ewma_reacts_faster_to_a_burst() {
+        let window = 20;
+        let mut rolling = RollingVolatility::new(window);
+        let mut ewma = EwmaVolatility::with_window(window);
+
+        // Calm market, +/-1 bp returns
+        let mut price = 100.0;
+        for i in 0..100 {
+            price *= if i % 2 == 0 { 1.0001 } else { 0.9999 };
+            rolling.update(price);
+            ewma.update(price);
+        }
+        let calm = (rolling.current_vol, ewma.current_vol);
+        assert!((calm.0 - calm.1).abs() / calm.0 < 0.05);
+
+        // Three +/-1% returns
+        for i in 0..3 {
+            price *= if i % 2 == 0 { 1.01 } else { 0.99 };
+            rolling.update(price);
+            ewma.update(price);
+        }
+        assert!(ewma.current_vol / calm.1 > rolling.current_vol / calm.0);
+    }
+
+    #[test]
+    fn test_ewma_first_returns() {
+        let mut ewma = EwmaVolatility::default();
+        assert!(ewma.update(100.0).is_none());
+        // The first return seeds the variance
+        assert!(ewma.update(101.0).is_none());
+        assert!((ewma.current_vol - (1.01f64).ln().abs()).abs() < 1e-12);
+
+        let (vol, _) = ewma.update(101.0).unwrap();
+        assert!((vol - (0.94f64).sqrt() * (1.01f64).ln()).abs() < 1e-12);
+        assert_eq!(ewma.current_count(), 2);
+    }
+
+    #[test]
+    fn test_parkinson_volatility() {
+        let mut parkinson = ParkinsonVolatility::new(2);
+        let expected = |high: f64, low: f64| (high / low).ln() / (4.0 * 2f64.ln()).sqrt();
+
+        let vol = parkinson.update(101.0, 99.0).unwrap();
+        assert!((vol - expected(101.0, 99.0)).abs() < 1e-12);
+
+        // Inverted or non-positive ranges are skipped
+        assert!(parkinson.update(99.0, 101.0).is_none());
+        assert!(parkinson.update(1.0, 0.0).is_none());
+        assert_eq!(parkinson.current_count(), 1);
+
+        // The window averages the squared ranges
+        parkinson.update(100.0, 100.0);
+        parkinson.update(100.0, 100.0);
+        assert_eq!(parkinson.current_count(), 2);
+        assert_eq!(parkinson.current_vol, 0.0);
+    }
+
+    #[test]
+    fn test_rolling_z_score() {
+        let mut rolling = RollingVolatility::new(30);
+        assert_eq!(rolling.z_score(), 0.0);
+
+        // Returns cycling through +1 bp, -1 bp and flat, the flat one sits on the mean
+        let mut price = 100.0;
+        for i in 0..60 {
+            price *= [1.0001, 0.9999, 1.0][i % 3];
+            rolling.update(price);
+        }
+        assert!(rolling.z_score().abs() < 0.01);
+
+        // A 1% jump is far outside a window of 1 bp moves
+        rolling.update(price * 1.01);
+        assert!(rolling.z_score() > 4.0);
+
+        rolling.reset();
+        assert_eq!(rolling.z_score(), 0.0);
+    }
+}
@@ -0,0 +1,100 @@
+use skeleton::exchange::exchange::TradeType;
+
+/// High and low of the trade prices in fixed periods, e.g. to feed a range volatility once
+/// per period rather than once per tick.
+///
+/// Each update only adds the trades that arrived since the last one. A bar closes once a
+/// trade of a later period arrives, periods without trades have no bar.
+#[derive(Debug, Clone)]
+pub struct TradeBars {
+    period_ms: u64,
+    // period the open bar covers and its high and low so far
+    bar: Option<(u64, f64, f64)>,
+    // id of the newest trade already added
+    last_trade: Option<String>,
+}
+
+impl TradeBars {
+    pub fn new(period_ms: u64) -> Self {
+        Self {
+            period_ms: period_ms.max(1),
+            bar: None,
+            last_trade: None,
+        }
+    }
+
+    /// Adds the trades that arrived since the last update and returns the (high, low) of
+    /// the bars they closed, oldest first. `trades` is the rolling trade buffer of the
+    /// symbol, oldest first.
+    pub fn update(&mut self, trades: &TradeType) -> Vec<(f64, f64)> {
+        let start = self
+            .last_trade
+            .as_ref()
+            .and_then(|id| trades.iter().rposition(|trade| &trade.id == id))
+            .map_or(0, |i| i + 1);
+        if let Some(trade) = trades.back() {
+            self.last_trade = Some(trade.id.clone());
+        }
+
+        let mut closed = Vec::new();
+        for trade in trades.iter().skip(start) {
+            if !(trade.price > 0.0 && trade.price.is_finite()) {
+                continue;
+            }
+            let period = trade.timestamp / self.period_ms;
+            match &mut self.bar {
+                Some((open, high, low)) if *open == period => {
+                    *high = high.max(trade.price);
+                    *low = low.min(trade.price);
+                }
+                // Trades of a period already closed are late, they can't reopen it
+                Some((open, _, _)) if *open > period => {}
+                bar => {
+                    if let Some((_, high, low)) = bar.replace((period, trade.price, trade.price)) {
+                        closed.push((high, low));
+                    }
+                }
+            }
+        }
+        closed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use skeleton::exchange::exchange::WsTrade;
+
+    fn trade(id: usize, price: f64, timestamp: u64) -> WsTrade {
+        WsTrade {
+            id: id.to_string(),
+            price,
+            volume: 1.0,
+            timestamp,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_bars_close_once() {
+        let mut bars = TradeBars::new(60_000);
+        let mut trades = TradeType::new();
+        trades.push_back(trade(1, 100.0, 0));
+        trades.push_back(trade(2, 101.5, 10_000));
+        trades.push_back(trade(3, 99.5, 59_999));
+        assert!(bars.update(&trades).is_empty());
+        // The same trades on the next tick add nothing
+        assert!(bars.update(&trades).is_empty());
+
+        // A trade of the next minute closes the first bar, a gap skips a bar
+        trades.push_back(trade(4, 100.0, 60_000));
+        trades.push_back(trade(5, 102.0, 180_000));
+        assert_eq!(bars.update(&trades), [(101.5, 99.5), (100.0, 100.0)]);
+        assert!(bars.update(&trades).is_empty());
+
+        // A late trade of a closed bar is left out
+        trades.push_back(trade(6, 90.0, 100_000));
+        trades.push_back(trade(7, 103.0, 240_000));
+        assert_eq!(bars.update(&trades), [(102.0, 102.0)]);
+    }
+}
@@ -0,0 +1,139 @@
+use std::collections::VecDeque;
+
+use skeleton::utils::localorderbook::OrderBook;
+
+/// Premium of a symbol's micro-price on the venue it's quoted on over the other venue,
+/// in bps, kept over `window` updates.
+///
+/// Venues keep a small standing basis from fees and funding, so the signal scores the
+/// latest premium against the window rather than against zero.
+#[derive(Debug, Clone)]
+pub struct CrossVenueBasis {
+    window: usize,
+    history: VecDeque<f64>,
+    sum: f64,
+    sum_squares: f64,
+    current: f64,
+}
+
+impl CrossVenueBasis {
+    pub fn new(window: usize) -> Self {
+        let window = window.max(2);
+        Self {
+            window,
+            history: VecDeque::with_capacity(window),
+            sum: 0.0,
+            sum_squares: 0.0,
+            current: 0.0,
+        }
+    }
+
+    /// Compares the books of the quoted venue and the other venue, skipping empty or
+    /// crossed books.
+    pub fn update(&mut self, quoted: &impl OrderBook, other: &impl OrderBook) {
+        if quoted.is_crossed() || other.is_crossed() {
+            return;
+        }
+        let (quoted_price, other_price) = (quoted.get_microprice(None), other.get_microprice(None));
+        if !(quoted_price > 0.0 && other_price > 0.0 && quoted_price.is_finite()) {
+            return;
+        }
+        let bps = (quoted_price - other_price) / other_price * 10_000.0;
+        if self.history.len() == self.window {
+            if let Some(old) = self.history.pop_front() {
+                self.sum -= old;
+                self.sum_squares -= old.powi(2);
+            }
+        }
+        self.history.push_back(bps);
+        self.sum += bps;
+        self.sum_squares += bps.powi(2);
+        self.current = bps;
+    }
+
+    /// Latest premium of the quoted venue in bps, negative while it trades cheap
+    pub fn bps(&self) -> f64 {
+        self.current
+    }
+
+    pub fn mean(&self) -> f64 {
+        if self.history.is_empty() {
+            return 0.0;
+        }
+        self.sum / self.history.len() as f64
+    }
+
+    /// Leans away from the quoted venue while it trades rich against its usual premium,
+    /// expecting the venues to converge, between -1.0 and 1.0.
+    pub fn signal(&self) -> f64 {
+        let n = self.history.len() as f64;
+        if n < 2.0 {
+            return 0.0;
+        }
+        let mean = self.mean();
+        let variance = (self.sum_squares / n - mean.powi(2)).max(0.0);
+        if variance <= f64::EPSILON {
+            return 0.0;
+        }
+        -((self.current - mean) / variance.sqrt()).tanh()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use skeleton::utils::models::{BinanceBook, BybitBook};
+
+    fn bybit_book(mid: f64) -> BybitBook {
+        let mut book = BybitBook::new();
+        book.best_bid.price = mid - 0.5;
+        book.best_bid.qty = 1.0;
+        book.best_ask.price = mid + 0.5;
+        book.best_ask.qty = 1.0;
+        book.set_mid_price();
+        book
+    }
+
+    fn binance_book(mid: f64) -> BinanceBook {
+        let mut book = BinanceBook::new();
+        book.best_bid.price = mid - 0.5;
+        book.best_bid.qty = 1.0;
+        book.best_ask.price = mid + 0.5;
+        book.best_ask.qty = 1.0;
+        book.set_mid_price();
+        book
+    }
+
+    #[test]
+    fn test_basis_is_the_quoted_venue_premium() {
+        let mut basis = CrossVenueBasis::new(10);
+        basis.update(&bybit_book(100.1), &binance_book(100.0));
+        assert!((basis.bps() - 10.0).abs() < 1e-9);
+        // Not enough history to score it yet
+        assert_eq!(basis.signal(), 0.0);
+
+        // Quoted on Binance the premium flips
+        let mut basis = CrossVenueBasis::new(10);
+        basis.update(&binance_book(100.0), &bybit_book(100.1));
+        assert!(basis.bps() < 0.0);
+
+        // Empty books are skipped
+        basis.update(&binance_book(100.0), &BybitBook::new());
+        assert_eq!(basis.mean(), basis.bps());
+    }
+
+    #[test]
+    fn test_signal_leans_against_an_unusual_premium() {
+        let mut basis = CrossVenueBasis::new(20);
+        for i in 0..19 {
+            // A standing premium of about 2 bps
+            let mid = if i % 2 == 0 { 100.02 } else { 100.01 };
+            basis.update(&bybit_book(mid), &binance_book(100.0));
+        }
+        assert!(basis.signal().abs() < 1.0);
+
+        basis.update(&bybit_book(100.2), &binance_book(100.0));
+        assert!(basis.bps() > 19.0);
+        assert!(basis.signal() < -0.9);
+    }
+}
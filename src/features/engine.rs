@@ -1,14 +1,34 @@
-use std::collections::VecDeque;
+use std::{collections::VecDeque, time::Duration};
 
+use serde::Serialize;
 use skeleton::{
     exchange::exchange::TradeType,
-    utils::{localorderbook::OrderBook, models::BybitBook, vol::RollingVolatility},
+    utils::{
+        ema::EMA,
+        localorderbook::{dead_zone, OrderBook},
+        models::{BybitBook, SkewWeights, VolEstimator},
+        vol::{EwmaVolatility, ParkinsonVolatility, RollingVolatility},
+    },
 };
 
 use super::{
+    bars::TradeBars,
+    basis::CrossVenueBasis,
     impact::{mid_price_avg, rate_of_change},
+    momentum::MicropriceMomentum,
+    rsi::Rsi,
     trade::{avg_trade_price, trade_imbalance},
+    vpin::Vpin,
+    vwap::SessionVwap,
 };
+// Period of the trade bars feeding the range volatility, that of the klines seeding it
+pub const RANGE_BAR_SECS: u64 = 60;
+// RSI beyond these levels counts as overbought / oversold
+const RSI_OVERBOUGHT: f64 = 70.0;
+const RSI_OVERSOLD: f64 = 30.0;
+// Funding rate per interval that maps to a funding skew of tanh(1), Bybit's default 0.01%
+const FUNDING_RATE_SCALE: f64 = 0.0001;
+
 #[derive(Debug, Clone)]
 pub struct Engine {
     pub bba_imbalance: f64,
@@ -18,14 +38,82 @@ pub struct Engine {
     pub trade_imbalance: f64,
     pub price_impact: f64,
     pub volatility: RollingVolatility,
+    pub ewma_volatility: EwmaVolatility,
+    pub range_volatility: ParkinsonVolatility,
+    // bars of the trades, each closed one feeds its high and low to the range volatility
+    range_bars: TradeBars,
+    pub vol_estimator: VolEstimator,
     pub rate_of_change: ROC,
+    pub microprice_momentum: MicropriceMomentum,
+    /// Premium of the quoted venue over the other one, only fed while both are streamed
+    pub cross_venue_basis: CrossVenueBasis,
+    pub rsi: Rsi,
+    pub vpin: Vpin,
     pub avg_trade_price: f64,
+    pub vwap: SessionVwap,
     pub mpb: MPB,
+    /// Composite skew of the latest update, before smoothing
     pub skew: f64,
+    pub skew_ema: EMA,
+    pub skew_weights: SkewWeights,
+    pub imbalance_corr: RollingCorrelation,
+    pub ofi_corr: RollingCorrelation,
+    // trade imbalance and ofi of the last update, scored against the next mid move
+    last_signals: Option<(f64, f64)>,
+    pub anomaly: AnomalyGuard,
+    /// Z-score of the mid return against its rolling history that counts as a price shock,
+    /// 0 disables it
+    pub shock_sigma: f64,
+    /// Mid move in bps within one update that counts as a price shock, 0 disables it
+    pub shock_bps: f64,
+    // whether the latest update moved the mid past either shock limit
+    price_shock: bool,
+    /// Latest funding rate per interval, positive when longs pay shorts
+    pub funding_rate: f64,
+    /// Weight of the funding skew added on top of the weighted signals, 0 disables it
+    pub funding_weight: f64,
+    /// Seconds between feature updates, the volatility is scaled by it to stay per second
+    pub update_interval_secs: f64,
+    /// Book imbalances within this distance of zero count as balanced, 0 keeps them all
+    pub imbalance_dead_zone: f64,
+}
+
+/// Copy of every signal of an `Engine` after an update, for logging and analysis.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct FeatureSnapshot {
+    pub bba_imbalance: f64,
+    pub deep_imbalance: Vec<f64>,
+    pub voi: f64,
+    pub ofi: f64,
+    pub trade_imbalance: f64,
+    pub price_impact: f64,
+    pub avg_trade_price: f64,
+    /// VWAP of the UTC day's trades, `None` before the first one
+    pub vwap: Option<f64>,
+    /// Per second, from the configured estimator
+    pub volatility: f64,
+    pub volatility_z_score: f64,
+    pub range_volatility: f64,
+    /// Latest mid return
+    pub rate_of_change: f64,
+    /// Latest mid price basis of the average trade price
+    pub mpb: f64,
+    pub microprice_momentum: f64,
+    /// Premium of the quoted venue's micro-price over the other venue's in bps
+    pub cross_venue_basis: f64,
+    pub rsi: f64,
+    pub toxicity: f64,
+    pub funding_rate: f64,
+    pub raw_skew: f64,
+    pub skew: f64,
+    pub anomalous: bool,
 }
 
 impl Engine {
-    pub fn new(tick_window: usize) -> Self {
+    /// `skew_window` is the EMA window smoothing the skew, a quarter of `tick_window`
+    /// when `None`.
+    pub fn new(tick_window: usize, skew_window: Option<usize>) -> Self {
+        let skew_window = skew_window.unwrap_or(tick_window / 4);
         Self {
             bba_imbalance: 0.0,
             deep_imbalance: Vec::new(),
@@ -34,10 +122,32 @@ impl Engine {
             trade_imbalance: 0.0,
             price_impact: 0.0,
             volatility: RollingVolatility::new(tick_window),
+            ewma_volatility: EwmaVolatility::default(),
+            range_volatility: ParkinsonVolatility::new(tick_window),
+            range_bars: TradeBars::new(RANGE_BAR_SECS * 1000),
+            vol_estimator: VolEstimator::default(),
             rate_of_change: ROC::new(tick_window),
+            microprice_momentum: MicropriceMomentum::new(tick_window),
+            cross_venue_basis: CrossVenueBasis::new(tick_window),
+            rsi: Rsi::new(tick_window),
+            vpin: Vpin::new(tick_window),
             avg_trade_price: 0.0,
+            vwap: SessionVwap::new(),
             mpb: MPB::new(tick_window),
             skew: 0.0,
+            skew_ema: EMA::new(skew_window),
+            skew_weights: SkewWeights::default(),
+            imbalance_corr: RollingCorrelation::new(tick_window),
+            ofi_corr: RollingCorrelation::new(tick_window),
+            last_signals: None,
+            anomaly: AnomalyGuard::new(tick_window, 0.0),
+            shock_sigma: 0.0,
+            shock_bps: 0.0,
+            price_shock: false,
+            funding_rate: 0.0,
+            funding_weight: 0.0,
+            update_interval_secs: 1.0,
+            imbalance_dead_zone: 0.0,
         }
     }
 
@@ -96,12 +206,110 @@ impl Engine {
         self.price_impact
     }
 
+    pub fn set_vol_estimator(&mut self, estimator: VolEstimator) {
+        self.vol_estimator = estimator;
+    }
+
     fn set_volatility(&mut self, price: f64) {
-        self.volatility.update(price);
+        match self.vol_estimator {
+            VolEstimator::Rolling => {
+                self.volatility.update(price);
+            }
+            VolEstimator::Ewma => {
+                self.ewma_volatility.update(price);
+            }
+            // Fed once per trade bar rather than per tick
+            VolEstimator::Parkinson => {}
+        }
     }
 
+    /// Volatility of the mid per second, whatever the feature update interval.
     pub fn get_volatility(&self) -> f64 {
-        self.volatility.clone().current_vol
+        let vol = match self.vol_estimator {
+            VolEstimator::Rolling => self.volatility.current_vol,
+            VolEstimator::Ewma => self.ewma_volatility.current_vol,
+            VolEstimator::Parkinson => return self.get_range_volatility(),
+        };
+        vol / self.update_interval_secs.sqrt()
+    }
+
+    /// Time between two calls to `update`, ignored unless positive.
+    pub fn set_update_interval(&mut self, interval: Duration) {
+        let secs = interval.as_secs_f64();
+        if secs > 0.0 {
+            self.update_interval_secs = secs;
+        }
+    }
+
+    /// Warms the volatility estimators up with the closes of recent candles, oldest first.
+    ///
+    /// Each candle return is scaled down to the update interval by `interval_secs` so
+    /// it's comparable with the tick returns. The path is rebuilt backwards from the last
+    /// close so the first live mid continues from the real price.
+    pub fn seed_volatility(&mut self, closes: &[f64], interval_secs: f64) {
+        let closes: Vec<f64> = closes.iter().copied().filter(|c| *c > 0.0).collect();
+        let Some(&last) = closes.last() else {
+            return;
+        };
+        if interval_secs <= 0.0 {
+            return;
+        }
+
+        let scale = (interval_secs / self.update_interval_secs).sqrt();
+        let mut path = vec![last];
+        for pair in closes.windows(2).rev() {
+            let ret = (pair[1] / pair[0]).ln() / scale;
+            path.push(path[path.len() - 1] / ret.exp());
+        }
+        for price in path.into_iter().rev() {
+            self.volatility.update(price);
+            self.ewma_volatility.update(price);
+        }
+    }
+
+    /// Z-score of the latest return against the rolling volatility window, large when
+    /// the last move was an outlier
+    pub fn get_volatility_z_score(&self) -> f64 {
+        self.volatility.z_score()
+    }
+
+    /// Warms the range volatility up with the high and low of recent candles of
+    /// `RANGE_BAR_SECS`, oldest first.
+    pub fn seed_range(&mut self, ranges: &[(f64, f64)]) {
+        for &(high, low) in ranges {
+            self.range_volatility.update(high, low);
+        }
+    }
+
+    /// Parkinson volatility of the high-low range of the trade bars, scaled down to a
+    /// second so it's comparable with `get_volatility`
+    pub fn get_range_volatility(&self) -> f64 {
+        self.range_volatility.current_vol / (RANGE_BAR_SECS as f64).sqrt()
+    }
+
+    /// Funding rate of the perpetual per funding interval, e.g. 0.0001 for 0.01%.
+    pub fn set_funding_rate(&mut self, rate: f64) {
+        if rate.is_finite() {
+            self.funding_rate = rate;
+        }
+    }
+
+    pub fn get_funding_rate(&self) -> f64 {
+        self.funding_rate
+    }
+
+    /// Weight of the funding skew, which leans the quotes towards the side collecting
+    /// funding: short when the rate is positive, long when it's negative. Keeps the
+    /// current weight unless `weight` is finite and non-negative.
+    pub fn set_funding_weight(&mut self, weight: f64) -> Result<(), String> {
+        if !(weight.is_finite() && weight >= 0.0) {
+            return Err(format!(
+                "Funding skew weight must be finite and non-negative, got {}",
+                weight
+            ));
+        }
+        self.funding_weight = weight;
+        Ok(())
     }
 
     fn set_roc(&mut self, price: f64) {
@@ -112,6 +320,44 @@ impl Engine {
         self.rate_of_change.clone()
     }
 
+    /// Log return of the top of book micro-price over `tick_window` updates.
+    pub fn get_microprice_momentum(&self) -> f64 {
+        self.microprice_momentum.value()
+    }
+
+    /// Feeds the symbol's book on the venue it's quoted on and on the other venue, the
+    /// skew picks the divergence up on the next update.
+    pub fn update_cross_venue_basis(&mut self, quoted: &impl OrderBook, other: &impl OrderBook) {
+        self.cross_venue_basis.update(quoted, other);
+    }
+
+    /// Premium of the quoted venue's micro-price over the other venue's in bps.
+    pub fn get_cross_venue_basis(&self) -> f64 {
+        self.cross_venue_basis.bps()
+    }
+
+    /// RSI of the mid over `tick_window` updates, a neutral 50.0 until the window fills.
+    pub fn get_rsi(&self) -> f64 {
+        self.rsi.value().unwrap_or(50.0)
+    }
+
+    /// VPIN of the trades over the last `tick_window` volume buckets, from 0.0 for
+    /// balanced flow to 1.0 for one-sided, likely informed, flow.
+    pub fn get_toxicity(&self) -> f64 {
+        self.vpin.value()
+    }
+
+    /// VWAP of the trades since the start of the UTC day, `None` before the first one.
+    pub fn get_vwap(&self) -> Option<f64> {
+        self.vwap.value()
+    }
+
+    /// The session VWAP minus and plus `k` volume weighted standard deviations of its
+    /// trade prices.
+    pub fn get_vwap_bands(&self, k: f64) -> Option<(f64, f64)> {
+        self.vwap.bands(k)
+    }
+
     fn set_mpb(&mut self, price: f64) {
         self.mpb.update_basis(price);
     }
@@ -120,10 +366,111 @@ impl Engine {
         self.mpb.clone()
     }
 
+    /// Replaces the coefficients of the composite skew, scaled to sum to 1.0, keeping the
+    /// current ones when `weights` don't validate.
+    pub fn set_skew_weights(&mut self, weights: SkewWeights) -> Result<(), String> {
+        weights.validate()?;
+        self.skew_weights = weights.normalized();
+        Ok(())
+    }
+
+    /// Skew smoothed by the EMA, so a single noisy update doesn't move the grid
     pub fn get_skew(&self) -> f64 {
+        self.skew_ema.value()
+    }
+
+    pub fn get_raw_skew(&self) -> f64 {
         self.skew
     }
 
+    /// Correlation between the trade imbalance and the following mid move
+    pub fn get_imbalance_correlation(&self) -> f64 {
+        self.imbalance_corr.correlation()
+    }
+
+    /// Correlation between the ofi and the following mid move
+    pub fn get_ofi_correlation(&self) -> f64 {
+        self.ofi_corr.correlation()
+    }
+
+    /// Flags the features once any of them moves more than `sigma` standard deviations
+    /// away from its rolling history, 0.0 disables the check
+    pub fn set_anomaly_threshold(&mut self, sigma: f64) {
+        self.anomaly.set_threshold(sigma);
+    }
+
+    /// Reads book imbalances within `threshold` of zero as balanced, between 0.0 and 1.0.
+    ///
+    /// The book imbalances feed the skew as continuous values, a dead zone trades the small
+    /// imbalances for quotes that don't move with every change at the touch. 0.0 disables it.
+    pub fn set_imbalance_dead_zone(&mut self, threshold: f64) {
+        if threshold.is_finite() {
+            self.imbalance_dead_zone = threshold.clamp(0.0, 1.0);
+        }
+    }
+
+    /// Counts a mid move as a price shock once its return is more than `sigma` standard
+    /// deviations from the rolling mean or the mid moves more than `bps` in one update.
+    /// 0.0 disables either check.
+    pub fn set_price_shock_limits(&mut self, sigma: f64, bps: f64) {
+        self.shock_sigma = if sigma.is_finite() {
+            sigma.max(0.0)
+        } else {
+            0.0
+        };
+        self.shock_bps = if bps.is_finite() { bps.max(0.0) } else { 0.0 };
+        if self.shock_sigma == 0.0 && self.shock_bps == 0.0 {
+            self.price_shock = false;
+        }
+    }
+
+    /// Whether the latest update moved the mid fast enough to pull the quotes
+    pub fn is_price_shock(&self) -> bool {
+        self.price_shock
+    }
+
+    /// Whether the latest features look like a blowup and quoting should wait
+    pub fn is_anomalous(&self) -> bool {
+        self.anomaly.is_flagged()
+    }
+
+    /// Copies the current signals out, cheap enough to take on every update.
+    pub fn snapshot(&self) -> FeatureSnapshot {
+        FeatureSnapshot {
+            bba_imbalance: self.bba_imbalance,
+            deep_imbalance: self.deep_imbalance.clone(),
+            voi: self.voi,
+            ofi: self.ofi,
+            trade_imbalance: self.trade_imbalance,
+            price_impact: self.price_impact,
+            avg_trade_price: self.avg_trade_price,
+            vwap: self.get_vwap(),
+            volatility: self.get_volatility(),
+            volatility_z_score: self.get_volatility_z_score(),
+            range_volatility: self.get_range_volatility(),
+            rate_of_change: self.rate_of_change.current(),
+            mpb: self.mpb.current_basis(),
+            microprice_momentum: self.get_microprice_momentum(),
+            cross_venue_basis: self.get_cross_venue_basis(),
+            rsi: self.get_rsi(),
+            toxicity: self.get_toxicity(),
+            funding_rate: self.funding_rate,
+            raw_skew: self.skew,
+            skew: self.get_skew(),
+            anomalous: self.is_anomalous(),
+        }
+    }
+
+    fn score_signals(&mut self, mid_return: f64) {
+        if !mid_return.is_finite() {
+            return;
+        }
+        if let Some((trade_imbalance, ofi)) = self.last_signals {
+            self.imbalance_corr.update(trade_imbalance, mid_return);
+            self.ofi_corr.update(ofi, mid_return);
+        }
+    }
+
     pub fn update(
         &mut self,
         current_book: &BybitBook,
@@ -133,12 +480,16 @@ impl Engine {
         prev_avg_trade_price: f64,
         depth: &[usize],
     ) {
-        self.set_bba_imbalance(current_book.imbalance_ratio(None));
+        let mid_return =
+            rate_of_change(previous_book.get_mid_price(), current_book.get_mid_price());
+        self.score_signals(mid_return);
 
-        let deep_imbalance = depth[0..]
-            .iter()
-            .map(|x| current_book.imbalance_ratio(Some(*x)))
-            .collect();
+        let dead_zone_threshold = self.imbalance_dead_zone;
+        let imbalance =
+            |depth| dead_zone(current_book.raw_imbalance_ratio(depth), dead_zone_threshold);
+        self.set_bba_imbalance(imbalance(None));
+
+        let deep_imbalance = depth[0..].iter().map(|x| imbalance(Some(*x))).collect();
 
         self.set_deep_imbalance(deep_imbalance);
         let voi = current_book.voi(&previous_book, None);
@@ -148,16 +499,22 @@ impl Engine {
         self.set_ofi(ofi);
 
         self.set_trade_imbalance(trade_imbalance(current_trades));
+        self.vpin.update(current_trades);
+        self.vwap.update(current_trades);
+        for (high, low) in self.range_bars.update(current_trades) {
+            self.range_volatility.update(high, low);
+        }
 
         let impact = current_book.price_impact(&previous_book, None);
         self.set_price_impact(impact);
 
         self.set_volatility(current_book.get_mid_price());
 
-        self.set_roc(rate_of_change(
-            previous_book.get_mid_price(),
-            current_book.get_mid_price(),
-        ));
+        self.set_roc(mid_return);
+        self.price_shock = self.detect_price_shock(mid_return);
+        self.microprice_momentum
+            .update(current_book.get_microprice(None));
+        self.rsi.update(current_book.get_mid_price());
 
         let avg_trade_price = avg_trade_price(
             current_book.get_mid_price(),
@@ -172,10 +529,30 @@ impl Engine {
             avg_trade_price
                 - mid_price_avg(previous_book.get_mid_price(), current_book.get_mid_price()),
         );
+        self.last_signals = Some((self.trade_imbalance, self.ofi));
+        self.anomaly
+            .update([self.voi, self.ofi, self.price_impact, mid_return]);
         self.generate_skew();
     }
 
+    /// `mid_return` is in percent and already part of the rate of change history.
+    fn detect_price_shock(&self, mid_return: f64) -> bool {
+        // The first update has no previous mid to move from
+        if !mid_return.is_finite() {
+            return false;
+        }
+        let sigma_breached = self.shock_sigma > 0.0
+            && self.rate_of_change.len() >= MIN_ANOMALY_SAMPLES
+            && self.rate_of_change.z_score().abs() > self.shock_sigma;
+        let bps_breached = self.shock_bps > 0.0 && mid_return.abs() * 100.0 > self.shock_bps;
+        sigma_breached || bps_breached
+    }
+
     fn generate_skew(&mut self) {
+        // 0. Down-weight the flow signals that aren't predicting the mid lately
+        let ofi_weight = self.ofi_corr.signal_weight();
+        let trade_weight = self.imbalance_corr.signal_weight();
+
         // 1. Order Flow Signal
         let order_flow = if self.ofi > 0.0 && self.voi > 0.0 {
             1.0 // Strong buying pressure
@@ -211,15 +588,190 @@ impl Engine {
         // };
         // let volatility_factor = 1.0 / (self.volatility.current_vol.max(0.001));
 
-        // 6. Composite skew calculation with order flow
-        let raw_skew = 0.3 * trade_skew
-            + 0.25 * book_skew
-            + 0.2 * depth_mean
-            + 0.15 * basis_skew
-            + 0.1 * order_flow;
+        // 6. Mean reversion: past 70 or 30 the RSI leans against the move, up to 1.0 at
+        // the 100 and 0 bounds, so the skew doesn't chase an extended market
+        let rsi = self.get_rsi();
+        let rsi_skew = if rsi > RSI_OVERBOUGHT {
+            -(rsi - RSI_OVERBOUGHT) / (100.0 - RSI_OVERBOUGHT)
+        } else if rsi < RSI_OVERSOLD {
+            (RSI_OVERSOLD - rsi) / RSI_OVERSOLD
+        } else {
+            0.0
+        };
 
-        // 7. Apply momentum and volatility scaling
-        self.skew = raw_skew
+        // 7. Micro-price momentum: the micro-price moves ahead of the mid, so its trend
+        // leads the mid based ROC
+        let momentum_skew = self.microprice_momentum.signal();
+
+        // 8. Cross venue basis: lean away from the quoted venue while it trades rich
+        // against its usual premium, the venues tend to converge
+        let cross_venue_skew = self.cross_venue_basis.signal();
+
+        // 9. Funding carry: longs pay shorts on a positive rate, so lean short
+        let funding_skew = -(self.funding_rate / FUNDING_RATE_SCALE).tanh();
+
+        // 10. Composite skew calculation with order flow
+        let w = self.skew_weights;
+        let raw_skew = (w.trade * trade_weight * trade_skew
+            + w.book * book_skew
+            + w.depth * depth_mean
+            + w.basis * basis_skew
+            + w.order_flow * ofi_weight * order_flow
+            + w.rsi * rsi_skew
+            + w.microprice_momentum * momentum_skew
+            + w.cross_venue_basis * cross_venue_skew
+            + self.funding_weight * funding_skew)
+            .clamp(-1.0, 1.0);
+
+        // 11. Apply momentum and volatility scaling
+        self.skew = raw_skew;
+        self.skew_ema.update(raw_skew);
+    }
+}
+
+// Minimum history before a feature can be judged anomalous
+const MIN_ANOMALY_SAMPLES: usize = 10;
+// Consecutive normal updates needed to clear the anomaly flag
+const ANOMALY_COOLDOWN: usize = 3;
+
+/// Flags feature blowups (voi, ofi, price impact and mid return) against their rolling history
+#[derive(Debug, Clone)]
+pub struct AnomalyGuard {
+    threshold: f64,
+    history: [ROC; 4],
+    normal_streak: usize,
+    flagged: bool,
+}
+
+impl AnomalyGuard {
+    pub fn new(window_size: usize, threshold: f64) -> Self {
+        Self {
+            threshold,
+            history: std::array::from_fn(|_| ROC::new(window_size)),
+            normal_streak: 0,
+            flagged: false,
+        }
+    }
+
+    pub fn set_threshold(&mut self, sigma: f64) {
+        self.threshold = sigma.max(0.0);
+        if self.threshold == 0.0 {
+            self.flagged = false;
+        }
+    }
+
+    pub fn is_flagged(&self) -> bool {
+        self.flagged
+    }
+
+    /// Scores the new feature values against the history before adding them to it,
+    /// the flag stays up until `ANOMALY_COOLDOWN` updates in a row look normal
+    pub fn update(&mut self, values: [f64; 4]) -> bool {
+        let mut anomalous = false;
+        for (history, value) in self.history.iter_mut().zip(values) {
+            if !value.is_finite() {
+                anomalous = true;
+                continue;
+            }
+            let std_dev = history.std_dev();
+            if self.threshold > 0.0
+                && history.len() >= MIN_ANOMALY_SAMPLES
+                && std_dev > f64::EPSILON
+                && ((value - history.mean()) / std_dev).abs() > self.threshold
+            {
+                anomalous = true;
+            }
+            history.update(value);
+        }
+
+        if self.threshold == 0.0 {
+            self.flagged = false;
+        } else if anomalous {
+            self.flagged = true;
+            self.normal_streak = 0;
+        } else if self.flagged {
+            self.normal_streak += 1;
+            if self.normal_streak >= ANOMALY_COOLDOWN {
+                self.flagged = false;
+            }
+        }
+        self.flagged
+    }
+}
+
+/// Rolling Pearson correlation between two series
+#[derive(Debug, Clone)]
+pub struct RollingCorrelation {
+    window_size: usize,
+    pairs: VecDeque<(f64, f64)>,
+    sum_x: f64,
+    sum_y: f64,
+    sum_xx: f64,
+    sum_yy: f64,
+    sum_xy: f64,
+}
+
+impl RollingCorrelation {
+    /// Creates a new RollingCorrelation with a minimum window size of 2
+    pub fn new(window_size: usize) -> Self {
+        let window_size = window_size.max(2);
+        Self {
+            window_size,
+            pairs: VecDeque::with_capacity(window_size),
+            sum_x: 0.0,
+            sum_y: 0.0,
+            sum_xx: 0.0,
+            sum_yy: 0.0,
+            sum_xy: 0.0,
+        }
+    }
+
+    /// Adds a new pair in O(1) time
+    pub fn update(&mut self, x: f64, y: f64) {
+        if self.pairs.len() == self.window_size {
+            if let Some((old_x, old_y)) = self.pairs.pop_front() {
+                self.sum_x -= old_x;
+                self.sum_y -= old_y;
+                self.sum_xx -= old_x * old_x;
+                self.sum_yy -= old_y * old_y;
+                self.sum_xy -= old_x * old_y;
+            }
+        }
+        self.pairs.push_back((x, y));
+        self.sum_x += x;
+        self.sum_y += y;
+        self.sum_xx += x * x;
+        self.sum_yy += y * y;
+        self.sum_xy += x * y;
+    }
+
+    /// Calculates the correlation in O(1) time, 0.0 when either series is flat
+    pub fn correlation(&self) -> f64 {
+        if self.pairs.len() < 2 {
+            return 0.0;
+        }
+
+        let n = self.pairs.len() as f64;
+        let cov = self.sum_xy / n - (self.sum_x / n) * (self.sum_y / n);
+        let var_x = (self.sum_xx / n - (self.sum_x / n).powi(2)).max(0.0);
+        let var_y = (self.sum_yy / n - (self.sum_y / n).powi(2)).max(0.0);
+        let denom = (var_x * var_y).sqrt();
+
+        if denom <= f64::EPSILON {
+            0.0
+        } else {
+            (cov / denom).clamp(-1.0, 1.0)
+        }
+    }
+
+    /// Weight for the signal on `x`: 1.0 until the window fills up, then the
+    /// correlation floored at 0.0 so signals that stopped predicting are muted
+    pub fn signal_weight(&self) -> f64 {
+        if self.pairs.len() < self.window_size {
+            1.0
+        } else {
+            self.correlation().max(0.0)
+        }
     }
 }
 
@@ -257,6 +809,15 @@ impl ROC {
         self.sum_squares += new_value.powi(2);
     }
 
+    /// Number of values in the window
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
     /// Gets current rate of change in O(1) time
     pub fn current(&self) -> f64 {
         self.values.back().copied().unwrap_or(0.0)
@@ -306,6 +867,7 @@ impl ROC {
 
 #[derive(Debug, Clone)]
 pub struct MPB {
+    window_size: usize,
     mid_price_basis: f64,
     basis_array: VecDeque<f64>,
     sum: f64,
@@ -313,18 +875,21 @@ pub struct MPB {
 }
 
 impl MPB {
+    /// Creates a new MPB with the same minimum window size of 2 as `ROC`
     pub fn new(tick_window: usize) -> Self {
+        let window_size = tick_window.max(2);
         Self {
+            window_size,
             mid_price_basis: 0.0,
-            basis_array: VecDeque::with_capacity(tick_window),
+            basis_array: VecDeque::with_capacity(window_size),
             sum: 0.0,
             sum_squares: 0.0,
         }
     }
 
     pub fn update_basis(&mut self, new_basis: f64) {
-        // Maintain sliding window
-        if self.basis_array.len() == self.basis_array.capacity() {
+        // Maintain sliding window, capacity() may over-allocate so use the logical size
+        if self.basis_array.len() == self.window_size {
             if let Some(old_value) = self.basis_array.pop_front() {
                 self.sum -= old_value;
                 self.sum_squares -= old_value.powi(2);
@@ -379,3 +944,407 @@ impl MPB {
         &self.basis_array
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use skeleton::exchange::exchange::TradeType;
+
+    fn book_at(mid: f64) -> BybitBook {
+        let mut book = BybitBook::new();
+        book.best_bid.price = mid - 0.01;
+        book.best_bid.qty = 10.0;
+        book.best_ask.price = mid + 0.01;
+        book.best_ask.qty = 10.0;
+        book.set_mid_price();
+        book
+    }
+
+    #[test]
+    fn test_seed_volatility_from_candles() {
+        let mut engine = Engine::new(30, None);
+        let closes: Vec<f64> = (0..31)
+            .map(|i| 100.0 * if i % 2 == 0 { 1.006 } else { 1.0 })
+            .collect();
+        engine.seed_volatility(&closes, 36.0);
+
+        // Alternating 0.6% moves scaled by sqrt(36)
+        let expected = 1.006_f64.ln() / 6.0;
+        assert!((engine.volatility.current_vol - expected).abs() < 1e-6);
+        assert!(engine.ewma_volatility.current_vol > 0.0);
+        assert_eq!(engine.volatility.current_count(), 30);
+
+        // The last seeded price is the last close, so a flat first tick isn't an outlier
+        engine.volatility.update(100.6);
+        assert!(engine.volatility.z_score().abs() < 0.5);
+    }
+
+    #[test]
+    fn test_volatility_stays_per_second_with_update_interval() {
+        let closes: Vec<f64> = (0..31)
+            .map(|i| 100.0 * if i % 2 == 0 { 1.006 } else { 1.0 })
+            .collect();
+        let mut every_second = Engine::new(30, None);
+        every_second.seed_volatility(&closes, 36.0);
+        let mut every_quarter = Engine::new(30, None);
+        every_quarter.set_update_interval(Duration::from_millis(250));
+        every_quarter.seed_volatility(&closes, 36.0);
+
+        // Returns over a quarter second are half the size, scaled back up to a second
+        let ratio = every_quarter.volatility.current_vol / every_second.volatility.current_vol;
+        assert!((ratio - 0.5).abs() < 1e-9);
+        assert!((every_quarter.get_volatility() - every_second.get_volatility()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_parkinson_estimator_uses_the_bar_ranges() {
+        let mut engine = Engine::new(30, None);
+        engine.set_vol_estimator(VolEstimator::Parkinson);
+        engine.seed_range(&[(101.0, 99.0), (100.5, 99.5)]);
+        // Ticks don't feed it, the ranges already come per second
+        engine.set_volatility(150.0);
+        assert!(engine.get_volatility() > 0.0);
+        assert_eq!(engine.get_volatility(), engine.get_range_volatility());
+    }
+
+    #[test]
+    fn test_funding_skew_leans_towards_collecting() {
+        let mut engine = Engine::new(30, None);
+        engine.set_funding_rate(0.0005);
+        engine.generate_skew();
+        let unweighted = engine.skew;
+
+        // Longs pay shorts, so the skew leans short
+        engine.set_funding_weight(0.2).unwrap();
+        engine.generate_skew();
+        assert!((engine.skew - (unweighted - 0.2 * 5.0_f64.tanh())).abs() < 1e-9);
+
+        // Shorts pay longs
+        engine.set_funding_rate(-0.0005);
+        engine.generate_skew();
+        assert!((engine.skew - (unweighted + 0.2 * 5.0_f64.tanh())).abs() < 1e-9);
+
+        // A weight that would blow the skew up is rejected and the current one kept
+        assert!(engine.set_funding_weight(-0.2).is_err());
+        assert!(engine.set_funding_weight(f64::INFINITY).is_err());
+        assert_eq!(engine.funding_weight, 0.2);
+    }
+
+    #[test]
+    fn test_price_shock_on_a_sudden_move() {
+        let mut engine = Engine::new(30, None);
+        let trades = TradeType::new();
+        let update = |engine: &mut Engine, previous: f64, mid: f64| {
+            engine.update(
+                &book_at(mid),
+                &book_at(previous),
+                &trades,
+                &trades,
+                0.0,
+                &[3],
+            );
+            engine.is_price_shock()
+        };
+        let mut previous = 100.0;
+        for i in 0..20 {
+            let mid = 100.0 + if i % 2 == 0 { 0.02 } else { -0.02 };
+            assert!(!update(&mut engine, previous, mid));
+            previous = mid;
+        }
+        // Disabled by default
+        assert!(!update(&mut engine, previous, previous * 1.01));
+
+        // A 1% move is far outside the 2bps wiggle of the history
+        engine.set_price_shock_limits(3.0, 0.0);
+        assert!(update(&mut engine, 100.0, 101.0));
+        assert!(!update(&mut engine, 101.0, 101.02));
+
+        // 100bps in one update
+        engine.set_price_shock_limits(0.0, 50.0);
+        assert!(update(&mut engine, 101.0, 99.99));
+        assert!(!update(&mut engine, 99.99, 100.01));
+    }
+
+    #[test]
+    fn test_anomaly_flag_sets_and_clears() {
+        let mut engine = Engine::new(30, None);
+        engine.set_anomaly_threshold(6.0);
+        let trades = TradeType::new();
+        let mids: Vec<f64> = (0..40)
+            .map(|i| 100.0 + if i % 2 == 0 { 0.02 } else { -0.02 })
+            .collect();
+
+        for pair in mids.windows(2) {
+            engine.update(
+                &book_at(pair[1]),
+                &book_at(pair[0]),
+                &trades,
+                &trades,
+                0.0,
+                &[3],
+            );
+            assert!(!engine.is_anomalous());
+        }
+
+        // Fat finger print moves the mid 5%
+        engine.update(
+            &book_at(105.0),
+            &book_at(100.0),
+            &trades,
+            &trades,
+            0.0,
+            &[3],
+        );
+        assert!(engine.is_anomalous());
+
+        let mut previous = 105.0;
+        for i in 0..ANOMALY_COOLDOWN {
+            let mid = 105.0 + if i % 2 == 0 { 0.02 } else { -0.02 };
+            assert!(engine.is_anomalous());
+            engine.update(
+                &book_at(mid),
+                &book_at(previous),
+                &trades,
+                &trades,
+                0.0,
+                &[3],
+            );
+            previous = mid;
+        }
+        assert!(!engine.is_anomalous());
+    }
+
+    #[test]
+    fn test_smoothed_skew_lags_a_step() {
+        let mut engine = Engine::new(30, Some(8));
+        let trades = TradeType::new();
+        let flat = book_at(100.0);
+        let mut bid_heavy = book_at(100.0);
+        bid_heavy.best_bid.qty = 30.0;
+
+        for _ in 0..5 {
+            engine.update(&flat, &flat, &trades, &trades, 0.0, &[1]);
+        }
+        let before = engine.get_skew();
+        assert!((before - engine.get_raw_skew()).abs() < 1e-9);
+
+        // The book turns bid heavy, the raw skew jumps at once
+        engine.update(&bid_heavy, &flat, &trades, &trades, 0.0, &[1]);
+        let raw = engine.get_raw_skew();
+        assert!(raw > before);
+        let smoothed = engine.get_skew();
+        assert!(smoothed > before && smoothed < raw);
+
+        // and the smoothed skew closes the gap over the next updates
+        let mut gap = raw - smoothed;
+        for _ in 0..20 {
+            engine.update(&bid_heavy, &bid_heavy, &trades, &trades, 0.0, &[1]);
+            let next_gap = (engine.get_raw_skew() - engine.get_skew()).abs();
+            assert!(next_gap <= gap + 1e-12);
+            gap = next_gap;
+        }
+        assert!(gap < 0.05 * (raw - before));
+    }
+
+    #[test]
+    fn test_skew_weights() {
+        let mut engine = Engine::new(30, None);
+        engine.trade_imbalance = 0.4;
+        engine.bba_imbalance = 0.7;
+        engine.deep_imbalance = vec![-0.3, 0.5];
+        engine.ofi = 2.0;
+        engine.voi = 1.0;
+
+        let pure_trade = SkewWeights {
+            trade: 1.0,
+            book: 0.0,
+            depth: 0.0,
+            basis: 0.0,
+            order_flow: 0.0,
+            rsi: 0.0,
+            microprice_momentum: 0.0,
+            cross_venue_basis: 0.0,
+        };
+        engine.set_skew_weights(pure_trade).unwrap();
+        engine.generate_skew();
+        assert_eq!(engine.get_raw_skew(), 0.4);
+
+        // Weights are relative, doubling them all changes nothing
+        let doubled = SkewWeights {
+            trade: 2.0,
+            ..pure_trade
+        };
+        engine.set_skew_weights(doubled).unwrap();
+        assert_eq!(engine.skew_weights, pure_trade);
+        let halves = SkewWeights {
+            book: 1.0,
+            ..pure_trade
+        };
+        engine.set_skew_weights(halves).unwrap();
+        engine.generate_skew();
+        assert!((engine.get_raw_skew() - 0.55).abs() < 1e-9);
+
+        // Negative or all zero weights are rejected and the current ones kept
+        let negative = SkewWeights {
+            trade: -0.5,
+            ..SkewWeights::default()
+        };
+        assert!(engine.set_skew_weights(negative).is_err());
+        let zero = SkewWeights {
+            trade: 0.0,
+            ..pure_trade
+        };
+        assert!(engine.set_skew_weights(zero).is_err());
+        assert_eq!(engine.skew_weights, halves.normalized());
+        assert!(SkewWeights::default().validate().is_ok());
+    }
+
+    #[test]
+    fn test_rsi_dampens_the_skew_at_extremes() {
+        let mut engine = Engine::new(14, None);
+        engine
+            .set_skew_weights(SkewWeights {
+                trade: 0.8,
+                book: 0.0,
+                depth: 0.0,
+                basis: 0.0,
+                order_flow: 0.0,
+                rsi: 0.2,
+                microprice_momentum: 0.0,
+                cross_venue_basis: 0.0,
+            })
+            .unwrap();
+        engine.trade_imbalance = 1.0;
+
+        // Neutral until the window fills
+        engine.generate_skew();
+        assert_eq!(engine.get_rsi(), 50.0);
+        assert!((engine.get_raw_skew() - 0.8).abs() < 1e-9);
+
+        // A straight rally is overbought at 100, leaning fully against it
+        for i in 0..15 {
+            engine.rsi.update(100.0 + i as f64);
+        }
+        engine.generate_skew();
+        assert_eq!(engine.get_rsi(), 100.0);
+        assert!((engine.get_raw_skew() - 0.6).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_microprice_momentum_leads_the_mid() {
+        let mut engine = Engine::new(10, None);
+        engine
+            .set_skew_weights(SkewWeights {
+                trade: 0.0,
+                book: 0.0,
+                depth: 0.0,
+                basis: 0.0,
+                order_flow: 0.0,
+                rsi: 0.0,
+                microprice_momentum: 1.0,
+                cross_venue_basis: 0.0,
+            })
+            .unwrap();
+        let trades = TradeType::new();
+
+        // The bids fill up under a flat mid, pulling the micro-price up
+        let mut previous = book_at(100.0);
+        for qty in [10.0, 15.0, 20.0, 25.0, 30.0] {
+            let mut book = book_at(100.0);
+            book.best_bid.qty = qty;
+            engine.update(&book, &previous, &trades, &trades, 0.0, &[1]);
+            previous = book;
+        }
+
+        assert_eq!(engine.get_rate_of_change().current(), 0.0);
+        assert!(engine.get_microprice_momentum() > 0.0);
+        assert!(engine.get_raw_skew() > 0.9);
+    }
+
+    #[test]
+    fn test_small_imbalances_reach_the_skew() {
+        let mut engine = Engine::new(10, None);
+        let trades = TradeType::new();
+        let previous = book_at(100.0);
+        // A 10% imbalance at the touch, inside the old dead zone
+        let mut book = book_at(100.0);
+        book.best_bid.qty = 11.0;
+        book.best_ask.qty = 9.0;
+
+        engine.update(&book, &previous, &trades, &trades, 0.0, &[1]);
+        assert!((engine.get_bba_imbalance() - 0.1).abs() < 1e-9);
+        assert_eq!(book.imbalance_ratio(None), 0.0);
+
+        // Opting back into a dead zone
+        engine.set_imbalance_dead_zone(0.2);
+        engine.update(&book, &previous, &trades, &trades, 0.0, &[1]);
+        assert_eq!(engine.get_bba_imbalance(), 0.0);
+    }
+
+    #[test]
+    fn test_cross_venue_basis_leans_off_the_rich_venue() {
+        let mut engine = Engine::new(10, None);
+        engine
+            .set_skew_weights(SkewWeights {
+                trade: 0.0,
+                book: 0.0,
+                depth: 0.0,
+                basis: 0.0,
+                order_flow: 0.0,
+                rsi: 0.0,
+                microprice_momentum: 0.0,
+                cross_venue_basis: 1.0,
+            })
+            .unwrap();
+
+        // A standing premium of 1 to 2 bps, then the quoted venue spikes 10 bps rich
+        for premium in [0.01, 0.02, 0.01, 0.02, 0.01, 0.02, 0.1] {
+            engine.update_cross_venue_basis(&book_at(100.0 + premium), &book_at(100.0));
+        }
+        engine.generate_skew();
+
+        assert!((engine.get_cross_venue_basis() - 10.0).abs() < 1e-6);
+        assert_eq!(
+            engine.snapshot().cross_venue_basis,
+            engine.get_cross_venue_basis()
+        );
+        assert!(engine.get_raw_skew() < -0.9);
+    }
+
+    #[test]
+    fn test_rolling_correlation() {
+        // Imbalance that perfectly predicts the next move
+        let mut corr = RollingCorrelation::new(20);
+        for i in 0..50 {
+            let imbalance = ((i as f64) * 0.7).sin();
+            corr.update(imbalance, imbalance * 0.002);
+        }
+        assert!((corr.correlation() - 1.0).abs() < 1e-9);
+        assert!((corr.signal_weight() - 1.0).abs() < 1e-9);
+
+        // Imbalance unrelated to the next move
+        let mut corr = RollingCorrelation::new(20);
+        for i in 0..40 {
+            let imbalance = if i % 2 == 0 { 1.0 } else { -1.0 };
+            let mid_return = if (i / 2) % 2 == 0 { 0.001 } else { -0.001 };
+            corr.update(imbalance, mid_return);
+        }
+        assert!(corr.correlation().abs() < 1e-9);
+        assert_eq!(corr.signal_weight(), 0.0);
+    }
+
+    #[test]
+    fn test_mpb_evicts_at_window_size() {
+        for tick_window in [0, 1, 3, 5, 17] {
+            let mut mpb = MPB::new(tick_window);
+            let window_size = tick_window.max(2);
+            for i in 0..(window_size * 3) {
+                mpb.update_basis(i as f64);
+                assert!(mpb.history().len() <= window_size);
+            }
+            assert_eq!(mpb.history().len(), window_size);
+            assert_eq!(mpb.current_basis(), (window_size * 3 - 1) as f64);
+            assert_eq!(*mpb.history().front().unwrap(), (window_size * 2) as f64);
+        }
+    }
+}
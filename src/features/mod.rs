@@ -1,3 +1,9 @@
-pub mod trade;
+pub mod bars;
+pub mod basis;
+pub mod engine;
 pub mod impact;
-pub mod engine;
\ No newline at end of file
+pub mod momentum;
+pub mod rsi;
+pub mod trade;
+pub mod vpin;
+pub mod vwap;
\ No newline at end of file
@@ -0,0 +1,100 @@
+use std::collections::VecDeque;
+
+/// Rate of change of the micro-price over `window` updates, updated in O(1).
+///
+/// The micro-price leans towards the side of the touch with less size left, so it moves
+/// before the mid does and its momentum leads the mid based ROC.
+#[derive(Debug, Clone)]
+pub struct MicropriceMomentum {
+    window: usize,
+    last_price: Option<f64>,
+    returns: VecDeque<f64>,
+    sum: f64,
+    sum_squares: f64,
+}
+
+impl MicropriceMomentum {
+    pub fn new(window: usize) -> Self {
+        let window = window.max(1);
+        Self {
+            window,
+            last_price: None,
+            returns: VecDeque::with_capacity(window),
+            sum: 0.0,
+            sum_squares: 0.0,
+        }
+    }
+
+    /// Feeds the latest micro-price, ignoring empty books
+    pub fn update(&mut self, microprice: f64) {
+        if !microprice.is_finite() || microprice <= 0.0 {
+            return;
+        }
+        let Some(prev_price) = self.last_price.replace(microprice) else {
+            return;
+        };
+        if self.returns.len() == self.window {
+            if let Some(old) = self.returns.pop_front() {
+                self.sum -= old;
+                self.sum_squares -= old.powi(2);
+            }
+        }
+        let ret = (microprice / prev_price).ln();
+        self.returns.push_back(ret);
+        self.sum += ret;
+        self.sum_squares += ret.powi(2);
+    }
+
+    /// Log return of the micro-price across the window, 0.0 until it moved once
+    pub fn value(&self) -> f64 {
+        self.sum
+    }
+
+    /// The window's return against the size of its moves, between -1.0 and 1.0.
+    ///
+    /// Close to ±1.0 when every move went the same way, around 0.0 when they cancel out.
+    pub fn signal(&self) -> f64 {
+        if self.sum_squares <= f64::EPSILON.powi(2) {
+            return 0.0;
+        }
+        (self.sum / self.sum_squares.sqrt()).tanh()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_momentum_follows_the_window() {
+        let mut momentum = MicropriceMomentum::new(3);
+        momentum.update(100.0);
+        assert_eq!(momentum.value(), 0.0);
+        assert_eq!(momentum.signal(), 0.0);
+
+        for price in [101.0, 102.0, 103.0] {
+            momentum.update(price);
+        }
+        assert!((momentum.value() - 1.03_f64.ln()).abs() < 1e-12);
+        assert!(momentum.signal() > 0.9);
+
+        // The first move rolls out of the window
+        momentum.update(102.0);
+        assert!((momentum.value() - (102.0_f64 / 101.0).ln()).abs() < 1e-12);
+
+        // Empty books are skipped
+        momentum.update(0.0);
+        momentum.update(f64::NAN);
+        assert!((momentum.value() - (102.0_f64 / 101.0).ln()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_choppy_moves_cancel_out() {
+        let mut momentum = MicropriceMomentum::new(10);
+        for i in 0..=10 {
+            momentum.update(if i % 2 == 0 { 100.0 } else { 100.5 });
+        }
+        assert!(momentum.value().abs() < 1e-12);
+        assert!(momentum.signal().abs() < 1e-6);
+    }
+}
@@ -0,0 +1,109 @@
+/// Wilder's relative strength index over `period` price changes, updated in O(1).
+///
+/// The first `period` changes seed the average gain and loss with a simple mean, after
+/// that each change is smoothed in with a weight of `1 / period`.
+#[derive(Debug, Clone)]
+pub struct Rsi {
+    period: usize,
+    last_price: Option<f64>,
+    changes: usize,
+    avg_gain: f64,
+    avg_loss: f64,
+}
+
+impl Rsi {
+    pub fn new(period: usize) -> Self {
+        Self {
+            period: period.max(1),
+            last_price: None,
+            changes: 0,
+            avg_gain: 0.0,
+            avg_loss: 0.0,
+        }
+    }
+
+    /// Feeds a new price and returns the RSI once `period` changes were seen
+    pub fn update(&mut self, price: f64) -> Option<f64> {
+        if !price.is_finite() {
+            return self.value();
+        }
+        if let Some(prev_price) = self.last_price.replace(price) {
+            let change = price - prev_price;
+            let (gain, loss) = (change.max(0.0), (-change).max(0.0));
+            let period = self.period as f64;
+            self.changes += 1;
+            if self.changes <= self.period {
+                self.avg_gain += gain / period;
+                self.avg_loss += loss / period;
+            } else {
+                self.avg_gain = (self.avg_gain * (period - 1.0) + gain) / period;
+                self.avg_loss = (self.avg_loss * (period - 1.0) + loss) / period;
+            }
+        }
+        self.value()
+    }
+
+    /// RSI between 0 and 100, `None` until `period` changes were seen. A flat window is 50.
+    pub fn value(&self) -> Option<f64> {
+        if self.changes < self.period {
+            return None;
+        }
+        if self.avg_gain + self.avg_loss <= 0.0 {
+            return Some(50.0);
+        }
+        Some(100.0 * self.avg_gain / (self.avg_gain + self.avg_loss))
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new(self.period);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wilder_reference_values() {
+        // The 14 period example of StockCharts' ChartSchool RSI article and its published
+        // RSI column, which starts at the 15th close
+        let closes = [
+            44.3389, 44.0902, 44.1497, 43.6124, 44.2779, 44.8264, 45.0955, 45.4245, 45.8433,
+            46.0826, 45.8931, 46.0328, 45.6140, 46.2820, 46.2820, 46.0028, 46.0328, 46.4116,
+            46.2222, 45.6439, 46.2122, 46.2521, 45.7137, 46.4515, 45.7835, 45.3548, 44.0288,
+            44.1783, 44.2181, 44.5672, 43.4205, 42.6628, 43.1314,
+        ];
+        let expected = [
+            70.53, 66.32, 66.55, 69.41, 66.36, 57.97, 62.93, 63.26, 56.06, 62.38, 54.71, 50.42,
+            39.99, 41.46, 41.87, 45.46, 37.30, 33.08, 37.77,
+        ];
+
+        let mut rsi = Rsi::new(14);
+        let values: Vec<f64> = closes.iter().filter_map(|c| rsi.update(*c)).collect();
+        assert_eq!(values.len(), expected.len());
+        for (value, expected) in values.iter().zip(expected) {
+            assert!(
+                (value - expected).abs() < 0.005,
+                "{} != {}",
+                value,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_one_sided_and_flat_series() {
+        let mut rsi = Rsi::new(3);
+        assert_eq!(rsi.update(1.0), None);
+        for price in [2.0, 3.0, 4.0] {
+            rsi.update(price);
+        }
+        assert_eq!(rsi.value(), Some(100.0));
+
+        rsi.reset();
+        for _ in 0..4 {
+            rsi.update(5.0);
+        }
+        assert_eq!(rsi.value(), Some(50.0));
+    }
+}
@@ -1,4 +1,4 @@
-use skeleton::exchange::exchange::TradeType;
+use skeleton::exchange::exchange::{TradeType, WsTrade};
 
 /// Calculate the trade imbalance for a given TradeType.
 ///
@@ -17,18 +17,18 @@ pub fn trade_imbalance(trades: &TradeType) -> f64 {
     2.0 * ratio - 1.0
 }
 
-/// Given a TradeType, this function calculates the total volume and buy volume.
+/// Given the trades of a TradeType, this function calculates the total volume and buy volume.
 /// It supports both Bybit and Binance formats.
 ///
 /// # Arguments
 ///
-/// * `trades`: The TradeType to calculate the volumes from
+/// * `trades`: The trades to calculate the volumes from
 ///
 /// # Returns
 ///
 /// A tuple of two f64s, the first one being the total volume and the second one being the buy volume
-fn calculate_volumes(trades: &TradeType) -> (f64, f64) {
-    let (total_volume, buy_volume) = trades.iter().fold((0.0, 0.0), |(total, buy), trade| {
+pub fn calculate_volumes<'a>(trades: impl IntoIterator<Item = &'a WsTrade>) -> (f64, f64) {
+    let (total_volume, buy_volume) = trades.into_iter().fold((0.0, 0.0), |(total, buy), trade| {
         let new_total = total + trade.volume;
         let new_buy = if trade.side == "Buy" {
             buy + trade.volume
@@ -0,0 +1,169 @@
+use std::collections::VecDeque;
+
+use skeleton::exchange::exchange::TradeType;
+
+use super::trade::calculate_volumes;
+
+/// Volume-synchronized probability of informed trading, a measure of how one-sided
+/// (toxic) the recent order flow is.
+///
+/// Trades are grouped into buckets of equal volume and the VPIN is the mean of
+/// `|buy - sell| / bucket_volume` over the last `window` full buckets, 0.0 for balanced
+/// flow up to 1.0 when every bucket went one way.
+///
+/// The bucket volume is calibrated from the flow itself: the volume traded over the first
+/// `window` updates divided by `window`, so a bucket holds about one update's worth of
+/// typical volume and the window covers roughly the last `window` updates of it. With
+/// `window` set to `tick_window` the VPIN spans the same horizon as the other features.
+#[derive(Debug, Clone)]
+pub struct Vpin {
+    window: usize,
+    bucket_volume: f64,
+    // (volume, updates) seen while calibrating the bucket volume
+    calibration: (f64, usize),
+    // (total, buy) volume of the bucket being filled
+    current: (f64, f64),
+    // |buy - sell| of the full buckets
+    imbalances: VecDeque<f64>,
+    sum: f64,
+    // id of the newest trade already bucketed
+    last_trade: Option<String>,
+}
+
+impl Vpin {
+    pub fn new(window: usize) -> Self {
+        let window = window.max(1);
+        Self {
+            window,
+            bucket_volume: 0.0,
+            calibration: (0.0, 0),
+            current: (0.0, 0.0),
+            imbalances: VecDeque::with_capacity(window),
+            sum: 0.0,
+            last_trade: None,
+        }
+    }
+
+    /// Buckets the trades that arrived since the last update. `trades` is the rolling
+    /// trade buffer of the symbol, oldest first.
+    pub fn update(&mut self, trades: &TradeType) {
+        let start = self
+            .last_trade
+            .as_ref()
+            .and_then(|id| trades.iter().rposition(|trade| &trade.id == id))
+            .map_or(0, |i| i + 1);
+        if let Some(trade) = trades.back() {
+            self.last_trade = Some(trade.id.clone());
+        }
+        let (total, buy) = calculate_volumes(trades.iter().skip(start));
+
+        if self.bucket_volume <= 0.0 {
+            self.calibration.0 += total;
+            self.calibration.1 += 1;
+            if self.calibration.1 < self.window || self.calibration.0 <= 0.0 {
+                return;
+            }
+            self.bucket_volume = self.calibration.0 / self.window as f64;
+        }
+        self.add_volume(total, buy);
+    }
+
+    /// Splits the new volume over the buckets at its own buy ratio.
+    fn add_volume(&mut self, mut total: f64, mut buy: f64) {
+        while total > 0.0 {
+            let room = self.bucket_volume - self.current.0;
+            let fill = total.min(room);
+            let fill_buy = buy * fill / total;
+            self.current.0 += fill;
+            self.current.1 += fill_buy;
+            total -= fill;
+            buy -= fill_buy;
+
+            if self.current.0 >= self.bucket_volume * (1.0 - f64::EPSILON) {
+                let (bucket_total, bucket_buy) = std::mem::take(&mut self.current);
+                let imbalance = (2.0 * bucket_buy - bucket_total).abs();
+                if self.imbalances.len() == self.window {
+                    if let Some(old) = self.imbalances.pop_front() {
+                        self.sum -= old;
+                    }
+                }
+                self.imbalances.push_back(imbalance);
+                self.sum += imbalance;
+            }
+        }
+    }
+
+    /// VPIN of the full buckets in the window, 0.0 until the first bucket fills.
+    pub fn value(&self) -> f64 {
+        if self.imbalances.is_empty() || self.bucket_volume <= 0.0 {
+            return 0.0;
+        }
+        (self.sum / (self.imbalances.len() as f64 * self.bucket_volume)).clamp(0.0, 1.0)
+    }
+
+    /// Volume of a bucket, 0.0 while still calibrating.
+    pub fn bucket_volume(&self) -> f64 {
+        self.bucket_volume
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use skeleton::exchange::exchange::WsTrade;
+
+    fn trade(id: usize, side: &str, volume: f64) -> WsTrade {
+        WsTrade {
+            id: id.to_string(),
+            side: side.to_string(),
+            volume,
+            price: 100.0,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_balanced_and_one_sided_flow() {
+        let mut vpin = Vpin::new(4);
+        let mut trades = TradeType::new();
+        let mut id = 0;
+        for _ in 0..8 {
+            for side in ["Buy", "Sell"] {
+                id += 1;
+                trades.push_back(trade(id, side, 1.0));
+            }
+            vpin.update(&trades);
+        }
+        // 2.0 per update during calibration, balanced buckets since
+        assert_eq!(vpin.bucket_volume(), 2.0);
+        assert!(vpin.value().abs() < 1e-9);
+
+        // Already bucketed trades aren't counted again
+        vpin.update(&trades);
+        assert!(vpin.value().abs() < 1e-9);
+
+        for _ in 0..4 {
+            id += 1;
+            trades.push_back(trade(id, "Buy", 2.0));
+            trades.pop_front();
+            vpin.update(&trades);
+        }
+        assert!((vpin.value() - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_volume_is_split_across_buckets() {
+        let mut vpin = Vpin::new(2);
+        let mut trades = TradeType::new();
+        trades.push_back(trade(1, "Buy", 1.0));
+        vpin.update(&trades);
+        trades.push_back(trade(2, "Sell", 1.0));
+        vpin.update(&trades);
+        assert_eq!(vpin.bucket_volume(), 1.0);
+
+        // 3.0 of buys fill three buckets, only the last 2 are kept
+        trades.push_back(trade(3, "Buy", 3.0));
+        vpin.update(&trades);
+        assert!((vpin.value() - 1.0).abs() < 1e-9);
+    }
+}
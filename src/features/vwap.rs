@@ -0,0 +1,140 @@
+use skeleton::exchange::exchange::TradeType;
+
+const DAY_MS: u64 = 86_400_000;
+
+/// Volume weighted average price of the trades since the start of the UTC day, with the
+/// volume weighted standard deviation of their prices for bands around it.
+///
+/// The volume and turnover of the new trades are added up on each update instead of being
+/// summed over the whole buffer again, so the VWAP covers the session and not only the
+/// rolling trade buffer. The deviations are accumulated from the session's first price,
+/// which keeps the variance of prices far from zero accurate.
+#[derive(Debug, Clone, Default)]
+pub struct SessionVwap {
+    // UTC day the sums cover
+    day: u64,
+    volume: f64,
+    turnover: f64,
+    reference: f64,
+    // sums of volume * (price - reference) and volume * (price - reference)^2
+    deviation: f64,
+    squared_deviation: f64,
+    // id of the newest trade already added
+    last_trade: Option<String>,
+}
+
+impl SessionVwap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the trades that arrived since the last update. `trades` is the rolling trade
+    /// buffer of the symbol, oldest first. A trade from a new UTC day starts a new session.
+    pub fn update(&mut self, trades: &TradeType) {
+        let start = self
+            .last_trade
+            .as_ref()
+            .and_then(|id| trades.iter().rposition(|trade| &trade.id == id))
+            .map_or(0, |i| i + 1);
+        if let Some(trade) = trades.back() {
+            self.last_trade = Some(trade.id.clone());
+        }
+        for trade in trades.iter().skip(start) {
+            if !(trade.volume > 0.0 && trade.price.is_finite()) {
+                continue;
+            }
+            let day = trade.timestamp / DAY_MS;
+            if self.volume == 0.0 || day != self.day {
+                self.day = day;
+                self.volume = 0.0;
+                self.turnover = 0.0;
+                self.reference = trade.price;
+                self.deviation = 0.0;
+                self.squared_deviation = 0.0;
+            }
+            let deviation = trade.price - self.reference;
+            self.volume += trade.volume;
+            self.turnover += trade.volume * trade.price;
+            self.deviation += trade.volume * deviation;
+            self.squared_deviation += trade.volume * deviation * deviation;
+        }
+    }
+
+    /// VWAP of the session, `None` before its first trade.
+    pub fn value(&self) -> Option<f64> {
+        (self.volume > 0.0).then(|| self.turnover / self.volume)
+    }
+
+    /// Volume weighted standard deviation of the session's trade prices around the VWAP.
+    pub fn std_dev(&self) -> Option<f64> {
+        (self.volume > 0.0).then(|| {
+            let mean = self.deviation / self.volume;
+            (self.squared_deviation / self.volume - mean * mean)
+                .max(0.0)
+                .sqrt()
+        })
+    }
+
+    /// The VWAP minus and plus `k` standard deviations.
+    pub fn bands(&self, k: f64) -> Option<(f64, f64)> {
+        let vwap = self.value()?;
+        let width = k * self.std_dev()?;
+        Some((vwap - width, vwap + width))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use skeleton::exchange::exchange::WsTrade;
+
+    fn trade(id: usize, price: f64, volume: f64, timestamp: u64) -> WsTrade {
+        WsTrade {
+            id: id.to_string(),
+            side: "Buy".to_string(),
+            volume,
+            price,
+            timestamp,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_session_vwap_and_bands() {
+        let mut vwap = SessionVwap::new();
+        assert_eq!(vwap.value(), None);
+        assert_eq!(vwap.bands(2.0), None);
+
+        let mut trades = TradeType::new();
+        trades.push_back(trade(1, 100_000.0, 1.0, 1_000));
+        trades.push_back(trade(2, 100_010.0, 3.0, 2_000));
+        vwap.update(&trades);
+        // Already added trades aren't counted again
+        vwap.update(&trades);
+        assert!((vwap.value().unwrap() - 100_007.5).abs() < 1e-9);
+        // sqrt((1 * 7.5^2 + 3 * 2.5^2) / 4)
+        let std_dev = 18.75f64.sqrt();
+        assert!((vwap.std_dev().unwrap() - std_dev).abs() < 1e-9);
+        let (lower, upper) = vwap.bands(2.0).unwrap();
+        assert!((lower - (100_007.5 - 2.0 * std_dev)).abs() < 1e-9);
+        assert!((upper - (100_007.5 + 2.0 * std_dev)).abs() < 1e-9);
+
+        // The session outlives the rolling buffer
+        trades.clear();
+        trades.push_back(trade(3, 100_007.5, 4.0, 3_000));
+        vwap.update(&trades);
+        assert!((vwap.value().unwrap() - 100_007.5).abs() < 1e-9);
+        assert!(vwap.std_dev().unwrap() < std_dev);
+    }
+
+    #[test]
+    fn test_new_day_starts_a_new_session() {
+        let mut vwap = SessionVwap::new();
+        let mut trades = TradeType::new();
+        trades.push_back(trade(1, 100.0, 1.0, DAY_MS - 1));
+        trades.push_back(trade(2, 110.0, 1.0, DAY_MS));
+        vwap.update(&trades);
+        assert_eq!(vwap.value(), Some(110.0));
+        assert_eq!(vwap.std_dev(), Some(0.0));
+    }
+}
@@ -1,12 +1,28 @@
 use std::collections::HashMap;
 
-use rs_smm_v2::{params::params::use_toml, strategy::maker::Maker};
+use rs_smm_v2::{
+    params::params::{use_toml, CONFIG_PATH},
+    strategy::{control::read_commands, maker::Maker},
+};
 use skeleton::{
     exchange::exchange::Exchange,
     ss,
-    utils::models::{BybitClient, Config},
+    utils::{
+        bot::LiveBot,
+        config::watch_config,
+        logger::{FileSink, LogSinks, Logger},
+        metrics,
+        models::{BinanceClient, BybitClient, Config},
+        recorder::Recorder,
+    },
+};
+use tokio::{
+    io::BufReader,
+    sync::{mpsc, watch},
 };
-use tokio::sync::mpsc;
+
+/// State updates buffered for the quoting loop before market updates get skipped.
+const STATE_CHANNEL_CAPACITY: usize = 64;
 
 #[tokio::main]
 async fn main() {
@@ -15,18 +31,114 @@ async fn main() {
         balances,
         leverage,
         orders_per_side,
+        final_order_distance,
         depths,
         rate_limit,
         tick_window,
         bps,
+        log_intended_quotes,
+        fallback_min_notional,
+        floor_size_to_lot,
+        dust_fraction,
+        quote_anchor,
+        spread_model,
+        vol_estimator,
+        cancel_on_disconnect,
+        anomaly_sigma,
+        warmup_snapshots,
+        reduce_only_delta,
+        time_in_force,
+        quoting_model,
+        bid_ratio,
+        ask_ratio,
+        skew_weighted_ratio,
+        dry_run,
+        max_drawdown_pct,
+        flatten_on_kill_switch,
+        skew_weights,
+        range_vol_weight,
+        state_dir,
+        stale_data_ms,
+        record_dir,
+        funding_skew_weight,
+        min_edge_bps,
+        toxicity_weight,
+        feature_interval_ms,
+        feature_log,
+        metrics_port,
+        log_level,
+        log_dir,
+        log_file_level,
+        telegram_level,
+        binance_api_keys,
+        inventory_adjustment,
+        volatility_multiplier,
+        max_spread_multiplier,
+        book_decay_rate,
+        imbalance_dead_zone,
+        stream_timeout,
+        max_reconnect_delay,
+        vwap_band_sigma,
+        vwap_weight,
+        max_order_age,
+        post_only_retries,
+        quote_side,
+        account_rate_limit,
+        wallet_refresh,
+        circuit_breaker_sigma,
+        circuit_breaker_bps,
+        circuit_breaker_cooldown,
+        trail_drift_bps,
+        trail_min_ticks,
+        safety_factor,
+        max_order_latency,
+        metrics_host,
         ..
     } = use_toml().await;
 
-    let mut state = ss::SharedState::new("bybit".to_string());
+    // drops the levels below log_level, keeps the logs in daily files and only sends
+    // Telegram the levels worth a message. Set before any client creates its logger
+    Logger::set_sinks(LogSinks {
+        min_level: log_level,
+        file: (!log_dir.is_empty()).then(|| FileSink::new(&log_dir, log_file_level)),
+        telegram_level,
+    });
+
+    // streams Binance alongside Bybit once any symbol is quoted there
+    let exchange = match (api_keys.is_empty(), binance_api_keys.is_empty()) {
+        (_, true) => "bybit",
+        (true, false) => "binance",
+        (false, false) => "both",
+    };
+    let mut state = ss::SharedState::new(exchange.to_string());
 
     let clients = api_keys;
     for (key, secret, symbol) in clients {
-        state.add_clients(symbol, BybitClient::init(key, secret).await);
+        let mut client = BybitClient::init(key, secret).await;
+        // lets Bybit cancel every order if the private stream drops
+        client.set_cancel_on_disconnect(cancel_on_disconnect);
+        // reconnects the streams once they go quiet for this long
+        client.set_stream_timeout(stream_timeout);
+        // caps the wait between failed reconnects
+        client.set_max_reconnect_delay(max_reconnect_delay);
+        // only subscribes to the orderbook feeds deep enough for the imbalance depths
+        client.set_book_depths(&depths);
+        state.add_clients(symbol, client);
+    }
+    for (key, secret, symbol) in binance_api_keys {
+        let mut client = BinanceClient::init(key, secret).await;
+        client.set_stream_timeout(stream_timeout);
+        client.set_max_reconnect_delay(max_reconnect_delay);
+        state.add_binance_clients(symbol, client);
+    }
+
+    // how fast the level weights of the depth weighted book signals decay away from the touch
+    state.set_book_decay_rate(book_decay_rate);
+
+    // records the streamed updates to daily files for replay
+    if !record_dir.is_empty() {
+        let (recorder, _) = Recorder::start(&record_dir);
+        state.set_recorder(recorder);
     }
 
     // Create a hashmap for balances of each client/symbols
@@ -44,19 +156,189 @@ async fn main() {
     )
     .await;
 
+    // reports rejected config reloads and operator commands to Telegram as well
+    match LiveBot::new(CONFIG_PATH).await {
+        Ok(bot) => market_maker.set_logger(Logger::new(bot)),
+        Err(e) => eprintln!("Failed to start the maker's Telegram logger: {}", e),
+    }
+
     // sets the  base spread in bps for profit
     market_maker.set_spread_toml(bps);
 
-    // create an unbounded channel
-    let (sender, receiver) = mpsc::unbounded_channel();
+    // logs the grid that would be sent instead of placing it
+    market_maker.set_log_intended_quotes(log_intended_quotes);
+
+    // paper trades: orders stay local and fill once the mid crosses them
+    market_maker.set_dry_run(dry_run);
+
+    // saves the orders and position on change and picks them back up after a restart
+    market_maker.set_state_dir(&state_dir);
+
+    // min notional used for symbols whose exchange min notional is unknown
+    market_maker.set_fallback_min_notional(fallback_min_notional);
+
+    // quotes one lot instead of dropping levels whose size rounds to zero
+    market_maker.set_floor_size_to_lot(floor_size_to_lot);
+
+    // skips grid levels too small to be worth quoting
+    market_maker.set_dust_fraction(dust_fraction);
+
+    // skips grid levels that wouldn't clear the maker fee plus this edge in bps
+    market_maker.set_min_edge_bps(min_edge_bps);
+
+    // selects the quote anchor, spread model and volatility estimator
+    market_maker.set_quote_anchor(quote_anchor);
+    market_maker.set_spread_model(spread_model);
+    market_maker.set_vol_estimator(vol_estimator);
+
+    // blends the ticker's high-low range volatility into the spread
+    market_maker.set_range_vol_weight(range_vol_weight);
+
+    // how hard the inventory pulls the quotes, and the band the vol-linear spread moves in
+    market_maker.set_inventory_adjustment(inventory_adjustment);
+    market_maker.set_spread_multipliers(volatility_multiplier, max_spread_multiplier);
+
+    // widens the spread with the order flow toxicity (VPIN) of the recent trades
+    market_maker.set_toxicity_weight(toxicity_weight);
+
+    // quotes tighter near the session VWAP and leans back towards it when stretched
+    market_maker.set_vwap_overlay(vwap_band_sigma, vwap_weight);
+
+    // re-places orders left too long at one price
+    market_maker.set_max_order_age(max_order_age);
+
+    // re-sends post-only rejections a tick further from the touch
+    market_maker.set_post_only_retries(post_only_retries);
+
+    // quotes both sides, or only the bids or the asks
+    market_maker.set_quote_side(quote_side);
+
+    // caps the order, amend and cancel requests of every symbol together
+    market_maker.set_account_rate_limit(account_rate_limit);
+
+    // share of the leveraged balances the position caps allow
+    market_maker.set_safety_factor(safety_factor);
+
+    // sizes the position caps from the live wallet equity instead of the balances
+    market_maker.set_wallet_refresh(wallet_refresh);
+
+    // pulls the quotes for a cooldown when the mid jumps, e.g. a flash crash or fat finger
+    market_maker.set_circuit_breaker(
+        circuit_breaker_sigma,
+        circuit_breaker_bps,
+        circuit_breaker_cooldown,
+    );
+
+    // pulls the quotes for the cooldown too while the exchange is slow to take orders
+    market_maker.set_max_order_latency(max_order_latency);
+
+    // follows a slowly drifting mid by amending the grid instead of re-quoting it
+    market_maker.set_trailing(trail_drift_bps, trail_min_ticks);
+
+    // blend of the trade, book, depth, basis and order flow signals in the skew
+    market_maker.set_skew_weights(skew_weights);
+
+    // book imbalances this close to zero count as balanced, 0 lets the skew see them all
+    market_maker.set_imbalance_dead_zone(imbalance_dead_zone);
+
+    // pauses quoting while a feature is this many std devs off its history
+    market_maker.set_anomaly_sigma(anomaly_sigma);
+
+    // stops quoting a symbol whose book hasn't updated for this long
+    market_maker.set_stale_data_ms(stale_data_ms);
+
+    // market snapshots that only seed the previous book before features are computed
+    market_maker.set_warmup_snapshots(warmup_snapshots);
+
+    // sends the inventory reducing side as reduce-only past this inventory delta
+    market_maker.set_reduce_only_delta(reduce_only_delta);
+
+    // time in force of the grid orders, post-only unless configured otherwise
+    market_maker.set_time_in_force(time_in_force);
+
+    // heuristic skewed grid or Avellaneda-Stoikov reservation price quoting
+    market_maker.set_quoting_model(quoting_model);
+
+    // halts quoting past this drawdown from the peak equity, optionally flattening
+    market_maker.set_max_drawdown_pct(max_drawdown_pct, flatten_on_kill_switch);
+
+    // how far the outermost grid level sits from the touch
+    market_maker.set_final_order_distance(final_order_distance);
+
+    // size ratios of the grid levels, optionally following the skew on the favoured side
+    market_maker.set_grid_ratios(bid_ratio, ask_ratio, skew_weighted_ratio);
+
+    // milliseconds between feature updates, before seeding so the volatility is scaled to it
+    market_maker.set_feature_interval_ms(feature_interval_ms);
+
+    // appends every symbol's features to a JSON lines file after each update
+    market_maker.set_feature_log(&feature_log);
+
+    // warms the volatility up with the latest candles instead of starting from zero
+    market_maker.seed_volatility(&state.clients).await;
+
+    // starts the trade signals from the latest public trades instead of an empty buffer
+    market_maker.seed_trades(&state.clients).await;
+
+    // leans the quotes towards the side collecting funding, 0 leaves the skew unchanged
+    market_maker.set_funding_skew_weight(funding_skew_weight);
+    market_maker.seed_funding_rates(&state.clients).await;
+
+    // applies the spreads, leverage, orders per side and depths of the config as it's edited
+    let config_reloads = market_maker.config_channel();
+    tokio::spawn(async move {
+        if let Err(e) = watch_config(CONFIG_PATH, config_reloads).await {
+            eprintln!("Stopped watching {}: {}", CONFIG_PATH, e);
+        }
+    });
+
+    // places, cancels and flattens orders typed on stdin outside the grid, and adjusts,
+    // pauses and queries the risk limits of each symbol
+    let order_commands = market_maker.order_channel();
+    let risk_commands = market_maker.risk_channel();
+    tokio::spawn(read_commands(
+        BufReader::new(tokio::io::stdin()),
+        order_commands,
+        risk_commands,
+    ));
+
+    // create a bounded channel so a slow loop can't queue up state updates without limit
+    let (sender, receiver) = mpsc::channel(STATE_CHANNEL_CAPACITY);
+
+    // the market maker keeps its own copy of the state and merges the updates into it
+    let local_state = state.clone();
+
+    // stops the streams and the quoting loop, which cancels the resting orders on the way out
+    let (shutdown_sender, shutdown) = watch::channel(false);
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            let _ = shutdown_sender.send(true);
+        }
+    });
+
+    // serves the Prometheus metrics until shutdown
+    if let Some(port) = metrics_port {
+        let metrics_shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            if let Err(e) = metrics::serve(&metrics_host, port, metrics_shutdown).await {
+                eprintln!(
+                    "Failed to serve the metrics on {}:{}: {}",
+                    metrics_host, port, e
+                );
+            }
+        });
+    }
 
-    // loads up the shareed state and sends it across the channel
+    // loads up the shareed state and sends its updates across the channel, skipping market updates while full
+    let loader_shutdown = shutdown.clone();
     tokio::spawn(async move {
-        ss::SharedState::load_data(state, sender).await;
+        ss::SharedState::load_data_bounded(state, sender, loader_shutdown).await;
     });
 
     // passes in the data receiver to the market maker and starts the loop
-    market_maker.start_loop(receiver).await;
+    market_maker
+        .start_loop(local_state, receiver, shutdown)
+        .await;
 }
 
 fn map_balances(arr: Vec<(String, f64)>) -> HashMap<String, f64> {
@@ -1,7 +1,21 @@
 use skeleton::utils::{config::read_toml, models::Config};
 
+pub const CONFIG_PATH: &str = "./config.toml";
+
+/// Reads `CONFIG_PATH`, exiting with the reason when it can't be read or a value is
+/// invalid rather than panicking somewhere in the bot.
 pub async fn use_toml() -> Config {
-    let path = "./config.toml";
-    let result = read_toml(path).await.unwrap();
-    result
+    let path = CONFIG_PATH;
+    let config: Config = match read_toml(path).await {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", path, e);
+            std::process::exit(1);
+        }
+    };
+    if let Err(e) = config.validate() {
+        eprintln!("Invalid config in {}: {}", path, e);
+        std::process::exit(1);
+    }
+    config
 }
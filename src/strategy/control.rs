@@ -0,0 +1,162 @@
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt},
+    sync::{mpsc, oneshot},
+};
+
+use super::maker::{OrderCommand, RiskCommand};
+
+/// A command typed by an operator, one per line:
+///
+/// - `place <symbol> <buy|sell> <qty> <price>`
+/// - `cancel <symbol> <order id>`
+/// - `flatten <symbol>`
+/// - `max_position <symbol> <usd>`, `max_drawdown <symbol> <usd>` with 0 disabling it
+/// - `spread <symbol> <bps>`
+/// - `pause <symbol>`, `resume <symbol>`
+/// - `reset <symbol>` re-enables quoting after the kill switch tripped
+/// - `risk` prints the risk state of every symbol
+#[derive(Debug)]
+pub enum Command {
+    Order(OrderCommand),
+    Risk(RiskCommand),
+    QueryRisk,
+}
+
+/// Parses a command line, `None` for a blank one.
+pub fn parse_command(line: &str) -> Result<Option<Command>, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let Some((&name, args)) = words.split_first() else {
+        return Ok(None);
+    };
+    let command = match (name, args) {
+        ("place", &[symbol, side, qty, price]) => Command::Order(OrderCommand::Place {
+            symbol: symbol.to_string(),
+            price: parse_number(price)?,
+            qty: parse_number(qty)?,
+            is_buy: match side {
+                "buy" => true,
+                "sell" => false,
+                _ => return Err(format!("Unknown side {}, expected buy or sell", side)),
+            },
+        }),
+        ("cancel", &[symbol, order_id]) => Command::Order(OrderCommand::Cancel {
+            symbol: symbol.to_string(),
+            order_id: order_id.to_string(),
+        }),
+        ("flatten", &[symbol]) => Command::Order(OrderCommand::Flatten {
+            symbol: symbol.to_string(),
+        }),
+        ("max_position", &[symbol, usd]) => Command::Risk(RiskCommand::SetMaxPosition {
+            symbol: symbol.to_string(),
+            usd: parse_number(usd)?,
+        }),
+        ("max_drawdown", &[symbol, usd]) => Command::Risk(RiskCommand::SetMaxDrawdown {
+            symbol: symbol.to_string(),
+            usd: parse_number(usd)?,
+        }),
+        ("spread", &[symbol, bps]) => Command::Risk(RiskCommand::SetSpread {
+            symbol: symbol.to_string(),
+            bps: parse_number(bps)?,
+        }),
+        ("pause" | "resume", &[symbol]) => Command::Risk(RiskCommand::Pause {
+            symbol: symbol.to_string(),
+            paused: name == "pause",
+        }),
+        ("reset", &[symbol]) => Command::Risk(RiskCommand::ResetKillSwitch {
+            symbol: symbol.to_string(),
+        }),
+        ("risk", &[]) => Command::QueryRisk,
+        _ => return Err(format!("Unknown command: {}", line.trim())),
+    };
+    Ok(Some(command))
+}
+
+fn parse_number(value: &str) -> Result<f64, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{} isn't a number", value))
+}
+
+/// Reads commands from `reader` until it closes, e.g. stdin, and forwards them to the
+/// maker. Lines that don't parse are reported and skipped.
+pub async fn read_commands<R: AsyncBufRead + Unpin>(
+    reader: R,
+    orders: mpsc::UnboundedSender<OrderCommand>,
+    risk: mpsc::UnboundedSender<RiskCommand>,
+) {
+    let mut lines = reader.lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        let sent = match parse_command(&line) {
+            Ok(Some(Command::Order(command))) => orders.send(command).is_ok(),
+            Ok(Some(Command::Risk(command))) => risk.send(command).is_ok(),
+            Ok(Some(Command::QueryRisk)) => {
+                let (reply, state) = oneshot::channel();
+                if risk.send(RiskCommand::Query(reply)).is_err() {
+                    break;
+                }
+                // Answered once the maker handles its next update
+                match state.await {
+                    Ok(state) => println!("{:#?}", state),
+                    Err(_) => break,
+                }
+                true
+            }
+            Ok(None) => true,
+            Err(err) => {
+                eprintln!("{}", err);
+                true
+            }
+        };
+        // The maker stopped
+        if !sent {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_command() {
+        assert!(matches!(parse_command("  "), Ok(None)));
+        assert!(matches!(
+            parse_command("place SOLUSDT sell 0.5 101.5"),
+            Ok(Some(Command::Order(OrderCommand::Place {
+                symbol, price, qty, is_buy: false
+            }))) if symbol == "SOLUSDT" && price == 101.5 && qty == 0.5
+        ));
+        assert!(matches!(
+            parse_command("cancel SOLUSDT abc-123"),
+            Ok(Some(Command::Order(OrderCommand::Cancel { order_id, .. }))) if order_id == "abc-123"
+        ));
+        assert!(matches!(
+            parse_command("flatten SOLUSDT"),
+            Ok(Some(Command::Order(OrderCommand::Flatten { .. })))
+        ));
+
+        assert!(matches!(
+            parse_command("max_drawdown SOLUSDT 0"),
+            Ok(Some(Command::Risk(RiskCommand::SetMaxDrawdown { usd, .. }))) if usd == 0.0
+        ));
+        assert!(matches!(
+            parse_command("resume SOLUSDT"),
+            Ok(Some(Command::Risk(RiskCommand::Pause {
+                paused: false,
+                ..
+            })))
+        ));
+        assert!(matches!(
+            parse_command("risk"),
+            Ok(Some(Command::QueryRisk))
+        ));
+
+        // Wrong side, number or arity
+        assert!(parse_command("place SOLUSDT long 0.5 101.5").is_err());
+        assert!(parse_command("place SOLUSDT buy half 101.5").is_err());
+        assert!(parse_command("flatten").is_err());
+        assert!(parse_command("spread SOLUSDT").is_err());
+        assert!(parse_command("buy SOLUSDT").is_err());
+    }
+}
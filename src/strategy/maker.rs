@@ -1,15 +1,97 @@
+use serde::Serialize;
 use skeleton::{
-    exchange::exchange::{Exchange, MarketData, TradeType},
-    ss::SharedState,
-    utils::models::{BybitBook, BybitClient, BybitMarket, BybitPrivate},
+    exchange::exchange::{wait_for_shutdown, Exchange, MarketData, TradeType},
+    ss::{SharedState, StateUpdate},
+    utils::{
+        logger::Logger,
+        metrics,
+        models::{
+            ws_trade_from_aggr, BinanceClient, BinanceMarket, BybitBook, BybitClient, BybitMarket,
+            Config, QuoteAnchor, QuoteSide, QuotingModel, SkewWeights, SpreadModel, TimeInForce,
+            VolEstimator,
+        },
+        persistence::FilePersistence,
+        time::generate_timestamp,
+    },
 };
 use std::{
-    collections::{BTreeMap, HashMap},
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs::{File, OpenOptions},
+    io::{BufWriter, Write},
     time::Duration,
 };
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, oneshot, watch};
 
-use crate::{features::engine::Engine, trader::quote_gen::QuoteGenerator};
+use crate::{
+    features::engine::{Engine, FeatureSnapshot},
+    trader::{
+        fair_value::{anchor_source, FairValueSource},
+        quote_gen::{settle_coin, QuoteGenerator, RiskState},
+        rate_limit::SharedRateLimiter,
+        venue::{PrivateData, Venue},
+    },
+};
+
+/// Reloaded configs buffered until the quoting loop applies the newest.
+const CONFIG_CHANNEL_CAPACITY: usize = 4;
+/// Trades fetched per symbol at startup, as many as the stream keeps.
+const SEED_TRADES: u16 = 1000;
+/// Most klines the exchanges return for one request.
+const MAX_SEED_KLINES: usize = 1000;
+/// Shortest interval between the wallet balance requests of an account.
+const MIN_WALLET_REFRESH_MS: u64 = 10_000;
+/// How often the books are checked for staleness while no update comes in.
+const STALE_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Runtime changes to the risk limits of a symbol, applied before the next quoting cycle.
+#[derive(Debug)]
+pub enum RiskCommand {
+    SetMaxPosition {
+        symbol: String,
+        usd: f64,
+    },
+    /// 0.0 disables the drawdown limit
+    SetMaxDrawdown {
+        symbol: String,
+        usd: f64,
+    },
+    SetSpread {
+        symbol: String,
+        bps: f64,
+    },
+    /// Stops quoting the symbol until resumed, on top of the anomaly pause
+    Pause {
+        symbol: String,
+        paused: bool,
+    },
+    /// Re-enables quoting after the drawdown kill switch tripped
+    ResetKillSwitch {
+        symbol: String,
+    },
+    /// Replies with the risk state of every symbol
+    Query(oneshot::Sender<BTreeMap<String, RiskState>>),
+}
+
+/// One-off orders from an operator, sent outside the automated grid.
+#[derive(Debug)]
+pub enum OrderCommand {
+    /// A limit order with the grid's time in force, adopted by the grid once resting
+    Place {
+        symbol: String,
+        price: f64,
+        qty: f64,
+        is_buy: bool,
+    },
+    Cancel {
+        symbol: String,
+        order_id: String,
+    },
+    /// Cancels every order of the symbol and closes its position at the touch
+    Flatten {
+        symbol: String,
+    },
+}
 
 pub struct Maker {
     pub features: BTreeMap<String, Engine>,
@@ -20,6 +102,36 @@ pub struct Maker {
     pub generators: BTreeMap<String, QuoteGenerator>,
     pub depths: Vec<usize>,
     pub tick_window: usize,
+    leverage: f64,
+    // snapshots seeded per symbol before the previous book is trusted
+    pub warmup_snapshots: usize,
+    snapshots: BTreeMap<String, usize>,
+    risk_commands: Option<mpsc::UnboundedReceiver<RiskCommand>>,
+    order_commands: Option<mpsc::UnboundedReceiver<OrderCommand>>,
+    config_reloads: Option<mpsc::Receiver<Config>>,
+    // symbols paused by a `RiskCommand::Pause`
+    halted: BTreeSet<String>,
+    // books older than this many ms aren't quoted on, 0 disables the check
+    stale_data_ms: u64,
+    // time between feature updates, also used to scale the engines' volatility
+    feature_interval: Duration,
+    last_feature_update: tokio::time::Instant,
+    // JSON lines file the features of each symbol are appended to after every update
+    feature_log: Option<BufWriter<File>>,
+    // reports the rejected config reloads and operator commands
+    logger: Logger,
+    // interval between the wallet balance requests, 0 disables them
+    wallet_refresh_ms: u64,
+    last_wallet_refresh: u64,
+}
+
+/// A line of the feature log.
+#[derive(Serialize)]
+struct FeatureLine<'a> {
+    /// Milliseconds since the epoch
+    time: u64,
+    symbol: &'a str,
+    features: FeatureSnapshot,
 }
 
 impl Maker {
@@ -39,7 +151,7 @@ impl Maker {
             current_trades: BTreeMap::new(),
             previous_avg_trade_price: BTreeMap::new(),
             generators: Self::build_generators(
-                ss.clients,
+                Self::venues(ss.clients, ss.binance_clients),
                 asset,
                 leverage,
                 orders_per_side,
@@ -49,48 +161,149 @@ impl Maker {
             .await,
             depths,
             tick_window,
+            leverage,
+            warmup_snapshots: 1,
+            snapshots: BTreeMap::new(),
+            risk_commands: None,
+            order_commands: None,
+            config_reloads: None,
+            halted: BTreeSet::new(),
+            stale_data_ms: 0,
+            feature_interval: Duration::from_secs(1),
+            last_feature_update: tokio::time::Instant::now(),
+            feature_log: None,
+            logger: Logger::local(),
+            wallet_refresh_ms: 0,
+            last_wallet_refresh: 0,
         }
     }
 
-    pub async fn start_loop(&mut self, mut receiver: mpsc::UnboundedReceiver<SharedState>) {
+    /// Logger the rejected config reloads and operator commands are reported to. Until
+    /// set they only go to stdout and the log file.
+    pub fn set_logger(&mut self, logger: Logger) {
+        self.logger = logger;
+    }
+
+    // logs a rejected config reload or operator command and counts it by `source`
+    fn report_rejection(&self, source: &str, err: &str) {
+        self.logger.error(&format!("Rejected {}: {}", source, err));
+        metrics::inc(metrics::REJECTED_COMMANDS, &[("source", source)]);
+    }
+
+    /// Runs the quoting loop, merging each update from the loaders into `state`.
+    ///
+    /// Returns once `true` is sent on `shutdown` or the loaders stop, after cancelling the
+    /// orders of every symbol.
+    pub async fn start_loop(
+        &mut self,
+        mut state: SharedState,
+        mut receiver: mpsc::Receiver<StateUpdate>,
+        mut shutdown: watch::Receiver<bool>,
+    ) {
         let mut send_orders = 0;
-        let mut last_feature_update = tokio::time::Instant::now();
-        let feature_update_interval = Duration::from_secs(1);
-        let depths = self.depths.clone();
-
-        while let Some(ss) = receiver.recv().await {
-            let private = ss.privates;
-            let latest_market_data = match ss.markets.get(0) {
-                Some(MarketData::Bybit(market)) => market.clone(),
-                _ => continue,
+        self.last_feature_update = tokio::time::Instant::now();
+        let mut last_pnl_log = tokio::time::Instant::now();
+        let pnl_log_interval = Duration::from_secs(60);
+        // An outage brings no update, the books' age is checked on a timer as well
+        let mut stale_check = tokio::time::interval(STALE_CHECK_INTERVAL);
+        stale_check.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+        loop {
+            let update = tokio::select! {
+                update = receiver.recv() => match update {
+                    Some(update) => update,
+                    None => break,
+                },
+                _ = stale_check.tick(), if self.stale_data_ms > 0 => {
+                    if let Some(market) = self.quoted_market(&state.markets) {
+                        self.check_stale_data(&market).await;
+                    }
+                    continue;
+                }
+                // Handled between updates so they don't race the grid's own requests
+                Some(command) = next_order_command(&mut self.order_commands) => {
+                    let market = self.quoted_market(&state.markets).unwrap_or_default();
+                    if let Err(err) = self.apply_order_command(command, &market).await {
+                        self.report_rejection("order command", &err);
+                    }
+                    continue;
+                }
+                Some(config) = next_config_reload(&mut self.config_reloads) => {
+                    self.apply_config_reloads(config).await;
+                    continue;
+                }
+                _ = wait_for_shutdown(&mut shutdown) => break,
             };
+            state.apply(update);
+            self.drain_risk_commands();
 
-            // Update features every second
+            let Some(latest_market_data) = self.quoted_market(&state.markets) else {
+                continue;
+            };
+
+            // Update features once per feature interval
             let now = tokio::time::Instant::now();
-            if now.duration_since(last_feature_update) >= feature_update_interval {
-                self.update_features(latest_market_data.clone(), &depths);
+            if self.features_due(now) {
+                self.update_cross_venue_basis(&state.markets);
+                let depths = self.depths.clone();
+                self.update_features(latest_market_data.clone().into_owned(), &depths);
                 if send_orders <= self.tick_window {
                     send_orders += 1;
                 }
-                last_feature_update = now;
+            }
+
+            if now.duration_since(last_pnl_log) >= pnl_log_interval {
+                self.log_pnl(&latest_market_data);
+                last_pnl_log = now;
             }
 
             // Always try to update quotes
             if send_orders > self.tick_window {
-                self.potentially_update(private, latest_market_data).await;
+                self.refresh_position_caps(generate_timestamp().unwrap_or_default())
+                    .await;
+                self.potentially_update(&state, &latest_market_data).await;
             }
         }
+
+        self.shutdown().await;
+    }
+
+    /// Cancels the orders of every symbol so nothing is left resting once the bot exits.
+    pub async fn shutdown(&mut self) {
+        for (symbol, generator) in self.generators.iter_mut() {
+            generator.shutdown(symbol).await;
+        }
     }
 
     fn build_features(symbols: Vec<String>, tick_window: usize) -> BTreeMap<String, Engine> {
         symbols
             .into_iter()
-            .map(|symbol| (symbol, Engine::new(tick_window)))
+            .map(|symbol| (symbol, Engine::new(tick_window, None)))
             .collect()
     }
 
-    async fn build_generators(
+    /// The venue each symbol is quoted on, Bybit when a symbol has keys for both.
+    fn venues(
         clients: BTreeMap<String, BybitClient>,
+        binance_clients: BTreeMap<String, BinanceClient>,
+    ) -> BTreeMap<String, Venue> {
+        let mut venues: BTreeMap<String, Venue> = binance_clients
+            .into_iter()
+            .map(|(symbol, client)| (symbol, client.into()))
+            .collect();
+        for (symbol, client) in clients {
+            if venues.insert(symbol.clone(), client.into()).is_some() {
+                eprintln!(
+                    "{} has keys for both exchanges, quoting it on Bybit",
+                    symbol
+                );
+            }
+        }
+        venues
+    }
+
+    async fn build_generators(
+        clients: BTreeMap<String, Venue>,
         mut asset: HashMap<String, f64>,
         leverage: f64,
         orders_per_side: usize,
@@ -100,98 +313,1559 @@ impl Maker {
         let mut generators = BTreeMap::new();
         let mut tasks = Vec::new();
 
+        // Symbols quoted on one account split its equity in proportion to their balances
+        let mut account_capital: HashMap<String, f64> = HashMap::new();
+        for (symbol, client) in &clients {
+            if let Some(asset_value) = asset.get(symbol) {
+                *account_capital
+                    .entry(client.api_key().to_string())
+                    .or_default() += asset_value;
+            }
+        }
+
         for (symbol, client) in clients {
             let Some(asset_value) = asset.remove(&symbol) else {
                 eprintln!("Missing asset for {}", symbol);
                 continue;
             };
+            let equity_share = asset_value / account_capital[client.api_key()];
 
             let symbol_clone = symbol.clone();
             tasks.push(async move {
-                let _ = client.set_leverage(&symbol_clone, leverage as u8).await;
-
-                (
-                    symbol,
-                    QuoteGenerator::new(
-                        client,
-                        asset_value,
-                        leverage,
-                        orders_per_side,
-                        tick_window,
-                        rate_limit,
-                    )
-                    .await,
+                let info = client.symbol_info(&symbol_clone).await;
+                let fees = client.fees(symbol_clone).await;
+
+                let generator = QuoteGenerator::new(
+                    client,
+                    asset_value,
+                    leverage,
+                    orders_per_side,
+                    tick_window,
+                    rate_limit,
                 )
+                .await
+                .map(|mut generator| {
+                    generator.set_equity_share(equity_share);
+                    // Keeps the default rates when the account's can't be fetched
+                    if let Ok(fees) = fees {
+                        generator.set_fees(fees);
+                    }
+                    // Clamps the leverage to the symbol's max before it's set on the exchange
+                    if let Ok(info) = info {
+                        generator.set_max_leverage(info.max_leverage);
+                    }
+                    generator
+                });
+                // Binance reports no max leverage, a leverage above the symbol's bracket is
+                // only caught here when the exchange rejects it
+                if let Ok(generator) = &generator {
+                    let leverage = generator.leverage() as u8;
+                    if let Err(e) = generator.venue().set_leverage(&symbol, leverage).await {
+                        eprintln!("Failed to set the leverage of {}: {}", symbol, e);
+                    }
+                }
+                (symbol, generator)
             });
         }
 
         for task in tasks {
-            let (symbol, generator) = task.await;
-            generators.insert(symbol, generator);
+            match task.await {
+                (symbol, Ok(generator)) => {
+                    generators.insert(symbol, generator);
+                }
+                (symbol, Err(err)) => eprintln!("Rejected {}: {}", symbol, err),
+            }
         }
 
         generators
     }
 
     fn update_features(&mut self, market_data: BybitMarket, depths: &[usize]) {
+        let no_trades = TradeType::new();
+        let mut updated = Vec::new();
         for (symbol, current_book) in market_data.books.clone() {
-            let (Some(prev_book), Some(prev_trades), Some(curr_trades), Some(prev_avg)) = (
-                self.previous_book.get(&symbol),
-                self.previous_trades.get(&symbol),
-                market_data.trades.get(&symbol),
-                self.previous_avg_trade_price.get(&symbol),
-            ) else {
+            // The first snapshots only seed the previous book and trades
+            let seen = self.snapshots.entry(symbol.clone()).or_default();
+            *seen = seen.saturating_add(1);
+            if *seen <= self.warmup_snapshots {
+                continue;
+            }
+
+            let Some(prev_book) = self.previous_book.get(&symbol) else {
                 continue;
             };
+            // A symbol without trades yet still gets its book features. The trades fetched
+            // at startup are fed once, on the first update, unless the stream already
+            // brought its own
+            let seeded = self.current_trades.remove(&symbol);
+            let prev_trades = self.previous_trades.get(&symbol).unwrap_or(&no_trades);
+            let curr_trades = match market_data.trades.get(&symbol) {
+                Some(trades) if !trades.is_empty() => trades,
+                _ => seeded.as_ref().unwrap_or(&no_trades),
+            };
+            let prev_avg = self
+                .previous_avg_trade_price
+                .get(&symbol)
+                .copied()
+                .unwrap_or(prev_book.mid_price);
 
-            match self.features.get_mut(&symbol) {
-                Some(f) => {
-                    f.update(
-                        &current_book,
-                        prev_book,
-                        curr_trades,
-                        prev_trades,
-                        *prev_avg,
-                        depths,
-                    );
-                    f
+            if let Some(f) = self.features.get_mut(&symbol) {
+                if let Some(rate) = market_data.funding_rate(&symbol) {
+                    f.set_funding_rate(rate);
                 }
-                None => continue,
-            };
+                f.update(
+                    &current_book,
+                    prev_book,
+                    curr_trades,
+                    prev_trades,
+                    prev_avg,
+                    depths,
+                );
+                updated.push(symbol);
+            }
         }
+        self.log_features(&updated);
         for (symbol, feature) in self.features.iter() {
             self.previous_avg_trade_price
                 .insert(symbol.clone(), feature.get_avg_trade_price());
         }
         self.previous_book = market_data.books;
-        self.previous_trades = market_data.trades;
+        for (symbol, trades) in market_data.trades {
+            if !trades.is_empty() {
+                self.previous_trades.insert(symbol, trades);
+            }
+        }
     }
 
-    async fn potentially_update(
-        &mut self,
-        private: BTreeMap<String, BybitPrivate>,
-        data: BybitMarket,
-    ) {
-        for (symbol, book) in data.books {
-            if let (Some(engine), Some(generator), Some(private)) = (
-                self.features.get(&symbol),
-                self.generators.get_mut(&symbol),
-                private.get(&symbol),
-            ) {
+    /// Feeds every symbol streamed on both venues its two books, the book of the venue it's
+    /// quoted on first.
+    fn update_cross_venue_basis(&mut self, markets: &[MarketData]) {
+        let (Some(bybit), Some(binance)) = venue_markets(markets) else {
+            return;
+        };
+        for (symbol, engine) in self.features.iter_mut() {
+            let (Some(bybit_book), Some(binance_book)) =
+                (bybit.books.get(symbol), binance.books.get(symbol))
+            else {
+                continue;
+            };
+            match self
+                .generators
+                .get(symbol)
+                .map(|generator| generator.venue())
+            {
+                Some(Venue::Binance(_)) => {
+                    engine.update_cross_venue_basis(binance_book, bybit_book)
+                }
+                _ => engine.update_cross_venue_basis(bybit_book, binance_book),
+            }
+        }
+    }
+
+    fn log_features(&mut self, symbols: &[String]) {
+        let Some(file) = self.feature_log.as_mut() else {
+            return;
+        };
+        let time = generate_timestamp().unwrap_or_default();
+        for symbol in symbols {
+            let Some(engine) = self.features.get(symbol) else {
+                continue;
+            };
+            let line = FeatureLine {
+                time,
+                symbol,
+                features: engine.snapshot(),
+            };
+            let written = serde_json::to_writer(&mut *file, &line)
+                .map_err(std::io::Error::from)
+                .and_then(|_| file.write_all(b"\n"));
+            if let Err(e) = written {
+                eprintln!("Failed to log the features of {}: {}", symbol, e);
+            }
+        }
+        if let Err(e) = file.flush() {
+            eprintln!("Failed to flush the feature log: {}", e);
+        }
+    }
+
+    /// The market each symbol is quoted on, the Binance books and trades converted for the
+    /// symbols of Binance generators. Binance tickers carry neither the 24h range nor the
+    /// funding rate, so those symbols go without.
+    ///
+    /// Borrows the Bybit market as is while no generator quotes on Binance.
+    fn quoted_market<'a>(&self, markets: &'a [MarketData]) -> Option<Cow<'a, BybitMarket>> {
+        let (bybit, binance) = venue_markets(markets);
+        let binance_symbols: Vec<&String> = self
+            .generators
+            .iter()
+            .filter(|(_, generator)| matches!(generator.venue(), Venue::Binance(_)))
+            .map(|(symbol, _)| symbol)
+            .collect();
+        let Some(binance) = binance.filter(|_| !binance_symbols.is_empty()) else {
+            return bybit.map(Cow::Borrowed);
+        };
+
+        let mut market = bybit.cloned().unwrap_or_default();
+        market.timestamp = market.timestamp.max(binance.timestamp);
+        for symbol in binance_symbols {
+            market.ticker.remove(symbol);
+            match binance.books.get(symbol) {
+                Some(book) => market.books.insert(symbol.clone(), book.into()),
+                None => market.books.remove(symbol),
+            };
+            match binance.trades.get(symbol) {
+                Some(trades) => market.trades.insert(
+                    symbol.clone(),
+                    trades.iter().map(ws_trade_from_aggr).collect(),
+                ),
+                None => market.trades.remove(symbol),
+            };
+        }
+        Some(Cow::Owned(market))
+    }
+
+    fn log_pnl(&self, data: &BybitMarket) {
+        for (symbol, book) in data.books.iter() {
+            if let Some(generator) = self.generators.get(symbol) {
+                generator.log_pnl(symbol, book.mid_price);
+                generator.log_quote_stats(symbol);
+            }
+        }
+    }
+
+    async fn potentially_update(&mut self, state: &SharedState, data: &BybitMarket) {
+        let now = generate_timestamp().unwrap_or_default();
+        for (symbol, book) in data.books.iter() {
+            let stale = self.is_stale(book, now);
+            if let (Some(engine), Some(generator)) =
+                (self.features.get(symbol), self.generators.get_mut(symbol))
+            {
+                // The private data of the venue the symbol is quoted on
+                let private: PrivateData = match generator.venue() {
+                    Venue::Bybit(_) => match state.privates.get(symbol) {
+                        Some(private) => private.clone().into(),
+                        None => continue,
+                    },
+                    Venue::Binance(_) => match state.binance_privates.get(symbol) {
+                        Some(private) => private.clone().into(),
+                        None => continue,
+                    },
+                };
+
+                // Holds off quoting while the features look like a blowup or an operator paused it
+                generator.set_paused(engine.is_anomalous() || self.halted.contains(symbol));
+                generator.set_stale_data(symbol, stale).await;
+                generator.set_price_shock(symbol, engine.is_price_shock(), now);
+
                 let skew = engine.get_skew();
                 let volatility = engine.get_volatility();
+                let fair_value = generator.fair_value(book, engine);
+                generator.set_range_volatility(engine.get_range_volatility());
+                generator.set_toxicity(engine.get_toxicity());
+                generator.set_vwap_bands(engine.get_vwap_bands(generator.vwap_band_sigma()));
 
                 generator
-                    .update_grid(private.clone(), skew, book, symbol, volatility)
+                    .update_grid(
+                        private,
+                        skew,
+                        book.clone(),
+                        symbol.clone(),
+                        volatility,
+                        fair_value,
+                    )
                     .await;
+                generator.record_metrics(symbol, book.mid_price);
+            }
+        }
+    }
+
+    /// Seeds the volatility of every symbol from its recent 1 minute candles so quoting
+    /// doesn't start on an empty window. Symbols whose candles can't be fetched start cold.
+    pub async fn seed_volatility(&mut self, clients: &BTreeMap<String, BybitClient>) {
+        for (symbol, engine) in self.features.iter_mut() {
+            let Some(client) = clients.get(symbol) else {
+                continue;
+            };
+            match client
+                .get_klines(symbol, "1", seed_klines(self.tick_window))
+                .await
+            {
+                Ok(candles) => {
+                    let closes: Vec<f64> = candles.iter().map(|candle| candle.close).collect();
+                    engine.seed_volatility(&closes, 60.0);
+                    let ranges: Vec<(f64, f64)> = candles
+                        .iter()
+                        .map(|candle| (candle.high, candle.low))
+                        .collect();
+                    engine.seed_range(&ranges);
+                }
+                Err(e) => eprintln!("Failed to seed the volatility of {}: {:?}", symbol, e),
             }
         }
     }
 
+    /// Seeds the trades of every symbol from its latest public trades, so the trade
+    /// imbalance and average trade price don't start from nothing while the stream fills
+    /// up. Symbols whose trades can't be fetched start cold.
+    pub async fn seed_trades(&mut self, clients: &BTreeMap<String, BybitClient>) {
+        for symbol in self.features.keys() {
+            let Some(client) = clients.get(symbol) else {
+                continue;
+            };
+            match client.get_recent_trades(symbol, SEED_TRADES).await {
+                Ok(trades) => {
+                    self.previous_trades.insert(symbol.clone(), trades.clone());
+                    self.current_trades.insert(symbol.clone(), trades);
+                }
+                Err(e) => eprintln!("Failed to seed the trades of {}: {:?}", symbol, e),
+            }
+        }
+    }
+
+    /// Leans the skew towards collecting funding by `weight`, on top of the weighted
+    /// signals. 0 leaves the skew unchanged.
+    pub fn set_funding_skew_weight(&mut self, weight: f64) {
+        for engine in self.features.values_mut() {
+            if let Err(err) = engine.set_funding_weight(weight) {
+                self.logger
+                    .error(&format!("Rejected funding skew weight: {}", err));
+                return;
+            }
+        }
+    }
+
+    /// Starts each symbol from its last settled funding rate until the tickers bring the
+    /// predicted one. Does nothing while the funding skew is disabled.
+    pub async fn seed_funding_rates(&mut self, clients: &BTreeMap<String, BybitClient>) {
+        for (symbol, engine) in self.features.iter_mut() {
+            let Some(client) = clients.get(symbol) else {
+                continue;
+            };
+            if engine.funding_weight == 0.0 {
+                continue;
+            }
+            match client.get_funding_rate(symbol).await {
+                Ok(rate) => engine.set_funding_rate(rate),
+                Err(e) => eprintln!("Failed to fetch the funding rate of {}: {:?}", symbol, e),
+            }
+        }
+    }
+
+    /// Pauses quoting a symbol while its book hasn't updated for `ms` milliseconds of
+    /// wall clock time, e.g. during a feed outage. 0 disables the check.
+    pub fn set_stale_data_ms(&mut self, ms: u64) {
+        self.stale_data_ms = ms;
+    }
+
+    fn is_stale(&self, book: &BybitBook, now: u64) -> bool {
+        self.stale_data_ms > 0 && now.saturating_sub(book.last_update) > self.stale_data_ms
+    }
+
+    /// Flags the symbols whose book went stale, which pulls their resting orders.
+    async fn check_stale_data(&mut self, market: &BybitMarket) {
+        let now = generate_timestamp().unwrap_or_default();
+        for (symbol, book) in market.books.iter() {
+            let stale = self.is_stale(book, now);
+            if let Some(generator) = self.generators.get_mut(symbol) {
+                generator.set_stale_data(symbol, stale).await;
+            }
+        }
+    }
+
+    /// Time between feature updates in milliseconds, 0 keeps the default of a second.
+    ///
+    /// The tick window counts feature updates, so a shorter interval also shortens the
+    /// time it covers.
+    pub fn set_feature_interval_ms(&mut self, ms: u64) {
+        if ms == 0 {
+            return;
+        }
+        self.feature_interval = Duration::from_millis(ms);
+        let interval = self.feature_interval;
+        self.features
+            .values_mut()
+            .for_each(|engine| engine.set_update_interval(interval));
+    }
+
+    // true once a feature interval has passed since the last update, which it then restarts
+    fn features_due(&mut self, now: tokio::time::Instant) -> bool {
+        if now.saturating_duration_since(self.last_feature_update) < self.feature_interval {
+            return false;
+        }
+        self.last_feature_update = now;
+        true
+    }
+
+    /// Number of market snapshots per symbol used only to seed the previous book
+    /// and trades, features are computed from the next one. At least 1.
+    pub fn set_warmup_snapshots(&mut self, count: usize) {
+        self.warmup_snapshots = count.max(1);
+    }
+
+    /// Returns the sender for runtime risk commands, replacing any previous channel.
+    pub fn risk_channel(&mut self) -> mpsc::UnboundedSender<RiskCommand> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.risk_commands = Some(receiver);
+        sender
+    }
+
+    fn drain_risk_commands(&mut self) {
+        let mut commands = Vec::new();
+        if let Some(receiver) = self.risk_commands.as_mut() {
+            while let Ok(command) = receiver.try_recv() {
+                commands.push(command);
+            }
+        }
+        for command in commands {
+            if let Err(err) = self.apply_risk_command(command) {
+                self.report_rejection("risk command", &err);
+            }
+        }
+    }
+
+    /// Validates and applies a risk command, leaving the limits untouched on error.
+    pub fn apply_risk_command(&mut self, command: RiskCommand) -> Result<(), String> {
+        match command {
+            RiskCommand::SetMaxPosition { symbol, usd } => {
+                check_limit("max position", usd, false)?;
+                self.generator_mut(&symbol)?.set_max_position_usd(usd);
+            }
+            RiskCommand::SetMaxDrawdown { symbol, usd } => {
+                check_limit("max drawdown", usd, true)?;
+                self.generator_mut(&symbol)?.set_max_drawdown_usd(usd);
+            }
+            RiskCommand::SetSpread { symbol, bps } => {
+                check_limit("spread", bps, false)?;
+                self.generator_mut(&symbol)?.set_min_spread(bps);
+            }
+            RiskCommand::Pause { symbol, paused } => {
+                self.generator_mut(&symbol)?;
+                if paused {
+                    self.halted.insert(symbol);
+                } else {
+                    self.halted.remove(&symbol);
+                }
+            }
+            RiskCommand::ResetKillSwitch { symbol } => {
+                self.generator_mut(&symbol)?.reset_kill_switch();
+            }
+            RiskCommand::Query(reply) => {
+                let _ = reply.send(self.risk_state());
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the sender for reloaded configs, replacing any previous channel. Only the
+    /// spreads, leverage, orders per side, quote side and depths are picked up, the rest
+    /// needs a restart.
+    pub fn config_channel(&mut self) -> mpsc::Sender<Config> {
+        let (sender, receiver) = mpsc::channel(CONFIG_CHANNEL_CAPACITY);
+        self.config_reloads = Some(receiver);
+        sender
+    }
+
+    // applies the newest of `config` and the configs queued behind it, the older ones are
+    // superseded by it
+    async fn apply_config_reloads(&mut self, mut config: Config) {
+        if let Some(receiver) = self.config_reloads.as_mut() {
+            while let Ok(newer) = receiver.try_recv() {
+                config = newer;
+            }
+        }
+        if let Err(err) = self.apply_config(&config).await {
+            self.report_rejection("config reload", &err);
+        }
+    }
+
+    /// Validates a reloaded config and applies its spreads, leverage, orders per side, quote
+    /// side and depths without touching the live orders, leaving everything unchanged on
+    /// error. The orders of a side no longer quoted are cancelled by the next grid.
+    pub async fn apply_config(&mut self, config: &Config) -> Result<(), String> {
+        config.validate().map_err(|e| e.to_string())?;
+
+        for (symbol, bps) in config.symbols().into_iter().zip(config.bps.iter()) {
+            match self.generators.get_mut(symbol) {
+                Some(generator) => generator.set_min_spread(*bps),
+                None => {
+                    self.logger
+                        .warning(&format!("{} isn't quoted, restart to quote it", symbol));
+                }
+            }
+        }
+        if config.leverage != self.leverage {
+            self.leverage = config.leverage;
+            for (symbol, generator) in self.generators.iter_mut() {
+                generator.set_leverage(config.leverage);
+                if let Err(e) = generator
+                    .venue()
+                    .set_leverage(symbol, generator.leverage() as u8)
+                    .await
+                {
+                    self.logger
+                        .error(&format!("Failed to set the leverage of {}: {}", symbol, e));
+                }
+            }
+        }
+        self.generators
+            .values_mut()
+            .for_each(|gen| gen.set_orders_per_side(config.orders_per_side));
+        self.set_quote_side(config.quote_side);
+        self.depths = config.depths.clone();
+        Ok(())
+    }
+
+    /// Returns the sender for manual orders, replacing any previous channel.
+    pub fn order_channel(&mut self) -> mpsc::UnboundedSender<OrderCommand> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.order_commands = Some(receiver);
+        sender
+    }
+
+    /// Sends a manual order through the symbol's generator, `market` supplying the book
+    /// a flatten closes the position at.
+    pub async fn apply_order_command(
+        &mut self,
+        command: OrderCommand,
+        market: &BybitMarket,
+    ) -> Result<(), String> {
+        match command {
+            OrderCommand::Place {
+                symbol,
+                price,
+                qty,
+                is_buy,
+            } => {
+                self.generator_mut(&symbol)?
+                    .manual_order(&symbol, price, qty, is_buy)
+                    .await
+            }
+            OrderCommand::Cancel { symbol, order_id } => {
+                self.generator_mut(&symbol)?
+                    .cancel_manual(&symbol, &order_id)
+                    .await
+            }
+            OrderCommand::Flatten { symbol } => {
+                let book = market
+                    .books
+                    .get(&symbol)
+                    .ok_or_else(|| format!("No book for {}", symbol))?;
+                self.generator_mut(&symbol)?.flatten(&symbol, book).await
+            }
+        }
+    }
+
+    pub fn risk_state(&self) -> BTreeMap<String, RiskState> {
+        self.generators
+            .iter()
+            .map(|(symbol, gen)| (symbol.clone(), gen.risk_state()))
+            .collect()
+    }
+
+    fn generator_mut(&mut self, symbol: &str) -> Result<&mut QuoteGenerator, String> {
+        self.generators
+            .get_mut(symbol)
+            .ok_or_else(|| format!("Unknown symbol {}", symbol))
+    }
+
     pub fn set_spread_toml(&mut self, bps: Vec<f64>) {
         self.generators
             .values_mut()
             .zip(bps.into_iter())
             .for_each(|(gen, spread)| gen.set_min_spread(spread));
     }
+
+    /// Centers the quotes of `symbol` on the price given by `source` instead of the book mid.
+    pub fn set_fair_value_source(&mut self, symbol: &str, source: Box<dyn FairValueSource>) {
+        if let Some(generator) = self.generators.get_mut(symbol) {
+            generator.set_fair_value_source(source);
+        }
+    }
+
+    /// Centers the quotes of every symbol on the configured anchor.
+    pub fn set_quote_anchor(&mut self, anchor: QuoteAnchor) {
+        self.generators
+            .values_mut()
+            .for_each(|gen| gen.set_fair_value_source(anchor_source(anchor)));
+    }
+
+    pub fn set_spread_model(&mut self, model: SpreadModel) {
+        self.generators
+            .values_mut()
+            .for_each(|gen| gen.set_spread_model(model));
+    }
+
+    pub fn set_vol_estimator(&mut self, estimator: VolEstimator) {
+        self.features
+            .values_mut()
+            .for_each(|engine| engine.set_vol_estimator(estimator));
+    }
+
+    pub fn set_skew_weights(&mut self, weights: SkewWeights) {
+        for engine in self.features.values_mut() {
+            if let Err(err) = engine.set_skew_weights(weights) {
+                self.logger
+                    .error(&format!("Rejected skew weights: {}", err));
+                return;
+            }
+        }
+    }
+
+    pub fn set_imbalance_dead_zone(&mut self, threshold: f64) {
+        self.features
+            .values_mut()
+            .for_each(|engine| engine.set_imbalance_dead_zone(threshold));
+    }
+
+    pub fn set_anomaly_sigma(&mut self, sigma: f64) {
+        self.features
+            .values_mut()
+            .for_each(|engine| engine.set_anomaly_threshold(sigma));
+    }
+
+    /// Pulls every quote of a symbol for `cooldown_secs` once its mid return is more than
+    /// `sigma` standard deviations from the rolling mean or the mid moves more than `bps`
+    /// in one feature update. 0.0 disables either check.
+    pub fn set_circuit_breaker(&mut self, sigma: f64, bps: f64, cooldown_secs: u64) {
+        self.features
+            .values_mut()
+            .for_each(|engine| engine.set_price_shock_limits(sigma, bps));
+        self.generators
+            .values_mut()
+            .for_each(|gen| gen.set_circuit_breaker_cooldown(cooldown_secs));
+    }
+
+    /// Pulls the quotes of a symbol for the circuit breaker's cooldown once its order
+    /// requests take `ms` on average, 0 disables it.
+    pub fn set_max_order_latency(&mut self, ms: u64) {
+        self.generators
+            .values_mut()
+            .for_each(|gen| gen.set_max_order_latency(ms));
+    }
+
+    pub fn set_log_intended_quotes(&mut self, enabled: bool) {
+        self.generators
+            .values_mut()
+            .for_each(|gen| gen.set_log_intended_quotes(enabled));
+    }
+
+    /// Trips the kill switch of a symbol once its equity falls `pct` percent from the peak,
+    /// optionally flattening the position. 0.0 disables it.
+    pub fn set_max_drawdown_pct(&mut self, pct: f64, flatten: bool) {
+        self.generators.values_mut().for_each(|gen| {
+            gen.set_max_drawdown_pct(Some(pct));
+            gen.set_flatten_on_kill_switch(flatten);
+        });
+    }
+
+    pub fn set_toxicity_weight(&mut self, weight: f64) {
+        self.generators
+            .values_mut()
+            .for_each(|gen| gen.set_toxicity_weight(weight));
+    }
+
+    /// Tightens the quotes near the session VWAP and leans them back towards it as the
+    /// price stretches to the bands `sigma` standard deviations away, by up to `weight`.
+    pub fn set_vwap_overlay(&mut self, sigma: f64, weight: f64) {
+        self.generators.values_mut().for_each(|gen| {
+            gen.set_vwap_band_sigma(sigma);
+            gen.set_vwap_weight(weight);
+        });
+    }
+
+    pub fn set_max_order_age(&mut self, secs: u64) {
+        self.generators
+            .values_mut()
+            .for_each(|gen| gen.set_max_order_age(secs));
+    }
+
+    pub fn set_quote_side(&mut self, side: QuoteSide) {
+        self.generators
+            .values_mut()
+            .for_each(|gen| gen.set_quote_side(side));
+    }
+
+    pub fn set_safety_factor(&mut self, safety_factor: f64) {
+        self.generators
+            .values_mut()
+            .for_each(|gen| gen.set_safety_factor(safety_factor));
+    }
+
+    /// Resizes the position caps from the live account equity every `secs` seconds, at
+    /// least 10. 0 disables it and keeps the caps sized from the starting capital.
+    pub fn set_wallet_refresh(&mut self, secs: u64) {
+        self.wallet_refresh_ms = match secs.saturating_mul(1000) {
+            0 => 0,
+            ms => ms.max(MIN_WALLET_REFRESH_MS),
+        };
+    }
+
+    /// Symbols placing orders, by the account and settle coin their equity is fetched for.
+    fn wallet_accounts(&self) -> BTreeMap<(String, &'static str), Vec<String>> {
+        let mut accounts: BTreeMap<(String, &'static str), Vec<String>> = BTreeMap::new();
+        for (symbol, generator) in &self.generators {
+            if generator.places_orders() {
+                let account = (generator.venue().api_key().to_string(), settle_coin(symbol));
+                accounts.entry(account).or_default().push(symbol.clone());
+            }
+        }
+        accounts
+    }
+
+    /// Fetches the equity of every account once the refresh interval has passed, one
+    /// request per account and settle coin, and resizes the position caps of its symbols.
+    /// A failed request keeps their last caps.
+    async fn refresh_position_caps(&mut self, now: u64) {
+        if self.wallet_refresh_ms == 0
+            || now.saturating_sub(self.last_wallet_refresh) < self.wallet_refresh_ms
+        {
+            return;
+        }
+        self.last_wallet_refresh = now;
+        for ((_, coin), symbols) in self.wallet_accounts() {
+            let venue = self.generators[&symbols[0]].venue().clone();
+            match venue.get_wallet_balance(coin).await {
+                Ok(equity) => {
+                    for symbol in &symbols {
+                        if let Some(generator) = self.generators.get_mut(symbol) {
+                            generator.set_wallet_equity(equity);
+                        }
+                    }
+                }
+                Err(e) => {
+                    self.logger.error(&format!(
+                        "Failed to fetch the {} balance for {}: {}",
+                        coin,
+                        symbols.join(", "),
+                        e
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Shares a budget of `requests_per_sec` REST requests between the generators of every
+    /// symbol, 0 leaves each symbol with only its own budget.
+    pub fn set_account_rate_limit(&mut self, requests_per_sec: u32) {
+        let limiter = (requests_per_sec > 0).then(|| SharedRateLimiter::new(requests_per_sec));
+        self.generators
+            .values_mut()
+            .for_each(|gen| gen.set_rate_limiter(limiter.clone()));
+    }
+
+    /// Shifts the grids with amends while their mid drifts by at most `drift_bps` per
+    /// update, once it's `min_ticks` ticks away. 0.0 disables it.
+    pub fn set_trailing(&mut self, drift_bps: f64, min_ticks: u32) {
+        self.generators
+            .values_mut()
+            .for_each(|gen| gen.set_trailing(drift_bps, min_ticks));
+    }
+
+    pub fn set_post_only_retries(&mut self, retries: usize) {
+        self.generators
+            .values_mut()
+            .for_each(|gen| gen.set_post_only_retries(retries));
+    }
+
+    pub fn set_inventory_adjustment(&mut self, adjustment: f64) {
+        self.generators
+            .values_mut()
+            .for_each(|gen| gen.set_inventory_adjustment(adjustment));
+    }
+
+    pub fn set_spread_multipliers(
+        &mut self,
+        volatility_multiplier: f64,
+        max_spread_multiplier: f64,
+    ) {
+        self.generators.values_mut().for_each(|gen| {
+            gen.set_spread_multipliers(volatility_multiplier, max_spread_multiplier)
+        });
+    }
+
+    pub fn set_range_vol_weight(&mut self, weight: f64) {
+        self.generators
+            .values_mut()
+            .for_each(|gen| gen.set_range_vol_weight(weight));
+    }
+
+    /// Appends the features of every symbol to `path` as a JSON line after each update.
+    /// An empty `path` disables the log.
+    pub fn set_feature_log(&mut self, path: &str) {
+        if path.is_empty() {
+            return;
+        }
+        match OpenOptions::new().create(true).append(true).open(path) {
+            Ok(file) => self.feature_log = Some(BufWriter::new(file)),
+            Err(e) => eprintln!("Failed to open the feature log {}: {}", path, e),
+        }
+    }
+
+    /// Saves each generator's orders, position and PnL to `<dir>/<symbol>.json` and
+    /// restores what a previous run left there. An empty `dir` disables persistence.
+    pub fn set_state_dir(&mut self, dir: &str) {
+        if dir.is_empty() {
+            return;
+        }
+        for (symbol, generator) in self.generators.iter_mut() {
+            generator.set_persistence(Box::new(FilePersistence::new(dir)));
+            generator.restore_state(symbol);
+        }
+    }
+
+    pub fn set_dry_run(&mut self, enabled: bool) {
+        self.generators
+            .values_mut()
+            .for_each(|gen| gen.set_dry_run(enabled));
+    }
+
+    pub fn set_fallback_min_notional(&mut self, notional: f64) {
+        self.generators
+            .values_mut()
+            .for_each(|gen| gen.set_fallback_min_notional(notional));
+    }
+
+    pub fn set_floor_size_to_lot(&mut self, enabled: bool) {
+        self.generators
+            .values_mut()
+            .for_each(|gen| gen.set_floor_size_to_lot(enabled));
+    }
+
+    pub fn set_dust_fraction(&mut self, fraction: f64) {
+        self.generators
+            .values_mut()
+            .for_each(|gen| gen.set_dust_fraction(fraction));
+    }
+
+    pub fn set_min_edge_bps(&mut self, bps: f64) {
+        self.generators
+            .values_mut()
+            .for_each(|gen| gen.set_min_edge_bps(bps));
+    }
+
+    pub fn set_reduce_only_delta(&mut self, delta: f64) {
+        self.generators
+            .values_mut()
+            .for_each(|gen| gen.set_reduce_only_delta(delta));
+    }
+
+    pub fn set_quoting_model(&mut self, model: QuotingModel) {
+        self.generators
+            .values_mut()
+            .for_each(|gen| gen.set_quoting_model(model));
+    }
+
+    pub fn set_time_in_force(&mut self, time_in_force: TimeInForce) {
+        self.generators
+            .values_mut()
+            .for_each(|gen| gen.set_time_in_force(time_in_force));
+    }
+
+    /// Distance of every grid's outermost level from the touch, in multiples of the spread.
+    pub fn set_final_order_distance(&mut self, distance: f64) {
+        self.generators
+            .values_mut()
+            .for_each(|gen| gen.set_final_order_distance(distance));
+    }
+
+    pub fn set_grid_ratios(&mut self, bid_ratio: f64, ask_ratio: f64, skew_weighted: bool) {
+        self.generators.values_mut().for_each(|gen| {
+            gen.set_bid_ratio(bid_ratio);
+            gen.set_ask_ratio(ask_ratio);
+            gen.set_skew_weighted_ratio(skew_weighted);
+        });
+    }
+}
+
+/// Waits for the next reloaded config, forever while no channel was opened.
+async fn next_config_reload(receiver: &mut Option<mpsc::Receiver<Config>>) -> Option<Config> {
+    match receiver {
+        Some(receiver) => receiver.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Waits for the next manual order, forever while no channel was opened.
+async fn next_order_command(
+    receiver: &mut Option<mpsc::UnboundedReceiver<OrderCommand>>,
+) -> Option<OrderCommand> {
+    match receiver {
+        Some(receiver) => receiver.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// The Bybit and Binance markets among `markets`.
+fn venue_markets(markets: &[MarketData]) -> (Option<&BybitMarket>, Option<&BinanceMarket>) {
+    let mut bybit = None;
+    let mut binance = None;
+    for market in markets {
+        match market {
+            MarketData::Bybit(market) => bybit = Some(market),
+            MarketData::Binance(market) => binance = Some(market),
+        }
+    }
+    (bybit, binance)
+}
+
+fn check_limit(name: &str, value: f64, allow_zero: bool) -> Result<(), String> {
+    if value.is_finite() && (value > 0.0 || (allow_zero && value == 0.0)) {
+        Ok(())
+    } else {
+        Err(format!("Invalid {}: {}", name, value))
+    }
+}
+
+/// Klines seeding a volatility over `tick_window` returns, capped at one request's worth
+/// rather than wrapping around `u16`.
+fn seed_klines(tick_window: usize) -> u16 {
+    tick_window.saturating_add(1).min(MAX_SEED_KLINES) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use skeleton::{
+        exchange::exchange::WsTrade,
+        utils::{
+            localorderbook::OrderBook,
+            logger::{LogLevel, Logger},
+            models::{BinanceBook, LiveOrder},
+        },
+    };
+
+    #[test]
+    fn test_seed_klines() {
+        assert_eq!(seed_klines(60), 61);
+        // Long windows take the most one request returns instead of wrapping around
+        assert_eq!(seed_klines(999), 1000);
+        assert_eq!(seed_klines(65_536), 1000);
+        assert_eq!(seed_klines(usize::MAX), 1000);
+    }
+
+    #[tokio::test]
+    async fn test_wallet_refresh_is_per_account() {
+        let mut maker = Maker::new(
+            SharedState::new("bybit".to_string()),
+            HashMap::new(),
+            10.0,
+            4,
+            10,
+            10,
+            vec![3],
+        )
+        .await;
+        let logger = Logger::capturing();
+        for (symbol, key) in [
+            ("SOLUSDT", "a"),
+            ("ETHUSDT", "a"),
+            ("BTCPERP", "a"),
+            ("XRPUSDT", "b"),
+        ] {
+            let client = BybitClient::new(key.to_string(), String::new(), logger.clone());
+            maker.generators.insert(
+                symbol.to_string(),
+                QuoteGenerator::with_logger(client, logger.clone(), 1000.0, 1.0, 4, 10, 10)
+                    .unwrap(),
+            );
+        }
+
+        // One request per account and settle coin
+        let accounts = maker.wallet_accounts();
+        assert_eq!(accounts.len(), 3);
+        assert_eq!(accounts[&("a".to_string(), "USDT")], ["ETHUSDT", "SOLUSDT"]);
+        assert_eq!(accounts[&("a".to_string(), "USDC")], ["BTCPERP"]);
+        // A dry run symbol never asks for its wallet
+        maker
+            .generators
+            .get_mut("XRPUSDT")
+            .unwrap()
+            .set_dry_run(true);
+        assert!(!maker
+            .wallet_accounts()
+            .contains_key(&("b".to_string(), "USDT")));
+
+        // Disabled by default, then no more often than the interval (the clients have no
+        // keys, so reaching the exchange would fail the test)
+        maker.refresh_position_caps(1_000_000).await;
+        assert_eq!(maker.last_wallet_refresh, 0);
+        maker.set_wallet_refresh(1);
+        assert_eq!(maker.wallet_refresh_ms, 10_000);
+        maker.last_wallet_refresh = 1_000_000;
+        maker.refresh_position_caps(1_009_999).await;
+        assert_eq!(maker.last_wallet_refresh, 1_000_000);
+    }
+
+    #[tokio::test]
+    async fn test_strategy_modes_are_wired() {
+        let mut maker = Maker::new(
+            SharedState::new("bybit".to_string()),
+            HashMap::new(),
+            10.0,
+            4,
+            10,
+            10,
+            vec![3],
+        )
+        .await;
+        let logger = Logger::capturing();
+        let client = BybitClient::new(String::new(), String::new(), logger.clone());
+        maker.generators.insert(
+            "SOLUSDT".to_string(),
+            QuoteGenerator::with_logger(client, logger, 1000.0, 1.0, 4, 10, 10).unwrap(),
+        );
+        maker
+            .features
+            .insert("SOLUSDT".to_string(), Engine::new(10, None));
+
+        let mut book = BybitBook::new();
+        book.best_bid.price = 99.0;
+        book.best_bid.qty = 30.0;
+        book.best_ask.price = 101.0;
+        book.best_ask.qty = 10.0;
+        book.set_mid_price();
+
+        for (anchor, expected) in [
+            (QuoteAnchor::Mid, book.get_mid_price()),
+            (QuoteAnchor::Wmid, book.get_wmid(None)),
+            (QuoteAnchor::Microprice, book.get_microprice(None)),
+        ] {
+            maker.set_quote_anchor(anchor);
+            let engine = &maker.features["SOLUSDT"];
+            assert_eq!(
+                maker.generators["SOLUSDT"].fair_value(&book, engine),
+                expected
+            );
+        }
+
+        for model in [SpreadModel::Constant, SpreadModel::VolLinear] {
+            maker.set_spread_model(model);
+            assert_eq!(maker.generators["SOLUSDT"].spread_model(), model);
+        }
+
+        maker.set_vol_estimator(VolEstimator::Parkinson);
+        assert_eq!(
+            maker.features["SOLUSDT"].vol_estimator,
+            VolEstimator::Parkinson
+        );
+    }
+
+    #[tokio::test]
+    async fn test_features_start_after_warmup() {
+        let mut maker = Maker::new(
+            SharedState::new("bybit".to_string()),
+            HashMap::new(),
+            10.0,
+            4,
+            10,
+            10,
+            vec![1],
+        )
+        .await;
+        maker.set_warmup_snapshots(2);
+        maker
+            .features
+            .insert("SOLUSDT".to_string(), Engine::new(10, None));
+
+        let mut book = BybitBook::new();
+        book.best_bid.price = 99.0;
+        book.best_bid.qty = 10.0;
+        book.best_ask.price = 101.0;
+        book.best_ask.qty = 10.0;
+        book.set_mid_price();
+
+        // Only books: the trades of a symbol can show up later than its book
+        let mut market = BybitMarket::default();
+        market.books.insert("SOLUSDT".to_string(), book);
+        let updates = |maker: &Maker| maker.features["SOLUSDT"].get_rate_of_change().len();
+
+        maker.update_features(market.clone(), &[1]);
+        assert_eq!(updates(&maker), 0);
+        assert!(maker.previous_book.contains_key("SOLUSDT"));
+        maker.update_features(market.clone(), &[1]);
+        assert_eq!(updates(&maker), 0);
+        maker.update_features(market.clone(), &[1]);
+        assert_eq!(updates(&maker), 1);
+        maker.update_features(market, &[1]);
+        assert_eq!(updates(&maker), 2);
+    }
+
+    #[tokio::test]
+    async fn test_seeded_trades_are_fed_once() {
+        let mut maker = Maker::new(
+            SharedState::new("bybit".to_string()),
+            HashMap::new(),
+            10.0,
+            4,
+            10,
+            10,
+            vec![1],
+        )
+        .await;
+        maker
+            .features
+            .insert("SOLUSDT".to_string(), Engine::new(10, None));
+        let trades = |side: &str| -> TradeType {
+            (0..3)
+                .map(|i| WsTrade {
+                    id: format!("{}{}", side, i),
+                    side: side.to_string(),
+                    volume: 1.0,
+                    price: 100.0,
+                    ..Default::default()
+                })
+                .collect()
+        };
+        // As `seed_trades` leaves them
+        maker
+            .previous_trades
+            .insert("SOLUSDT".to_string(), trades("Buy"));
+        maker
+            .current_trades
+            .insert("SOLUSDT".to_string(), trades("Buy"));
+
+        let mut book = BybitBook::new();
+        book.best_bid.price = 99.0;
+        book.best_bid.qty = 10.0;
+        book.best_ask.price = 101.0;
+        book.best_ask.qty = 10.0;
+        book.set_mid_price();
+        let mut market = BybitMarket::default();
+        market.books.insert("SOLUSDT".to_string(), book);
+        market
+            .trades
+            .insert("SOLUSDT".to_string(), TradeType::new());
+
+        // The first snapshot is the warmup, the first update takes the seeded trades
+        maker.update_features(market.clone(), &[1]);
+        assert!(maker.current_trades.contains_key("SOLUSDT"));
+        maker.update_features(market.clone(), &[1]);
+        assert!(maker.features["SOLUSDT"].trade_imbalance > 0.0);
+        assert!(!maker.current_trades.contains_key("SOLUSDT"));
+        // and they aren't fed again while the stream has none
+        maker.update_features(market.clone(), &[1]);
+        assert_eq!(maker.features["SOLUSDT"].trade_imbalance, 0.0);
+
+        market.trades.insert("SOLUSDT".to_string(), trades("Sell"));
+        maker.update_features(market, &[1]);
+        assert!(maker.features["SOLUSDT"].trade_imbalance < 0.0);
+        assert_eq!(maker.previous_trades["SOLUSDT"][0].side, "Sell");
+    }
+
+    #[tokio::test]
+    async fn test_features_are_logged_each_update() {
+        let mut maker = Maker::new(
+            SharedState::new("bybit".to_string()),
+            HashMap::new(),
+            10.0,
+            4,
+            10,
+            10,
+            vec![1],
+        )
+        .await;
+        maker
+            .features
+            .insert("SOLUSDT".to_string(), Engine::new(10, None));
+        let path =
+            std::env::temp_dir().join(format!("rs_smm_v2_features_{}.ndjson", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        maker.set_feature_log(path.to_str().unwrap());
+
+        let mut book = BybitBook::new();
+        book.best_bid.price = 99.0;
+        book.best_bid.qty = 30.0;
+        book.best_ask.price = 101.0;
+        book.best_ask.qty = 10.0;
+        book.set_mid_price();
+        let mut market = BybitMarket::default();
+        market.books.insert("SOLUSDT".to_string(), book);
+
+        // The warmup snapshot isn't logged
+        for _ in 0..3 {
+            maker.update_features(market.clone(), &[1]);
+        }
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<serde_json::Value> = contents
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1]["symbol"], "SOLUSDT");
+        assert_eq!(lines[1]["features"]["bba_imbalance"], 0.5);
+        assert_eq!(
+            maker.features["SOLUSDT"].snapshot().bba_imbalance,
+            maker.features["SOLUSDT"].get_bba_imbalance()
+        );
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_risk_commands_apply_on_next_cycle() {
+        let mut maker = Maker::new(
+            SharedState::new("bybit".to_string()),
+            HashMap::new(),
+            10.0,
+            4,
+            10,
+            10,
+            vec![1],
+        )
+        .await;
+        let logger = Logger::capturing();
+        let client = BybitClient::new(String::new(), String::new(), logger.clone());
+        maker.generators.insert(
+            "SOLUSDT".to_string(),
+            QuoteGenerator::with_logger(client, logger, 1000.0, 1.0, 4, 10, 10).unwrap(),
+        );
+        let commands = maker.risk_channel();
+        let symbol = || "SOLUSDT".to_string();
+
+        for command in [
+            RiskCommand::SetMaxPosition {
+                symbol: symbol(),
+                usd: 500.0,
+            },
+            RiskCommand::SetMaxDrawdown {
+                symbol: symbol(),
+                usd: 50.0,
+            },
+            RiskCommand::SetSpread {
+                symbol: symbol(),
+                bps: 12.0,
+            },
+            RiskCommand::Pause {
+                symbol: symbol(),
+                paused: true,
+            },
+            // Rejected: a zero cap, a NaN spread and an unknown symbol
+            RiskCommand::SetMaxPosition {
+                symbol: symbol(),
+                usd: 0.0,
+            },
+            RiskCommand::SetSpread {
+                symbol: symbol(),
+                bps: f64::NAN,
+            },
+            RiskCommand::SetMaxPosition {
+                symbol: "BTCUSDT".to_string(),
+                usd: 500.0,
+            },
+        ] {
+            commands.send(command).unwrap();
+        }
+        // Nothing changes until the loop drains the channel
+        assert_eq!(maker.risk_state()["SOLUSDT"].spread_bps, 25.0);
+
+        let (reply, mut state) = oneshot::channel();
+        commands.send(RiskCommand::Query(reply)).unwrap();
+        maker.drain_risk_commands();
+
+        let state = state.try_recv().unwrap();
+        let sol = &state["SOLUSDT"];
+        assert_eq!(sol.max_position_usd, 500.0);
+        assert_eq!(sol.max_drawdown_usd, 50.0);
+        assert_eq!(sol.spread_bps, 12.0);
+        assert!(maker.halted.contains("SOLUSDT"));
+        assert!(maker
+            .apply_risk_command(RiskCommand::Pause {
+                symbol: symbol(),
+                paused: false,
+            })
+            .is_ok());
+        assert!(maker.halted.is_empty());
+        assert!(maker
+            .apply_risk_command(RiskCommand::ResetKillSwitch { symbol: symbol() })
+            .is_ok());
+        assert!(!maker.risk_state()["SOLUSDT"].kill_switch);
+    }
+
+    #[tokio::test]
+    async fn test_config_reloads_apply_live() {
+        let mut maker = Maker::new(
+            SharedState::new("bybit".to_string()),
+            HashMap::new(),
+            10.0,
+            4,
+            10,
+            10,
+            vec![1],
+        )
+        .await;
+        let logger = Logger::capturing();
+        maker.set_logger(logger.clone());
+        let client = BybitClient::new(String::new(), String::new(), logger.clone());
+        maker.generators.insert(
+            "SOLUSDT".to_string(),
+            QuoteGenerator::with_logger(client, logger, 1000.0, 10.0, 4, 10, 10).unwrap(),
+        );
+        let config = |tick_window: usize| -> Config {
+            serde_json::from_value(serde_json::json!({
+                "token": "token",
+                "chat_id": 1,
+                "api_keys": [["key", "secret", "SOLUSDT"]],
+                "balances": [["SOLUSDT", 1000.0]],
+                "leverage": 10.0,
+                "orders_per_side": 6,
+                "depths": [5, 10],
+                "rate_limit": 10,
+                "bps": [15.0],
+                "tick_window": tick_window,
+            }))
+            .unwrap()
+        };
+        let reloads = maker.config_channel();
+
+        // Queued reloads wait for the loop, which applies the newest
+        reloads.send(config(1)).await.unwrap();
+        reloads.send(config(10)).await.unwrap();
+        let reload = next_config_reload(&mut maker.config_reloads).await.unwrap();
+        maker.apply_config_reloads(reload).await;
+        assert_eq!(maker.depths, vec![5, 10]);
+        assert_eq!(maker.risk_state()["SOLUSDT"].spread_bps, 15.0);
+
+        // An invalid config changes nothing and is reported
+        let mut invalid = config(1);
+        invalid.bps = vec![30.0];
+        invalid.depths = vec![3];
+        invalid.leverage = 2.5;
+        maker.apply_config_reloads(invalid).await;
+        assert_eq!(maker.depths, vec![5, 10]);
+        assert_eq!(maker.risk_state()["SOLUSDT"].spread_bps, 15.0);
+        assert!(maker.logger.captured().iter().any(|(level, msg)| {
+            *level == LogLevel::Error
+                && msg.starts_with("Rejected config reload: invalid `leverage`")
+        }));
+        assert!(
+            metrics::render().contains("smm_rejected_commands_total{source=\"config reload\"} 1")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_manual_orders_go_through_the_generator() {
+        let mut maker = Maker::new(
+            SharedState::new("bybit".to_string()),
+            HashMap::new(),
+            10.0,
+            4,
+            10,
+            10,
+            vec![1],
+        )
+        .await;
+        let logger = Logger::capturing();
+        let client = BybitClient::new(String::new(), String::new(), logger.clone());
+        let mut generator =
+            QuoteGenerator::with_logger(client, logger, 1000.0, 1.0, 4, 10, 10).unwrap();
+        generator.set_dry_run(true);
+        maker.generators.insert("SOLUSDT".to_string(), generator);
+        let symbol = || "SOLUSDT".to_string();
+
+        let mut market = BybitMarket::default();
+        let mut book = BybitBook::new();
+        book.best_bid.price = 99.0;
+        book.best_ask.price = 101.0;
+        book.lot_size = 0.1;
+        book.set_mid_price();
+        market.books.insert(symbol(), book);
+
+        let place = |price: f64, qty: f64| OrderCommand::Place {
+            symbol: symbol(),
+            price,
+            qty,
+            is_buy: true,
+        };
+        assert!(maker
+            .apply_order_command(place(98.0, 1.0), &market)
+            .await
+            .is_ok());
+        // Kept apart from the grid orders
+        assert!(maker.generators["SOLUSDT"].live_buys.is_empty());
+        let order_id = maker.generators["SOLUSDT"].manual_orders[0]
+            .order_id
+            .clone();
+        assert_eq!(maker.generators["SOLUSDT"].manual_orders[0].price, 98.0);
+
+        // Rejected: a zero qty and an unknown symbol
+        assert!(maker
+            .apply_order_command(place(98.0, 0.0), &market)
+            .await
+            .is_err());
+        let unknown = OrderCommand::Flatten {
+            symbol: "BTCUSDT".to_string(),
+        };
+        assert!(maker.apply_order_command(unknown, &market).await.is_err());
+
+        let cancel = OrderCommand::Cancel {
+            symbol: symbol(),
+            order_id,
+        };
+        assert!(maker.apply_order_command(cancel, &market).await.is_ok());
+        assert!(maker.generators["SOLUSDT"].manual_orders.is_empty());
+
+        // Flattening a long sells it at the best bid
+        maker.generators.get_mut("SOLUSDT").unwrap().position_qty = 2.0;
+        assert!(maker
+            .apply_order_command(place(97.0, 1.0), &market)
+            .await
+            .is_ok());
+        let flatten = OrderCommand::Flatten { symbol: symbol() };
+        assert!(maker.apply_order_command(flatten, &market).await.is_ok());
+        assert!(maker.generators["SOLUSDT"].manual_orders.is_empty());
+        assert!(maker.generators["SOLUSDT"].position_qty.abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn test_stale_books_are_not_quoted() {
+        let mut maker = Maker::new(
+            SharedState::new("bybit".to_string()),
+            HashMap::new(),
+            10.0,
+            4,
+            10,
+            10,
+            vec![3],
+        )
+        .await;
+        let mut book = BybitBook::new();
+        book.last_update = 1_700_000_000_000;
+
+        // Disabled by default
+        assert!(!maker.is_stale(&book, book.last_update + 60_000));
+
+        maker.set_stale_data_ms(5_000);
+        assert!(!maker.is_stale(&book, book.last_update + 5_000));
+        assert!(maker.is_stale(&book, book.last_update + 5_001));
+        // A clock behind the exchange doesn't wrap around
+        assert!(!maker.is_stale(&book, book.last_update - 1_000));
+
+        // Without updates the timer pulls the resting orders of a stale book
+        let logger = Logger::capturing();
+        let client = BybitClient::new(String::new(), String::new(), logger.clone());
+        let mut generator =
+            QuoteGenerator::with_logger(client, logger, 1000.0, 1.0, 4, 10, 10).unwrap();
+        generator.set_dry_run(true);
+        generator
+            .live_buys
+            .push_back(LiveOrder::new("buy".to_string(), 99.0, 1.0, true));
+        maker.generators.insert("SOLUSDT".to_string(), generator);
+        let mut market = BybitMarket::default();
+        market.books.insert("SOLUSDT".to_string(), book);
+        maker.check_stale_data(&market).await;
+        assert!(maker.generators["SOLUSDT"].live_buys.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_feature_interval_sets_the_update_rate() {
+        let mut maker = Maker::new(
+            SharedState::new("bybit".to_string()),
+            HashMap::new(),
+            10.0,
+            4,
+            10,
+            10,
+            vec![3],
+        )
+        .await;
+        maker
+            .features
+            .insert("SOLUSDT".to_string(), Engine::new(10, None));
+
+        // Counts the updates over a simulated second of 10ms market updates
+        let updates_per_second = |maker: &mut Maker| {
+            let start = tokio::time::Instant::now();
+            maker.last_feature_update = start;
+            (1..=100)
+                .filter(|step| maker.features_due(start + Duration::from_millis(step * 10)))
+                .count()
+        };
+
+        assert_eq!(updates_per_second(&mut maker), 1);
+
+        maker.set_feature_interval_ms(250);
+        assert_eq!(updates_per_second(&mut maker), 4);
+        assert_eq!(maker.features["SOLUSDT"].update_interval_secs, 0.25);
+
+        // 0 leaves the interval unchanged
+        maker.set_feature_interval_ms(0);
+        assert_eq!(updates_per_second(&mut maker), 4);
+    }
+
+    #[tokio::test]
+    async fn test_symbols_are_quoted_on_their_venue() {
+        let mut maker = Maker::new(
+            SharedState::new("both".to_string()),
+            HashMap::new(),
+            10.0,
+            4,
+            10,
+            10,
+            vec![3],
+        )
+        .await;
+        let logger = Logger::capturing();
+        let bybit = BybitClient::new(String::new(), String::new(), logger.clone());
+        maker.generators.insert(
+            "SOLUSDT".to_string(),
+            QuoteGenerator::with_logger(bybit, logger.clone(), 1000.0, 1.0, 4, 10, 10).unwrap(),
+        );
+
+        let book = |mid: f64| {
+            let mut book = BybitBook::new();
+            book.best_bid.price = mid - 1.0;
+            book.best_ask.price = mid + 1.0;
+            book.set_mid_price();
+            book
+        };
+        let mut bybit_market = BybitMarket::default();
+        for symbol in ["SOLUSDT", "BTCUSDT"] {
+            bybit_market.books.insert(symbol.to_string(), book(100.0));
+            bybit_market
+                .trades
+                .insert(symbol.to_string(), TradeType::new());
+        }
+        let mut binance_book = BinanceBook::new();
+        binance_book.best_bid.price = 199.0;
+        binance_book.best_ask.price = 201.0;
+        binance_book.set_mid_price();
+        let mut binance_market = BinanceMarket::default();
+        for symbol in ["SOLUSDT", "BTCUSDT"] {
+            binance_market
+                .books
+                .insert(symbol.to_string(), binance_book.clone());
+        }
+        let markets = vec![
+            MarketData::Bybit(bybit_market),
+            MarketData::Binance(binance_market),
+        ];
+
+        // Nothing to convert while every symbol is quoted on Bybit
+        let market = maker.quoted_market(&markets).unwrap();
+        assert!(matches!(market, Cow::Borrowed(_)));
+        assert_eq!(market.books["BTCUSDT"].mid_price, 100.0);
+
+        let binance = BinanceClient::new(String::new(), String::new(), logger.clone());
+        maker.generators.insert(
+            "BTCUSDT".to_string(),
+            QuoteGenerator::with_logger(binance, logger, 1000.0, 1.0, 4, 10, 10).unwrap(),
+        );
+        let market = maker.quoted_market(&markets).unwrap();
+        assert_eq!(market.books["SOLUSDT"].mid_price, 100.0);
+        assert_eq!(market.books["BTCUSDT"].mid_price, 200.0);
+        assert_eq!(market.books["BTCUSDT"].best_bid.price, 199.0);
+        // No Binance trades yet, the Bybit ones aren't mixed in
+        assert!(market.trades.contains_key("SOLUSDT"));
+        assert!(!market.trades.contains_key("BTCUSDT"));
+
+        // The basis is the premium of the venue each symbol is quoted on
+        for symbol in ["SOLUSDT", "BTCUSDT"] {
+            maker
+                .features
+                .insert(symbol.to_string(), Engine::new(10, None));
+        }
+        maker.update_cross_venue_basis(&markets);
+        assert_eq!(maker.features["SOLUSDT"].get_cross_venue_basis(), -5_000.0);
+        assert_eq!(maker.features["BTCUSDT"].get_cross_venue_basis(), 10_000.0);
+    }
 }
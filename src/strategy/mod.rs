@@ -1 +1,2 @@
-pub mod maker;
\ No newline at end of file
+pub mod control;
+pub mod maker;
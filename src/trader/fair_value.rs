@@ -0,0 +1,53 @@
+use skeleton::utils::{
+    localorderbook::OrderBook,
+    models::{BybitBook, QuoteAnchor},
+};
+
+use crate::features::engine::Engine;
+
+/// Supplies the price the quote grid is centered on.
+///
+/// Implement it to anchor quotes on an external model or another feed
+/// instead of the book mid.
+pub trait FairValueSource: std::fmt::Debug + Send + Sync {
+    fn fair_value(&self, book: &BybitBook, features: &Engine) -> f64;
+}
+
+/// Default source, centers the grid on the book mid price.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MidPrice;
+
+impl FairValueSource for MidPrice {
+    fn fair_value(&self, book: &BybitBook, _features: &Engine) -> f64 {
+        book.get_mid_price()
+    }
+}
+
+/// Centers the grid on the top of book mid weighted by the bid/ask quantities.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WeightedMid;
+
+impl FairValueSource for WeightedMid {
+    fn fair_value(&self, book: &BybitBook, _features: &Engine) -> f64 {
+        book.get_wmid(None)
+    }
+}
+
+/// Centers the grid on the top of book microprice.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Microprice;
+
+impl FairValueSource for Microprice {
+    fn fair_value(&self, book: &BybitBook, _features: &Engine) -> f64 {
+        book.get_microprice(None)
+    }
+}
+
+/// Builds the source matching the configured `quote_anchor`.
+pub fn anchor_source(anchor: QuoteAnchor) -> Box<dyn FairValueSource> {
+    match anchor {
+        QuoteAnchor::Mid => Box::new(MidPrice),
+        QuoteAnchor::Wmid => Box::new(WeightedMid),
+        QuoteAnchor::Microprice => Box::new(Microprice),
+    }
+}
@@ -1 +1,6 @@
-pub mod quote_gen;
\ No newline at end of file
+pub mod fair_value;
+pub mod pnl;
+pub mod quote_gen;
+pub mod quote_stats;
+pub mod rate_limit;
+pub mod venue;
@@ -0,0 +1,241 @@
+use std::collections::VecDeque;
+
+#[derive(Debug, Clone, Copy)]
+struct Lot {
+    price: f64,
+    // Positive for buys, negative for sells
+    qty: f64,
+}
+
+/// Matches fills FIFO into round trips and tracks the spread they captured.
+///
+/// The realized edge only covers closed round trips and is kept apart from
+/// mark-to-market PnL on the open inventory. Alongside it the position is booked at
+/// its average entry price for the realized and unrealized PnL.
+#[derive(Debug, Clone)]
+pub struct PnlTracker {
+    fee_rate: f64,
+    window_size: usize,
+    open_lots: VecDeque<Lot>,
+    // (edge per contract, matched qty) of the latest round trips
+    round_trips: VecDeque<(f64, f64)>,
+    total_edge: f64,
+    // Signed position booked at `entry_price`
+    position: f64,
+    entry_price: f64,
+    realized_pnl: f64,
+}
+
+impl PnlTracker {
+    /// Creates a tracker charging `fee_rate` (e.g. 0.0002 for 2 bps) on each fill and
+    /// averaging the edge over the last `window_size` round trips.
+    pub fn new(fee_rate: f64, window_size: usize) -> Self {
+        let window_size = window_size.max(1);
+        Self {
+            fee_rate,
+            window_size,
+            open_lots: VecDeque::new(),
+            round_trips: VecDeque::with_capacity(window_size),
+            total_edge: 0.0,
+            position: 0.0,
+            entry_price: 0.0,
+            realized_pnl: 0.0,
+        }
+    }
+
+    /// Fee rate charged on the fills recorded from now on.
+    pub fn set_fee_rate(&mut self, fee_rate: f64) {
+        self.fee_rate = fee_rate;
+    }
+
+    /// Records a fill, closing any opposite lots first in FIFO order.
+    pub fn on_fill(&mut self, price: f64, qty: f64, is_buy: bool) {
+        let mut remaining = if is_buy { qty } else { -qty };
+        self.book_fill(price, remaining);
+
+        while remaining.abs() > f64::EPSILON {
+            let Some(lot) = self.open_lots.front().copied() else {
+                break;
+            };
+            if lot.qty.signum() == remaining.signum() {
+                break;
+            }
+
+            let matched = remaining.abs().min(lot.qty.abs());
+            let (buy_price, sell_price) = if is_buy {
+                (price, lot.price)
+            } else {
+                (lot.price, price)
+            };
+            let fees = (buy_price + sell_price) * self.fee_rate;
+            self.record_round_trip(sell_price - buy_price - fees, matched);
+
+            let step = matched * remaining.signum();
+            remaining -= step;
+            if (lot.qty + step).abs() <= f64::EPSILON {
+                self.open_lots.pop_front();
+            } else if let Some(front) = self.open_lots.front_mut() {
+                front.qty += step;
+            }
+        }
+
+        if remaining.abs() > f64::EPSILON {
+            self.open_lots.push_back(Lot {
+                price,
+                qty: remaining,
+            });
+        }
+    }
+
+    /// Averages the entry price on increases and realizes PnL against it on reductions.
+    /// A fill crossing through flat closes the position and opens the rest at its price.
+    fn book_fill(&mut self, price: f64, qty: f64) {
+        self.realized_pnl -= price * qty.abs() * self.fee_rate;
+
+        if self.position.abs() <= f64::EPSILON || self.position.signum() == qty.signum() {
+            let size = self.position.abs() + qty.abs();
+            self.entry_price = (self.entry_price * self.position.abs() + price * qty.abs()) / size;
+            self.position += qty;
+            return;
+        }
+
+        let closed = qty.abs().min(self.position.abs());
+        self.realized_pnl += closed * (price - self.entry_price) * self.position.signum();
+        self.position += qty;
+        if qty.abs() > closed + f64::EPSILON {
+            self.entry_price = price;
+        } else if self.position.abs() <= f64::EPSILON {
+            self.position = 0.0;
+            self.entry_price = 0.0;
+        }
+    }
+
+    fn record_round_trip(&mut self, edge: f64, qty: f64) {
+        if self.round_trips.len() == self.window_size {
+            self.round_trips.pop_front();
+        }
+        self.round_trips.push_back((edge, qty));
+        self.total_edge += edge * qty;
+    }
+
+    /// Qty weighted edge per contract over the latest round trips, net of fees.
+    pub fn realized_edge(&self) -> f64 {
+        let qty: f64 = self.round_trips.iter().map(|(_, q)| q).sum();
+        if qty <= 0.0 {
+            return 0.0;
+        }
+        self.round_trips.iter().map(|(e, q)| e * q).sum::<f64>() / qty
+    }
+
+    /// Total spread captured by every closed round trip, net of fees.
+    pub fn total_realized_edge(&self) -> f64 {
+        self.total_edge
+    }
+
+    /// PnL booked by the fills that reduced the position, net of fees.
+    pub fn realized_pnl(&self) -> f64 {
+        self.realized_pnl
+    }
+
+    /// Average price the open position was entered at, 0.0 when flat.
+    pub fn average_entry_price(&self) -> f64 {
+        self.entry_price
+    }
+
+    /// Mark-to-market PnL of the open position at `mid_price`.
+    pub fn unrealized_pnl(&self, mid_price: f64) -> f64 {
+        self.position * (mid_price - self.entry_price)
+    }
+
+    /// Signed position booked at `average_entry_price`, positive for long.
+    pub fn position(&self) -> f64 {
+        self.position
+    }
+
+    /// Seeds the tracker with a position and PnL saved by a previous run. The position
+    /// becomes a single open lot at `entry_price`, the round trip history starts empty.
+    pub fn restore(&mut self, position: f64, entry_price: f64, realized_pnl: f64) {
+        self.position = position;
+        self.entry_price = if position.abs() > f64::EPSILON {
+            entry_price
+        } else {
+            0.0
+        };
+        self.realized_pnl = realized_pnl;
+        self.open_lots.clear();
+        if position.abs() > f64::EPSILON {
+            self.open_lots.push_back(Lot {
+                price: entry_price,
+                qty: position,
+            });
+        }
+    }
+
+    /// Net qty of the lots still waiting to be matched.
+    pub fn open_qty(&self) -> f64 {
+        self.open_lots.iter().map(|lot| lot.qty).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buy_then_higher_sell() {
+        let mut pnl = PnlTracker::new(0.0002, 10);
+        pnl.on_fill(100.0, 2.0, true);
+        assert_eq!(pnl.realized_edge(), 0.0);
+
+        pnl.on_fill(101.0, 2.0, false);
+        let expected = (101.0 - 100.0) - (100.0 + 101.0) * 0.0002;
+        assert!((pnl.realized_edge() - expected).abs() < 1e-9);
+        assert!((pnl.total_realized_edge() - expected * 2.0).abs() < 1e-9);
+        assert_eq!(pnl.open_qty(), 0.0);
+    }
+
+    #[test]
+    fn test_fifo_partial_match() {
+        let mut pnl = PnlTracker::new(0.0, 10);
+        pnl.on_fill(100.0, 1.0, false);
+        pnl.on_fill(102.0, 1.0, false);
+        pnl.on_fill(99.0, 1.5, true);
+
+        // 1 @ (100 - 99) and 0.5 @ (102 - 99)
+        assert!((pnl.total_realized_edge() - 2.5).abs() < 1e-9);
+        assert!((pnl.realized_edge() - 2.5 / 1.5).abs() < 1e-9);
+        assert!((pnl.open_qty() + 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_average_entry_and_realized_pnl() {
+        let mut pnl = PnlTracker::new(0.0, 10);
+        pnl.on_fill(100.0, 1.0, true);
+        pnl.on_fill(103.0, 2.0, true);
+        assert!((pnl.average_entry_price() - 102.0).abs() < 1e-9);
+        assert!((pnl.unrealized_pnl(104.0) - 6.0).abs() < 1e-9);
+
+        pnl.on_fill(105.0, 1.0, false);
+        assert!((pnl.realized_pnl() - 3.0).abs() < 1e-9);
+        assert!((pnl.average_entry_price() - 102.0).abs() < 1e-9);
+
+        // Crossing through flat: 2 close at 101, 1 opens short at 101
+        pnl.on_fill(101.0, 3.0, false);
+        assert!((pnl.realized_pnl() - 1.0).abs() < 1e-9);
+        assert!((pnl.average_entry_price() - 101.0).abs() < 1e-9);
+        assert!((pnl.unrealized_pnl(100.0) - 1.0).abs() < 1e-9);
+
+        pnl.on_fill(100.0, 1.0, true);
+        assert!((pnl.realized_pnl() - 2.0).abs() < 1e-9);
+        assert_eq!(pnl.average_entry_price(), 0.0);
+        assert_eq!(pnl.unrealized_pnl(90.0), 0.0);
+    }
+
+    #[test]
+    fn test_realized_pnl_is_net_of_fees() {
+        let mut pnl = PnlTracker::new(0.001, 10);
+        pnl.on_fill(100.0, 1.0, true);
+        pnl.on_fill(110.0, 1.0, false);
+        assert!((pnl.realized_pnl() - (10.0 - 0.1 - 0.11)).abs() < 1e-9);
+    }
+}
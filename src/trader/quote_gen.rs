@@ -1,427 +1,4668 @@
-use skeleton::{
-    exchange::exchange::Exchange,
-    utils::{
-        bot::LiveBot,
-        localorderbook::OrderBook,
-        logger::Logger,
-        models::{sort_grid, BatchOrder, BybitBook, BybitClient, BybitPrivate, LiveOrder},
-        number::{geometric_weights, geomspace, nbsqrt, round_step, Round},
+use skeleton::utils::{
+    bot::LiveBot,
+    localorderbook::OrderBook,
+    logger::Logger,
+    metrics,
+    models::{
+        sort_grid, BatchAmend, BatchOrder, BybitBook, FeeTier, Fill, LiveOrder, QuoteSide,
+        QuotingModel, RejectedAmend, RejectedOrder, SpreadModel, TimeInForce,
     },
+    number::{geometric_weights, geomspace, nbsqrt, round_step, truncate_weights, Round},
+    persistence::{PersistedState, StatePersistence},
+    time::generate_timestamp,
 };
-use std::collections::{HashSet, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use super::{
+    fair_value::{FairValueSource, MidPrice},
+    pnl::PnlTracker,
+    quote_stats::QuoteStats,
+    rate_limit::SharedRateLimiter,
+    venue::{PrivateData, Venue},
+};
+use crate::features::engine::Engine;
 
 type Result<T> = std::result::Result<T, f64>;
 
 // Named constants for magic numbers
 const SAFETY_FACTOR: f64 = 0.95;
+// Widest leverage range of the exchanges, narrowed to each symbol's max once fetched
+const MIN_LEVERAGE: f64 = 1.0;
+const MAX_LEVERAGE: f64 = 125.0;
 const DEFAULT_BPS: f64 = 25.0;
 const VOLATILITY_MULTIPLIER: f64 = 100.0;
 const MAX_SPREAD_MULTIPLIER: f64 = 3.7;
 const INVENTORY_ADJUSTMENT: f64 = -0.63;
+const MAKER_FEE_RATE: f64 = 0.0002;
+const TAKER_FEE_RATE: f64 = 0.00055;
+const GRID_WEIGHT_RATIO: f64 = 0.37;
+const DEFAULT_ORDER_DISTANCE: f64 = 10.0;
+// Standard deviations between the session VWAP and its bands
+const VWAP_BAND_SIGMA: f64 = 2.0;
+// Share of the best bid the grid may span, keeps the outermost bid above zero
+const MAX_GRID_SPAN: f64 = 0.9;
 
 const MIN_CANCEL_LIMIT: usize = 1;
 const ORDER_CHUNK_SIZE: usize = 10;
+// Resting qty, relative to the filled qty, below which an order counts as fully filled
+const FILLED_TOLERANCE: f64 = 1e-9;
+// Ids of the latest processed executions remembered to skip replayed ones
+const SEEN_EXECUTIONS: usize = 1000;
+// Time quoting stays off after a price shock when no cooldown is configured
+const CIRCUIT_BREAKER_COOLDOWN_MS: u64 = 30_000;
+
+/// Risk limits and exposure of a generator at the time it was queried.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RiskState {
+    pub max_position_usd: f64,
+    /// 0.0 when no drawdown limit is set
+    pub max_drawdown_usd: f64,
+    pub spread_bps: f64,
+    pub position_qty: f64,
+    pub inventory_delta: f64,
+    pub paused: bool,
+    /// Spread captured by every closed round trip, net of maker fees
+    pub realized_edge: f64,
+    /// Quoting is halted until the kill switch is reset
+    pub kill_switch: bool,
+}
 
 #[derive(Debug)]
 pub struct QuoteGenerator {
     logger: Logger,
-    client: BybitClient,
+    client: Venue,
     max_position_usd: f64,
+    max_drawdown_usd: f64,
     pub position_qty: f64,
     minimum_spread: f64,
     pub adjusted_spread: f64,
     pub inventory_delta: f64,
     pub live_buys: VecDeque<LiveOrder>,
     pub live_sells: VecDeque<LiveOrder>,
+    /// Orders placed by an operator, kept out of the grid so it never amends or cancels them
+    pub manual_orders: VecDeque<LiveOrder>,
     total_order: usize,
     final_order_distance: f64,
-    rate_limit: usize,
-    cancel_limit: usize,
+    // Request budgets of each side, the limit split between them and refilled after a
+    // quiet period
+    buy_rate_limit: usize,
+    sell_rate_limit: usize,
+    buy_cancel_limit: usize,
+    sell_cancel_limit: usize,
     initial_limit: usize,
     bounds: f64,
     last_update_price: f64,
     time_limit: u64,
     tick_window: usize,
+    log_intended_quotes: bool,
+    fallback_min_notional: f64,
+    floor_size_to_lot: bool,
+    pnl: PnlTracker,
+    // (exec time, seq) of the newest processed execution
+    last_fill: (u64, u64),
+    // ids of the latest processed executions, oldest first
+    seen_executions: VecDeque<String>,
+    seen_execution_ids: HashSet<String>,
+    fair_value_source: Box<dyn FairValueSource>,
+    dust_fraction: f64,
+    // edge over the maker fee each level needs from the mid, 0 disables the check
+    min_edge_bps: f64,
+    // (bids, asks) of the last reduced grid, logged again only once they change
+    reduced_levels: Option<(usize, usize)>,
+    // levels the last quote dropped as unprofitable, logged again only once it changes
+    suppressed_levels: usize,
+    // whether the last grid found the exchange min notional unknown, warned about only
+    // when it starts and once it clears
+    unknown_min_notional: bool,
+    spread_model: SpreadModel,
+    paused: bool,
+    fees: FeeTier,
+    // private stream session the live orders were last reconciled against
+    session: u64,
+    reduce_only_delta: f64,
+    time_in_force: TimeInForce,
+    book_crossed: bool,
+    // the book stopped updating for longer than the maker's staleness threshold
+    stale_data: bool,
+    quoting_model: QuotingModel,
+    // grid refreshes done by amending the live orders vs by sending a whole new grid
+    amends: usize,
+    requotes: usize,
+    // mid moves per update up to this many bps are followed by shifting the grid with
+    // amends, larger ones re-quote it. 0 disables trailing
+    trail_drift_bps: f64,
+    // ticks the mid drifts from the grid's center before the grid is shifted
+    trail_min_ticks: u32,
+    trails: usize,
+    // mid of the previous grid update, tells a slow drift from a jump
+    previous_mid: f64,
+    bid_ratio: f64,
+    ask_ratio: f64,
+    skew_weighted_ratio: bool,
+    dry_run: bool,
+    // ids handed out to the simulated orders
+    dry_run_orders: u64,
+    // starting equity the drawdown is measured on
+    capital: f64,
+    leverage: f64,
+    // highest leverage the exchange allows on the symbol
+    max_leverage: f64,
+    // share of the leveraged capital the position cap allows
+    safety_factor: f64,
+    // this symbol's share of the live account equity, which replaces `capital` in the
+    // position cap once fetched
+    equity_share: f64,
+    wallet_capital: Option<f64>,
+    max_drawdown_pct: Option<f64>,
+    flatten_on_kill_switch: bool,
+    peak_equity: f64,
+    kill_switch: bool,
+    // time the circuit breaker tripped by a price shock lets quoting resume, in ms
+    circuit_breaker_until: Option<u64>,
+    circuit_breaker_cooldown_ms: u64,
+    // whether everything was cancelled since the circuit breaker tripped
+    circuit_breaker_pulled: bool,
+    // average order request round trip tripping the circuit breaker, 0 disables it
+    max_order_latency_ms: f64,
+    range_volatility: f64,
+    range_vol_weight: f64,
+    // VPIN of the latest trades and how much of it widens the spread
+    toxicity: f64,
+    toxicity_weight: f64,
+    // pull of the inventory on the skew and the volatility scaling of the spread
+    inventory_adjustment: f64,
+    volatility_multiplier: f64,
+    max_spread_multiplier: f64,
+    // session VWAP bands and the weight of the mean reversion overlay around them
+    vwap_bands: Option<(f64, f64)>,
+    vwap_band_sigma: f64,
+    vwap_weight: f64,
+    // orders resting longer than this at one price are re-placed, 0 disables it
+    max_order_age_ms: u64,
+    // times a post-only rejection is re-sent a tick behind the touch
+    post_only_retries: usize,
+    // post-only cancels of the order stream re-sent since the grid was generated
+    post_only_resent: usize,
+    quote_side: QuoteSide,
+    // account-wide request budget shared with the other symbols' generators
+    rate_limiter: Option<SharedRateLimiter>,
+    persistence: Option<Box<dyn StatePersistence>>,
+    // last state handed to `persistence`, saves are skipped while nothing changed
+    persisted: PersistedState,
+    // fills since startup by side, exported as counters
+    buy_fills: u64,
+    sell_fills: u64,
+    // fill rate, time to fill and spread capture of the quotes since startup
+    stats: QuoteStats,
 }
 
 impl QuoteGenerator {
     pub async fn new(
-        client: BybitClient,
+        client: impl Into<Venue>,
         asset: f64,
         leverage: f64,
         orders_per_side: usize,
         tick_window: usize,
         rate_limit: usize,
-    ) -> Self {
+    ) -> std::result::Result<Self, String> {
         let bot = LiveBot::new("./config.toml").await.unwrap();
-        Self {
-            logger: Logger::new(bot),
+        Self::with_logger(
             client,
-            max_position_usd: Self::max_position_usd(asset, leverage),
+            Logger::new(bot),
+            asset,
+            leverage,
+            orders_per_side,
+            tick_window,
+            rate_limit,
+        )
+    }
+
+    /// Builds a generator around an already constructed `Logger`.
+    ///
+    /// Errors when `leverage` is outside the exchanges' range of 1 to 125, or when `asset`
+    /// and `leverage` leave no room for a position, since a zero max position turns the
+    /// inventory delta into NaN.
+    pub fn with_logger(
+        client: impl Into<Venue>,
+        logger: Logger,
+        asset: f64,
+        leverage: f64,
+        orders_per_side: usize,
+        tick_window: usize,
+        rate_limit: usize,
+    ) -> std::result::Result<Self, String> {
+        if !(MIN_LEVERAGE..=MAX_LEVERAGE).contains(&leverage) {
+            return Err(format!(
+                "Leverage must be between {} and {}, got {}",
+                MIN_LEVERAGE, MAX_LEVERAGE, leverage
+            ));
+        }
+        let max_position_usd = Self::max_position_usd(asset, leverage, SAFETY_FACTOR);
+        if max_position_usd <= 0.0 {
+            return Err(format!(
+                "Max position must be positive, got {} for asset {} and leverage {}",
+                max_position_usd, asset, leverage
+            ));
+        }
+        let (buy_limit, sell_limit) = split_limit(rate_limit);
+        Ok(Self {
+            logger,
+            client: client.into(),
+            max_position_usd,
+            max_drawdown_usd: 0.0,
             position_qty: 0.0,
             minimum_spread: 0.0,
             adjusted_spread: 0.0,
             inventory_delta: 0.0,
             live_buys: VecDeque::with_capacity(ORDER_CHUNK_SIZE),
             live_sells: VecDeque::with_capacity(ORDER_CHUNK_SIZE),
+            manual_orders: VecDeque::new(),
             total_order: orders_per_side,
-            final_order_distance: 10.0,
-            rate_limit,
+            final_order_distance: DEFAULT_ORDER_DISTANCE,
+            buy_rate_limit: buy_limit,
+            sell_rate_limit: sell_limit,
             initial_limit: rate_limit,
-            cancel_limit: rate_limit,
+            buy_cancel_limit: buy_limit,
+            sell_cancel_limit: sell_limit,
             bounds: 0.0,
             time_limit: 0,
             last_update_price: 0.0,
             tick_window,
-        }
+            log_intended_quotes: false,
+            fallback_min_notional: 0.0,
+            floor_size_to_lot: false,
+            pnl: PnlTracker::new(MAKER_FEE_RATE, tick_window),
+            last_fill: (0, 0),
+            seen_executions: VecDeque::with_capacity(SEEN_EXECUTIONS),
+            seen_execution_ids: HashSet::with_capacity(SEEN_EXECUTIONS),
+            fair_value_source: Box::new(MidPrice),
+            dust_fraction: 0.0,
+            min_edge_bps: 0.0,
+            reduced_levels: None,
+            suppressed_levels: 0,
+            unknown_min_notional: false,
+            spread_model: SpreadModel::default(),
+            paused: false,
+            fees: FeeTier::new(MAKER_FEE_RATE, TAKER_FEE_RATE),
+            session: 0,
+            reduce_only_delta: 0.0,
+            time_in_force: TimeInForce::PostOnly,
+            book_crossed: false,
+            stale_data: false,
+            quoting_model: QuotingModel::Heuristic,
+            amends: 0,
+            requotes: 0,
+            trail_drift_bps: 0.0,
+            trail_min_ticks: 1,
+            trails: 0,
+            previous_mid: 0.0,
+            bid_ratio: GRID_WEIGHT_RATIO,
+            ask_ratio: GRID_WEIGHT_RATIO,
+            skew_weighted_ratio: false,
+            dry_run: false,
+            dry_run_orders: 0,
+            capital: asset,
+            leverage,
+            max_leverage: MAX_LEVERAGE,
+            safety_factor: SAFETY_FACTOR,
+            equity_share: 1.0,
+            wallet_capital: None,
+            max_drawdown_pct: None,
+            flatten_on_kill_switch: false,
+            peak_equity: asset,
+            kill_switch: false,
+            circuit_breaker_until: None,
+            circuit_breaker_cooldown_ms: CIRCUIT_BREAKER_COOLDOWN_MS,
+            circuit_breaker_pulled: false,
+            max_order_latency_ms: 0.0,
+            range_volatility: 0.0,
+            range_vol_weight: 0.0,
+            toxicity: 0.0,
+            toxicity_weight: 0.0,
+            inventory_adjustment: INVENTORY_ADJUSTMENT,
+            volatility_multiplier: VOLATILITY_MULTIPLIER,
+            max_spread_multiplier: MAX_SPREAD_MULTIPLIER,
+            vwap_bands: None,
+            vwap_band_sigma: VWAP_BAND_SIGMA,
+            vwap_weight: 0.0,
+            max_order_age_ms: 0,
+            post_only_retries: 0,
+            post_only_resent: 0,
+            quote_side: QuoteSide::Both,
+            rate_limiter: None,
+            persistence: None,
+            persisted: PersistedState::default(),
+            buy_fills: 0,
+            sell_fills: 0,
+            stats: QuoteStats::new(),
+        })
     }
 
-    fn max_position_usd(asset: f64, leverage: f64) -> f64 {
-        (asset * leverage) * SAFETY_FACTOR
+    fn max_position_usd(asset: f64, leverage: f64, safety_factor: f64) -> f64 {
+        let max_position = (asset * leverage) * safety_factor;
+        // Keeps a NaN or negative limit from leaking into the inventory delta
+        if max_position.is_finite() {
+            max_position.max(0.0)
+        } else {
+            0.0
+        }
     }
 
     pub fn set_min_spread(&mut self, spread: f64) {
         self.minimum_spread = spread;
     }
 
-    fn set_inventory_delta(&mut self, price: f64) {
-        self.inventory_delta = if self.position_qty.abs() > f64::EPSILON {
-            (self.position_qty * price) / self.max_position_usd
+    /// Distance of the outermost level from the touch, in multiples of the spread, 10 by
+    /// default. Values that aren't positive are ignored.
+    pub fn set_final_order_distance(&mut self, distance: f64) {
+        if distance > 0.0 && distance.is_finite() {
+            self.final_order_distance = distance;
         } else {
-            0.0
-        };
+            self.logger.warning(&format!(
+                "Ignoring final order distance {}, it must be positive",
+                distance
+            ));
+        }
     }
 
-    fn calculate_vol_adjusted_value(
-        &mut self,
-        base_value: f64,
-        book: &BybitBook,
-        volatility: f64,
-    ) -> f64 {
-        let volatility_multiplier = 1.0 + (volatility * VOLATILITY_MULTIPLIER);
-        let min_value = base_value * volatility_multiplier;
-        let max_value = min_value * MAX_SPREAD_MULTIPLIER * volatility_multiplier;
-        book.get_spread().clip(min_value, max_value)
+    /// Replaces the position cap, the inventory delta picks it up on the next grid update.
+    pub fn set_max_position_usd(&mut self, max_position_usd: f64) {
+        self.max_position_usd = max_position_usd;
     }
 
-    fn vol_adjusted_spread(&mut self, book: &BybitBook, volatility: f64) -> f64 {
-        let mid_price = book.get_mid_price();
-        let base_min_spread = bps_to_decimal(if self.minimum_spread.abs() < f64::EPSILON {
-            DEFAULT_BPS
-        } else {
-            self.minimum_spread
-        }) * mid_price;
+    /// Resizes the position cap to the capital at `leverage`, clamped to the range the
+    /// exchange allows on the symbol and ignored when it leaves no room for a position.
+    /// The capital is the live wallet share once fetched, the starting capital before.
+    pub fn set_leverage(&mut self, leverage: f64) {
+        if !(leverage.is_finite() && leverage > 0.0) {
+            self.logger.warning(&format!(
+                "Ignoring leverage {}, it must be positive",
+                leverage
+            ));
+            return;
+        }
+        let clamped = leverage.clamp(MIN_LEVERAGE, self.max_leverage);
+        if clamped != leverage {
+            self.logger.warning(&format!(
+                "Leverage {} is outside the allowed range of {} to {}, using {}",
+                leverage, MIN_LEVERAGE, self.max_leverage, clamped
+            ));
+        }
+        let capital = self.wallet_capital.unwrap_or(self.capital);
+        let max_position_usd = Self::max_position_usd(capital, clamped, self.safety_factor);
+        if max_position_usd <= 0.0 {
+            self.logger.warning(&format!(
+                "Ignoring leverage {}, it leaves no room for a position",
+                leverage
+            ));
+            return;
+        }
+        self.leverage = clamped;
+        self.max_position_usd = max_position_usd;
+    }
 
-        self.adjusted_spread = self.calculate_vol_adjusted_value(base_min_spread, book, volatility);
-        self.adjusted_spread
+    /// Leverage the position cap is sized with, the one to set on the exchange. A whole
+    /// number as long as the configured one is, since the symbol's max is rounded down.
+    pub fn leverage(&self) -> f64 {
+        self.leverage
     }
 
-    fn vol_adjusted_bounds(&mut self, book: &BybitBook, volatility: f64) -> f64 {
-        let base_min_spread = bps_to_decimal(if self.minimum_spread.abs() < f64::EPSILON {
-            DEFAULT_BPS
-        } else {
-            self.minimum_spread
-        }) * self.last_update_price;
+    /// Narrows the leverage range to the symbol's max from its instrument info, rounded
+    /// down to a whole number, clamping the current leverage down to it. 0.0 (unknown)
+    /// keeps the range of 1 to 125.
+    pub fn set_max_leverage(&mut self, max_leverage: f64) {
+        if !(max_leverage.is_finite() && max_leverage >= MIN_LEVERAGE) {
+            return;
+        }
+        self.max_leverage = max_leverage.floor().min(MAX_LEVERAGE);
+        if self.leverage > self.max_leverage {
+            self.set_leverage(self.leverage);
+        }
+    }
 
-        self.bounds = self.calculate_vol_adjusted_value(base_min_spread, book, volatility);
-        self.bounds
+    /// Share of the leveraged capital the position cap allows, within `0.0..=1.0`. 0.0
+    /// restores the default of 0.95, other values are ignored.
+    pub fn set_safety_factor(&mut self, safety_factor: f64) {
+        if safety_factor == 0.0 {
+            self.safety_factor = SAFETY_FACTOR;
+        } else if safety_factor > 0.0 && safety_factor <= 1.0 {
+            self.safety_factor = safety_factor;
+        } else {
+            self.logger.warning(&format!(
+                "Ignoring safety factor {}, it must be within 0.0..=1.0",
+                safety_factor
+            ));
+            return;
+        }
+        let capital = self.wallet_capital.unwrap_or(self.capital);
+        self.max_position_usd = Self::max_position_usd(capital, self.leverage, self.safety_factor);
     }
 
-    fn order_refresh_time(&self, volatility: f64, mid_price: f64) -> f64 {
-        let spread_decimal = self.adjusted_spread / mid_price;
-        if volatility < f64::EPSILON {
-            return f64::INFINITY;
+    /// Share of the account equity this symbol is sized from, for accounts quoting several
+    /// symbols. 1.0 by default, values outside `0.0..=1.0` are ignored.
+    pub fn set_equity_share(&mut self, share: f64) {
+        if share > 0.0 && share <= 1.0 {
+            self.equity_share = share;
+        } else {
+            self.logger.warning(&format!(
+                "Ignoring equity share {}, it must be within 0.0..=1.0",
+                share
+            ));
         }
+    }
 
-        let per_second_vol = volatility;
-        if per_second_vol < f64::EPSILON {
-            return f64::INFINITY;
+    /// Resizes the position cap from this symbol's share of the live account `equity`,
+    /// keeping the last cap when it leaves no room for a position.
+    pub fn set_wallet_equity(&mut self, equity: f64) {
+        let capital = equity * self.equity_share;
+        let max_position_usd = Self::max_position_usd(capital, self.leverage, self.safety_factor);
+        if max_position_usd <= 0.0 {
+            self.logger.warning(&format!(
+                "Keeping the position cap, the wallet equity of {} leaves no room for a position",
+                equity
+            ));
+            return;
+        }
+        if (max_position_usd - self.max_position_usd).abs() > f64::EPSILON {
+            self.logger.info(&format!(
+                "Position cap resized from {:.2} to {:.2} for a wallet equity of {:.2}",
+                self.max_position_usd, max_position_usd, equity
+            ));
         }
+        self.wallet_capital = Some(capital);
+        self.max_position_usd = max_position_usd;
+    }
 
-        let ratio = spread_decimal / (2.0 * per_second_vol);
-        ratio.powf(2.0)
+    /// Levels per side of the next grid, the live orders are amended onto it. At least 1.
+    pub fn set_orders_per_side(&mut self, orders_per_side: usize) {
+        self.total_order = orders_per_side.max(1);
     }
 
-    fn generate_quotes(
-        &mut self,
-        symbol: &str,
-        book: &BybitBook,
-        skew: f64,
-        volatility: f64,
-    ) -> Result<Vec<BatchOrder>> {
-        let spread = self.vol_adjusted_spread(book, volatility);
+    /// Loss the generator may run before it stops quoting, 0.0 disables the limit.
+    pub fn set_max_drawdown_usd(&mut self, max_drawdown_usd: f64) {
+        self.max_drawdown_usd = max_drawdown_usd;
+    }
 
-        let inventory_factor = nbsqrt(self.inventory_delta)?;
-        let skew_factor = skew * (1.0 - inventory_factor.abs());
-        let combined_skew =
-            (skew_factor + INVENTORY_ADJUSTMENT * inventory_factor).clamp(-1.0, 1.0);
+    /// Drawdown from the peak equity, in percent of that peak, that trips the kill switch.
+    /// `None` or a non-positive value disables the limit.
+    pub fn set_max_drawdown_pct(&mut self, max_drawdown_pct: Option<f64>) {
+        self.max_drawdown_pct = max_drawdown_pct.filter(|pct| *pct > 0.0);
+    }
 
-        let is_positive_skew = combined_skew >= 0.0;
-        let orders = self.generate_skew_orders(symbol, spread, skew.abs(), book, is_positive_skew);
+    /// When enabled, tripping the kill switch also closes the position with a reduce-only
+    /// IOC order at the touch.
+    pub fn set_flatten_on_kill_switch(&mut self, enabled: bool) {
+        self.flatten_on_kill_switch = enabled;
+    }
 
-        Ok(orders)
+    pub fn is_kill_switch_tripped(&self) -> bool {
+        self.kill_switch
     }
 
-    fn generate_skew_orders(
-        &self,
-        symbol: &str,
-        spread: f64,
-        skew: f64,
-        book: &BybitBook,
-        is_positive_skew: bool,
-    ) -> Vec<BatchOrder> {
-        let mid_price = book.get_mid_price();
-        let notional = book.min_notional;
-        // let clipped_r = skew.clamp(0.10, 0.63);
-        let post_only_max = book.post_only_max;
+    /// Re-enables quoting after the kill switch tripped. The peak equity restarts from the
+    /// current one and the orders and position are synced with the exchange again.
+    pub fn reset_kill_switch(&mut self) {
+        if self.kill_switch {
+            self.logger.warning("Kill switch reset, quoting re-enabled");
+        }
+        self.kill_switch = false;
+        self.peak_equity = f64::NEG_INFINITY;
+        self.session = 0;
+    }
 
-        let (best_bid, best_ask) = if is_positive_skew {
-            let bid = mid_price - (spread * (1.0 - skew.sqrt()));
-            (bid, bid + spread)
-        } else {
-            let ask = mid_price + (spread * (1.0 - skew.sqrt()));
-            (ask - spread, ask)
-        };
+    /// Capital plus the realized and unrealized PnL at `mid_price`.
+    pub fn equity(&self, mid_price: f64) -> f64 {
+        self.capital + self.pnl.realized_pnl() + self.pnl.unrealized_pnl(mid_price)
+    }
 
-        let end = spread * self.final_order_distance;
-        let bid_prices = geomspace(best_bid - end, best_bid, self.total_order);
-        let ask_prices = geomspace(best_ask, best_ask + end, self.total_order);
+    /// Updates the peak equity and returns the drawdown from it when past either limit.
+    fn drawdown_breach(&mut self, mid_price: f64) -> Option<f64> {
+        let equity = self.equity(mid_price);
+        self.peak_equity = self.peak_equity.max(equity);
+        let drawdown = self.peak_equity - equity;
 
-        let (bid_r, ask_r) = if is_positive_skew {
-            // (clipped_r, 0.37)
-            (0.37, 0.37)
-        } else {
-            // (0.37, clipped_r)
-            (0.37, 0.37)
-        };
+        let usd_breached = self.max_drawdown_usd > 0.0 && drawdown > self.max_drawdown_usd;
+        let pct_breached = self
+            .max_drawdown_pct
+            .is_some_and(|pct| self.peak_equity > 0.0 && drawdown / self.peak_equity * 100.0 > pct);
+        (usd_breached || pct_breached).then_some(drawdown)
+    }
 
-        let max_buy_qty = if self.position_qty != 0.0 {
-            (self.max_position_usd / 2.0) - (self.position_qty * mid_price)
-        } else {
-            self.max_position_usd / 2.0
-        };
-        let bid_sizes = if self.inventory_delta < 0.5 {
-            geometric_weights(bid_r, self.total_order, false)
-                .into_iter()
-                .map(|w| w * max_buy_qty)
-                .collect()
-        } else {
-            vec![]
-        };
+    /// Returns `true` while the kill switch is tripped, tripping it first when the drawdown
+    /// breaches a limit. Resting orders are cancelled until none are left.
+    async fn check_kill_switch(&mut self, symbol: &str, book: &BybitBook) -> bool {
+        if !self.kill_switch {
+            // An empty or cleared book has no mid to mark the position at
+            if book.mid_price <= 0.0 {
+                return false;
+            }
+            let Some(drawdown) = self.drawdown_breach(book.mid_price) else {
+                return false;
+            };
+            self.kill_switch = true;
+            self.logger.critical(&format!(
+                "Kill switch tripped for {}: drawdown of {:.2} from peak equity {:.2}, quoting halted",
+                symbol, drawdown, self.peak_equity
+            ));
+            // Nothing was ever sent while only logging the grid
+            if !self.log_intended_quotes {
+                // Whatever rests on the exchange goes, including orders we lost track of
+                self.pull_quotes(symbol).await;
+                if self.flatten_on_kill_switch {
+                    self.flatten_position(symbol, book).await;
+                }
+            }
+            return true;
+        }
 
-        let max_sell_qty = if self.position_qty != 0.0 {
-            (self.max_position_usd / 2.0) + (self.position_qty * mid_price)
-        } else {
-            self.max_position_usd / 2.0
-        };
-        let ask_sizes = if self.inventory_delta > -0.5 {
-            geometric_weights(ask_r, self.total_order, true)
-                .into_iter()
-                .map(|w| w * max_sell_qty)
-                .collect()
+        if !self.live_buys.is_empty() || !self.live_sells.is_empty() {
+            self.pull_quotes(symbol).await;
+        }
+        true
+    }
+
+    /// Returns `false` when the closing order couldn't be sent.
+    async fn flatten_position(&mut self, symbol: &str, book: &BybitBook) -> bool {
+        let qty = round_step(self.position_qty.abs(), book.lot_size);
+        if qty <= 0.0 {
+            return true;
+        }
+        let is_buy = self.position_qty < 0.0;
+        let price = if is_buy {
+            book.best_ask.price
         } else {
-            vec![]
+            book.best_bid.price
         };
 
-        let mut orders = Vec::with_capacity(self.total_order * 2);
-        for i in 0..self.total_order {
-            if let (Some(&bid_price), Some(&bid_size)) = (bid_prices.get(i), bid_sizes.get(i)) {
-                let size = (bid_size / bid_price).min(post_only_max);
-                orders.push(BatchOrder::new(
-                    symbol.to_string(),
-                    round_price(book, bid_price),
-                    round_size(size, book),
-                    true,
-                ));
-            }
+        if self.dry_run {
+            self.logger.info(&format!(
+                "Dry run: flattening {} {} @ {}",
+                if is_buy { "BUY" } else { "SELL" },
+                qty,
+                price
+            ));
+            self.pnl.on_fill(price, qty, is_buy);
+            self.position_qty += if is_buy { qty } else { -qty };
+            return true;
+        }
 
-            if let (Some(&ask_price), Some(&ask_size)) = (ask_prices.get(i), ask_sizes.get(i)) {
-                let size = (ask_size / ask_price).min(post_only_max);
-                orders.push(BatchOrder::new(
-                    symbol.to_string(),
-                    round_price(book, ask_price),
-                    round_size(size, book),
-                    false,
-                ));
-            }
+        let result = self
+            .client
+            .place_order(
+                symbol,
+                price,
+                qty,
+                is_buy,
+                true,
+                TimeInForce::ImmediateOrCancel,
+            )
+            .await;
+        if let Err(e) = &result {
+            self.logger
+                .error(&format!("Failed to flatten position for {}: {}", symbol, e));
         }
-        orders.retain(|order| (order.1 * order.2) >= notional);
-        orders
+        result.is_ok()
     }
 
-    async fn send_batch_orders(&mut self, orders: Vec<BatchOrder>) -> bool {
-        let mut result = false;
-        for chunk in orders.chunks(ORDER_CHUNK_SIZE) {
-            if self.rate_limit == 0 {
-                break;
-            }
-
-            if let Ok((live_buys, live_sells)) = self.client.batch_orders(chunk.to_vec()).await {
-                self.live_buys.extend(live_buys);
-                self.live_sells.extend(live_sells);
-                self.live_buys = sort_grid(&mut self.live_buys, -1);
-                self.live_sells = sort_grid(&mut self.live_sells, 1);
-                self.rate_limit -= 1;
-                result = true;
+    pub fn risk_state(&self) -> RiskState {
+        RiskState {
+            max_position_usd: self.max_position_usd,
+            max_drawdown_usd: self.max_drawdown_usd,
+            spread_bps: if self.minimum_spread.abs() < f64::EPSILON {
+                DEFAULT_BPS
             } else {
-                self.logger.error("Failed to send batch orders");
-                self.rate_limit -= 1;
-            }
+                self.minimum_spread
+            },
+            position_qty: self.position_qty,
+            inventory_delta: self.inventory_delta,
+            paused: self.paused,
+            realized_edge: self.pnl.total_realized_edge(),
+            kill_switch: self.kill_switch,
         }
-        result
     }
 
-    fn check_for_fills(&mut self, info: &BybitPrivate) {
-        let mut buy_indices = Vec::new();
-        let mut sell_indices = Vec::new();
+    /// When enabled, `update_grid` logs the grid it would send instead of placing it.
+    pub fn set_log_intended_quotes(&mut self, enabled: bool) {
+        self.log_intended_quotes = enabled;
+    }
 
-        for exec in &info.executions {
-            let Ok(qty) = exec.exec_qty.replace(',', "").parse::<f64>() else {
-                continue;
-            };
-            if qty <= 0.0 {
-                continue;
-            }
+    /// Latest high-low range volatility, per second like the return based one.
+    pub fn set_range_volatility(&mut self, volatility: f64) {
+        self.range_volatility = volatility;
+    }
 
-            match exec.side.as_str() {
-                "Buy" => {
-                    if let Some(idx) = self
-                        .live_buys
-                        .iter()
-                        .position(|o| o.order_id == exec.order_id)
-                    {
-                        // Update position with executed qty
-                        self.position_qty += qty;
-                        // Update the order's remaining quantity
-                        self.live_buys[idx].qty -= qty;
-                        // Log the executed qty
-                        let msg = format!("Buy fill: {:.2} @ {}", qty, self.live_buys[idx].price);
-                        self.logger.info(&msg);
-                        // Mark for removal only if fully filled
-                        if self.live_buys[idx].qty <= f64::EPSILON {
-                            buy_indices.push(idx);
-                        }
-                    }
-                }
-                "Sell" => {
-                    if let Some(idx) = self
-                        .live_sells
-                        .iter()
-                        .position(|o| o.order_id == exec.order_id)
-                    {
-                        // Update position with executed qty
-                        self.position_qty -= qty;
-                        // Update the order's remaining quantity
-                        self.live_sells[idx].qty -= qty;
-                        // Log the executed qty
-                        let msg = format!("Sell fill: {:.2} @ {}", qty, self.live_sells[idx].price);
-                        self.logger.info(&msg);
-                        // Mark for removal only if fully filled
-                        if self.live_sells[idx].qty <= f64::EPSILON {
-                            sell_indices.push(idx);
-                        }
-                    }
-                }
-                _ => (),
-            }
-        }
+    /// Share of the range volatility blended into the return based one when sizing the
+    /// spread, 0.0 only uses the returns.
+    pub fn set_range_vol_weight(&mut self, weight: f64) {
+        self.range_vol_weight = weight.clamp(0.0, 1.0);
+    }
 
-        // Remove fully filled orders (backwards to preserve indices)
-        buy_indices.sort_unstable_by(|a, b| b.cmp(a));
-        for idx in buy_indices {
-            self.live_buys.remove(idx);
+    /// Latest order flow toxicity (VPIN) between 0.0 and 1.0.
+    pub fn set_toxicity(&mut self, toxicity: f64) {
+        if toxicity.is_finite() {
+            self.toxicity = toxicity.clamp(0.0, 1.0);
         }
+    }
+
+    /// Widens the spread by `1 + weight * toxicity`, so one-sided flow is quoted wider.
+    /// 0.0 disables it.
+    pub fn set_toxicity_weight(&mut self, weight: f64) {
+        self.toxicity_weight = weight.max(0.0);
+    }
 
-        sell_indices.sort_unstable_by(|a, b| b.cmp(a));
-        for idx in sell_indices {
-            self.live_sells.remove(idx);
+    /// How hard the inventory pulls the quotes against the position, from -1.0 (the
+    /// inventory alone decides the skew once it's large) to 0.0 (the inventory is ignored).
+    /// The default is -0.63, anything outside the range is ignored.
+    pub fn set_inventory_adjustment(&mut self, adjustment: f64) {
+        if (-1.0..=0.0).contains(&adjustment) {
+            self.inventory_adjustment = adjustment;
+        } else {
+            self.logger.warning(&format!(
+                "Ignoring inventory adjustment {}, it must be between -1 and 0",
+                adjustment
+            ));
         }
     }
 
-    async fn out_of_bounds(
+    /// Volatility scaling of the vol-linear spread and bounds. The spread is at least the
+    /// base spread times `1 + volatility * volatility_multiplier` and at most
+    /// `max_spread_multiplier` times that again, so the book's own spread is only followed
+    /// within that band. The defaults are 100 and 3.7, a volatility multiplier of 0.0 keeps
+    /// the band at the base spread. A negative volatility multiplier or a max below 1 would
+    /// let the band shrink or invert, so they're ignored.
+    pub fn set_spread_multipliers(
         &mut self,
-        book: &BybitBook,
-        symbol: &str,
-        private: BybitPrivate,
-    ) -> bool {
-        if self.live_buys.is_empty() && self.live_sells.is_empty() {
-            self.last_update_price = book.mid_price;
-            return true;
+        volatility_multiplier: f64,
+        max_spread_multiplier: f64,
+    ) {
+        if volatility_multiplier >= 0.0 && volatility_multiplier.is_finite() {
+            self.volatility_multiplier = volatility_multiplier;
+        } else {
+            self.logger.warning(&format!(
+                "Ignoring volatility multiplier {}, it can't be negative",
+                volatility_multiplier
+            ));
+        }
+        if max_spread_multiplier >= 1.0 && max_spread_multiplier.is_finite() {
+            self.max_spread_multiplier = max_spread_multiplier;
+        } else {
+            self.logger.warning(&format!(
+                "Ignoring max spread multiplier {}, it must be at least 1",
+                max_spread_multiplier
+            ));
+        }
+    }
+
+    /// Latest bands around the session VWAP, `None` until the symbol traded.
+    pub fn set_vwap_bands(&mut self, bands: Option<(f64, f64)>) {
+        self.vwap_bands = bands.filter(|(lower, upper)| lower.is_finite() && upper.is_finite());
+    }
+
+    /// Standard deviations between the session VWAP and its bands. 0.0 restores the default
+    /// of 2, a negative value is ignored.
+    pub fn set_vwap_band_sigma(&mut self, sigma: f64) {
+        if sigma == 0.0 {
+            self.vwap_band_sigma = VWAP_BAND_SIGMA;
+        } else if sigma > 0.0 && sigma.is_finite() {
+            self.vwap_band_sigma = sigma;
+        } else {
+            self.logger.warning(&format!(
+                "Ignoring VWAP band sigma {}, it must be positive",
+                sigma
+            ));
         }
+    }
 
-        let bounds = self.bounds;
-        let current_bid_bound = self.last_update_price - bounds;
-        let current_ask_bound = self.last_update_price + bounds;
+    pub fn vwap_band_sigma(&self) -> f64 {
+        self.vwap_band_sigma
+    }
 
-        let bounds_violated = !(current_bid_bound..=current_ask_bound).contains(&book.mid_price);
-        let stale_data = (book.last_update - self.time_limit) > (self.tick_window as u64 * 1000);
-        self.check_for_fills(&private);
-        self.set_inventory_delta(book.get_mid_price());
+    /// Weight of the VWAP band overlay, between 0.0 and 1.0. The spread tightens by up to
+    /// half the weight while the mid sits at the VWAP, and the skew leans back towards the
+    /// VWAP by up to the weight as the mid stretches to a band. 0.0 disables it.
+    pub fn set_vwap_weight(&mut self, weight: f64) {
+        if weight.is_finite() {
+            self.vwap_weight = weight.clamp(0.0, 1.0);
+        }
+    }
 
-        if (bounds_violated || stale_data) && self.cancel_limit > MIN_CANCEL_LIMIT {
-            if let Ok(cancelled) = self.client.cancel_all(symbol).await {
-                let cancelled_ids: HashSet<_> = cancelled.iter().map(|o| &o.order_id).collect();
-                self.live_buys
-                    .retain(|o| !cancelled_ids.contains(&o.order_id));
-                self.live_sells
-                    .retain(|o| !cancelled_ids.contains(&o.order_id));
-                self.last_update_price = book.mid_price;
-                self.cancel_limit -= 1;
-                return true;
-            } else {
-                self.logger.error("Failed to cancel all orders");
-                self.cancel_limit -= 1;
-            }
+    /// Where `mid_price` sits between the VWAP bands, from -1.0 at or below the lower one
+    /// to 1.0 at or above the upper one. `None` while the overlay is off or has no bands.
+    fn vwap_stretch(&self, mid_price: f64) -> Option<f64> {
+        if self.vwap_weight == 0.0 {
+            return None;
         }
-        false
+        let (lower, upper) = self.vwap_bands?;
+        let half_width = (upper - lower) / 2.0;
+        // A session trading at a single price has no bands to stretch to
+        (half_width > 0.0)
+            .then(|| ((mid_price - (lower + upper) / 2.0) / half_width).clamp(-1.0, 1.0))
     }
 
-    pub async fn update_grid(
-        &mut self,
-        private: BybitPrivate,
-        skew: f64,
-        book: BybitBook,
-        symbol: String,
-        volatility: f64,
-    ) {
-        self.vol_adjusted_bounds(&book, volatility);
+    /// Re-places the orders that rested more than `secs` seconds at their price, even while
+    /// the mid stays within the bounds. 0 disables it.
+    pub fn set_max_order_age(&mut self, secs: u64) {
+        self.max_order_age_ms = secs.saturating_mul(1000);
+    }
 
-        if self.time_limit > 1 && (book.last_update - self.time_limit) > 1000 {
-            self.rate_limit = self.initial_limit;
-            self.cancel_limit = self.initial_limit;
+    /// Follows a mid drifting by at most `drift_bps` per update with amends shifting the
+    /// whole grid, once it's `min_ticks` ticks (1 when 0) from the grid's center. A larger
+    /// move re-quotes the grid. 0.0 disables trailing, the grid then only moves once the
+    /// mid leaves the bounds.
+    pub fn set_trailing(&mut self, drift_bps: f64, min_ticks: u32) {
+        if drift_bps.is_finite() && drift_bps >= 0.0 {
+            self.trail_drift_bps = drift_bps;
+        } else {
+            self.logger.warning(&format!(
+                "Ignoring trailing drift {}, it can't be negative",
+                drift_bps
+            ));
         }
+        self.trail_min_ticks = min_ticks.max(1);
+    }
 
-        if self.out_of_bounds(&book, &symbol, private).await {
-            self.set_inventory_delta(book.get_mid_price());
-            if let Ok(orders) = self.generate_quotes(&symbol, &book, skew, volatility) {
-                if self.rate_limit > 1 {
-                    let order_len = orders.len();
-
-                    if self.send_batch_orders(orders).await {
-                        self.logger.info(&format!(
-                            "Generated {} orders for {} at {} Position: {:#?} Skew: {:#?}",
-                            order_len,
-                            symbol,
-                            round_price(&book, book.get_mid_price()),
-                            self.position_qty,
-                            skew,
-                        ));
-                    }
-                }
-                self.time_limit = book.last_update;
-            }
+    /// Re-sends the orders rejected for crossing the book a tick behind the touch, up to
+    /// `retries` times per batch. The post-only orders the exchange cancels later on the
+    /// order stream are re-sent the same way, up to `retries` times per grid. 0 leaves them
+    /// rejected.
+    pub fn set_post_only_retries(&mut self, retries: usize) {
+        self.post_only_retries = retries;
+    }
+
+    /// Milliseconds the oldest live order has rested at its price by `now`, `None` without
+    /// live orders of known age.
+    pub fn oldest_order_age(&self, now: u64) -> Option<u64> {
+        self.live_buys
+            .iter()
+            .chain(self.live_sells.iter())
+            .filter_map(|order| order.age(now))
+            .max()
+    }
+
+    fn is_expired(&self, order: &LiveOrder, now: u64) -> bool {
+        self.max_order_age_ms > 0
+            && order
+                .age(now)
+                .is_some_and(|age| age > self.max_order_age_ms)
+    }
+
+    fn blended_volatility(&self, volatility: f64) -> f64 {
+        if self.range_vol_weight == 0.0 || !self.range_volatility.is_finite() {
+            return volatility;
         }
+        (1.0 - self.range_vol_weight) * volatility + self.range_vol_weight * self.range_volatility
     }
-}
 
-fn bps_to_decimal(bps: f64) -> f64 {
-    bps * 0.0001
-}
+    /// When enabled, orders, amends and cancels are only logged and applied to the live
+    /// orders locally, and an order fills once the mid crosses its price. Fills still move
+    /// the position and PnL, so the whole pipeline runs without touching the exchange.
+    pub fn set_dry_run(&mut self, enabled: bool) {
+        self.dry_run = enabled;
+    }
 
-fn round_price(book: &BybitBook, price: f64) -> f64 {
-    price.round_to(book.tick_size.count_decimal_places() as u8)
-}
+    /// Min notional used when the book's one is unknown (0.0), a value of 0.0 refuses to quote.
+    pub fn set_fallback_min_notional(&mut self, notional: f64) {
+        self.fallback_min_notional = notional;
+    }
+
+    /// When enabled, a level whose size rounds to zero is quoted with one lot instead of dropped.
+    pub fn set_floor_size_to_lot(&mut self, enabled: bool) {
+        self.floor_size_to_lot = enabled;
+    }
+
+    /// Drops the levels whose weight is under `fraction` of a side's total size and spreads
+    /// their size over the remaining levels, 0.0 quotes every level.
+    pub fn set_dust_fraction(&mut self, fraction: f64) {
+        self.dust_fraction = fraction.clamp(0.0, 1.0);
+    }
+
+    /// Drops the heuristic grid's levels closer to the mid than the maker fee plus `bps`,
+    /// so a tight or heavily skewed grid can't quote at a loss. 0.0 disables it.
+    pub fn set_min_edge_bps(&mut self, bps: f64) {
+        self.min_edge_bps = bps.max(0.0);
+    }
+
+    /// Marks the side flattening the inventory as reduce-only once the inventory delta
+    /// reaches `delta` in either direction, 0.0 disables it.
+    pub fn set_reduce_only_delta(&mut self, delta: f64) {
+        self.reduce_only_delta = delta.clamp(0.0, 1.0);
+    }
+
+    /// Ratio between the sizes of consecutive bid levels, the closer to 1.0 the more even
+    /// the sizes, 0.37 by default. Values outside `0.0..=1.0` are ignored, and so is 0.0,
+    /// which `geometric_weights` can't size a reversed grid with.
+    pub fn set_bid_ratio(&mut self, ratio: f64) {
+        if let Some(ratio) = self.valid_ratio("bid", ratio) {
+            self.bid_ratio = ratio;
+        }
+    }
+
+    /// Ratio between the sizes of consecutive ask levels, see `set_bid_ratio`.
+    pub fn set_ask_ratio(&mut self, ratio: f64) {
+        if let Some(ratio) = self.valid_ratio("ask", ratio) {
+            self.ask_ratio = ratio;
+        }
+    }
 
-fn round_size(qty: f64, book: &BybitBook) -> f64 {
-    round_step(qty, book.lot_size)
+    fn valid_ratio(&self, side: &str, ratio: f64) -> Option<f64> {
+        if ratio > 0.0 && ratio <= 1.0 {
+            Some(ratio)
+        } else {
+            self.logger.warning(&format!(
+                "Ignoring {} ratio {}, it must be above 0 and at most 1",
+                side, ratio
+            ));
+            None
+        }
+    }
+
+    /// When enabled, the side the skew favours sizes its levels with the skew itself
+    /// (clipped to `0.10..=0.63`) instead of its configured ratio, so a stronger positive
+    /// skew moves the bid size towards the touch.
+    pub fn set_skew_weighted_ratio(&mut self, enabled: bool) {
+        self.skew_weighted_ratio = enabled;
+    }
+
+    /// Quotes only the bids or only the asks, or both sides by default. The live orders of a
+    /// side that is turned off are cancelled on the next update.
+    pub fn set_quote_side(&mut self, side: QuoteSide) {
+        if side != self.quote_side {
+            self.logger.info(&format!("Quoting {:?}", side));
+        }
+        self.quote_side = side;
+    }
+
+    pub fn quote_side(&self) -> QuoteSide {
+        self.quote_side
+    }
+
+    /// Makes every order, amend and cancel request also take a token from `limiter`, on
+    /// top of this generator's own budgets. `None` removes it.
+    pub fn set_rate_limiter(&mut self, limiter: Option<SharedRateLimiter>) {
+        self.rate_limiter = limiter;
+    }
+
+    /// Time in force of the grid orders, post-only by default. IOC lets an aggressive
+    /// inventory unwind cross the spread instead of resting.
+    pub fn set_time_in_force(&mut self, time_in_force: TimeInForce) {
+        self.time_in_force = time_in_force;
+    }
+
+    /// Returns the min notional to filter the grid with, or `None` when the symbol should not
+    /// be quoted because the exchange min notional is unknown and no fallback is configured.
+    fn min_notional(&mut self, symbol: &str, book: &BybitBook) -> Option<f64> {
+        if book.min_notional > 0.0 {
+            if std::mem::take(&mut self.unknown_min_notional) {
+                self.logger.info(&format!(
+                    "Min notional for {} known again: {}",
+                    symbol, book.min_notional
+                ));
+            }
+            return Some(book.min_notional);
+        }
+
+        // Warned about once rather than on every grid
+        let first = !std::mem::replace(&mut self.unknown_min_notional, true);
+        if self.fallback_min_notional > 0.0 {
+            if first {
+                self.logger.warning(&format!(
+                    "Unknown min notional for {}, using fallback of {}",
+                    symbol, self.fallback_min_notional
+                ));
+            }
+            Some(self.fallback_min_notional)
+        } else {
+            if first {
+                self.logger
+                    .warning(&format!("Unknown min notional for {}, not quoting", symbol));
+            }
+            None
+        }
+    }
+
+    /// Replaces the source of the price the grid is centered on, the book mid by default.
+    pub fn set_fair_value_source(&mut self, source: Box<dyn FairValueSource>) {
+        self.fair_value_source = source;
+    }
+
+    /// Stops `update_grid` from quoting until called again with `false`, e.g. while
+    /// the features look anomalous. Fills are still picked up after resuming.
+    pub fn set_paused(&mut self, paused: bool) {
+        if paused != self.paused {
+            if paused {
+                self.logger.warning("Quoting paused");
+            } else {
+                self.logger.info("Quoting resumed");
+            }
+        }
+        self.paused = paused;
+    }
+
+    /// Stops `update_grid` from quoting while the book of `symbol` is older than the
+    /// staleness threshold, e.g. during a feed outage. Logs on every transition.
+    ///
+    /// The resting orders can't follow a market that isn't seen, they're cancelled until
+    /// none are left.
+    pub async fn set_stale_data(&mut self, symbol: &str, stale: bool) {
+        if stale != self.stale_data {
+            if stale {
+                self.logger
+                    .warning(&format!("Market data for {} is stale, not quoting", symbol));
+            } else {
+                self.logger.info(&format!(
+                    "Market data for {} is fresh again, quoting",
+                    symbol
+                ));
+            }
+        }
+        self.stale_data = stale;
+        let resting = !self.live_buys.is_empty() || !self.live_sells.is_empty();
+        if stale && resting && !self.log_intended_quotes && self.pull_quotes(symbol).await {
+            self.persist_state(symbol);
+        }
+    }
+
+    /// Seconds quoting stays off after a price shock trips the circuit breaker, 0 keeps
+    /// the default of 30.
+    pub fn set_circuit_breaker_cooldown(&mut self, secs: u64) {
+        self.circuit_breaker_cooldown_ms = match secs {
+            0 => CIRCUIT_BREAKER_COOLDOWN_MS,
+            secs => secs.saturating_mul(1000),
+        };
+    }
+
+    /// Trips the circuit breaker at the rolling average order request round trip of `ms`,
+    /// since quotes the exchange is slow to move get picked off. 0 disables it.
+    pub fn set_max_order_latency(&mut self, ms: u64) {
+        self.max_order_latency_ms = ms as f64;
+    }
+
+    /// Rolling average round trip of the slowest kind of order request in ms, `None` before
+    /// the first request or on venues whose requests aren't timed.
+    pub fn order_latency_ms(&self) -> Option<f64> {
+        self.client.latency()?.slowest_avg_ms()
+    }
+
+    /// Trips the circuit breaker on a price shock or once the order requests get slower than
+    /// the max latency, the cooldown restarting at `now` (ms) on every update that still
+    /// sees a shock. Once it runs out quoting resumes. Returns whether the breaker is
+    /// tripped.
+    ///
+    /// Nothing is sent while the breaker is tripped, so a slow exchange trips it once and
+    /// clears the latencies, the requests after the cooldown tell whether it recovered.
+    pub fn set_price_shock(&mut self, symbol: &str, shock: bool, now: u64) -> bool {
+        let slow = self
+            .order_latency_ms()
+            .filter(|ms| self.max_order_latency_ms > 0.0 && *ms > self.max_order_latency_ms)
+            .filter(|_| self.circuit_breaker_until.is_none());
+        if let (Some(_), Some(latency)) = (slow, self.client.latency()) {
+            latency.reset();
+        }
+        if shock || slow.is_some() {
+            if self.circuit_breaker_until.is_none() {
+                let reason = match slow {
+                    Some(ms) if !shock => format!("order requests take {:.0}ms", ms),
+                    _ => "price shock".to_string(),
+                };
+                self.logger.warning(&format!(
+                    "Circuit breaker tripped for {}: {}, pulling quotes for {}s",
+                    symbol,
+                    reason,
+                    self.circuit_breaker_cooldown_ms / 1000
+                ));
+                self.circuit_breaker_pulled = false;
+            }
+            self.circuit_breaker_until = Some(now.saturating_add(self.circuit_breaker_cooldown_ms));
+        } else if self.circuit_breaker_until.is_some_and(|until| now >= until) {
+            self.circuit_breaker_until = None;
+            self.logger
+                .info(&format!("Circuit breaker for {} reset, quoting", symbol));
+        }
+        self.circuit_breaker_until.is_some()
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Replaces the default fee rates with the account's, the spread never goes below the
+    /// maker fees of a round trip.
+    pub fn set_fees(&mut self, fees: FeeTier) {
+        self.fees = fees;
+        self.pnl.set_fee_rate(fees.maker);
+    }
+
+    pub fn fees(&self) -> FeeTier {
+        self.fees
+    }
+
+    /// The exchange the orders are sent to.
+    pub fn venue(&self) -> &Venue {
+        &self.client
+    }
+
+    /// Whether the grid reaches the exchange, i.e. neither dry run nor logging the intended
+    /// quotes.
+    pub fn places_orders(&self) -> bool {
+        !self.log_intended_quotes && !self.dry_run
+    }
+
+    pub fn set_spread_model(&mut self, model: SpreadModel) {
+        self.spread_model = model;
+    }
+
+    pub fn spread_model(&self) -> SpreadModel {
+        self.spread_model
+    }
+
+    /// Switches between the heuristic grid and Avellaneda-Stoikov quoting. A model with a
+    /// non positive `gamma` or `k` is rejected and the heuristic grid is kept.
+    pub fn set_quoting_model(&mut self, model: QuotingModel) {
+        if let QuotingModel::AvellanedaStoikov { gamma, k } = model {
+            if !(gamma.is_finite() && gamma > 0.0 && k.is_finite() && k > 0.0) {
+                self.logger.warning(&format!(
+                    "Invalid Avellaneda-Stoikov parameters gamma {} k {}, using the heuristic grid",
+                    gamma, k
+                ));
+                self.quoting_model = QuotingModel::Heuristic;
+                return;
+            }
+        }
+        self.quoting_model = model;
+    }
+
+    pub fn quoting_model(&self) -> QuotingModel {
+        self.quoting_model
+    }
+
+    /// Price the next grid will be centered on.
+    pub fn fair_value(&self, book: &BybitBook, features: &Engine) -> f64 {
+        self.fair_value_source.fair_value(book, features)
+    }
+
+    /// Spread captured per contract over the latest round trips, net of maker fees.
+    pub fn realized_edge(&self) -> f64 {
+        self.pnl.realized_edge()
+    }
+
+    /// PnL booked against the average entry price by the fills so far, net of maker fees.
+    pub fn realized_pnl(&self) -> f64 {
+        self.pnl.realized_pnl()
+    }
+
+    pub fn average_entry_price(&self) -> f64 {
+        self.pnl.average_entry_price()
+    }
+
+    /// Mark-to-market PnL of the filled position at `mid_price`.
+    pub fn unrealized_pnl(&self, mid_price: f64) -> f64 {
+        self.pnl.unrealized_pnl(mid_price)
+    }
+
+    pub fn log_pnl(&self, symbol: &str, mid_price: f64) -> String {
+        self.logger.info(&format!(
+            "PnL for {}: realized {:.4}, unrealized {:.4}, position {} @ {}",
+            symbol,
+            self.realized_pnl(),
+            self.unrealized_pnl(mid_price),
+            self.position_qty,
+            self.average_entry_price()
+        ))
+    }
+
+    /// Fill rate, time to fill and spread captured by the quotes since startup.
+    pub fn stats(&self) -> QuoteStats {
+        self.stats
+    }
+
+    pub fn log_quote_stats(&self, symbol: &str) -> String {
+        let stats = self.stats;
+        self.logger.info(&format!(
+            "Quotes for {}: {} placed, {} filled ({:.2}%), avg time to fill {}, captured {:.4} ({} bps avg)",
+            symbol,
+            stats.quotes,
+            stats.filled_quotes,
+            stats.fill_rate() * 100.0,
+            stats
+                .avg_time_to_fill_ms()
+                .map_or("n/a".to_string(), |ms| format!("{:.1}s", ms / 1000.0)),
+            stats.captured(),
+            stats
+                .avg_capture_bps()
+                .map_or("n/a".to_string(), |bps| format!("{:.2}", bps))
+        ))
+    }
+
+    /// Exports whether the symbol is quoting, its fills, position, PnL and request
+    /// budgets to the metrics endpoint.
+    pub fn record_metrics(&self, symbol: &str, mid_price: f64) {
+        let symbol = [("symbol", symbol)];
+        let quoting = !(self.paused
+            || self.stale_data
+            || self.kill_switch
+            || self.circuit_breaker_until.is_some()
+            || self.book_crossed
+            || (self.live_buys.is_empty() && self.live_sells.is_empty()));
+        metrics::set(metrics::QUOTING, &symbol, f64::from(u8::from(quoting)));
+        metrics::set(metrics::POSITION, &symbol, self.position_qty);
+        metrics::set(metrics::REALIZED_PNL, &symbol, self.realized_pnl());
+        metrics::set(
+            metrics::UNREALIZED_PNL,
+            &symbol,
+            self.unrealized_pnl(mid_price),
+        );
+        for (side, fills, budget) in [
+            ("buy", self.buy_fills, self.buy_rate_limit),
+            ("sell", self.sell_fills, self.sell_rate_limit),
+        ] {
+            let labels = [symbol[0], ("side", side)];
+            metrics::set(metrics::FILLS, &labels, fills as f64);
+            metrics::set(metrics::RATE_LIMIT_REMAINING, &labels, budget as f64);
+        }
+        let now = generate_timestamp().unwrap_or_default();
+        let age = self.oldest_order_age(now).unwrap_or_default();
+        metrics::set(metrics::OLDEST_ORDER_AGE, &symbol, age as f64 / 1000.0);
+    }
+
+    fn set_inventory_delta(&mut self, price: f64) {
+        let delta = if self.position_qty.abs() <= f64::EPSILON {
+            0.0
+        } else if self.max_position_usd <= 0.0 {
+            // Any position uses up a zero sized limit
+            self.position_qty.signum()
+        } else {
+            (self.position_qty * price) / self.max_position_usd
+        };
+
+        // A NaN delta makes `nbsqrt` fail and would stop quoting for good
+        self.inventory_delta = if delta.is_nan() {
+            self.logger.warning(&format!(
+                "Invalid inventory delta for position {} at price {}, resetting to 0",
+                self.position_qty, price
+            ));
+            0.0
+        } else if delta.is_infinite() {
+            delta.signum()
+        } else {
+            delta
+        };
+    }
+
+    fn calculate_vol_adjusted_value(
+        &mut self,
+        base_value: f64,
+        book: &BybitBook,
+        volatility: f64,
+    ) -> f64 {
+        let volatility_multiplier = 1.0 + (volatility * self.volatility_multiplier);
+        let min_value = base_value * volatility_multiplier;
+        let max_value = min_value * self.max_spread_multiplier * volatility_multiplier;
+        book.get_spread().clip(min_value, max_value)
+    }
+
+    fn vol_adjusted_spread(&mut self, book: &BybitBook, volatility: f64) -> f64 {
+        let mid_price = book.get_mid_price();
+        let base_min_spread = bps_to_decimal(if self.minimum_spread.abs() < f64::EPSILON {
+            DEFAULT_BPS
+        } else {
+            self.minimum_spread
+        }) * mid_price;
+
+        let spread = match self.spread_model {
+            SpreadModel::Constant => base_min_spread,
+            SpreadModel::VolLinear => {
+                let volatility = self.blended_volatility(volatility);
+                self.calculate_vol_adjusted_value(base_min_spread, book, volatility)
+            }
+        };
+        // One-sided flow is likely informed, quote it wider
+        let spread = spread * (1.0 + self.toxicity_weight * self.toxicity);
+        // Near its VWAP the price tends to stay put, quote it tighter
+        let spread = match self.vwap_stretch(mid_price) {
+            Some(stretch) => spread * (1.0 - 0.5 * self.vwap_weight * (1.0 - stretch.abs())),
+            None => spread,
+        };
+        // Buying and selling back both pay the maker fee, a rebate needs no floor
+        let break_even = 2.0 * self.fees.maker.max(0.0) * mid_price;
+        self.adjusted_spread = spread.max(break_even);
+        self.adjusted_spread
+    }
+
+    fn vol_adjusted_bounds(&mut self, book: &BybitBook, volatility: f64) -> f64 {
+        let base_min_spread = bps_to_decimal(if self.minimum_spread.abs() < f64::EPSILON {
+            DEFAULT_BPS
+        } else {
+            self.minimum_spread
+        }) * self.last_update_price;
+
+        self.bounds = self.calculate_vol_adjusted_value(base_min_spread, book, volatility);
+        self.bounds
+    }
+
+    fn order_refresh_time(&self, volatility: f64, mid_price: f64) -> f64 {
+        let spread_decimal = self.adjusted_spread / mid_price;
+        if volatility < f64::EPSILON {
+            return f64::INFINITY;
+        }
+
+        let per_second_vol = volatility;
+        if per_second_vol < f64::EPSILON {
+            return f64::INFINITY;
+        }
+
+        let ratio = spread_decimal / (2.0 * per_second_vol);
+        ratio.powf(2.0)
+    }
+
+    /// Whether the orders of a side are sent reduce-only, i.e. it's the side reducing an
+    /// inventory past `reduce_only_delta`.
+    fn reduce_only(&self, is_buy: bool) -> bool {
+        let reducing =
+            self.reduce_only_delta > 0.0 && self.inventory_delta.abs() >= self.reduce_only_delta;
+        reducing && (self.inventory_delta < 0.0) == is_buy
+    }
+
+    fn generate_quotes(
+        &mut self,
+        symbol: &str,
+        book: &BybitBook,
+        skew: f64,
+        volatility: f64,
+        fair_value: f64,
+    ) -> Result<Vec<BatchOrder>> {
+        if let QuotingModel::AvellanedaStoikov { gamma, k } = self.quoting_model {
+            return Ok(self.reservation_orders(symbol, book, volatility, fair_value, gamma, k));
+        }
+
+        let spread = self.vol_adjusted_spread(book, volatility);
+        // Leans back towards the VWAP once the price is stretched away from it
+        let skew = match self.vwap_stretch(book.get_mid_price()) {
+            Some(stretch) => (skew - self.vwap_weight * stretch).clamp(-1.0, 1.0),
+            None => skew,
+        };
+
+        let inventory_factor = nbsqrt(self.inventory_delta)?;
+        let skew_factor = skew * (1.0 - inventory_factor.abs());
+        let combined_skew =
+            (skew_factor + self.inventory_adjustment * inventory_factor).clamp(-1.0, 1.0);
+
+        let is_positive_skew = combined_skew >= 0.0;
+        let orders = self.generate_skew_orders(
+            symbol,
+            spread,
+            skew.abs(),
+            book,
+            fair_value,
+            is_positive_skew,
+        );
+
+        Ok(orders)
+    }
+
+    fn generate_skew_orders(
+        &mut self,
+        symbol: &str,
+        spread: f64,
+        skew: f64,
+        book: &BybitBook,
+        fair_value: f64,
+        is_positive_skew: bool,
+    ) -> Vec<BatchOrder> {
+        let mid_price = fair_value;
+
+        let (best_bid, best_ask) = if is_positive_skew {
+            let bid = mid_price - (spread * (1.0 - skew.sqrt()));
+            (bid, bid + spread)
+        } else {
+            let ask = mid_price + (spread * (1.0 - skew.sqrt()));
+            (ask - spread, ask)
+        };
+
+        let clipped_r = if skew.is_finite() {
+            skew.clamp(0.10, 0.63)
+        } else {
+            GRID_WEIGHT_RATIO
+        };
+        let ratios = if !self.skew_weighted_ratio {
+            (self.bid_ratio, self.ask_ratio)
+        } else if is_positive_skew {
+            (clipped_r, self.ask_ratio)
+        } else {
+            (self.bid_ratio, clipped_r)
+        };
+
+        let orders = self.grid_orders(
+            symbol,
+            spread,
+            (best_bid, best_ask),
+            ratios,
+            book,
+            mid_price,
+        );
+        self.profitable_orders(symbol, orders, mid_price)
+    }
+
+    /// Keeps the orders at least the maker fee plus `min_edge_bps` away from `mid_price`.
+    fn profitable_orders(
+        &mut self,
+        symbol: &str,
+        orders: Vec<BatchOrder>,
+        mid_price: f64,
+    ) -> Vec<BatchOrder> {
+        if self.min_edge_bps <= 0.0 {
+            self.suppressed_levels = 0;
+            return orders;
+        }
+        let min_distance =
+            (self.fees.maker.max(0.0) + bps_to_decimal(self.min_edge_bps)) * mid_price;
+        let count = orders.len();
+        let orders: Vec<_> = orders
+            .into_iter()
+            .filter(|order| {
+                let distance = if order.3 {
+                    mid_price - order.1
+                } else {
+                    order.1 - mid_price
+                };
+                distance >= min_distance
+            })
+            .collect();
+        // Logged when the count changes rather than on every quote
+        let suppressed = count - orders.len();
+        if suppressed > 0 && suppressed != self.suppressed_levels {
+            self.logger.warning(&format!(
+                "Suppressed {} unprofitable levels for {}",
+                suppressed, symbol
+            ));
+        }
+        self.suppressed_levels = suppressed;
+        orders
+    }
+
+    /// Centers the grid on the Avellaneda-Stoikov reservation price with the model's
+    /// optimal spread, floored at the maker fee break-even.
+    ///
+    /// The inventory `q` is `position_qty`, i.e. `inventory_delta * max_position_usd / mid`
+    /// in contracts. `sigma` is the rolling volatility of the per second log returns times
+    /// the fair value, and the horizon `T - t` is `tick_window` seconds.
+    fn reservation_orders(
+        &mut self,
+        symbol: &str,
+        book: &BybitBook,
+        volatility: f64,
+        fair_value: f64,
+        gamma: f64,
+        k: f64,
+    ) -> Vec<BatchOrder> {
+        let sigma = volatility * fair_value;
+        let (reservation, half_spread) = avellaneda_stoikov(
+            fair_value,
+            self.position_qty,
+            sigma,
+            self.tick_window as f64,
+            gamma,
+            k,
+        );
+        let break_even = 2.0 * self.fees.maker.max(0.0) * fair_value;
+        let spread = (2.0 * half_spread).max(break_even);
+        self.adjusted_spread = spread;
+
+        let touch = (reservation - spread / 2.0, reservation + spread / 2.0);
+        let ratios = (self.bid_ratio, self.ask_ratio);
+        self.grid_orders(symbol, spread, touch, ratios, book, fair_value)
+    }
+
+    /// Lays out `total_order` levels per side outwards from `best_bid` and `best_ask`,
+    /// sized by geometric weights with the given (bid, ask) ratios.
+    fn grid_orders(
+        &mut self,
+        symbol: &str,
+        spread: f64,
+        (best_bid, best_ask): (f64, f64),
+        (bid_r, ask_r): (f64, f64),
+        book: &BybitBook,
+        mid_price: f64,
+    ) -> Vec<BatchOrder> {
+        let Some(notional) = self.min_notional(symbol, book) else {
+            return vec![];
+        };
+        let post_only_max = book.post_only_max;
+
+        if best_bid <= 0.0 {
+            return vec![];
+        }
+        // A wide grid on a cheap symbol would put the outer bids below zero
+        let end = (spread * self.final_order_distance).min(best_bid * MAX_GRID_SPAN);
+
+        let max_buy_qty = if self.position_qty != 0.0 {
+            (self.max_position_usd / 2.0) - (self.position_qty * mid_price)
+        } else {
+            self.max_position_usd / 2.0
+        };
+        let max_sell_qty = if self.position_qty != 0.0 {
+            (self.max_position_usd / 2.0) + (self.position_qty * mid_price)
+        } else {
+            self.max_position_usd / 2.0
+        };
+
+        // Thin books and small budgets get fewer levels rather than none passing the filters
+        let bid_levels =
+            self.supported_levels(end, book.tick_size, bid_r, max_buy_qty, notional, false);
+        let ask_levels =
+            self.supported_levels(end, book.tick_size, ask_r, max_sell_qty, notional, true);
+        // Logged when the supported levels change rather than on every quote
+        let reduced =
+            (bid_levels.min(ask_levels) < self.total_order).then_some((bid_levels, ask_levels));
+        if reduced.is_some() && reduced != self.reduced_levels {
+            self.logger.info(&format!(
+                "Reduced the grid of {} to {} bids and {} asks, the book can't fit {} levels per side",
+                symbol, bid_levels, ask_levels, self.total_order
+            ));
+        }
+        self.reduced_levels = reduced;
+        let bid_prices = geomspace(best_bid - end, best_bid, bid_levels);
+        let ask_prices = geomspace(best_ask, best_ask + end, ask_levels);
+
+        let bid_sizes = if self.inventory_delta < 0.5 && self.quote_side.quotes_bids() {
+            truncate_weights(
+                &geometric_weights(bid_r, bid_levels, false),
+                self.dust_fraction,
+            )
+            .into_iter()
+            .map(|w| w * max_buy_qty)
+            .collect()
+        } else {
+            vec![]
+        };
+        let ask_sizes = if self.inventory_delta > -0.5 && self.quote_side.quotes_asks() {
+            truncate_weights(
+                &geometric_weights(ask_r, ask_levels, true),
+                self.dust_fraction,
+            )
+            .into_iter()
+            .map(|w| w * max_sell_qty)
+            .collect()
+        } else {
+            vec![]
+        };
+
+        // Past the threshold the reducing side can't overshoot into the opposite position
+        let bids_reduce_only = self.reduce_only(true);
+        let asks_reduce_only = self.reduce_only(false);
+
+        let mut orders = Vec::with_capacity(self.total_order * 2);
+        // Levels truncated as dust have a size of 0.0 and are never generated
+        for i in 0..self.total_order {
+            if let (Some(&bid_price), Some(&bid_size)) =
+                (bid_prices.get(i), bid_sizes.get(i).filter(|&&s| s > 0.0))
+            {
+                let size = (bid_size / bid_price).min(post_only_max);
+                orders.push(BatchOrder::new(
+                    symbol.to_string(),
+                    round_price_passive(book, bid_price, true),
+                    round_size(size, book, self.floor_size_to_lot),
+                    true,
+                    bids_reduce_only,
+                    self.time_in_force,
+                ));
+            }
+
+            if let (Some(&ask_price), Some(&ask_size)) =
+                (ask_prices.get(i), ask_sizes.get(i).filter(|&&s| s > 0.0))
+            {
+                let size = (ask_size / ask_price).min(post_only_max);
+                orders.push(BatchOrder::new(
+                    symbol.to_string(),
+                    round_price_passive(book, ask_price, false),
+                    round_size(size, book, self.floor_size_to_lot),
+                    false,
+                    asks_reduce_only,
+                    self.time_in_force,
+                ));
+            }
+        }
+        orders.retain(|order| order.2 > 0.0 && (order.1 * order.2) >= notional);
+        orders
+    }
+
+    /// Most levels per side, between 2 and `total_order`, that fit in `span` at least a tick
+    /// apart on average and each get `notional` out of `budget` with the weights of `ratio`.
+    fn supported_levels(
+        &self,
+        span: f64,
+        tick_size: f64,
+        ratio: f64,
+        budget: f64,
+        notional: f64,
+        reverse: bool,
+    ) -> usize {
+        let mut levels = self.total_order;
+        if tick_size > 0.0 {
+            // Leaves room for the float error of a span of whole ticks
+            levels = levels.min((span / tick_size + 1e-9).floor() as usize + 1);
+        }
+        // A side that can't afford any level is left to the filters
+        if budget >= notional {
+            while levels > 2 {
+                let weights = truncate_weights(
+                    &geometric_weights(ratio, levels, reverse),
+                    self.dust_fraction,
+                );
+                let smallest = weights
+                    .iter()
+                    .copied()
+                    .filter(|&w| w > 0.0)
+                    .fold(f64::INFINITY, f64::min);
+                if smallest * budget >= notional {
+                    break;
+                }
+                levels -= 1;
+            }
+        }
+        levels.max(2)
+    }
+
+    /// Request budget of the bids when `is_buy`, of the asks otherwise.
+    fn rate_limit_mut(&mut self, is_buy: bool) -> &mut usize {
+        if is_buy {
+            &mut self.buy_rate_limit
+        } else {
+            &mut self.sell_rate_limit
+        }
+    }
+
+    /// Takes a request from the side's budget and from the shared limiter, `false` once
+    /// either is exhausted.
+    fn take_request(&mut self, is_buy: bool) -> bool {
+        if *self.rate_limit_mut(is_buy) == 0 || !self.take_shared_request() {
+            return false;
+        }
+        *self.rate_limit_mut(is_buy) -= 1;
+        true
+    }
+
+    fn take_shared_request(&self) -> bool {
+        self.rate_limiter
+            .as_ref()
+            .is_none_or(|limiter| limiter.try_acquire())
+    }
+
+    fn cancel_limit_mut(&mut self, is_buy: bool) -> &mut usize {
+        if is_buy {
+            &mut self.buy_cancel_limit
+        } else {
+            &mut self.sell_cancel_limit
+        }
+    }
+
+    /// Forgets the live orders among `cancelled`, the post-only orders the exchange cancelled
+    /// on the order stream for crossing the book, so they aren't amended or counted as
+    /// resting. While the grid has `post_only_retries` left they're re-sent, priced
+    /// `behind_touch` of the current book.
+    async fn resend_post_only_cancels(
+        &mut self,
+        symbol: &str,
+        book: &BybitBook,
+        cancelled: impl Iterator<Item = String>,
+    ) {
+        let cancelled: HashSet<String> = cancelled.collect();
+        if cancelled.is_empty() {
+            return;
+        }
+        let mut orders = Vec::new();
+        for live in [&mut self.live_buys, &mut self.live_sells] {
+            live.retain(|order| {
+                let keep = !cancelled.contains(&order.order_id);
+                if !keep {
+                    orders.push(order.clone());
+                }
+                keep
+            });
+        }
+        if orders.is_empty() {
+            return;
+        }
+
+        let resend = self.post_only_resent < self.post_only_retries;
+        self.logger.warning(&format!(
+            "{} post-only orders for {} were cancelled for crossing the book{}",
+            orders.len(),
+            symbol,
+            if resend { ", re-sending them" } else { "" }
+        ));
+        if resend {
+            self.post_only_resent += 1;
+            let orders = orders
+                .iter()
+                .map(|order| {
+                    BatchOrder::new(
+                        symbol.to_string(),
+                        behind_touch(book, order.price, order.is_buy),
+                        order.qty,
+                        order.is_buy,
+                        self.reduce_only(order.is_buy),
+                        self.time_in_force,
+                    )
+                })
+                .filter(|order| order.1 > 0.0)
+                .collect();
+            self.send_batch_orders(book, orders).await;
+        }
+        self.persist_state(symbol);
+    }
+
+    /// Places the orders in batches of one side each, so running out of one side's
+    /// budget doesn't hold back the other. Post-only rejections are re-sent a tick behind
+    /// the touch, up to `post_only_retries` times, each retry using the rate limit.
+    async fn send_batch_orders(&mut self, book: &BybitBook, orders: Vec<BatchOrder>) -> bool {
+        let mut result = false;
+        let (buys, sells): (Vec<_>, Vec<_>) = orders.into_iter().partition(|o| o.3);
+        for (is_buy, orders) in [(true, buys), (false, sells)] {
+            for chunk in orders.chunks(ORDER_CHUNK_SIZE) {
+                let mut pending = chunk.to_vec();
+                for attempt in 0..=self.post_only_retries {
+                    if !self.take_request(is_buy) {
+                        break;
+                    }
+
+                    let placed = if self.dry_run {
+                        let (buys, sells) = self.simulate_orders(&pending);
+                        Ok((buys, sells, Vec::new()))
+                    } else {
+                        self.client.batch_orders(pending).await
+                    };
+                    let Ok((live_buys, live_sells, rejected)) = placed else {
+                        self.logger.error("Failed to send batch orders");
+                        break;
+                    };
+                    // The quotes remember the mid they were placed at for the spread capture
+                    let mid_price = book.mid_price;
+                    self.stats.on_quotes(live_buys.len() + live_sells.len());
+                    let placed_at = |order| LiveOrder {
+                        placement_mid: mid_price,
+                        ..order
+                    };
+                    self.live_buys.extend(live_buys.into_iter().map(placed_at));
+                    self.live_sells
+                        .extend(live_sells.into_iter().map(placed_at));
+                    self.live_buys = sort_grid(&mut self.live_buys, -1);
+                    self.live_sells = sort_grid(&mut self.live_sells, 1);
+                    result = true;
+
+                    pending = retry_post_only(book, &rejected);
+                    if pending.is_empty() || attempt == self.post_only_retries {
+                        break;
+                    }
+                    self.logger.warning(&format!(
+                        "Retrying {} post-only rejected orders a tick behind the touch (retry {}/{})",
+                        pending.len(),
+                        attempt + 1,
+                        self.post_only_retries
+                    ));
+                }
+            }
+        }
+        result
+    }
+
+    fn simulate_orders(&mut self, orders: &[BatchOrder]) -> (Vec<LiveOrder>, Vec<LiveOrder>) {
+        let mut msg = format!("Dry run: placing {} orders:", orders.len());
+        let (mut buys, mut sells) = (vec![], vec![]);
+        for order in orders {
+            self.dry_run_orders += 1;
+            let order_id = format!("dry-run-{}", self.dry_run_orders);
+            msg.push_str(&format!(
+                "\n{} {} {} @ {}",
+                order_id,
+                if order.3 { "BUY" } else { "SELL" },
+                order.2,
+                order.1
+            ));
+            let live_order = LiveOrder::placed(order_id, order);
+            if order.3 {
+                buys.push(live_order);
+            } else {
+                sells.push(live_order);
+            }
+        }
+        self.logger.info(&msg);
+        (buys, sells)
+    }
+
+    fn simulate_amends(
+        &self,
+        amends: &[BatchAmend],
+    ) -> (Vec<(String, LiveOrder)>, Vec<RejectedAmend>) {
+        let amends = amends
+            .iter()
+            .map(|amend| {
+                self.logger.info(&format!(
+                    "Dry run: amending order {} to {} @ {}",
+                    amend.3, amend.2, amend.1
+                ));
+                (
+                    amend.3.clone(),
+                    LiveOrder::new(amend.3.clone(), amend.1, amend.2, amend.4),
+                )
+            })
+            .collect();
+        (amends, Vec::new())
+    }
+
+    /// Fills every simulated order the mid has crossed, a buy once the mid is at or
+    /// below its price and a sell once it's at or above.
+    fn simulate_fills(&mut self, book: &BybitBook) {
+        let mid_price = book.mid_price;
+        let fills: Vec<Fill> = self
+            .live_buys
+            .iter()
+            .filter(|o| mid_price <= o.price)
+            .chain(self.live_sells.iter().filter(|o| mid_price >= o.price))
+            .chain(self.manual_orders.iter().filter(|o| {
+                if o.is_buy {
+                    mid_price <= o.price
+                } else {
+                    mid_price >= o.price
+                }
+            }))
+            .map(|o| Fill {
+                order_id: o.order_id.clone(),
+                exec_id: format!("{}-fill", o.order_id),
+                price: o.price,
+                qty: o.qty,
+                is_buy: o.is_buy,
+                time: book.last_update,
+                seq: 0,
+            })
+            .collect();
+        for fill in fills {
+            self.apply_fill(&fill);
+        }
+    }
+
+    /// Replaces the live orders with the ones resting on the exchange.
+    ///
+    /// Drops phantom orders, e.g. ones cancelled or filled while the stream was down,
+    /// and adopts resting orders the generator lost track of. The manual orders still
+    /// resting stay out of the grid.
+    pub fn reconcile_orders(&mut self, open_orders: Vec<LiveOrder>) {
+        let manual_ids: HashSet<String> = self
+            .manual_orders
+            .iter()
+            .map(|o| o.order_id.clone())
+            .collect();
+        let (manual_orders, mut open_orders): (Vec<_>, Vec<_>) = open_orders
+            .into_iter()
+            .partition(|o| manual_ids.contains(&o.order_id));
+        self.manual_orders = manual_orders.into();
+
+        // Placement mids of the tracked orders, the exchange doesn't know them
+        let known: HashMap<String, f64> = self
+            .live_buys
+            .iter()
+            .chain(self.live_sells.iter())
+            .map(|o| (o.order_id.clone(), o.placement_mid))
+            .collect();
+        let open_ids: HashSet<&str> = open_orders.iter().map(|o| o.order_id.as_str()).collect();
+        let dropped = known
+            .keys()
+            .filter(|id| !open_ids.contains(id.as_str()))
+            .count();
+        let adopted = open_ids
+            .iter()
+            .filter(|id| !known.contains_key(**id))
+            .count();
+        for order in open_orders.iter_mut() {
+            if let Some(mid) = known.get(&order.order_id) {
+                order.placement_mid = *mid;
+            }
+        }
+
+        let (mut buys, mut sells): (VecDeque<_>, VecDeque<_>) =
+            open_orders.into_iter().partition(|o| o.is_buy);
+        self.live_buys = sort_grid(&mut buys, -1);
+        self.live_sells = sort_grid(&mut sells, 1);
+
+        if dropped > 0 || adopted > 0 {
+            self.logger.warning(&format!(
+                "Reconciled orders: dropped {} stale, adopted {} untracked",
+                dropped, adopted
+            ));
+        }
+    }
+
+    /// Reconciles the live orders and the position with the exchange once per private
+    /// stream session, i.e. on startup and after every reconnection. Retried next tick
+    /// when a request fails.
+    ///
+    /// Returns `false` until both succeeded for `session`, the grid isn't quoted from a
+    /// position that may be wrong meanwhile.
+    async fn sync_session(&mut self, symbol: &str, session: u64) -> bool {
+        if session == self.session {
+            return true;
+        }
+        if self.sync_open_orders(symbol).await && self.sync_position(symbol).await {
+            self.session = session;
+            return true;
+        }
+        false
+    }
+
+    async fn sync_open_orders(&mut self, symbol: &str) -> bool {
+        match self.client.get_open_orders(symbol).await {
+            Ok(open_orders) => {
+                self.reconcile_orders(open_orders);
+                true
+            }
+            Err(e) => {
+                self.logger.error(&format!(
+                    "Failed to fetch open orders for {}: {}",
+                    symbol, e
+                ));
+                false
+            }
+        }
+    }
+
+    /// Fetches the position held on the exchange, so a restart while holding inventory
+    /// doesn't quote as if flat. Returns `false` when the request fails.
+    pub async fn sync_position(&mut self, symbol: &str) -> bool {
+        match self.client.get_position(symbol).await {
+            Ok(position) => {
+                self.set_position(position);
+                true
+            }
+            Err(e) => {
+                self.logger
+                    .error(&format!("Failed to fetch position for {}: {}", symbol, e));
+                false
+            }
+        }
+    }
+
+    /// Overrides the tracked position, positive for long and negative for short.
+    pub fn set_position(&mut self, position_qty: f64) {
+        if (position_qty - self.position_qty).abs() > f64::EPSILON {
+            self.logger.warning(&format!(
+                "Position synced from {} to {}",
+                self.position_qty, position_qty
+            ));
+        }
+        self.position_qty = position_qty;
+    }
+
+    /// Walks the executions from the newest one and stops past the last processed one,
+    /// so the work per tick is proportional to the new fills rather than the buffer size.
+    /// Returns the new fills oldest first.
+    ///
+    /// Executions stamped like the last processed one may be new, e.g. several fills in
+    /// the same millisecond without a sequence number, so those are checked against the
+    /// ids of the processed executions. The same ids skip the executions a reconnected
+    /// stream replays, keeping the position from counting a fill twice.
+    fn take_new_fills(&mut self, executions: impl Iterator<Item = Fill>) -> Vec<Fill> {
+        let last_fill = self.last_fill;
+        let mut fills: Vec<Fill> = executions
+            .take_while(|fill| (fill.time, fill.seq) >= last_fill)
+            .collect();
+        fills.reverse();
+        fills.retain(|fill| self.mark_processed(&fill.exec_id));
+        if let Some(fill) = fills.last() {
+            self.last_fill = self.last_fill.max((fill.time, fill.seq));
+        }
+        fills
+    }
+
+    /// Remembers an execution as processed, `false` when it already was.
+    fn mark_processed(&mut self, exec_id: &str) -> bool {
+        if !self.seen_execution_ids.insert(exec_id.to_string()) {
+            return false;
+        }
+        self.seen_executions.push_back(exec_id.to_string());
+        if self.seen_executions.len() > SEEN_EXECUTIONS {
+            if let Some(oldest) = self.seen_executions.pop_front() {
+                self.seen_execution_ids.remove(&oldest);
+            }
+        }
+        true
+    }
+
+    fn check_for_fills(&mut self, info: &PrivateData) {
+        let fills = self.take_new_fills(info.fills());
+        for fill in fills {
+            self.apply_fill(&fill);
+        }
+    }
+
+    /// Matches a fill to its live order by id, the order's side moves the position.
+    ///
+    /// Exchanges send an execution per partial fill, each one moves the executed qty from
+    /// the order's resting qty to its filled qty. The order is only forgotten once nothing
+    /// of it rests anymore.
+    fn apply_fill(&mut self, fill: &Fill) {
+        let qty = fill.qty;
+        if qty <= 0.0 {
+            return;
+        }
+
+        let find =
+            |orders: &VecDeque<LiveOrder>| orders.iter().position(|o| o.order_id == fill.order_id);
+        let (orders, idx) = if let Some(idx) = find(&self.live_buys) {
+            (&mut self.live_buys, idx)
+        } else if let Some(idx) = find(&self.live_sells) {
+            (&mut self.live_sells, idx)
+        } else if let Some(idx) = find(&self.manual_orders) {
+            (&mut self.manual_orders, idx)
+        } else {
+            return;
+        };
+
+        let order = &mut orders[idx];
+        self.stats.on_fill(order, fill.price, qty, fill.time);
+        // Update position with executed qty
+        self.position_qty += if order.is_buy { qty } else { -qty };
+        self.pnl.on_fill(order.price, qty, order.is_buy);
+        // Update the order's remaining quantity
+        order.qty -= qty;
+        order.filled_qty += qty;
+        if order.is_buy {
+            self.buy_fills += 1;
+        } else {
+            self.sell_fills += 1;
+        }
+        // Log the executed qty
+        let side = if order.is_buy { "Buy" } else { "Sell" };
+        let msg = format!("{} fill: {:.2} @ {}", side, qty, order.price);
+        self.logger.info(&msg);
+        // Remove only if fully filled, within the float error of the partial fills
+        if order.qty <= FILLED_TOLERANCE * order.filled_qty {
+            orders.remove(idx);
+        }
+    }
+
+    fn out_of_bounds(&mut self, book: &BybitBook, private: PrivateData) -> bool {
+        let previous_mid = std::mem::replace(&mut self.previous_mid, book.mid_price);
+        if self.live_buys.is_empty() && self.live_sells.is_empty() {
+            self.last_update_price = book.mid_price;
+            return true;
+        }
+
+        let grid_outdated = self.grid_outdated(book);
+        // Orders left at one price for long only get filled by flow that already moved on
+        let now = generate_timestamp().unwrap_or_default();
+        let expired_orders = self
+            .live_buys
+            .iter()
+            .chain(self.live_sells.iter())
+            .any(|order| self.is_expired(order, now));
+        // A trailed grid only follows slow drifts, a jump gets a fresh grid
+        let jumped = self.trail_drift_bps > 0.0
+            && previous_mid > 0.0
+            && (book.mid_price / previous_mid - 1.0).abs() * 10_000.0 > self.trail_drift_bps;
+        // The orders of a side turned off are cancelled by the next grid
+        let disabled_side = (!self.quote_side.quotes_bids() && !self.live_buys.is_empty())
+            || (!self.quote_side.quotes_asks() && !self.live_sells.is_empty());
+        if self.dry_run {
+            self.simulate_fills(book);
+        } else {
+            self.check_for_fills(&private);
+        }
+        self.set_inventory_delta(book.get_mid_price());
+
+        // The live orders are amended into the new grid rather than cancelled
+        grid_outdated || expired_orders || disabled_side || jumped
+    }
+
+    /// Whether the mid left the bounds around the grid's center or the grid is older than
+    /// `tick_window` seconds.
+    fn grid_outdated(&self, book: &BybitBook) -> bool {
+        let bid_bound = self.last_update_price - self.bounds;
+        let ask_bound = self.last_update_price + self.bounds;
+        let bounds_violated = !(bid_bound..=ask_bound).contains(&book.mid_price);
+        let stale_data =
+            book.last_update.saturating_sub(self.time_limit) > (self.tick_window as u64 * 1000);
+        bounds_violated || stale_data
+    }
+
+    /// Shifts every live order by the mid's drift from the grid's center, rounded to
+    /// ticks, once it's at least `trail_min_ticks` ticks. The sizes and the distances to
+    /// the mid stay as they were, so a slowly trending mid is followed at the cost of an
+    /// amend per order instead of resting stale until it leaves the bounds.
+    ///
+    /// Returns `false` when the grid needs a re-quote instead: an order's reduce-only flag or
+    /// time in force differs from what its side is quoted with now, which an amend can't
+    /// change, or not every order moved. The grid's center only follows a complete trail.
+    async fn trail_grid(&mut self, symbol: &str, book: &BybitBook) -> bool {
+        if book.tick_size <= 0.0 || self.last_update_price <= 0.0 {
+            return true;
+        }
+        let ticks = ((book.mid_price - self.last_update_price) / book.tick_size).round();
+        if ticks.abs() < f64::from(self.trail_min_ticks) {
+            return true;
+        }
+        let stale_flags = self
+            .live_buys
+            .iter()
+            .chain(self.live_sells.iter())
+            .any(|order| {
+                order.reduce_only != self.reduce_only(order.is_buy)
+                    || order.time_in_force != self.time_in_force
+            });
+        if stale_flags {
+            return false;
+        }
+        let shift = ticks * book.tick_size;
+        let amends: Vec<BatchAmend> = self
+            .live_buys
+            .iter()
+            .chain(self.live_sells.iter())
+            .map(|order| {
+                BatchAmend::of_order(
+                    symbol.to_string(),
+                    order,
+                    round_price(book, order.price + shift),
+                    order.qty,
+                )
+            })
+            .collect();
+        let amend_count = amends.len();
+        let amended = self.send_amends(book, amends).await;
+        if amended < amend_count {
+            self.logger.warning(&format!(
+                "Trailed only {} of {} orders for {}, re-quoting",
+                amended, amend_count, symbol
+            ));
+            return false;
+        }
+        self.last_update_price += shift;
+        self.trails += 1;
+        self.logger.info(&format!(
+            "Trailed {} orders for {} by {} ticks to {} ({} trails so far)",
+            amended,
+            symbol,
+            ticks,
+            round_price(book, self.last_update_price),
+            self.trails
+        ));
+        true
+    }
+
+    /// Cancels both sides at once, so it's charged to both cancel budgets.
+    async fn cancel_live_orders(&mut self, symbol: &str) -> bool {
+        if self.buy_cancel_limit <= MIN_CANCEL_LIMIT
+            || self.sell_cancel_limit <= MIN_CANCEL_LIMIT
+            || !self.take_shared_request()
+        {
+            return false;
+        }
+        self.buy_cancel_limit -= 1;
+        self.sell_cancel_limit -= 1;
+        if self.dry_run {
+            self.logger
+                .info(&format!("Dry run: cancelling all orders for {}", symbol));
+            self.live_buys.clear();
+            self.live_sells.clear();
+            self.manual_orders.clear();
+            return true;
+        }
+        match self.client.cancel_all(symbol).await {
+            Ok(Some(cancelled)) => {
+                let cancelled_ids: HashSet<_> = cancelled.iter().collect();
+                self.live_buys
+                    .retain(|o| !cancelled_ids.contains(&o.order_id));
+                self.live_sells
+                    .retain(|o| !cancelled_ids.contains(&o.order_id));
+                self.manual_orders
+                    .retain(|o| !cancelled_ids.contains(&o.order_id));
+                true
+            }
+            Ok(None) => {
+                self.live_buys.clear();
+                self.live_sells.clear();
+                self.manual_orders.clear();
+                true
+            }
+            Err(_) => {
+                self.logger.error("Failed to cancel all orders");
+                false
+            }
+        }
+    }
+
+    /// Saves the orders, position and PnL to `persistence` whenever they change, so a
+    /// restart picks them up with `restore_state`. Nothing is saved in dry run.
+    pub fn set_persistence(&mut self, persistence: Box<dyn StatePersistence>) {
+        self.persistence = Some(persistence);
+    }
+
+    /// Loads the state saved for `symbol` by a previous run. The orders and position are
+    /// reconciled with the exchange on the first private stream session, which corrects
+    /// anything that changed while the bot was down. Returns `true` when a state was found.
+    pub fn restore_state(&mut self, symbol: &str) -> bool {
+        let Some(persistence) = &self.persistence else {
+            return false;
+        };
+        match persistence.load(symbol) {
+            Ok(Some(state)) => {
+                self.live_buys = state.live_buys.iter().cloned().collect();
+                self.live_sells = state.live_sells.iter().cloned().collect();
+                self.position_qty = state.position_qty;
+                self.pnl
+                    .restore(state.position_qty, state.entry_price, state.realized_pnl);
+                self.logger.info(&format!(
+                    "Restored {} orders and a position of {} for {}",
+                    state.live_buys.len() + state.live_sells.len(),
+                    state.position_qty,
+                    symbol
+                ));
+                self.persisted = state;
+                true
+            }
+            Ok(None) => false,
+            Err(e) => {
+                self.logger
+                    .error(&format!("Failed to restore the state of {}: {}", symbol, e));
+                false
+            }
+        }
+    }
+
+    fn persisted_state(&self) -> PersistedState {
+        PersistedState {
+            live_buys: self.live_buys.iter().cloned().collect(),
+            live_sells: self.live_sells.iter().cloned().collect(),
+            position_qty: self.position_qty,
+            entry_price: self.pnl.average_entry_price(),
+            realized_pnl: self.pnl.realized_pnl(),
+        }
+    }
+
+    /// Saves the state of `symbol` if it changed since the last save, a failed save is
+    /// retried on the next call.
+    fn persist_state(&mut self, symbol: &str) {
+        let Some(persistence) = &self.persistence else {
+            return;
+        };
+        if self.dry_run {
+            return;
+        }
+        let state = self.persisted_state();
+        if state == self.persisted {
+            return;
+        }
+        match persistence.save(symbol, &state) {
+            Ok(()) => self.persisted = state,
+            Err(e) => {
+                self.logger
+                    .error(&format!("Failed to save the state of {}: {}", symbol, e));
+            }
+        }
+    }
+
+    /// Cancels every order of `symbol` before the bot exits, whatever is left of the
+    /// cancel budget.
+    pub async fn shutdown(&mut self, symbol: &str) -> bool {
+        self.logger.info(&format!(
+            "Shutting down, cancelling all orders for {}",
+            symbol
+        ));
+        if let Err(e) = self.cancel_all(symbol).await {
+            self.logger.critical(&format!(
+                "Failed to cancel the orders for {} on shutdown: {}",
+                symbol, e
+            ));
+            return false;
+        }
+        self.persist_state(symbol);
+        true
+    }
+
+    /// Cancels every order of `symbol` regardless of the cancel budget.
+    async fn cancel_all(&mut self, symbol: &str) -> std::result::Result<(), String> {
+        if !self.dry_run {
+            self.client.cancel_all(symbol).await?;
+        }
+        self.live_buys.clear();
+        self.live_sells.clear();
+        self.manual_orders.clear();
+        Ok(())
+    }
+
+    /// Cancels every order of `symbol` outside the request budgets, protective cancels
+    /// can't wait for the quoting traffic of the other symbols to leave room. Returns
+    /// `false` when the cancel failed.
+    async fn pull_quotes(&mut self, symbol: &str) -> bool {
+        match self.cancel_all(symbol).await {
+            Ok(()) => true,
+            Err(e) => {
+                self.logger
+                    .error(&format!("Failed to pull the quotes for {}: {}", symbol, e));
+                false
+            }
+        }
+    }
+
+    /// Places a one-off limit order outside the grid with the grid's time in force. It's
+    /// tracked apart from the grid orders, so grid updates never amend or cancel it, but
+    /// its fills move the position. Cancelling every order, e.g. on the kill switch,
+    /// cancels it too.
+    pub async fn manual_order(
+        &mut self,
+        symbol: &str,
+        price: f64,
+        qty: f64,
+        is_buy: bool,
+    ) -> std::result::Result<(), String> {
+        if !(price > 0.0 && price.is_finite() && qty > 0.0 && qty.is_finite()) {
+            return Err(format!("Invalid order of {} @ {}", qty, price));
+        }
+        let order = BatchOrder::new(
+            symbol.to_string(),
+            price,
+            qty,
+            is_buy,
+            false,
+            self.time_in_force,
+        );
+        if self.dry_run {
+            let (buys, sells) = self.simulate_orders(&[order]);
+            self.manual_orders.extend(buys.into_iter().chain(sells));
+            return Ok(());
+        }
+
+        let order = self
+            .client
+            .place_order(symbol, price, qty, is_buy, false, self.time_in_force)
+            .await?;
+        self.manual_orders.push_back(order);
+        self.logger.info(&format!(
+            "Manual {} order for {}: {} @ {}",
+            if is_buy { "BUY" } else { "SELL" },
+            symbol,
+            qty,
+            price
+        ));
+        Ok(())
+    }
+
+    /// Cancels a single order, whether or not the generator placed it.
+    pub async fn cancel_manual(
+        &mut self,
+        symbol: &str,
+        order_id: &str,
+    ) -> std::result::Result<(), String> {
+        if self.dry_run {
+            self.logger
+                .info(&format!("Dry run: cancelling order {}", order_id));
+        } else {
+            self.client.cancel_order(order_id, symbol).await?;
+        }
+        self.live_buys.retain(|o| o.order_id != order_id);
+        self.live_sells.retain(|o| o.order_id != order_id);
+        self.manual_orders.retain(|o| o.order_id != order_id);
+        Ok(())
+    }
+
+    /// Cancels every order of `symbol` regardless of the cancel budget and closes the
+    /// position with a reduce-only IOC order at the touch of `book`.
+    ///
+    /// The grid is quoted again on the next update, pause the symbol to stay flat.
+    pub async fn flatten(
+        &mut self,
+        symbol: &str,
+        book: &BybitBook,
+    ) -> std::result::Result<(), String> {
+        self.logger
+            .warning(&format!("Flattening {} on request", symbol));
+        self.cancel_all(symbol).await?;
+        if !self.flatten_position(symbol, book).await {
+            return Err(format!("Failed to flatten position for {}", symbol));
+        }
+        Ok(())
+    }
+
+    /// Sends `amends` in batches within the request budgets and moves the live orders they
+    /// re-priced. Returns how many orders were amended.
+    async fn send_amends(&mut self, book: &BybitBook, amends: Vec<BatchAmend>) -> usize {
+        let mut amended = 0;
+        let (buy_amends, sell_amends): (Vec<_>, Vec<_>) = amends.into_iter().partition(|a| a.4);
+        for (is_buy, amends) in [(true, buy_amends), (false, sell_amends)] {
+            for chunk in amends.chunks(ORDER_CHUNK_SIZE) {
+                if !self.take_request(is_buy) {
+                    break;
+                }
+                let amends = if self.dry_run {
+                    Ok(self.simulate_amends(chunk))
+                } else {
+                    self.client.batch_amends(chunk.to_vec()).await
+                };
+                match amends {
+                    Ok((amends, rejected)) => {
+                        amended += self.apply_amends(book, amends);
+                        self.forget_rejected_amends(rejected).await;
+                    }
+                    Err(_) => {
+                        self.logger.error("Failed to send batch amends");
+                    }
+                }
+            }
+        }
+        self.live_buys = sort_grid(&mut self.live_buys, -1);
+        self.live_sells = sort_grid(&mut self.live_sells, 1);
+        amended
+    }
+
+    /// Moves the live orders amended under the ids of `amends` to their new price and size,
+    /// an order re-placed by the amend takes over the new id. Returns how many were found.
+    fn apply_amends(&mut self, book: &BybitBook, amends: Vec<(String, LiveOrder)>) -> usize {
+        let mut amended = 0;
+        for (order_id, amend) in amends {
+            let Some(order) = self
+                .live_buys
+                .iter_mut()
+                .chain(self.live_sells.iter_mut())
+                .find(|o| o.order_id == order_id)
+            else {
+                continue;
+            };
+            if amend.order_id != order.order_id {
+                // Nothing of the replacing order is filled yet, and it has the flags it was
+                // placed with
+                order.order_id = amend.order_id;
+                order.filled_qty = 0.0;
+                order.reduce_only = amend.reduce_only;
+                order.time_in_force = amend.time_in_force;
+            }
+            order.price = amend.price;
+            // Amends set the total qty, the filled part stays filled
+            order.qty = amend.qty - order.filled_qty;
+            order.created_at = amend.created_at;
+            order.placement_mid = book.mid_price;
+            self.stats.on_quotes(1);
+            amended += 1;
+        }
+        amended
+    }
+
+    /// Cancels and forgets the orders of the `rejected` amends, which either rest at a price
+    /// the grid moved away from or are gone already, e.g. filled before the amend. A cancel
+    /// failing means the latter, so the order is forgotten either way. Orders the cancel
+    /// budget can't cover stay at their old price for the next grid to move.
+    async fn forget_rejected_amends(&mut self, rejected: Vec<RejectedAmend>) {
+        for rejection in rejected {
+            let BatchAmend(symbol, _, _, order_id, is_buy, ..) = &rejection.amend;
+            if *self.cancel_limit_mut(*is_buy) <= MIN_CANCEL_LIMIT || !self.take_shared_request() {
+                continue;
+            }
+            *self.cancel_limit_mut(*is_buy) -= 1;
+            self.logger.warning(&format!(
+                "Amend of order {} rejected ({}): {}, cancelling it",
+                order_id, rejection.code, rejection.reason
+            ));
+            if !self.dry_run && self.client.cancel_order(order_id, symbol).await.is_err() {
+                self.logger
+                    .info(&format!("Order {} was already gone", order_id));
+            }
+            self.live_buys.retain(|o| &o.order_id != order_id);
+            self.live_sells.retain(|o| &o.order_id != order_id);
+        }
+    }
+
+    /// Moves the live orders onto a freshly generated grid with as few requests as
+    /// possible: levels that moved are amended, live orders outside the new grid are
+    /// cancelled and only the missing levels are placed.
+    async fn amend_grid(
+        &mut self,
+        symbol: &str,
+        book: &BybitBook,
+        orders: Vec<BatchOrder>,
+    ) -> bool {
+        let (bids, asks): (Vec<_>, Vec<_>) = orders.into_iter().partition(|o| o.3);
+        let now = generate_timestamp().unwrap_or_default();
+        let expired = |order: &LiveOrder| self.is_expired(order, now);
+        let mut diff = diff_grid(book, &self.live_buys, bids, expired);
+        diff.extend(diff_grid(book, &self.live_sells, asks, expired));
+
+        let mut cancelled = 0;
+        for order_id in &diff.cancels {
+            if self.cancel_grid_order(symbol, order_id).await {
+                cancelled += 1;
+            }
+        }
+        // A level is only placed again once the order it replaces is gone, or both would
+        // rest. The others stay as they are for the next grid.
+        let replace_count = diff.replaces.len();
+        let mut replaced = 0;
+        for (order_id, order) in diff.replaces {
+            if self.cancel_grid_order(symbol, &order_id).await {
+                cancelled += 1;
+                replaced += 1;
+                diff.places.push(order);
+            }
+        }
+
+        let amend_count = diff.amends.len();
+        let amended = self.send_amends(book, diff.amends).await;
+
+        let placed = diff.places.len();
+        let sent = diff.places.is_empty() || self.send_batch_orders(book, diff.places).await;
+        self.amends += 1;
+        self.logger.info(&format!(
+            "Amended {} orders for {}, cancelled {}, placed {} ({} amends, {} full re-quotes so far)",
+            amended, symbol, cancelled, placed, self.amends, self.requotes
+        ));
+        sent && amended == amend_count && replaced == replace_count
+    }
+
+    /// Cancels a live order of the grid within the cancel budgets and forgets it, `false`
+    /// when the budgets are spent or the cancel failed.
+    async fn cancel_grid_order(&mut self, symbol: &str, order_id: &str) -> bool {
+        let is_buy = self.live_buys.iter().any(|o| o.order_id == order_id);
+        if *self.cancel_limit_mut(is_buy) <= MIN_CANCEL_LIMIT || !self.take_shared_request() {
+            return false;
+        }
+        *self.cancel_limit_mut(is_buy) -= 1;
+        let cancel_ok = if self.dry_run {
+            self.logger
+                .info(&format!("Dry run: cancelling order {}", order_id));
+            true
+        } else {
+            self.client.cancel_order(order_id, symbol).await.is_ok()
+        };
+        if !cancel_ok {
+            self.logger
+                .error(&format!("Failed to cancel order {}", order_id));
+            return false;
+        }
+        self.live_buys.retain(|o| o.order_id != order_id);
+        self.live_sells.retain(|o| o.order_id != order_id);
+        true
+    }
+
+    /// Moves the grid onto `book`, `private` being the private stream data of the
+    /// generator's venue.
+    pub async fn update_grid(
+        &mut self,
+        private: impl Into<PrivateData>,
+        skew: f64,
+        book: BybitBook,
+        symbol: String,
+        volatility: f64,
+        fair_value: f64,
+    ) {
+        let private = private.into();
+        self.vol_adjusted_bounds(&book, volatility);
+
+        if self.time_limit > 1 && book.last_update.saturating_sub(self.time_limit) > 1000 {
+            (self.buy_rate_limit, self.sell_rate_limit) = split_limit(self.initial_limit);
+            (self.buy_cancel_limit, self.sell_cancel_limit) = split_limit(self.initial_limit);
+        }
+
+        // Nothing rests through a price shock, not even the orders of a paused symbol. The
+        // first update after the trip cancels everything, including orders we lost track of,
+        // and whatever rests later goes as well. Neither waits for the budgets.
+        if self.circuit_breaker_until.is_some() {
+            let resting = !self.live_buys.is_empty() || !self.live_sells.is_empty();
+            if (resting || !self.circuit_breaker_pulled)
+                && !self.log_intended_quotes
+                && self.pull_quotes(&symbol).await
+            {
+                self.circuit_breaker_pulled = true;
+                self.persist_state(&symbol);
+            }
+            return;
+        }
+
+        if self.paused
+            || self.stale_data
+            || (self.log_intended_quotes && !self.intended_quotes_due(&book))
+        {
+            return;
+        }
+
+        // A crossed book's mid is garbage, wait for it to be rebuilt
+        if book.is_crossed() != self.book_crossed {
+            self.book_crossed = book.is_crossed();
+            if self.book_crossed {
+                self.logger
+                    .warning(&format!("Book for {} is crossed, not quoting", symbol));
+            } else {
+                self.logger
+                    .info(&format!("Book for {} recovered, quoting", symbol));
+            }
+        }
+        if self.book_crossed {
+            return;
+        }
+
+        if self.check_kill_switch(&symbol, &book).await {
+            self.persist_state(&symbol);
+            return;
+        }
+
+        if self.places_orders() {
+            if !self.sync_session(&symbol, private.session()).await {
+                return;
+            }
+            self.resend_post_only_cancels(&symbol, &book, private.post_only_cancels())
+                .await;
+        }
+
+        let mut requote = self.out_of_bounds(&book, private);
+        if !requote && self.trail_drift_bps > 0.0 && !self.log_intended_quotes {
+            // A trail the amends can't complete falls back to a re-quote
+            requote = !self.trail_grid(&symbol, &book).await;
+            self.persist_state(&symbol);
+        }
+        if requote {
+            self.set_inventory_delta(book.get_mid_price());
+            match self.generate_quotes(&symbol, &book, skew, volatility, fair_value) {
+                Ok(orders) => {
+                    if self.log_intended_quotes {
+                        self.log_intended_orders(&symbol, &orders);
+                    } else if self.buy_rate_limit > 1 || self.sell_rate_limit > 1 {
+                        let order_len = orders.len();
+                        let sent = if self.live_buys.is_empty() && self.live_sells.is_empty() {
+                            self.requotes += 1;
+                            self.send_batch_orders(&book, orders).await
+                        } else {
+                            self.amend_grid(&symbol, &book, orders).await
+                        };
+
+                        if sent {
+                            self.last_update_price = book.mid_price;
+                            self.post_only_resent = 0;
+                            self.logger.info(&format!(
+                                "Generated {} orders for {} at {} Position: {:#?} Skew: {:#?}",
+                                order_len,
+                                symbol,
+                                round_price(&book, book.get_mid_price()),
+                                self.position_qty,
+                                skew,
+                            ));
+                        }
+                    }
+                    self.time_limit = book.last_update;
+                }
+                Err(_) => {
+                    self.logger.error(&format!(
+                        "Failed to generate quotes for {}, inventory delta: {}",
+                        symbol, self.inventory_delta
+                    ));
+                    // Don't leave the out of bounds orders resting
+                    if self.cancel_live_orders(&symbol).await {
+                        self.last_update_price = book.mid_price;
+                    }
+                }
+            }
+            self.persist_state(&symbol);
+        }
+    }
+
+    /// Returns `true` when a grid that was only logged should be logged again,
+    /// i.e. on the first pass, once the mid leaves the bounds or the grid goes stale.
+    fn intended_quotes_due(&self, book: &BybitBook) -> bool {
+        self.time_limit == 0 || self.grid_outdated(book)
+    }
+
+    /// Logs every order of the grid (side, size and price) without sending anything.
+    fn log_intended_orders(&self, symbol: &str, orders: &[BatchOrder]) -> String {
+        let mut msg = format!("Intended grid for {} ({} orders):", symbol, orders.len());
+        for order in orders {
+            msg.push_str(&format!(
+                "\n{} {} @ {}",
+                if order.3 { "BUY" } else { "SELL" },
+                order.2,
+                order.1
+            ));
+        }
+        self.logger.info(&msg)
+    }
+}
+
+/// Avellaneda-Stoikov reservation price and optimal half spread:
+///
+/// `r = mid - q * gamma * sigma^2 * (T - t)`
+/// `half_spread = (gamma * sigma^2 * (T - t) + (2 / gamma) * ln(1 + gamma / k)) / 2`
+///
+/// with `q` the signed inventory, `sigma` the volatility in price units and `horizon` the
+/// remaining time `T - t` in the same unit as `sigma`. Returns `(r, half_spread)`.
+pub fn avellaneda_stoikov(
+    mid: f64,
+    q: f64,
+    sigma: f64,
+    horizon: f64,
+    gamma: f64,
+    k: f64,
+) -> (f64, f64) {
+    let risk = gamma * sigma.powi(2) * horizon;
+    let reservation = mid - q * risk;
+    let half_spread = (risk + (2.0 / gamma) * (1.0 + gamma / k).ln()) / 2.0;
+    (reservation, half_spread)
+}
+
+/// Coin the linear contract `symbol` settles in, USDC for USDC perpetuals and USDT otherwise.
+pub fn settle_coin(symbol: &str) -> &'static str {
+    if symbol.ends_with("USDC") || symbol.ends_with("PERP") {
+        "USDC"
+    } else {
+        "USDT"
+    }
+}
+
+/// Splits a request limit into the (bids, asks) budgets. The sides share the exchange's
+/// limit, so together they never get more than `limit`, the odd request going to the bids.
+fn split_limit(limit: usize) -> (usize, usize) {
+    (limit - limit / 2, limit / 2)
+}
+
+fn bps_to_decimal(bps: f64) -> f64 {
+    bps * 0.0001
+}
+
+fn round_price(book: &BybitBook, price: f64) -> f64 {
+    price.round_to(book.tick_size.count_decimal_places() as u8)
+}
+
+/// Rounds `price` to the tick size on the passive side, bids down and asks up, so the
+/// rounding never moves a quote towards the other side of the book.
+fn round_price_passive(book: &BybitBook, price: f64, is_buy: bool) -> f64 {
+    if book.tick_size <= 0.0 {
+        return round_price(book, price);
+    }
+    let ticks = price / book.tick_size;
+    // Leaves room for the float error of prices already on a tick
+    let ticks = if is_buy {
+        (ticks + 1e-9).floor()
+    } else {
+        (ticks - 1e-9).ceil()
+    };
+    round_price(book, ticks * book.tick_size)
+}
+
+/// The post-only rejections among `rejected`, re-priced with `behind_touch`.
+fn retry_post_only(book: &BybitBook, rejected: &[RejectedOrder]) -> Vec<BatchOrder> {
+    rejected
+        .iter()
+        .filter(|rejection| rejection.is_post_only())
+        .map(|rejection| {
+            let mut order = rejection.order.clone();
+            order.1 = behind_touch(book, order.1, order.3);
+            order
+        })
+        .filter(|order| order.1 > 0.0)
+        .collect()
+}
+
+/// The price of a post-only order that crossed the book, moved a tick behind the touch it
+/// crossed: a bid goes a tick below the lower of its price and the best ask, an ask a tick
+/// above the higher of its price and the best bid. Even on a book lagging the exchange's,
+/// every retry moves a tick further away.
+fn behind_touch(book: &BybitBook, price: f64, is_buy: bool) -> f64 {
+    if is_buy {
+        let touch = if book.best_ask.price > 0.0 {
+            price.min(book.best_ask.price)
+        } else {
+            price
+        };
+        round_price(book, touch - book.tick_size)
+    } else {
+        round_price(book, price.max(book.best_bid.price) + book.tick_size)
+    }
+}
+
+/// Rounds `qty` to the lot size. A positive size that rounds below the exchange's min qty
+/// becomes the smallest tradable size (the min qty or one lot) when `floor_to_lot` is set,
+/// otherwise it becomes zero and the level gets dropped rather than rejected.
+fn round_size(qty: f64, book: &BybitBook, floor_to_lot: bool) -> f64 {
+    let size = round_step(qty, book.lot_size);
+    let min_size = book.min_qty.max(book.lot_size);
+    // Leaves room for the float error of the rounding
+    if size > 0.0 && size >= min_size * (1.0 - 1e-9) {
+        size
+    } else if floor_to_lot && qty > 0.0 {
+        min_size
+    } else {
+        0.0
+    }
+}
+
+/// Requests that move the live orders of one side onto a new grid.
+#[derive(Debug, Default)]
+struct GridDiff {
+    amends: Vec<BatchAmend>,
+    cancels: Vec<String>,
+    places: Vec<BatchOrder>,
+    // live orders to cancel with the level replacing each, placed once the cancel went through
+    replaces: Vec<(String, BatchOrder)>,
+}
+
+impl GridDiff {
+    fn extend(&mut self, other: GridDiff) {
+        self.amends.extend(other.amends);
+        self.cancels.extend(other.cancels);
+        self.places.extend(other.places);
+        self.replaces.extend(other.replaces);
+    }
+}
+
+/// Pairs the new orders of one side with the live ones, best price first. A pair is
+/// amended when its price or qty moved by at least half a tick or half a lot, live orders
+/// left over fall outside the new grid and are cancelled, new ones left over are placed.
+///
+/// An amend only changes the price and qty, so a live order whose reduce-only flag or time
+/// in force differs from its new level's is replaced: cancelled, and the level placed once
+/// the cancel went through. So is a live order that didn't move but is `expired`, since
+/// amending it to its own price would be rejected.
+///
+/// Exchanges take the total qty of an order in an amend, so a partially filled order is
+/// amended to its new resting qty plus what it already filled.
+fn diff_grid(
+    book: &BybitBook,
+    live: &VecDeque<LiveOrder>,
+    mut orders: Vec<BatchOrder>,
+    expired: impl Fn(&LiveOrder) -> bool,
+) -> GridDiff {
+    let is_buy = orders.iter().all(|o| o.3);
+    orders.sort_by(|a, b| {
+        if is_buy {
+            b.1.total_cmp(&a.1)
+        } else {
+            a.1.total_cmp(&b.1)
+        }
+    });
+
+    let mut diff = GridDiff::default();
+    let mut orders = orders.into_iter();
+    for live_order in live {
+        match orders.next() {
+            Some(order) => {
+                let moved = (order.1 - live_order.price).abs() >= book.tick_size / 2.0
+                    || (order.2 - live_order.qty).abs() >= book.lot_size / 2.0;
+                let same_flags =
+                    live_order.reduce_only == order.4 && live_order.time_in_force == order.5;
+                if !same_flags {
+                    diff.replaces.push((live_order.order_id.clone(), order));
+                } else if moved {
+                    diff.amends
+                        .push(BatchAmend::of_order(order.0, live_order, order.1, order.2));
+                } else if expired(live_order) {
+                    diff.replaces.push((live_order.order_id.clone(), order));
+                }
+            }
+            None => diff.cancels.push(live_order.order_id.clone()),
+        }
+    }
+    diff.places.extend(orders);
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use skeleton::utils::{
+        latency::MIN_LATENCY_REQUESTS,
+        logger::LogLevel,
+        models::{BybitClient, BybitPrivate},
+        persistence::FilePersistence,
+    };
+    use std::time::Duration;
+
+    fn test_generator() -> QuoteGenerator {
+        let logger = Logger::capturing();
+        let client = BybitClient::new(String::new(), String::new(), logger.clone());
+        QuoteGenerator::with_logger(client, logger, 1000.0, 1.0, 4, 10, 20).unwrap()
+    }
+
+    fn test_book() -> BybitBook {
+        let mut book = BybitBook::new();
+        book.best_bid.price = 99.99;
+        book.best_bid.qty = 10.0;
+        book.best_ask.price = 100.01;
+        book.best_ask.qty = 10.0;
+        book.set_mid_price();
+        book.tick_size = 0.01;
+        book.lot_size = 0.1;
+        book.min_notional = 5.0;
+        book.min_qty = 0.1;
+        book.post_only_max = 1000.0;
+        book.last_update = 1_700_000_000_000;
+        book
+    }
+
+    #[tokio::test]
+    async fn test_log_intended_quotes() {
+        let mut generator = test_generator();
+        generator.set_log_intended_quotes(true);
+        let book = test_book();
+
+        generator
+            .update_grid(
+                BybitPrivate::default(),
+                0.0,
+                book.clone(),
+                "SOLUSDT".to_string(),
+                0.0,
+                book.get_mid_price(),
+            )
+            .await;
+
+        // Nothing was sent: no live orders and the rate limit is untouched
+        assert!(generator.live_buys.is_empty());
+        assert!(generator.live_sells.is_empty());
+        assert_eq!(generator.buy_rate_limit, 10);
+        assert_eq!(generator.sell_rate_limit, 10);
+        assert_eq!(generator.time_limit, book.last_update);
+
+        // The logged grid is the one that would have been sent, one order per line
+        let orders = test_generator()
+            .generate_quotes("SOLUSDT", &book, 0.0, 0.0, book.get_mid_price())
+            .unwrap();
+        assert_eq!(orders.len(), 8);
+        let captured = generator.logger.captured();
+        let (_, logged) = captured
+            .iter()
+            .find(|(_, msg)| msg.starts_with("Intended grid"))
+            .unwrap();
+        let mut lines = logged.lines();
+        assert_eq!(lines.next(), Some("Intended grid for SOLUSDT (8 orders):"));
+        let lines: Vec<&str> = lines.collect();
+        assert_eq!(lines.len(), orders.len());
+        for (line, order) in lines.iter().zip(&orders) {
+            let (side, rest) = line.split_once(' ').unwrap();
+            let (size, price) = rest.split_once(" @ ").unwrap();
+            let (size, price): (f64, f64) = (size.parse().unwrap(), price.parse().unwrap());
+            assert_eq!(side, if order.3 { "BUY" } else { "SELL" });
+            assert_eq!((size, price), (order.2, order.1));
+
+            // Whole lots on the book's ticks, none crossing the book
+            assert!(size >= book.min_qty);
+            assert!(((size / book.lot_size).round() * book.lot_size - size).abs() < 1e-9);
+            assert!(((price / book.tick_size).round() * book.tick_size - price).abs() < 1e-9);
+            if order.3 {
+                assert!(price < book.best_ask.price);
+            } else {
+                assert!(price > book.best_bid.price);
+            }
+        }
+
+        // Logged again only once the grid would have been re-quoted
+        let intended = |gen: &QuoteGenerator| {
+            gen.logger
+                .captured()
+                .iter()
+                .filter(|(_, msg)| msg.starts_with("Intended grid"))
+                .count()
+        };
+        let mut book = book;
+        move_mid(&mut book, 0.01);
+        quote(&mut generator, &book).await;
+        assert_eq!(intended(&generator), 1);
+        move_mid(&mut book, 1.0);
+        quote(&mut generator, &book).await;
+        assert_eq!(intended(&generator), 2);
+    }
+
+    #[tokio::test]
+    async fn test_crossed_book_does_not_quote() {
+        let mut generator = test_generator();
+        generator.set_log_intended_quotes(true);
+        let mut book = test_book();
+        book.best_bid.price = 100.02;
+
+        generator
+            .update_grid(
+                BybitPrivate::default(),
+                0.0,
+                book.clone(),
+                "SOLUSDT".to_string(),
+                0.0,
+                book.get_mid_price(),
+            )
+            .await;
+        assert_eq!(generator.time_limit, 0);
+
+        let book = test_book();
+        generator
+            .update_grid(
+                BybitPrivate::default(),
+                0.0,
+                book.clone(),
+                "SOLUSDT".to_string(),
+                0.0,
+                book.get_mid_price(),
+            )
+            .await;
+        assert_eq!(generator.time_limit, book.last_update);
+        let captured = generator.logger.captured();
+        assert_eq!(
+            captured[..2],
+            [
+                (
+                    LogLevel::Warning,
+                    "Book for SOLUSDT is crossed, not quoting".to_string()
+                ),
+                (
+                    LogLevel::Info,
+                    "Book for SOLUSDT recovered, quoting".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_simulates_orders_and_fills() {
+        let mut generator = test_generator();
+        generator.set_dry_run(true);
+        let mut book = test_book();
+
+        generator
+            .update_grid(
+                BybitPrivate::default(),
+                0.0,
+                book.clone(),
+                "SOLUSDT".to_string(),
+                0.0,
+                book.get_mid_price(),
+            )
+            .await;
+        assert!(!generator.live_buys.is_empty() && !generator.live_sells.is_empty());
+        assert!(generator
+            .live_buys
+            .iter()
+            .chain(generator.live_sells.iter())
+            .all(|o| o.order_id.starts_with("dry-run-")));
+
+        // The mid drops through the best bid, which fills locally
+        let filled = generator.live_buys[0].clone();
+        book.best_bid.price = filled.price - 0.02;
+        book.best_ask.price = filled.price;
+        book.set_mid_price();
+        book.last_update += 1000;
+        generator
+            .update_grid(
+                BybitPrivate::default(),
+                0.0,
+                book.clone(),
+                "SOLUSDT".to_string(),
+                0.0,
+                book.get_mid_price(),
+            )
+            .await;
+        assert!((generator.position_qty - filled.qty).abs() < 1e-9);
+        assert!(generator
+            .live_buys
+            .iter()
+            .all(|o| o.order_id != filled.order_id));
+        assert!(generator
+            .logger
+            .captured()
+            .iter()
+            .any(|(_, msg)| msg.starts_with(&format!("Buy fill: {:.2}", filled.qty))));
+    }
+
+    #[tokio::test]
+    async fn test_quote_stats_track_fills_against_the_placement_mid() {
+        let mut generator = test_generator();
+        generator.set_dry_run(true);
+        let mut book = test_book();
+        quote(&mut generator, &book).await;
+        let placed = generator.live_buys.len() + generator.live_sells.len();
+        assert_eq!(generator.stats().quotes, placed as u64);
+        assert!(generator
+            .live_buys
+            .iter()
+            .chain(generator.live_sells.iter())
+            .all(|o| o.placement_mid == book.mid_price));
+
+        // The best bid fills once the mid drops through it
+        let filled = generator.live_buys[0].clone();
+        let placement_mid = book.mid_price;
+        book.best_bid.price = filled.price - 0.02;
+        book.best_ask.price = filled.price;
+        book.set_mid_price();
+        book.last_update += 1000;
+        quote(&mut generator, &book).await;
+
+        let stats = generator.stats();
+        assert_eq!((stats.fills, stats.filled_quotes), (1, 1));
+        assert!(stats.avg_time_to_fill_ms().is_some());
+        let capture = (placement_mid - filled.price) * filled.qty;
+        assert!((stats.captured() - capture).abs() < 1e-9);
+        let bps = (placement_mid - filled.price) / placement_mid * 10_000.0;
+        assert!((stats.avg_capture_bps().unwrap() - bps).abs() < 1e-9);
+    }
+
+    fn move_mid(book: &mut BybitBook, by: f64) {
+        book.best_bid.price += by;
+        book.best_ask.price += by;
+        book.set_mid_price();
+        book.last_update += 100;
+    }
+
+    #[tokio::test]
+    async fn test_trailing_shifts_the_grid_on_slow_drifts() {
+        let mut generator = test_generator();
+        generator.set_dry_run(true);
+        generator.set_trailing(5.0, 2);
+        let mut book = test_book();
+        quote(&mut generator, &book).await;
+        let prices = |gen: &QuoteGenerator| -> Vec<f64> {
+            gen.live_buys
+                .iter()
+                .chain(gen.live_sells.iter())
+                .map(|o| o.price)
+                .collect()
+        };
+        let grid = prices(&generator);
+
+        // A tick of drift is left alone
+        move_mid(&mut book, -0.01);
+        quote(&mut generator, &book).await;
+        assert_eq!(prices(&generator), grid);
+
+        // Three ticks down move every order three ticks down
+        move_mid(&mut book, -0.02);
+        quote(&mut generator, &book).await;
+        let trailed = prices(&generator);
+        assert_eq!(trailed.len(), grid.len());
+        assert!(grid
+            .iter()
+            .zip(trailed.iter())
+            .all(|(old, new)| (old - new - 0.03).abs() < 1e-9));
+        // The bounds follow the grid
+        assert!((generator.last_update_price - book.mid_price).abs() < 1e-9);
+        let trail = format!("Trailed {} orders for SOLUSDT by -3 ticks", grid.len());
+        assert!(generator
+            .logger
+            .captured()
+            .iter()
+            .any(|(_, msg)| msg.starts_with(&trail)));
+
+        // A trail that can't move every order leaves the center for a re-quote
+        let center = generator.last_update_price;
+        generator.buy_rate_limit = 0;
+        move_mid(&mut book, -0.03);
+        quote(&mut generator, &book).await;
+        assert_eq!(generator.last_update_price, center);
+        assert!(generator
+            .logger
+            .captured()
+            .iter()
+            .any(|(_, msg)| msg.starts_with("Trailed only")));
+        generator.buy_rate_limit = 10;
+
+        // So does an order with flags its side isn't quoted with anymore
+        quote(&mut generator, &book).await;
+        let center = generator.last_update_price;
+        generator.live_sells[0].reduce_only = true;
+        let trails = generator.trails;
+        move_mid(&mut book, -0.03);
+        quote(&mut generator, &book).await;
+        assert_eq!(generator.trails, trails);
+        assert!(generator.live_sells.iter().all(|o| !o.reduce_only));
+        assert!(generator.last_update_price < center);
+
+        // A jump within the bounds still re-quotes
+        move_mid(&mut book, -0.1);
+        quote(&mut generator, &book).await;
+        assert!(generator
+            .logger
+            .captured()
+            .iter()
+            .any(|(_, msg)| msg.starts_with("Amended")));
+    }
+
+    async fn quote(generator: &mut QuoteGenerator, book: &BybitBook) {
+        generator
+            .update_grid(
+                BybitPrivate::default(),
+                0.0,
+                book.clone(),
+                "SOLUSDT".to_string(),
+                0.0,
+                book.get_mid_price(),
+            )
+            .await;
+    }
+
+    #[tokio::test]
+    async fn test_drawdown_kill_switch() {
+        let mut generator = test_generator();
+        generator.set_dry_run(true);
+        generator.set_max_drawdown_pct(Some(1.0));
+        generator.set_flatten_on_kill_switch(true);
+        // Protective cancels don't wait for the budgets
+        generator.buy_cancel_limit = 0;
+        generator.set_rate_limiter(Some(SharedRateLimiter::new(1)));
+        assert!(generator.take_shared_request());
+        generator
+            .live_buys
+            .push_back(LiveOrder::new("buy".to_string(), 100.0, 20.0, true));
+        generator
+            .live_sells
+            .push_back(LiveOrder::new("sell".to_string(), 101.0, 1.0, false));
+        let mut fill = test_fill(0);
+        fill.order_id = "buy".to_string();
+        fill.qty = 20.0;
+        generator.apply_fill(&fill);
+
+        // A book cleared while crossed has no mid to value the position at
+        let mut cleared = test_book();
+        cleared.best_bid.price = 0.0;
+        cleared.best_ask.price = 0.0;
+        cleared.mid_price = 0.0;
+        cleared.needs_snapshot = true;
+        generator.peak_equity = generator.equity(100.0);
+        quote(&mut generator, &cleared).await;
+        assert!(!generator.is_kill_switch_tripped());
+
+        // Down 20 on 1000 of equity, past the 1% limit
+        let mut book = test_book();
+        book.best_bid.price = 98.99;
+        book.best_ask.price = 99.01;
+        book.set_mid_price();
+        quote(&mut generator, &book).await;
+        assert!(generator.is_kill_switch_tripped());
+        assert!(generator.risk_state().kill_switch);
+        assert!(generator.live_sells.is_empty());
+        assert_eq!(generator.position_qty, 0.0);
+        assert_eq!(generator.time_limit, 0);
+        assert!(generator.logger.captured().iter().any(|(level, msg)| {
+            *level == LogLevel::Critical && msg.starts_with("Kill switch tripped for SOLUSDT")
+        }));
+
+        // Stays halted until an operator resets it
+        quote(&mut generator, &book).await;
+        assert_eq!(generator.time_limit, 0);
+        generator.reset_kill_switch();
+        quote(&mut generator, &book).await;
+        assert!(!generator.is_kill_switch_tripped());
+        assert_eq!(generator.time_limit, book.last_update);
+    }
+
+    #[test]
+    fn test_unknown_min_notional_refuses_to_quote() {
+        let mut generator = test_generator();
+        let mut book = test_book();
+        // `parse_float` fallback when the symbol info failed to parse
+        book.min_notional = 0.0;
+
+        let orders = generator.generate_skew_orders("SOLUSDT", 0.05, 0.0, &book, 100.0, true);
+        assert!(orders.is_empty());
+        // Warned about once while it stays unknown, and noted once it's known again
+        generator.generate_skew_orders("SOLUSDT", 0.05, 0.0, &book, 100.0, true);
+        book.min_notional = 5.0;
+        generator.generate_skew_orders("SOLUSDT", 0.05, 0.0, &book, 100.0, true);
+        generator.generate_skew_orders("SOLUSDT", 0.05, 0.0, &book, 100.0, true);
+        assert_eq!(
+            generator.logger.captured(),
+            vec![
+                (
+                    LogLevel::Warning,
+                    "Unknown min notional for SOLUSDT, not quoting".to_string()
+                ),
+                (
+                    LogLevel::Info,
+                    "Min notional for SOLUSDT known again: 5".to_string()
+                )
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unknown_min_notional_uses_fallback() {
+        let mut generator = test_generator();
+        generator.set_fallback_min_notional(50.0);
+        let mut book = test_book();
+        book.min_notional = 0.0;
+
+        let orders = generator.generate_skew_orders("SOLUSDT", 0.05, 0.0, &book, 100.0, true);
+        assert!(!orders.is_empty());
+        assert!(orders.iter().all(|order| order.1 * order.2 >= 50.0));
+    }
+
+    #[test]
+    fn test_sizes_rounding_to_zero() {
+        let mut generator = test_generator();
+        let mut book = test_book();
+        // Every level is worth less than half a lot
+        book.lot_size = 100.0;
+        book.min_notional = 0.5;
+
+        let orders = generator.generate_skew_orders("SOLUSDT", 0.05, 0.0, &book, 100.0, true);
+        assert!(orders.is_empty());
+
+        generator.set_floor_size_to_lot(true);
+        let orders = generator.generate_skew_orders("SOLUSDT", 0.05, 0.0, &book, 100.0, true);
+        assert_eq!(orders.len(), 8);
+        assert!(orders.iter().all(|order| order.2 == book.lot_size));
+    }
+
+    #[test]
+    fn test_sizes_below_min_qty() {
+        let mut generator = test_generator();
+        let mut book = test_book();
+        // Every level clears the lot size and min notional but not the min qty
+        book.min_qty = 50.0;
+        book.min_notional = 0.5;
+
+        let orders = generator.generate_skew_orders("SOLUSDT", 0.05, 0.0, &book, 100.0, true);
+        assert!(orders.is_empty());
+
+        generator.set_floor_size_to_lot(true);
+        let orders = generator.generate_skew_orders("SOLUSDT", 0.05, 0.0, &book, 100.0, true);
+        assert_eq!(orders.len(), 8);
+        assert!(orders.iter().all(|order| order.2 >= book.min_qty));
+    }
+
+    fn test_fill(seq: u64) -> Fill {
+        Fill {
+            order_id: format!("order-{}", seq),
+            exec_id: format!("exec-{}", seq),
+            price: 100.0,
+            qty: 1.0,
+            is_buy: true,
+            time: 1_700_000_000_000 + seq,
+            seq,
+        }
+    }
+
+    #[test]
+    fn test_only_new_fills_are_examined() {
+        let mut generator = test_generator();
+        let mut executions: VecDeque<Fill> = (0..500).map(test_fill).collect();
+        assert_eq!(
+            generator
+                .take_new_fills(executions.iter().rev().cloned())
+                .len(),
+            500
+        );
+
+        executions.pop_front();
+        executions.push_back(test_fill(500));
+        let mut examined = 0;
+        let fills =
+            generator.take_new_fills(executions.iter().rev().inspect(|_| examined += 1).cloned());
+        assert_eq!(fills, vec![test_fill(500)]);
+        // The new fill, the last processed one and the older one the scan stops at
+        assert_eq!(examined, 3);
+        assert!(generator
+            .take_new_fills(executions.iter().rev().cloned())
+            .is_empty());
+    }
+
+    #[test]
+    fn test_degenerate_inventory_is_sanitized() {
+        let mut generator = test_generator();
+        let book = test_book();
+
+        generator.position_qty = 1.0;
+        generator.set_inventory_delta(f64::NAN);
+        assert_eq!(generator.inventory_delta, 0.0);
+        assert_eq!(generator.logger.captured()[0].0, LogLevel::Warning);
+        let orders = generator
+            .generate_quotes("SOLUSDT", &book, 0.0, 0.0, book.get_mid_price())
+            .unwrap();
+        assert!(!orders.is_empty());
+
+        // A zero max position saturates the delta instead of dividing by zero
+        generator.max_position_usd = QuoteGenerator::max_position_usd(0.0, 10.0, SAFETY_FACTOR);
+        generator.set_inventory_delta(book.get_mid_price());
+        assert_eq!(generator.inventory_delta, 1.0);
+        assert!(generator
+            .generate_quotes("SOLUSDT", &book, 0.0, 0.0, book.get_mid_price())
+            .is_ok());
+        assert_eq!(
+            QuoteGenerator::max_position_usd(f64::NAN, 10.0, SAFETY_FACTOR),
+            0.0
+        );
+    }
+
+    #[test]
+    fn test_position_cap_follows_the_wallet() {
+        let mut generator = test_generator();
+        // Half of an account quoting two symbols with equal balances
+        generator.set_equity_share(0.5);
+        generator.set_wallet_equity(3000.0);
+        assert_eq!(generator.max_position_usd, 1500.0 * SAFETY_FACTOR);
+        // A reloaded leverage sizes from the live equity too
+        generator.set_leverage(2.0);
+        assert_eq!(generator.max_position_usd, 1500.0 * 2.0 * SAFETY_FACTOR);
+        // An empty wallet keeps the last cap
+        generator.set_wallet_equity(0.0);
+        assert_eq!(generator.max_position_usd, 1500.0 * 2.0 * SAFETY_FACTOR);
+        assert_eq!(settle_coin("BTCPERP"), "USDC");
+        assert_eq!(settle_coin("SOLUSDT"), "USDT");
+    }
+
+    #[test]
+    fn test_reloaded_leverage_and_orders_per_side() {
+        let mut generator = test_generator();
+        let book = test_book();
+        generator.set_leverage(2.0);
+        assert_eq!(
+            generator.risk_state().max_position_usd,
+            1000.0 * 2.0 * SAFETY_FACTOR
+        );
+        // A leverage leaving no room for a position keeps the previous cap
+        generator.set_leverage(0.0);
+        assert_eq!(
+            generator.risk_state().max_position_usd,
+            1000.0 * 2.0 * SAFETY_FACTOR
+        );
+
+        let mut levels = Vec::new();
+        for orders_per_side in [2, 6] {
+            generator.set_orders_per_side(orders_per_side);
+            let orders = generator
+                .generate_quotes("SOLUSDT", &book, 0.0, 0.0, book.get_mid_price())
+                .unwrap();
+            let bids = orders.iter().filter(|order| order.3).count();
+            levels.push((bids, orders.len() - bids));
+        }
+        // 6 per side is cut to the 5 levels the 2x leverage budget can give the min notional
+        assert_eq!(levels, vec![(2, 2), (5, 5)]);
+    }
+
+    #[derive(Debug)]
+    struct FixedPrice(f64);
+
+    impl FairValueSource for FixedPrice {
+        fn fair_value(&self, _book: &BybitBook, _features: &Engine) -> f64 {
+            self.0
+        }
+    }
+
+    #[test]
+    fn test_custom_fair_value_source() {
+        let mut generator = test_generator();
+        let book = test_book();
+        let features = Engine::new(10, None);
+        assert_eq!(generator.fair_value(&book, &features), book.get_mid_price());
+
+        generator.set_fair_value_source(Box::new(FixedPrice(120.0)));
+        let fair_value = generator.fair_value(&book, &features);
+        assert_eq!(fair_value, 120.0);
+
+        let orders = generator
+            .generate_quotes("SOLUSDT", &book, 0.0, 0.0, fair_value)
+            .unwrap();
+        let best_bid = orders
+            .iter()
+            .filter(|o| o.3)
+            .map(|o| o.1)
+            .fold(f64::MIN, f64::max);
+        let best_ask = orders
+            .iter()
+            .filter(|o| !o.3)
+            .map(|o| o.1)
+            .fold(f64::MAX, f64::min);
+        // The grid sits around 120 even though the book mid is 100
+        assert!((119.0..120.0).contains(&best_bid));
+        assert!((120.0..121.0).contains(&best_ask));
+    }
+
+    #[test]
+    fn test_dust_levels_are_truncated() {
+        let mut generator = test_generator();
+        let book = test_book();
+        let orders = generator.generate_skew_orders("SOLUSDT", 0.05, 0.0, &book, 100.0, true);
+        assert_eq!(orders.len(), 8);
+
+        // The 4th level holds ~3% of each side
+        generator.set_dust_fraction(0.05);
+        let orders = generator.generate_skew_orders("SOLUSDT", 0.05, 0.0, &book, 100.0, true);
+        assert_eq!(orders.len(), 6);
+        let bid_prices = geomspace(99.95 - 0.5, 99.95, 4);
+        let ask_prices = geomspace(100.0, 100.0 + 0.5, 4);
+        // Bids weigh more away from the touch while asks are reversed
+        assert!(orders
+            .iter()
+            .all(|o| o.1 != round_price_passive(&book, bid_prices[3], true)
+                && o.1 != round_price_passive(&book, ask_prices[0], false)));
+    }
+
+    #[test]
+    fn test_thin_books_get_fewer_levels() {
+        let mut generator = test_generator();
+        let mut book = test_book();
+        // The grid spans two ticks, room for three levels
+        let orders = generator.generate_skew_orders("SOLUSDT", 0.002, 0.0, &book, 100.0, true);
+        assert_eq!(orders.len(), 6);
+        let prices: HashSet<_> = orders.iter().map(|o| o.1.to_string()).collect();
+        assert_eq!(prices.len(), 6);
+        // Logged once, not again while the grid stays reduced the same way
+        generator.generate_skew_orders("SOLUSDT", 0.002, 0.0, &book, 100.0, true);
+        assert_eq!(
+            generator.logger.captured(),
+            vec![(
+                LogLevel::Info,
+                "Reduced the grid of SOLUSDT to 3 bids and 3 asks, the book can't fit 4 levels per side"
+                    .to_string()
+            )]
+        );
+
+        // Spread over four levels no order reaches the min notional, the largest of two does
+        book.min_notional = 320.0;
+        let orders = generator.generate_skew_orders("SOLUSDT", 0.05, 0.0, &book, 100.0, true);
+        assert_eq!(orders.len(), 2);
+        assert!(orders.iter().all(|o| o.1 * o.2 >= 320.0));
+    }
+
+    #[test]
+    fn test_single_sided_quoting() {
+        let mut generator = test_generator();
+        let book = test_book();
+        generator.set_quote_side(QuoteSide::BidOnly);
+        let orders = generator.generate_skew_orders("SOLUSDT", 0.05, 0.0, &book, 100.0, true);
+        assert!(!orders.is_empty() && orders.iter().all(|o| o.3));
+        generator.set_quote_side(QuoteSide::AskOnly);
+        let orders = generator.generate_skew_orders("SOLUSDT", 0.05, 0.0, &book, 100.0, true);
+        assert!(!orders.is_empty() && orders.iter().all(|o| !o.3));
+
+        // Switching to bids only re-quotes the live asks away, though the mid didn't move
+        generator.set_quote_side(QuoteSide::Both);
+        generator
+            .live_buys
+            .push_back(LiveOrder::new("buy".to_string(), 99.9, 1.0, true));
+        generator
+            .live_sells
+            .push_back(LiveOrder::new("sell".to_string(), 100.1, 1.0, false));
+        generator.last_update_price = book.mid_price;
+        generator.bounds = 1.0;
+        generator.time_limit = book.last_update;
+        let private = PrivateData::from(BybitPrivate::default());
+        assert!(!generator.out_of_bounds(&book, private.clone()));
+        generator.set_quote_side(QuoteSide::BidOnly);
+        assert!(generator.out_of_bounds(&book, private));
+    }
+
+    #[test]
+    fn test_passive_rounding() {
+        let mut book = test_book();
+        for tick_size in [0.01, 0.5, 0.0001] {
+            book.tick_size = tick_size;
+            for i in 0..1000 {
+                let price = 100.0 + i as f64 * 0.000_37;
+                let bid = round_price_passive(&book, price, true);
+                let ask = round_price_passive(&book, price, false);
+                // Bids never round up and asks never round down, by at most a tick
+                assert!(bid <= price && price - bid < tick_size);
+                assert!(ask >= price && ask - price < tick_size);
+            }
+        }
+        // Prices already on a tick are left alone
+        book.tick_size = 0.01;
+        assert_eq!(round_price_passive(&book, 99.99, true), 99.99);
+        assert_eq!(round_price_passive(&book, 100.01, false), 100.01);
+        assert_eq!(round_price_passive(&book, 100.004, true), 100.0);
+        assert_eq!(round_price_passive(&book, 100.006, false), 100.01);
+    }
+
+    #[test]
+    fn test_zero_max_position_is_rejected() {
+        for (asset, leverage) in [(0.0, 10.0), (100.0, 0.0), (f64::NAN, 10.0)] {
+            let logger = Logger::capturing();
+            let client = BybitClient::new(String::new(), String::new(), logger.clone());
+            let generator = QuoteGenerator::with_logger(client, logger, asset, leverage, 4, 10, 10);
+            assert!(generator.is_err());
+        }
+    }
+
+    #[test]
+    fn test_leverage_stays_within_the_symbols_range() {
+        let logger = Logger::capturing();
+        let client = BybitClient::new(String::new(), String::new(), logger.clone());
+        let generator = QuoteGenerator::with_logger(client, logger, 1000.0, 200.0, 4, 10, 10);
+        assert!(generator.unwrap_err().contains("between 1 and 125"));
+
+        let logger = Logger::capturing();
+        let client = BybitClient::new(String::new(), String::new(), logger.clone());
+        let mut generator =
+            QuoteGenerator::with_logger(client, logger, 1000.0, 50.0, 4, 10, 10).unwrap();
+        // An unknown max keeps the range, a known one clamps the leverage down to it
+        generator.set_max_leverage(0.0);
+        assert_eq!(generator.leverage(), 50.0);
+        generator.set_max_leverage(20.0);
+        assert_eq!(generator.leverage(), 20.0);
+        assert_eq!(generator.max_position_usd, 1000.0 * 20.0 * SAFETY_FACTOR);
+        assert!(generator
+            .logger
+            .captured()
+            .iter()
+            .any(|(level, msg)| *level == LogLevel::Warning
+                && msg.starts_with("Leverage 50 is outside the allowed range of 1 to 20")));
+        generator.set_leverage(100.0);
+        assert_eq!(generator.leverage(), 20.0);
+        generator.set_leverage(0.5);
+        assert_eq!(generator.leverage(), 1.0);
+
+        generator.set_safety_factor(0.5);
+        assert_eq!(generator.max_position_usd, 1000.0 * 0.5);
+        generator.set_safety_factor(1.5);
+        assert_eq!(generator.max_position_usd, 1000.0 * 0.5);
+        generator.set_safety_factor(0.0);
+        assert_eq!(generator.max_position_usd, 1000.0 * SAFETY_FACTOR);
+
+        // A fractional max is rounded down, the exchange is set a whole leverage
+        generator.set_max_leverage(12.5);
+        generator.set_leverage(15.0);
+        assert_eq!(generator.leverage(), 12.0);
+    }
+
+    #[tokio::test]
+    async fn test_paused_generator_does_not_quote() {
+        let mut generator = test_generator();
+        generator.set_log_intended_quotes(true);
+        generator.set_paused(true);
+        let book = test_book();
+
+        generator
+            .update_grid(
+                BybitPrivate::default(),
+                0.0,
+                book.clone(),
+                "SOLUSDT".to_string(),
+                0.0,
+                book.get_mid_price(),
+            )
+            .await;
+        assert_eq!(generator.time_limit, 0);
+        assert_eq!(
+            generator.logger.captured(),
+            vec![(LogLevel::Warning, "Quoting paused".to_string())]
+        );
+
+        generator.set_paused(false);
+        generator
+            .update_grid(
+                BybitPrivate::default(),
+                0.0,
+                book.clone(),
+                "SOLUSDT".to_string(),
+                0.0,
+                book.get_mid_price(),
+            )
+            .await;
+        assert_eq!(generator.time_limit, book.last_update);
+    }
+
+    #[test]
+    fn test_generate_skew_orders_grid() {
+        let mut generator = test_generator();
+        let mut book = test_book();
+        // Low enough for the largest levels to be clamped
+        book.post_only_max = 2.0;
+        let mid = book.get_mid_price();
+        let spread = 0.1;
+        // sqrt(0.25) splits the spread evenly around mid
+        let skew = 0.25;
+
+        let orders = generator.generate_skew_orders("SOLUSDT", spread, skew, &book, mid, true);
+        let (bids, asks): (Vec<_>, Vec<_>) = orders.iter().partition(|order| order.3);
+        assert_eq!(bids.len(), 4);
+        assert_eq!(asks.len(), 4);
+
+        // Bids run from the far level up to the touch, asks from the touch outwards
+        assert!(bids.windows(2).all(|pair| pair[0].1 < pair[1].1));
+        assert!(asks.windows(2).all(|pair| pair[0].1 < pair[1].1));
+        assert!(bids.iter().all(|order| order.1 < mid));
+        assert!(asks.iter().all(|order| order.1 > mid));
+        assert!(bids.last().unwrap().1 < asks[0].1);
+
+        for order in &orders {
+            assert_eq!(order.0, "SOLUSDT");
+            assert!(order.2 <= book.post_only_max);
+            assert!(order.1 * order.2 >= book.min_notional);
+        }
+        assert!(orders.iter().any(|order| order.2 == book.post_only_max));
+
+        // Long enough inventory stops the bids
+        generator.inventory_delta = 0.6;
+        let orders = generator.generate_skew_orders("SOLUSDT", spread, skew, &book, mid, true);
+        assert!(!orders.is_empty());
+        assert!(orders.iter().all(|order| !order.3));
+
+        // Short enough inventory stops the asks
+        generator.inventory_delta = -0.6;
+        let orders = generator.generate_skew_orders("SOLUSDT", spread, skew, &book, mid, false);
+        assert!(!orders.is_empty());
+        assert!(orders.iter().all(|order| order.3));
+
+        // Both sides are quoted right below the threshold
+        generator.inventory_delta = 0.49;
+        let orders = generator.generate_skew_orders("SOLUSDT", spread, skew, &book, mid, true);
+        assert!(orders.iter().any(|order| order.3));
+        assert!(orders.iter().any(|order| !order.3));
+    }
+
+    #[test]
+    fn test_reducing_side_is_reduce_only() {
+        let mut generator = test_generator();
+        let book = test_book();
+        let mid = book.get_mid_price();
+        let quote = |generator: &mut QuoteGenerator| {
+            generator.generate_skew_orders("SOLUSDT", 0.1, 0.25, &book, mid, true)
+        };
+
+        // Disabled by default
+        generator.inventory_delta = 0.4;
+        assert!(quote(&mut generator).iter().all(|order| !order.4));
+
+        // Long inventory only reduces through the asks
+        generator.set_reduce_only_delta(0.3);
+        let orders = quote(&mut generator);
+        assert!(orders.iter().any(|order| order.3));
+        assert!(orders.iter().all(|order| order.4 != order.3));
+
+        // Short inventory only reduces through the bids
+        generator.inventory_delta = -0.4;
+        let orders = quote(&mut generator);
+        assert!(orders.iter().any(|order| !order.3));
+        assert!(orders.iter().all(|order| order.4 == order.3));
+
+        // Below the threshold nothing is reduce-only
+        generator.inventory_delta = 0.2;
+        assert!(quote(&mut generator).iter().all(|order| !order.4));
+    }
+
+    #[test]
+    fn test_grid_time_in_force() {
+        let mut generator = test_generator();
+        let book = test_book();
+        let mid = book.get_mid_price();
+        let orders = generator.generate_skew_orders("SOLUSDT", 0.1, 0.25, &book, mid, true);
+        assert!(!orders.is_empty());
+        assert!(orders.iter().all(|order| order.5 == TimeInForce::PostOnly));
+
+        generator.set_time_in_force(TimeInForce::ImmediateOrCancel);
+        let orders = generator.generate_skew_orders("SOLUSDT", 0.1, 0.25, &book, mid, true);
+        assert!(orders
+            .iter()
+            .all(|order| order.5 == TimeInForce::ImmediateOrCancel));
+    }
+
+    #[test]
+    fn test_grid_ratios() {
+        let mut generator = test_generator();
+        let book = test_book();
+        let mid = book.get_mid_price();
+        let bid_sizes = |generator: &mut QuoteGenerator, skew| {
+            generator
+                .generate_skew_orders("SOLUSDT", 0.1, skew, &book, mid, true)
+                .into_iter()
+                .filter(|order| order.3)
+                .map(|order| order.2)
+                .collect::<Vec<_>>()
+        };
+
+        // The default ratio piles the size on the outermost level
+        let sizes = bid_sizes(&mut generator, 0.25);
+        assert!(sizes[0] > 2.0 * sizes[sizes.len() - 1]);
+
+        generator.set_bid_ratio(1.0);
+        let sizes = bid_sizes(&mut generator, 0.25);
+        assert_eq!(sizes.len(), 4);
+        assert!(sizes
+            .iter()
+            .all(|s| (s - sizes[0]).abs() <= book.lot_size + 1e-9));
+
+        generator.set_bid_ratio(1.5);
+        assert_eq!(generator.bid_ratio, 1.0);
+        assert_eq!(
+            generator.logger.captured(),
+            vec![(
+                LogLevel::Warning,
+                "Ignoring bid ratio 1.5, it must be above 0 and at most 1".to_string()
+            )]
+        );
+        generator.set_bid_ratio(0.0);
+        assert_eq!(generator.bid_ratio, 1.0);
+
+        // A strong positive skew evens out the bids, a weak one concentrates them further out
+        generator.set_skew_weighted_ratio(true);
+        let strong = bid_sizes(&mut generator, 0.63);
+        let weak = bid_sizes(&mut generator, 0.1);
+        assert!(strong[strong.len() - 1] > weak[weak.len() - 1]);
+        assert!(strong[0] < weak[0]);
+    }
+
+    #[test]
+    fn test_final_order_distance() {
+        let mut generator = test_generator();
+        let book = test_book();
+        let mid = book.get_mid_price();
+
+        for distance in [1.0, 1e6] {
+            generator.set_final_order_distance(distance);
+            let orders = generator.generate_skew_orders("SOLUSDT", 0.1, 0.25, &book, mid, true);
+            let (bids, asks): (Vec<_>, Vec<_>) = orders.iter().partition(|order| order.3);
+            assert_eq!((bids.len(), asks.len()), (4, 4));
+
+            // Bids come outermost first and asks touch first, both ascending
+            assert!(bids.windows(2).all(|w| w[0].1 < w[1].1));
+            assert!(asks.windows(2).all(|w| w[0].1 < w[1].1));
+            assert!(bids.iter().all(|order| order.1 > 0.0));
+            assert!(bids[3].1 < asks[0].1);
+        }
+
+        generator.set_final_order_distance(-1.0);
+        assert_eq!(generator.final_order_distance, 1e6);
+        generator.set_final_order_distance(0.0);
+        assert_eq!(generator.final_order_distance, 1e6);
+    }
+
+    #[test]
+    fn test_avellaneda_stoikov() {
+        // Flat inventory quotes around the mid
+        let (r, half) = avellaneda_stoikov(100.0, 0.0, 0.5, 10.0, 0.1, 1.5);
+        assert_eq!(r, 100.0);
+        let expected = (0.1 * 0.25 * 10.0 + 20.0 * (1.0_f64 + 0.1 / 1.5).ln()) / 2.0;
+        assert!((half - expected).abs() < 1e-12);
+
+        // Long inventory lowers the reservation price, short raises it
+        let (long, long_half) = avellaneda_stoikov(100.0, 2.0, 0.5, 10.0, 0.1, 1.5);
+        assert!((long - (100.0 - 2.0 * 0.1 * 0.25 * 10.0)).abs() < 1e-12);
+        assert_eq!(long_half, half);
+        let (short, _) = avellaneda_stoikov(100.0, -2.0, 0.5, 10.0, 0.1, 1.5);
+        assert!(short > 100.0);
+    }
+
+    #[test]
+    fn test_reservation_price_grid() {
+        let mut generator = test_generator();
+        let book = test_book();
+        let mid = book.get_mid_price();
+        generator.set_quoting_model(QuotingModel::AvellanedaStoikov {
+            gamma: 0.1,
+            k: 10.0,
+        });
+        generator.position_qty = 2.0;
+        generator.set_inventory_delta(mid);
+
+        let orders = generator
+            .generate_quotes("SOLUSDT", &book, 0.0, 0.001, mid)
+            .unwrap();
+        let best_bid = orders
+            .iter()
+            .filter(|o| o.3)
+            .map(|o| o.1)
+            .fold(0.0, f64::max);
+        let best_ask = orders
+            .iter()
+            .filter(|o| !o.3)
+            .map(|o| o.1)
+            .fold(f64::MAX, f64::min);
+        let (r, half) = avellaneda_stoikov(mid, 2.0, 0.001 * mid, 10.0, 0.1, 10.0);
+        assert!(r < mid);
+        assert!((generator.adjusted_spread - 2.0 * half).abs() < 1e-9);
+        assert!(((best_bid + best_ask) / 2.0 - r).abs() < 0.02);
+
+        // Invalid parameters keep the heuristic grid
+        generator.set_quoting_model(QuotingModel::AvellanedaStoikov { gamma: 0.0, k: 1.0 });
+        assert_eq!(generator.quoting_model(), QuotingModel::Heuristic);
+    }
+
+    #[test]
+    fn test_replayed_executions_are_skipped() {
+        let mut generator = test_generator();
+        generator
+            .live_buys
+            .push_back(LiveOrder::new("order-1".to_string(), 99.9, 3.0, true));
+        let first = Fill {
+            order_id: "order-1".to_string(),
+            seq: 0,
+            ..test_fill(1)
+        };
+        let executions = [first.clone()];
+        for fill in generator.take_new_fills(executions.iter().rev().cloned()) {
+            generator.apply_fill(&fill);
+        }
+        assert_eq!(generator.position_qty, 1.0);
+
+        // The reconnected stream replays the fill next to a new one of the same millisecond
+        let second = Fill {
+            exec_id: "exec-2".to_string(),
+            ..first.clone()
+        };
+        let executions = [first, second];
+        for fill in generator.take_new_fills(executions.iter().rev().cloned()) {
+            generator.apply_fill(&fill);
+        }
+        assert_eq!(generator.position_qty, 2.0);
+        assert!((generator.live_buys[0].qty - 1.0).abs() < 1e-9);
+
+        // Replayed again, nothing moves
+        assert!(generator
+            .take_new_fills(executions.iter().rev().cloned())
+            .is_empty());
+        assert_eq!(generator.position_qty, 2.0);
+    }
+
+    #[test]
+    fn test_fills_follow_the_order_side() {
+        let mut generator = test_generator();
+        generator
+            .live_buys
+            .push_back(LiveOrder::new("buy".to_string(), 99.9, 1.0, true));
+        generator
+            .live_sells
+            .push_back(LiveOrder::new("sell".to_string(), 100.1, 1.0, false));
+
+        let mut fill = test_fill(0);
+        fill.order_id = "buy".to_string();
+        fill.qty = 0.4;
+        generator.apply_fill(&fill);
+        assert!((generator.position_qty - 0.4).abs() < 1e-9);
+        assert!((generator.live_buys[0].qty - 0.6).abs() < 1e-9);
+        assert!(generator.live_buys[0].is_buy);
+
+        // The order decides the side, not the execution payload
+        let mut fill = test_fill(1);
+        fill.order_id = "sell".to_string();
+        generator.apply_fill(&fill);
+        assert!((generator.position_qty + 0.6).abs() < 1e-9);
+        assert!(generator.live_sells.is_empty());
+
+        // Unknown orders are ignored
+        generator.apply_fill(&test_fill(2));
+        assert!((generator.position_qty + 0.6).abs() < 1e-9);
+        assert_eq!(generator.live_buys.len(), 1);
+        assert_eq!(
+            generator.logger.captured(),
+            vec![
+                (LogLevel::Info, "Buy fill: 0.40 @ 99.9".to_string()),
+                (LogLevel::Info, "Sell fill: 1.00 @ 100.1".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_partial_fills() {
+        let mut generator = test_generator();
+        generator
+            .live_buys
+            .push_back(LiveOrder::new("buy".to_string(), 99.9, 12.3, true));
+
+        let mut fill = test_fill(0);
+        fill.order_id = "buy".to_string();
+        fill.qty = 0.1;
+        generator.apply_fill(&fill);
+        assert!((generator.live_buys[0].qty - 12.2).abs() < 1e-9);
+        assert!((generator.live_buys[0].filled_qty - 0.1).abs() < 1e-9);
+
+        // A partially filled order is amended to its total qty
+        let book = test_book();
+        let bid = BatchOrder::new(
+            "SOLUSDT".to_string(),
+            99.8,
+            5.0,
+            true,
+            false,
+            TimeInForce::PostOnly,
+        );
+        let diff = diff_grid(&book, &generator.live_buys, vec![bid], |_| false);
+        assert!((diff.amends[0].2 - 5.1).abs() < 1e-9);
+
+        // The rest of the order leaves float dust behind, it's still fully filled
+        let mut fill = test_fill(1);
+        fill.order_id = "buy".to_string();
+        fill.qty = 12.2;
+        generator.apply_fill(&fill);
+        assert!(generator.live_buys.is_empty());
+        assert!((generator.position_qty - 12.3).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_record_metrics() {
+        let mut generator = test_generator();
+        generator
+            .live_buys
+            .push_back(LiveOrder::new("buy".to_string(), 99.9, 1.0, true));
+        let mut fill = test_fill(0);
+        fill.order_id = "buy".to_string();
+        fill.qty = 0.4;
+        generator.apply_fill(&fill);
+
+        generator.record_metrics("METRICUSDT", 100.0);
+        let text = metrics::render();
+        assert!(text.contains("smm_quoting{symbol=\"METRICUSDT\"} 1\n"));
+        assert!(text.contains("smm_position{symbol=\"METRICUSDT\"} 0.4\n"));
+        assert!(text.contains("smm_fills_total{symbol=\"METRICUSDT\",side=\"buy\"} 1\n"));
+        assert!(text.contains("smm_fills_total{symbol=\"METRICUSDT\",side=\"sell\"} 0\n"));
+
+        // A paused symbol isn't quoting even with orders resting
+        generator.set_paused(true);
+        generator.record_metrics("METRICUSDT", 100.0);
+        assert!(metrics::render().contains("smm_quoting{symbol=\"METRICUSDT\"} 0\n"));
+    }
+
+    #[test]
+    fn test_fills_book_pnl() {
+        let mut generator = test_generator();
+        generator.set_fees(FeeTier::new(0.0, 0.0));
+        generator
+            .live_buys
+            .push_back(LiveOrder::new("buy".to_string(), 99.9, 1.0, true));
+        generator
+            .live_sells
+            .push_back(LiveOrder::new("sell".to_string(), 100.1, 2.0, false));
+
+        let mut fill = test_fill(0);
+        fill.order_id = "buy".to_string();
+        generator.apply_fill(&fill);
+        let mut fill = test_fill(1);
+        fill.order_id = "sell".to_string();
+        fill.qty = 2.0;
+        generator.apply_fill(&fill);
+
+        // 1 closed at 0.2 of profit, 1 left short from 100.1
+        assert!((generator.realized_pnl() - 0.2).abs() < 1e-9);
+        assert!((generator.average_entry_price() - 100.1).abs() < 1e-9);
+        assert!((generator.unrealized_pnl(100.0) - 0.1).abs() < 1e-9);
+        assert!(generator
+            .log_pnl("SOLUSDT", 100.0)
+            .ends_with("PnL for SOLUSDT: realized 0.2000, unrealized 0.1000, position -1 @ 100.1"));
+    }
+
+    #[test]
+    fn test_reconcile_orders() {
+        let mut generator = test_generator();
+        generator
+            .live_buys
+            .push_back(LiveOrder::new("phantom".to_string(), 99.9, 1.0, true));
+        generator
+            .live_sells
+            .push_back(LiveOrder::new("sell".to_string(), 100.1, 1.0, false));
+
+        generator.reconcile_orders(vec![
+            LiveOrder::new("sell".to_string(), 100.1, 0.5, false),
+            LiveOrder::new("far".to_string(), 99.7, 1.0, true),
+            LiveOrder::new("near".to_string(), 99.8, 1.0, true),
+        ]);
+
+        // Best bid first, the exchange's remaining qty wins
+        let buys: Vec<_> = generator
+            .live_buys
+            .iter()
+            .map(|o| o.order_id.as_str())
+            .collect();
+        assert_eq!(buys, vec!["near", "far"]);
+        assert_eq!(generator.live_sells.len(), 1);
+        assert!((generator.live_sells[0].qty - 0.5).abs() < 1e-9);
+        assert_eq!(
+            generator.logger.captured(),
+            vec![(
+                LogLevel::Warning,
+                "Reconciled orders: dropped 1 stale, adopted 2 untracked".to_string()
+            )]
+        );
+
+        // Nothing to log when the books already agree
+        let open: Vec<_> = generator
+            .live_buys
+            .iter()
+            .chain(generator.live_sells.iter())
+            .cloned()
+            .collect();
+        generator.reconcile_orders(open);
+        assert_eq!(generator.logger.captured().len(), 1);
+        assert_eq!(generator.live_buys.len(), 2);
+
+        // A resting manual order stays out of the grid, a closed one is forgotten
+        generator
+            .manual_orders
+            .push_back(LiveOrder::new("manual".to_string(), 99.0, 1.0, true));
+        generator
+            .manual_orders
+            .push_back(LiveOrder::new("closed".to_string(), 101.0, 1.0, false));
+        let mut open: Vec<_> = generator.live_buys.iter().cloned().collect();
+        open.push(LiveOrder::new("manual".to_string(), 99.0, 1.0, true));
+        generator.reconcile_orders(open);
+        assert_eq!(generator.live_buys.len(), 2);
+        assert!(generator.live_sells.is_empty());
+        assert_eq!(generator.manual_orders.len(), 1);
+        assert_eq!(generator.manual_orders[0].order_id, "manual");
+    }
+
+    #[test]
+    fn test_diff_grid() {
+        let book = test_book();
+        let bid = |price, qty| {
+            BatchOrder::new(
+                "SOLUSDT".to_string(),
+                price,
+                qty,
+                true,
+                false,
+                TimeInForce::PostOnly,
+            )
+        };
+        let live: VecDeque<_> = vec![
+            LiveOrder::new("kept".to_string(), 99.9, 1.0, true),
+            LiveOrder::new("moved".to_string(), 99.8, 1.0, true),
+            LiveOrder::new("resized".to_string(), 99.7, 1.0, true),
+            LiveOrder::new("outside".to_string(), 99.6, 1.0, true),
+        ]
+        .into();
+
+        // Unsorted on purpose, levels pair up best price first
+        let diff = diff_grid(
+            &book,
+            &live,
+            vec![bid(99.7, 1.5), bid(99.9, 1.0), bid(99.75, 1.0)],
+            |_| false,
+        );
+        let amends: Vec<_> = diff
+            .amends
+            .iter()
+            .map(|a| (a.3.as_str(), a.1, a.2))
+            .collect();
+        assert_eq!(amends, vec![("moved", 99.75, 1.0), ("resized", 99.7, 1.5)]);
+        assert_eq!(diff.cancels, vec!["outside".to_string()]);
+        assert!(diff.places.is_empty());
+
+        // A grid wider than the live orders places the extra levels
+        let diff = diff_grid(&book, &VecDeque::new(), vec![bid(99.9, 1.0)], |_| false);
+        assert!(diff.amends.is_empty() && diff.cancels.is_empty());
+        assert_eq!(diff.places.len(), 1);
+
+        // An expired order that didn't move is re-placed, a moved one is amended as usual
+        let diff = diff_grid(
+            &book,
+            &live,
+            vec![bid(99.9, 1.0), bid(99.75, 1.0)],
+            |order| order.order_id != "outside",
+        );
+        assert_eq!(diff.amends.len(), 1);
+        assert_eq!(
+            diff.cancels,
+            vec!["resized".to_string(), "outside".to_string()]
+        );
+        assert!(diff.places.is_empty());
+        let replaces: Vec<_> = diff
+            .replaces
+            .iter()
+            .map(|(id, order)| (id.as_str(), order.1))
+            .collect();
+        assert_eq!(replaces, vec![("kept", 99.9)]);
+
+        // Amends can't make an order reduce-only or change its time in force
+        let mut reducing = bid(99.75, 1.0);
+        reducing.4 = true;
+        let mut gtc = bid(99.7, 1.0);
+        gtc.5 = TimeInForce::GoodTilCancel;
+        let diff = diff_grid(&book, &live, vec![bid(99.9, 1.0), reducing, gtc], |_| false);
+        assert!(diff.amends.is_empty() && diff.places.is_empty());
+        assert_eq!(diff.cancels, vec!["outside".to_string()]);
+        let replaces: Vec<_> = diff
+            .replaces
+            .iter()
+            .map(|(id, o)| (id.as_str(), o.1, o.4, o.5))
+            .collect();
+        assert_eq!(
+            replaces,
+            vec![
+                ("moved", 99.75, true, TimeInForce::PostOnly),
+                ("resized", 99.7, false, TimeInForce::GoodTilCancel)
+            ]
+        );
+        let placed = LiveOrder::placed("placed".to_string(), &diff.replaces[0].1);
+        assert!(placed.reduce_only && placed.time_in_force == TimeInForce::PostOnly);
+    }
+
+    #[tokio::test]
+    async fn test_replacements_wait_for_their_cancel() {
+        let mut generator = test_generator();
+        generator.set_dry_run(true);
+        let book = test_book();
+        quote(&mut generator, &book).await;
+        let ids = |gen: &QuoteGenerator| -> Vec<String> {
+            gen.live_buys
+                .iter()
+                .chain(gen.live_sells.iter())
+                .map(|o| o.order_id.clone())
+                .collect()
+        };
+        let grid = ids(&generator);
+        let now = generate_timestamp().unwrap();
+        for order in generator
+            .live_buys
+            .iter_mut()
+            .chain(generator.live_sells.iter_mut())
+        {
+            order.created_at = now - 90_000;
+        }
+        generator.set_max_order_age(60);
+        let orders = generator
+            .generate_quotes("SOLUSDT", &book, 0.0, 0.0, book.get_mid_price())
+            .unwrap();
+
+        // Without a cancel budget the expired orders stay and nothing is placed next to them
+        generator.buy_cancel_limit = 0;
+        generator.sell_cancel_limit = 0;
+        assert!(!generator.amend_grid("SOLUSDT", &book, orders.clone()).await);
+        assert_eq!(ids(&generator), grid);
+        assert!(
+            generator
+                .logger
+                .captured()
+                .iter()
+                .any(|(_, msg)| msg
+                    .starts_with("Amended 0 orders for SOLUSDT, cancelled 0, placed 0"))
+        );
+
+        generator.buy_cancel_limit = 10;
+        generator.sell_cancel_limit = 10;
+        assert!(generator.amend_grid("SOLUSDT", &book, orders).await);
+        let replaced = ids(&generator);
+        assert_eq!(replaced.len(), grid.len());
+        assert!(replaced.iter().all(|id| !grid.contains(id)));
+
+        // Neither is an order whose flags the new level changed
+        generator.set_max_order_age(0);
+        generator.live_buys[0].reduce_only = true;
+        generator.buy_cancel_limit = 0;
+        let orders = generator
+            .generate_quotes("SOLUSDT", &book, 0.0, 0.0, book.get_mid_price())
+            .unwrap();
+        assert!(!generator.amend_grid("SOLUSDT", &book, orders).await);
+        assert_eq!(ids(&generator), replaced);
+        assert!(generator.live_buys[0].reduce_only);
+    }
+
+    #[test]
+    fn test_order_age() {
+        let mut generator = test_generator();
+        let now = generate_timestamp().unwrap();
+        assert_eq!(generator.oldest_order_age(now), None);
+
+        let mut old = LiveOrder::new("old".to_string(), 99.9, 1.0, true);
+        old.created_at = now - 90_000;
+        let fresh = LiveOrder::new("fresh".to_string(), 100.1, 1.0, false);
+        // Orders restored without a placement time have no age
+        let unknown = LiveOrder {
+            created_at: 0,
+            ..LiveOrder::new("unknown".to_string(), 99.8, 1.0, true)
+        };
+        generator.live_buys.extend([old.clone(), unknown]);
+        generator.live_sells.push_back(fresh.clone());
+        assert_eq!(generator.oldest_order_age(now), Some(90_000));
+
+        // Disabled until a max age is set
+        assert!(!generator.is_expired(&old, now));
+        generator.set_max_order_age(60);
+        assert!(generator.is_expired(&old, now));
+        assert!(!generator.is_expired(&fresh, now));
+
+        // The mid didn't move, but the old order alone is due for a refresh
+        let book = test_book();
+        generator.last_update_price = book.mid_price;
+        generator.bounds = 1.0;
+        generator.time_limit = book.last_update;
+        let private = PrivateData::from(BybitPrivate::default());
+        assert!(generator.out_of_bounds(&book, private.clone()));
+        generator.set_max_order_age(0);
+        assert!(!generator.out_of_bounds(&book, private));
+    }
+
+    #[test]
+    fn test_post_only_rejections_move_off_the_touch() {
+        let book = test_book();
+        let order = |price: f64, is_buy: bool| {
+            BatchOrder::new(
+                "SOLUSDT".to_string(),
+                price,
+                1.0,
+                is_buy,
+                false,
+                TimeInForce::PostOnly,
+            )
+        };
+        let rejected = [
+            RejectedOrder::new(
+                order(100.01, true),
+                170218,
+                "OrderType PostOnly would take liquidity".to_string(),
+            ),
+            RejectedOrder::new(
+                order(99.99, false),
+                -5022,
+                "Due to the order could not be executed as maker".to_string(),
+            ),
+            // Not a post-only rejection, it's left alone
+            RejectedOrder::new(
+                order(99.9, true),
+                110007,
+                "Insufficient available balance".to_string(),
+            ),
+        ];
+        let retries = retry_post_only(&book, &rejected);
+        assert_eq!(retries.len(), 2);
+        assert!(retries[0].3 && retries[0].1 == 100.0);
+        assert!(!retries[1].3 && retries[1].1 == 100.0);
+        // A book lagging the exchange's still moves every retry a tick further away
+        assert_eq!(behind_touch(&book, 99.95, true), 99.94);
+        assert_eq!(behind_touch(&book, 100.05, false), 100.06);
+    }
+
+    #[tokio::test]
+    async fn test_amend_results_are_reconciled() {
+        let mut generator = test_generator();
+        generator.set_dry_run(true);
+        let book = test_book();
+        generator
+            .live_buys
+            .push_back(LiveOrder::new("b1".to_string(), 99.9, 1.0, true));
+        generator.live_sells.push_back(LiveOrder {
+            filled_qty: 0.5,
+            reduce_only: true,
+            time_in_force: TimeInForce::GoodTilCancel,
+            ..LiveOrder::new("s1".to_string(), 100.1, 0.5, false)
+        });
+
+        // Amending the half filled sell to rest 1.0 sets its total to 1.5, Binance re-places
+        // it under a new id with only the resting 1.0 and the sell's flags
+        let amend =
+            BatchAmend::of_order("SOLUSDT".to_string(), &generator.live_sells[0], 100.2, 1.0);
+        assert_eq!((amend.2, amend.resting_qty()), (1.5, 1.0));
+        let replacement = amend.replacement();
+        assert_eq!(
+            (replacement.2, replacement.4, replacement.5),
+            (1.0, true, TimeInForce::GoodTilCancel)
+        );
+        let amended = generator.apply_amends(
+            &book,
+            vec![(
+                "s1".to_string(),
+                LiveOrder::placed("s2".to_string(), &replacement),
+            )],
+        );
+        assert_eq!(amended, 1);
+        let sell = &generator.live_sells[0];
+        assert_eq!(
+            (
+                sell.order_id.as_str(),
+                sell.price,
+                sell.qty,
+                sell.filled_qty,
+                sell.reduce_only,
+                sell.time_in_force
+            ),
+            ("s2", 100.2, 1.0, 0.0, true, TimeInForce::GoodTilCancel)
+        );
+        assert_eq!(
+            generator.apply_amends(&book, vec![("s1".to_string(), LiveOrder::default())]),
+            0
+        );
+
+        // A rejected amend's order is cancelled and forgotten
+        let amend = BatchAmend::new("SOLUSDT".to_string(), 99.8, 1.0, "b1".to_string(), true);
+        let rejected = RejectedAmend::new(amend, 110001, "order not exists".to_string());
+        generator.forget_rejected_amends(vec![rejected]).await;
+        assert!(generator.live_buys.is_empty());
+        assert_eq!(generator.buy_cancel_limit, 9);
+    }
+
+    #[tokio::test]
+    async fn test_post_only_cancels_are_resent_behind_the_touch() {
+        let mut generator = test_generator();
+        generator.set_dry_run(true);
+        generator.set_post_only_retries(1);
+        let mut book = test_book();
+        generator
+            .live_buys
+            .push_back(LiveOrder::new("b1".to_string(), 100.0, 2.0, true));
+        generator
+            .live_buys
+            .push_back(LiveOrder::new("b2".to_string(), 99.9, 1.0, true));
+
+        // The ask came down through the bid before the exchange cancelled it
+        book.best_ask.price = 99.95;
+        generator
+            .resend_post_only_cancels("SOLUSDT", &book, ["b1".to_string()].into_iter())
+            .await;
+        let prices: Vec<_> = generator.live_buys.iter().map(|o| o.price).collect();
+        assert_eq!(prices, [99.94, 99.9]);
+        assert_eq!(generator.live_buys[0].qty, 2.0);
+
+        // Out of retries for this grid the cancelled order is only forgotten
+        generator
+            .resend_post_only_cancels("SOLUSDT", &book, ["b2".to_string()].into_iter())
+            .await;
+        assert_eq!(generator.live_buys.len(), 1);
+        // Ids of orders already forgotten are ignored
+        generator
+            .resend_post_only_cancels("SOLUSDT", &book, ["b1".to_string()].into_iter())
+            .await;
+        assert_eq!(generator.live_buys.len(), 1);
+    }
+
+    #[test]
+    fn test_set_position_from_exchange() {
+        let mut generator = test_generator();
+        generator.set_position(-2.5);
+        generator.set_inventory_delta(100.0);
+        assert_eq!(generator.position_qty, -2.5);
+        assert!(generator.inventory_delta < 0.0);
+
+        // A flat account keeps a flat generator quiet
+        let mut generator = test_generator();
+        generator.set_position(0.0);
+        assert_eq!(generator.position_qty, 0.0);
+        assert!(generator.logger.captured().is_empty());
+    }
+
+    #[test]
+    fn test_spread_covers_maker_fees() {
+        let mut generator = test_generator();
+        generator.set_spread_model(SpreadModel::Constant);
+        let book = test_book();
+        let mid = book.get_mid_price();
+
+        // 25 bps default spread against 2 bps maker fees
+        let spread = generator.vol_adjusted_spread(&book, 0.0);
+        assert!((spread - 0.0025 * mid).abs() < 1e-9);
+
+        generator.set_fees(FeeTier::new(0.002, 0.004));
+        let spread = generator.vol_adjusted_spread(&book, 0.0);
+        assert!((spread - 0.004 * mid).abs() < 1e-9);
+
+        // A maker rebate leaves the configured spread alone
+        generator.set_fees(FeeTier::new(-0.0001, 0.0003));
+        let spread = generator.vol_adjusted_spread(&book, 0.0);
+        assert!((spread - 0.0025 * mid).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_unprofitable_levels_are_suppressed() {
+        let mut generator = test_generator();
+        let book = test_book();
+        // No skew puts the best ask right at the mid
+        let all = generator.generate_skew_orders("SOLUSDT", 0.05, 0.0, &book, 100.0, true);
+
+        generator.set_min_edge_bps(1.0);
+        let orders = generator.generate_skew_orders("SOLUSDT", 0.05, 0.0, &book, 100.0, true);
+        let min_distance = (MAKER_FEE_RATE + 0.0001) * 100.0;
+        assert!(orders.len() < all.len());
+        assert!(orders.iter().all(|o| (o.1 - 100.0).abs() >= min_distance));
+        let expected = format!(
+            "Suppressed {} unprofitable levels for SOLUSDT",
+            all.len() - orders.len()
+        );
+        // Logged once while the same levels keep being suppressed
+        generator.generate_skew_orders("SOLUSDT", 0.05, 0.0, &book, 100.0, true);
+        let logged = generator.logger.captured();
+        assert_eq!(logged.iter().filter(|(_, msg)| *msg == expected).count(), 1);
+    }
+
+    #[test]
+    fn test_toxicity_widens_the_spread() {
+        let mut generator = test_generator();
+        generator.set_spread_model(SpreadModel::Constant);
+        let book = test_book();
+        let base = generator.vol_adjusted_spread(&book, 0.0);
+
+        // Ignored until weighted
+        generator.set_toxicity(0.5);
+        assert_eq!(generator.vol_adjusted_spread(&book, 0.0), base);
+
+        generator.set_toxicity_weight(2.0);
+        let spread = generator.vol_adjusted_spread(&book, 0.0);
+        assert!((spread - base * 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_vwap_band_overlay() {
+        let mut generator = test_generator();
+        generator.set_spread_model(SpreadModel::Constant);
+        let book = test_book();
+        let mid = book.get_mid_price();
+        let base = generator.vol_adjusted_spread(&book, 0.0);
+
+        // Ignored until weighted
+        generator.set_vwap_bands(Some((mid - 1.0, mid + 1.0)));
+        assert_eq!(generator.vwap_stretch(mid), None);
+        assert_eq!(generator.vol_adjusted_spread(&book, 0.0), base);
+
+        // At the VWAP the spread tightens by half the weight
+        generator.set_vwap_weight(0.5);
+        assert_eq!(generator.vwap_stretch(mid), Some(0.0));
+        let spread = generator.vol_adjusted_spread(&book, 0.0);
+        assert!((spread - base * 0.75).abs() < 1e-9);
+
+        // Stretched to the upper band, the spread is back to normal and the skew leans short
+        generator.set_vwap_bands(Some((mid - 3.0, mid - 1.0)));
+        assert_eq!(generator.vwap_stretch(mid), Some(1.0));
+        assert!((generator.vol_adjusted_spread(&book, 0.0) - base).abs() < 1e-9);
+        generator.set_vwap_bands(Some((mid - 2.5, mid - 0.5)));
+        assert_eq!(generator.vwap_stretch(mid), Some(1.0));
+        generator.set_vwap_bands(Some((mid - 1.5, mid + 0.5)));
+        assert!((generator.vwap_stretch(mid).unwrap() - 0.5).abs() < 1e-9);
+
+        // A session without any price dispersion has no bands
+        generator.set_vwap_bands(Some((mid, mid)));
+        assert_eq!(generator.vwap_stretch(mid), None);
+
+        generator.set_vwap_band_sigma(-1.0);
+        assert_eq!(generator.vwap_band_sigma(), VWAP_BAND_SIGMA);
+        generator.set_vwap_band_sigma(1.5);
+        assert_eq!(generator.vwap_band_sigma(), 1.5);
+        generator.set_vwap_band_sigma(0.0);
+        assert_eq!(generator.vwap_band_sigma(), VWAP_BAND_SIGMA);
+    }
+
+    #[test]
+    fn test_range_volatility_blend() {
+        let mut generator = test_generator();
+        let book = test_book();
+        let base = 0.0025 * book.get_mid_price();
+        generator.set_range_volatility(0.001);
+
+        // Ignored until it gets a weight
+        let spread = generator.vol_adjusted_spread(&book, 0.0);
+        assert!((spread - base).abs() < 1e-9);
+
+        generator.set_range_vol_weight(0.5);
+        let spread = generator.vol_adjusted_spread(&book, 0.0);
+        let volatility = 0.0005;
+        assert!((spread - base * (1.0 + volatility * VOLATILITY_MULTIPLIER)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_configurable_multipliers() {
+        let mut generator = test_generator();
+        let book = test_book();
+        let base = 0.0025 * book.get_mid_price();
+        let volatility = 0.001;
+
+        generator.set_spread_multipliers(50.0, 2.0);
+        let spread = generator.vol_adjusted_spread(&book, volatility);
+        assert!((spread - base * (1.0 + volatility * 50.0)).abs() < 1e-9);
+
+        // Out of range values are ignored
+        generator.set_spread_multipliers(-1.0, 0.5);
+        assert_eq!(generator.volatility_multiplier, 50.0);
+        assert_eq!(generator.max_spread_multiplier, 2.0);
+        generator.set_spread_multipliers(-1.0, 0.0);
+        assert_eq!(generator.max_spread_multiplier, 2.0);
+        // No volatility scaling leaves the base spread
+        generator.set_spread_multipliers(0.0, 2.0);
+        let spread = generator.vol_adjusted_spread(&book, volatility);
+        assert!((spread - base).abs() < 1e-9);
+
+        generator.set_inventory_adjustment(-0.2);
+        assert_eq!(generator.inventory_adjustment, -0.2);
+        generator.set_inventory_adjustment(0.5);
+        assert_eq!(generator.inventory_adjustment, -0.2);
+        // 0.0 turns the inventory pull off
+        generator.set_inventory_adjustment(0.0);
+        assert_eq!(generator.inventory_adjustment, 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_clears_the_grid() {
+        let mut generator = test_generator();
+        generator.set_dry_run(true);
+        quote(&mut generator, &test_book()).await;
+        assert!(!generator.live_buys.is_empty());
+
+        assert!(generator.shutdown("SOLUSDT").await);
+        assert!(generator.live_buys.is_empty() && generator.live_sells.is_empty());
+        assert!(generator
+            .logger
+            .captured()
+            .iter()
+            .any(|(_, msg)| msg == "Shutting down, cancelling all orders for SOLUSDT"));
+    }
+
+    #[tokio::test]
+    async fn test_circuit_breaker_pulls_quotes_for_the_cooldown() {
+        let mut generator = test_generator();
+        generator.set_dry_run(true);
+        generator.set_circuit_breaker_cooldown(5);
+        let book = test_book();
+        quote(&mut generator, &book).await;
+        assert!(!generator.live_buys.is_empty());
+
+        // Pulled even with the budgets spent
+        generator.buy_cancel_limit = 0;
+        generator.set_rate_limiter(Some(SharedRateLimiter::new(1)));
+        assert!(generator.take_shared_request());
+        assert!(generator.set_price_shock("SOLUSDT", true, 1_000));
+        quote(&mut generator, &book).await;
+        assert!(generator.live_buys.is_empty() && generator.live_sells.is_empty());
+        generator.set_rate_limiter(None);
+        // The move is over but the cooldown isn't
+        assert!(generator.set_price_shock("SOLUSDT", false, 5_999));
+        quote(&mut generator, &book).await;
+        assert!(generator.live_buys.is_empty());
+
+        assert!(!generator.set_price_shock("SOLUSDT", false, 6_000));
+        quote(&mut generator, &book).await;
+        assert!(!generator.live_buys.is_empty());
+        let captured = generator.logger.captured();
+        let messages: Vec<_> = captured.iter().map(|(_, msg)| msg.as_str()).collect();
+        assert!(messages
+            .contains(&"Circuit breaker tripped for SOLUSDT: price shock, pulling quotes for 5s"));
+        assert!(messages.contains(&"Circuit breaker for SOLUSDT reset, quoting"));
+    }
+
+    #[test]
+    fn test_slow_order_requests_trip_the_circuit_breaker() {
+        let mut generator = test_generator();
+        generator.set_circuit_breaker_cooldown(5);
+        let latency = generator.venue().latency().unwrap().clone();
+        // A single slow request doesn't count yet
+        latency.record("batch_orders", "SOLUSDT", Duration::from_millis(800));
+        assert_eq!(generator.order_latency_ms(), None);
+        for _ in 1..MIN_LATENCY_REQUESTS {
+            latency.record("batch_orders", "SOLUSDT", Duration::from_millis(800));
+        }
+        assert_eq!(generator.order_latency_ms(), Some(800.0));
+        // Disabled by default
+        assert!(!generator.set_price_shock("SOLUSDT", false, 1_000));
+
+        generator.set_max_order_latency(500);
+        assert!(generator.set_price_shock("SOLUSDT", false, 1_000));
+        assert!(generator.logger.captured().iter().any(|(_, msg)| msg
+            == "Circuit breaker tripped for SOLUSDT: order requests take 800ms, pulling quotes for 5s"));
+        // The slow requests are forgotten, the ones after the cooldown decide
+        assert_eq!(generator.order_latency_ms(), None);
+        assert!(!generator.set_price_shock("SOLUSDT", false, 6_000));
+        latency.record("batch_orders", "SOLUSDT", Duration::from_millis(20));
+        assert!(!generator.set_price_shock("SOLUSDT", false, 7_000));
+    }
+
+    #[tokio::test]
+    async fn test_exhausted_buy_budget_still_quotes_asks() {
+        let mut generator = test_generator();
+        generator.set_dry_run(true);
+        generator.buy_rate_limit = 0;
+        quote(&mut generator, &test_book()).await;
+
+        assert!(generator.live_buys.is_empty());
+        assert!(!generator.live_sells.is_empty());
+        assert_eq!(generator.buy_rate_limit, 0);
+        assert_eq!(generator.sell_rate_limit, 9);
+    }
+
+    #[test]
+    fn test_split_limit() {
+        // The sides never get more than the limit between them
+        assert_eq!(split_limit(10), (5, 5));
+        assert_eq!(split_limit(7), (4, 3));
+        assert_eq!(split_limit(1), (1, 0));
+        assert_eq!(split_limit(0), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn test_shared_rate_limit_spans_generators() {
+        // One request left for the account once the first generator placed its bids
+        let limiter = SharedRateLimiter::new(3);
+        let mut first = test_generator();
+        let mut second = test_generator();
+        for generator in [&mut first, &mut second] {
+            generator.set_dry_run(true);
+            generator.set_rate_limiter(Some(limiter.clone()));
+        }
+        quote(&mut first, &test_book()).await;
+        assert!(!first.live_buys.is_empty() && !first.live_sells.is_empty());
+        quote(&mut second, &test_book()).await;
+        assert!(!second.live_buys.is_empty() && second.live_sells.is_empty());
+        // The denied asks didn't use up the second generator's own budget
+        assert_eq!(second.sell_rate_limit, 10);
+    }
+
+    #[test]
+    fn test_state_survives_a_restart() {
+        let dir = std::env::temp_dir().join(format!("rs_smm_v2_restart_{}", std::process::id()));
+        let mut generator = test_generator();
+        generator.set_persistence(Box::new(FilePersistence::new(&dir)));
+        generator
+            .live_buys
+            .push_back(LiveOrder::new("buy-1".to_string(), 99.9, 1.0, true));
+        generator
+            .live_sells
+            .push_back(LiveOrder::new("sell-1".to_string(), 100.1, 1.0, false));
+        let mut fill = test_fill(0);
+        fill.order_id = "buy-1".to_string();
+        fill.qty = 0.4;
+        generator.apply_fill(&fill);
+        generator.persist_state("SOLUSDT");
+
+        let mut restarted = test_generator();
+        restarted.set_persistence(Box::new(FilePersistence::new(&dir)));
+        assert!(restarted.restore_state("SOLUSDT"));
+        assert_eq!(restarted.position_qty, 0.4);
+        assert!((restarted.live_buys[0].qty - 0.6).abs() < 1e-9);
+        assert_eq!(restarted.live_sells[0].order_id, "sell-1");
+        assert!((restarted.average_entry_price() - 99.9).abs() < 1e-9);
+        // Nothing changed since the restore, so nothing is written
+        assert_eq!(restarted.persisted_state(), restarted.persisted);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_stale_data_pauses_quoting() {
+        let mut generator = test_generator();
+        generator.set_dry_run(true);
+        generator.set_stale_data("SOLUSDT", true).await;
+        quote(&mut generator, &test_book()).await;
+        assert!(generator.live_buys.is_empty() && generator.live_sells.is_empty());
+
+        generator.set_stale_data("SOLUSDT", false).await;
+        quote(&mut generator, &test_book()).await;
+        assert!(!generator.live_buys.is_empty());
+
+        // The grid is pulled as soon as the data goes stale
+        generator.set_stale_data("SOLUSDT", true).await;
+        assert!(generator.live_buys.is_empty() && generator.live_sells.is_empty());
+
+        let captured = generator.logger.captured();
+        let messages: Vec<_> = captured.iter().map(|(_, msg)| msg.as_str()).collect();
+        assert!(messages.contains(&"Market data for SOLUSDT is stale, not quoting"));
+        assert!(messages.contains(&"Market data for SOLUSDT is fresh again, quoting"));
+    }
 }
@@ -0,0 +1,122 @@
+use skeleton::utils::models::LiveOrder;
+
+/// Fill rate, time to fill and spread captured by the quotes of a generator, to tell
+/// whether the spread is set too tight or too wide.
+///
+/// Only the grid's quotes are counted, they carry the mid they were placed at. The
+/// capture of a fill is its distance from that mid on the passive side, so a quote picked
+/// off after the mid moved through it captures less than its half spread, or even a loss.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct QuoteStats {
+    /// Orders placed or re-priced by the grid
+    pub quotes: u64,
+    /// Quotes filled at least partly
+    pub filled_quotes: u64,
+    /// Executions of the quotes, a quote filled in parts counts each
+    pub fills: u64,
+    // ms from placement to the first fill, summed over the filled quotes placed at a known time
+    time_to_fill_ms: u64,
+    timed_fills: u64,
+    // qty weighted capture in quote currency and in bps of the placement mid
+    captured: f64,
+    captured_bps: f64,
+    filled_qty: f64,
+}
+
+impl QuoteStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn on_quotes(&mut self, count: usize) {
+        self.quotes += count as u64;
+    }
+
+    /// Records an execution of `qty` at `price` against `order`, before the qty moves
+    /// to the order's filled qty. `time` is the execution time in ms.
+    pub fn on_fill(&mut self, order: &LiveOrder, price: f64, qty: f64, time: u64) {
+        if order.placement_mid <= 0.0 || qty <= 0.0 {
+            return;
+        }
+        self.fills += 1;
+        if order.filled_qty == 0.0 {
+            self.filled_quotes += 1;
+            if let Some(age) = order.age(time) {
+                self.time_to_fill_ms += age;
+                self.timed_fills += 1;
+            }
+        }
+        let capture = if order.is_buy {
+            order.placement_mid - price
+        } else {
+            price - order.placement_mid
+        };
+        self.captured += capture * qty;
+        self.captured_bps += capture / order.placement_mid * 10_000.0 * qty;
+        self.filled_qty += qty;
+    }
+
+    /// Share of the quotes that got filled.
+    pub fn fill_rate(&self) -> f64 {
+        if self.quotes == 0 {
+            0.0
+        } else {
+            self.filled_quotes as f64 / self.quotes as f64
+        }
+    }
+
+    /// Average ms from placement to the first fill, `None` before any timed fill.
+    pub fn avg_time_to_fill_ms(&self) -> Option<f64> {
+        (self.timed_fills > 0).then(|| self.time_to_fill_ms as f64 / self.timed_fills as f64)
+    }
+
+    /// Spread captured in quote currency over every fill.
+    pub fn captured(&self) -> f64 {
+        self.captured
+    }
+
+    /// Qty weighted average capture in bps of the placement mid, `None` before any fill.
+    pub fn avg_capture_bps(&self) -> Option<f64> {
+        (self.filled_qty > 0.0).then(|| self.captured_bps / self.filled_qty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quote(is_buy: bool, price: f64, created_at: u64) -> LiveOrder {
+        LiveOrder {
+            created_at,
+            placement_mid: 100.0,
+            ..LiveOrder::new("quote".to_string(), price, 2.0, is_buy)
+        }
+    }
+
+    #[test]
+    fn test_fill_rate_and_capture() {
+        let mut stats = QuoteStats::new();
+        stats.on_quotes(4);
+        assert_eq!(stats.fill_rate(), 0.0);
+        assert_eq!(stats.avg_time_to_fill_ms(), None);
+        assert_eq!(stats.avg_capture_bps(), None);
+
+        // A bid 10bps under the mid filled in two parts
+        let mut bid = quote(true, 99.9, 1_000);
+        stats.on_fill(&bid, 99.9, 1.0, 3_000);
+        bid.filled_qty = 1.0;
+        stats.on_fill(&bid, 99.9, 1.0, 9_000);
+        // An ask picked off 10bps through the placement mid
+        stats.on_fill(&quote(false, 99.9, 2_000), 99.9, 2.0, 6_000);
+        // Orders the grid didn't place aren't counted
+        let manual = LiveOrder::new("manual".to_string(), 99.0, 1.0, true);
+        stats.on_fill(&manual, 99.0, 1.0, 6_000);
+
+        assert_eq!((stats.fills, stats.filled_quotes), (3, 2));
+        assert_eq!(stats.fill_rate(), 0.5);
+        // Only the first fill of each quote is timed
+        assert_eq!(stats.avg_time_to_fill_ms(), Some(3_000.0));
+        assert!(stats.captured().abs() < 1e-9);
+        assert!(stats.avg_capture_bps().unwrap().abs() < 1e-9);
+    }
+}
@@ -0,0 +1,83 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+/// Token bucket of the REST requests of the whole account, shared by the generators of
+/// every symbol.
+///
+/// The per-generator budgets only bound each symbol, while Bybit limits the account as a
+/// whole, so with many symbols their sum can exceed the real limit. Every order, amend and
+/// cancel request also takes a token from here. The bucket holds a second's worth of
+/// requests and refills continuously.
+#[derive(Debug, Clone)]
+pub struct SharedRateLimiter(Arc<Mutex<TokenBucket>>);
+
+#[derive(Debug)]
+struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    // tokens added per second
+    refill_rate: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn try_acquire(&mut self, now: Instant) -> bool {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_rate).min(self.capacity);
+        self.last_refill = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl SharedRateLimiter {
+    /// Allows `requests_per_sec` requests per second on average, in bursts of as many.
+    pub fn new(requests_per_sec: u32) -> Self {
+        let rate = f64::from(requests_per_sec.max(1));
+        Self(Arc::new(Mutex::new(TokenBucket {
+            capacity: rate,
+            tokens: rate,
+            refill_rate: rate,
+            last_refill: Instant::now(),
+        })))
+    }
+
+    /// Takes a request if one is available, never waits.
+    pub fn try_acquire(&self) -> bool {
+        self.0.lock().unwrap().try_acquire(Instant::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_bucket_refills_over_time() {
+        let limiter = SharedRateLimiter::new(4);
+        // Clones draw from the same bucket
+        let other = limiter.clone();
+        assert!((0..2).all(|_| limiter.try_acquire()));
+        assert!((0..2).all(|_| other.try_acquire()));
+        assert!(!limiter.try_acquire());
+
+        let mut bucket = limiter.0.lock().unwrap();
+        let start = bucket.last_refill;
+        // A quarter of a second gives back one of the four requests
+        assert!(bucket.try_acquire(start + Duration::from_millis(250)));
+        assert!(!bucket.try_acquire(start + Duration::from_millis(250)));
+        // Idle time never fills the bucket past a second's worth
+        let later = start + Duration::from_secs(60);
+        assert!((0..4).all(|_| bucket.try_acquire(later)));
+        assert!(!bucket.try_acquire(later));
+    }
+}
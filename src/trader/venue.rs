@@ -0,0 +1,270 @@
+use skeleton::{
+    exchange::exchange::Exchange,
+    utils::{
+        latency::RequestLatency,
+        models::{
+            BatchAmend, BatchOrder, BinanceClient, BinancePrivate, BybitClient, BybitPrivate,
+            FeeTier, Fill, LiveOrder, RejectedAmend, RejectedOrder, SymbolInfo, TimeInForce,
+            BYBIT_POST_ONLY_REJECTED,
+        },
+    },
+};
+
+type Result<T> = std::result::Result<T, String>;
+
+/// The exchange a generator sends its orders to.
+///
+/// Both clients implement `Exchange` with their own output types, this narrows them to
+/// what the generator needs and turns the errors into messages.
+#[derive(Debug, Clone)]
+pub enum Venue {
+    Bybit(BybitClient),
+    Binance(BinanceClient),
+}
+
+impl From<BybitClient> for Venue {
+    fn from(client: BybitClient) -> Self {
+        Venue::Bybit(client)
+    }
+}
+
+impl From<BinanceClient> for Venue {
+    fn from(client: BinanceClient) -> Self {
+        Venue::Binance(client)
+    }
+}
+
+impl Venue {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Venue::Bybit(_) => "bybit",
+            Venue::Binance(_) => "binance",
+        }
+    }
+
+    /// Round trip times of the order requests, `None` on venues whose requests aren't timed.
+    pub fn latency(&self) -> Option<&RequestLatency> {
+        match self {
+            Venue::Bybit(client) => Some(&client.latency),
+            Venue::Binance(_) => None,
+        }
+    }
+
+    pub async fn set_leverage(&self, symbol: &str, leverage: u8) -> Result<bool> {
+        match self {
+            Venue::Bybit(client) => client
+                .set_leverage(symbol, leverage)
+                .await
+                .map_err(|e| e.to_string()),
+            Venue::Binance(client) => client
+                .set_leverage(symbol, leverage)
+                .await
+                .map_err(|e| e.to_string()),
+        }
+    }
+
+    pub async fn symbol_info(&self, symbol: &str) -> Result<SymbolInfo> {
+        match self {
+            Venue::Bybit(client) => client
+                .get_symbol_info(symbol)
+                .await
+                .map_err(|e| e.to_string()),
+            Venue::Binance(client) => client
+                .get_symbol_info(symbol)
+                .await
+                .map_err(|e| e.to_string()),
+        }
+    }
+
+    pub async fn fees(&self, symbol: String) -> Result<FeeTier> {
+        match self {
+            Venue::Bybit(client) => client.fees(symbol).await.map_err(|e| e.to_string()),
+            Venue::Binance(client) => client.fees(symbol).await.map_err(|e| e.to_string()),
+        }
+    }
+
+    pub async fn place_order(
+        &self,
+        symbol: &str,
+        price: f64,
+        qty: f64,
+        is_buy: bool,
+        reduce_only: bool,
+        time_in_force: TimeInForce,
+    ) -> Result<LiveOrder> {
+        match self {
+            Venue::Bybit(client) => client
+                .place_order(symbol, price, qty, is_buy, reduce_only, time_in_force)
+                .await
+                .map_err(|e| e.to_string()),
+            Venue::Binance(client) => client
+                .place_order(symbol, price, qty, is_buy, reduce_only, time_in_force)
+                .await
+                .map_err(|e| e.to_string()),
+        }
+    }
+
+    /// Places the orders, returns the live (buys, sells) and the orders the exchange refused.
+    pub async fn batch_orders(
+        &self,
+        orders: Vec<BatchOrder>,
+    ) -> Result<(Vec<LiveOrder>, Vec<LiveOrder>, Vec<RejectedOrder>)> {
+        match self {
+            Venue::Bybit(client) => client.batch_orders(orders).await.map_err(|e| e.to_string()),
+            Venue::Binance(client) => client.batch_orders(orders).await.map_err(|e| e.to_string()),
+        }
+    }
+
+    /// Amends the orders, returns the amended orders with the id each was amended under and
+    /// the amends the exchange refused. Binance amends come back with the id of the
+    /// replacing order.
+    pub async fn batch_amends(
+        &self,
+        orders: Vec<BatchAmend>,
+    ) -> Result<(Vec<(String, LiveOrder)>, Vec<RejectedAmend>)> {
+        match self {
+            Venue::Bybit(client) => client.batch_amends(orders).await.map_err(|e| e.to_string()),
+            Venue::Binance(client) => client.batch_amends(orders).await.map_err(|e| e.to_string()),
+        }
+    }
+
+    pub async fn cancel_order(&self, order_id: &str, symbol: &str) -> Result<()> {
+        match self {
+            Venue::Bybit(client) => client
+                .cancel_order(order_id, symbol)
+                .await
+                .map(|_| ())
+                .map_err(|e| e.to_string()),
+            Venue::Binance(client) => client
+                .cancel_order(order_id, symbol)
+                .await
+                .map(|_| ())
+                .map_err(|e| e.to_string()),
+        }
+    }
+
+    /// Cancels every order of `symbol`. Returns the ids of the cancelled orders, `None`
+    /// when the exchange doesn't list them and every order is gone.
+    pub async fn cancel_all(&self, symbol: &str) -> Result<Option<Vec<String>>> {
+        match self {
+            Venue::Bybit(client) => client
+                .cancel_all(symbol)
+                .await
+                .map(|cancelled| Some(cancelled.into_iter().map(|o| o.order_id).collect()))
+                .map_err(|e| e.to_string()),
+            Venue::Binance(client) => client
+                .cancel_all(symbol)
+                .await
+                .map(|_| None)
+                .map_err(|e| e.to_string()),
+        }
+    }
+
+    pub async fn get_open_orders(&self, symbol: &str) -> Result<Vec<LiveOrder>> {
+        match self {
+            Venue::Bybit(client) => client
+                .get_open_orders(symbol)
+                .await
+                .map_err(|e| e.to_string()),
+            Venue::Binance(client) => client
+                .get_open_orders(symbol)
+                .await
+                .map_err(|e| e.to_string()),
+        }
+    }
+
+    pub async fn get_position(&self, symbol: &str) -> Result<f64> {
+        match self {
+            Venue::Bybit(client) => client.get_position(symbol).await.map_err(|e| e.to_string()),
+            Venue::Binance(client) => client.get_position(symbol).await.map_err(|e| e.to_string()),
+        }
+    }
+
+    /// Equity of `coin` in the account, unrealized PnL included.
+    pub async fn get_wallet_balance(&self, coin: &str) -> Result<f64> {
+        match self {
+            Venue::Bybit(client) => client
+                .get_wallet_balance(coin)
+                .await
+                .map_err(|e| e.to_string()),
+            Venue::Binance(client) => client
+                .get_wallet_balance(coin)
+                .await
+                .map_err(|e| e.to_string()),
+        }
+    }
+
+    /// API key of the account, symbols sharing one share the account's equity.
+    pub fn api_key(&self) -> &str {
+        match self {
+            Venue::Bybit(client) => &client.api_key,
+            Venue::Binance(client) => &client.api_key,
+        }
+    }
+}
+
+/// The private stream data of a symbol on the venue it's quoted on.
+#[derive(Debug, Clone)]
+pub enum PrivateData {
+    Bybit(BybitPrivate),
+    Binance(BinancePrivate),
+}
+
+impl From<BybitPrivate> for PrivateData {
+    fn from(private: BybitPrivate) -> Self {
+        PrivateData::Bybit(private)
+    }
+}
+
+impl From<BinancePrivate> for PrivateData {
+    fn from(private: BinancePrivate) -> Self {
+        PrivateData::Binance(private)
+    }
+}
+
+impl PrivateData {
+    /// Connection the data came from, see `BybitPrivate::session`.
+    pub fn session(&self) -> u64 {
+        match self {
+            PrivateData::Bybit(private) => private.session,
+            PrivateData::Binance(private) => private.session,
+        }
+    }
+
+    /// Ids of the post-only orders the exchange cancelled on the order stream instead of
+    /// letting them take liquidity. Bybit accepts a crossing post-only order and cancels it
+    /// right after, Binance expires a crossing GTX order.
+    pub fn post_only_cancels(&self) -> Box<dyn Iterator<Item = String> + '_> {
+        match self {
+            PrivateData::Bybit(private) => Box::new(
+                private
+                    .orders
+                    .iter()
+                    .filter(|order| {
+                        order.order_status == "Cancelled"
+                            && order.reject_reason == BYBIT_POST_ONLY_REJECTED
+                    })
+                    .map(|order| order.order_id.clone()),
+            ),
+            PrivateData::Binance(private) => Box::new(
+                private
+                    .orders
+                    .iter()
+                    .filter(|order| order.order_status == "EXPIRED" && order.time_in_force == "GTX")
+                    .map(|order| order.order_id.to_string()),
+            ),
+        }
+    }
+
+    /// The executions normalized to fills, newest first.
+    pub fn fills(&self) -> Box<dyn Iterator<Item = Fill> + '_> {
+        match self {
+            PrivateData::Bybit(private) => {
+                Box::new(private.executions.iter().rev().map(Fill::from))
+            }
+            PrivateData::Binance(private) => {
+                Box::new(private.executions.iter().rev().map(Fill::from))
+            }
+        }
+    }
+}